@@ -0,0 +1,70 @@
+use crate::pricing::RoundingMode;
+
+/// Token deposits are paid in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Asset {
+    pub symbol: &'static str,
+    /// Token contract address
+    pub address: &'static str,
+    /// Decimals of the token's smallest unit (6 for USDC, 18 for most ERC-20s)
+    pub decimals: u32,
+}
+
+/// USDC on Base Sepolia
+pub const USDC: Asset = Asset {
+    symbol: "USDC",
+    address: "0x036CbD53842c5426634e7929541eC2318f3dCF7e",
+    decimals: 6,
+};
+
+impl Asset {
+    /// Whole tokens for an amount in the smallest unit
+    pub fn to_tokens(self, units: u128) -> f64 {
+        units_to_tokens(units, self.decimals)
+    }
+
+    /// Smallest units for an amount in whole tokens, rounded with `rounding`
+    pub fn to_units(self, tokens: f64, rounding: RoundingMode) -> u128 {
+        tokens_to_units(tokens, self.decimals, rounding)
+    }
+}
+
+/// Whole tokens for an amount in the smallest unit of a token with `decimals`
+/// Whole and fractional parts are converted separately so large amounts keep their precision
+pub fn units_to_tokens(units: u128, decimals: u32) -> f64 {
+    let scale = 10u128.pow(decimals);
+    (units / scale) as f64 + (units % scale) as f64 / scale as f64
+}
+
+/// Smallest units of a token with `decimals` for an amount in whole tokens, rounded with `rounding`
+pub fn tokens_to_units(tokens: f64, decimals: u32, rounding: RoundingMode) -> u128 {
+    rounding.apply(tokens * 10u128.pow(decimals) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WETH: Asset = Asset {
+        symbol: "WETH",
+        address: "0x4200000000000000000000000000000000000006",
+        decimals: 18,
+    };
+
+    #[test]
+    fn test_conversions_use_asset_decimals() {
+        assert_eq!(USDC.to_tokens(1_000_000), 1.0);
+        assert_eq!(USDC.to_tokens(2_500_001), 2.500001);
+        assert_eq!(USDC.to_units(1.0, RoundingMode::Round), 1_000_000);
+        assert_eq!(USDC.to_units(0.000001, RoundingMode::Round), 1);
+
+        // One whole 18-decimal token credits 1.0, not a trillion
+        assert_eq!(WETH.to_tokens(1_000_000_000_000_000_000), 1.0);
+        assert_eq!(WETH.to_tokens(1_500_000_000_000_000_000), 1.5);
+        assert_eq!(WETH.to_tokens(1), 1e-18);
+        assert_eq!(WETH.to_units(1.0, RoundingMode::Round), 1_000_000_000_000_000_000);
+
+        // Past u64::MAX (about 18.4 tokens at 18 decimals)
+        assert_eq!(WETH.to_tokens(100_000_000_000_000_000_000), 100.0);
+    }
+}
@@ -0,0 +1,854 @@
+use alloy::primitives::{keccak256, Address, Signature, B256};
+use axum::http::{HeaderMap, Method, StatusCode};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+use crate::block_time::BlockClock;
+use crate::clock::ClockMonitor;
+use crate::config::{BodyCanonicalization, BodyHashAlgorithm};
+use crate::signature_cache::SignatureCache;
+
+/// Timestamp window in seconds - requests must be within this time
+pub const TIMESTAMP_WINDOW_SECS: u64 = 60;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum AuthError {
+    /// The request carries no credentials for this scheme
+    #[error("Authentication headers required")]
+    Missing,
+
+    #[error("Replay detected: signature already used")]
+    Replay,
+
+    /// Under monotonic timestamps, the request isn't later than the address's last one
+    #[error("Replay detected: timestamp not after the last accepted request")]
+    StaleTimestamp,
+
+    #[error("Authentication failed: {0}")]
+    Invalid(String),
+
+    /// The same credential header was sent more than once
+    #[error("Duplicate {0} header")]
+    Duplicate(String),
+}
+
+impl AuthError {
+    /// HTTP status for the error; ambiguous requests are malformed rather than unauthenticated
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AuthError::Duplicate(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::UNAUTHORIZED,
+        }
+    }
+}
+
+/// An authenticated caller
+#[derive(Debug, Clone, PartialEq)]
+pub struct Authenticated {
+    /// Verified account; use `account_key` rather than any client-supplied string for storage
+    pub address: Address,
+
+    /// Request time recorded as the account's latest activity
+    pub timestamp: u64,
+
+    /// Signature to mark as used once the request is accepted (None for schemes without replay protection)
+    pub signature: Option<String>,
+}
+
+/// The parts of a request authentication schemes look at
+pub struct AuthRequest<'a> {
+    pub method: &'a Method,
+    pub path: &'a str,
+    pub headers: &'a HeaderMap,
+    pub body: &'a [u8],
+
+    /// Common name of the verified TLS client certificate, if the connection presented one
+    pub client_subject: Option<&'a str>,
+}
+
+/// A way of identifying the account a request is billed to
+pub trait AuthScheme: Send + Sync {
+    /// Authenticate a request, returning `AuthError::Missing` if it doesn't use this scheme
+    fn authenticate(&self, request: &AuthRequest<'_>) -> Result<Authenticated, AuthError>;
+}
+
+/// Try each scheme in order; the first one the request carries credentials for decides
+pub fn authenticate(schemes: &[Box<dyn AuthScheme>], request: &AuthRequest<'_>) -> Result<Authenticated, AuthError> {
+    reject_duplicate_credentials(request.headers)?;
+
+    for scheme in schemes {
+        match scheme.authenticate(request) {
+            Err(AuthError::Missing) => continue,
+            result => return result,
+        }
+    }
+    Err(AuthError::Missing)
+}
+
+/// Proxies may disagree on which of several copies of a header counts, so a request
+/// carrying any credential header more than once is rejected outright
+fn reject_duplicate_credentials(headers: &HeaderMap) -> Result<(), AuthError> {
+    for name in headers.keys() {
+        let credential = name.as_str().starts_with("x-auth-") || name == "x-api-key";
+        if credential && headers.get_all(name).iter().nth(1).is_some() {
+            return Err(AuthError::Duplicate(name.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Canonical database key for an account: 0x-prefixed lowercase hex
+pub fn account_key(address: &Address) -> String {
+    format!("{:#x}", address)
+}
+
+/// Address as shown in responses: EIP-55 checksummed, unlike the lowercase storage key
+/// Values that aren't addresses are passed through unchanged
+pub fn display_address(address: &str) -> String {
+    Address::from_str(address).map_or_else(|_| address.to_string(), |address| address.to_checksum(None))
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// ECDSA signature over the request, sent as X-Auth-Address, X-Auth-Signature and X-Auth-Timestamp
+pub struct SignatureAuth {
+    signature_cache: Arc<SignatureCache>,
+    clock: Arc<Mutex<ClockMonitor>>,
+    /// Chain time timestamps are judged against instead of the wall clock, if configured
+    block_clock: Option<Arc<BlockClock>>,
+    body_hash_algorithms: Vec<BodyHashAlgorithm>,
+    body_canonicalizations: Vec<BodyCanonicalization>,
+    /// Longest a session key delegation may run for (delegations are refused if None)
+    max_delegation_secs: Option<u64>,
+}
+
+impl SignatureAuth {
+    pub fn new(
+        signature_cache: Arc<SignatureCache>,
+        clock: Arc<Mutex<ClockMonitor>>,
+        block_clock: Option<Arc<BlockClock>>,
+        body_hash_algorithms: Vec<BodyHashAlgorithm>,
+        body_canonicalizations: Vec<BodyCanonicalization>,
+        max_delegation_secs: Option<u64>,
+    ) -> Self {
+        Self { signature_cache, clock, block_clock, body_hash_algorithms, body_canonicalizations, max_delegation_secs }
+    }
+
+    /// Session key allowed to sign for `address` by the request's X-Auth-Delegation, if it carries one
+    fn delegated_signer(&self, address: &str, now: u64, request: &AuthRequest<'_>) -> Result<Option<Address>, AuthError> {
+        let Some(value) = request.headers.get("x-auth-delegation") else {
+            return Ok(None);
+        };
+        let Some(max_delegation_secs) = self.max_delegation_secs else {
+            return Err(AuthError::Invalid("Session key delegations are not accepted".to_string()));
+        };
+        let delegation = value
+            .to_str()
+            .ok()
+            .and_then(Delegation::parse)
+            .ok_or_else(|| AuthError::Invalid("Invalid X-Auth-Delegation header".to_string()))?;
+
+        if delegation.expires < now {
+            return Err(AuthError::Invalid("Delegation expired".to_string()));
+        }
+        if delegation.expires - now > max_delegation_secs {
+            return Err(AuthError::Invalid(format!(
+                "Delegation runs longer than the {} seconds accepted",
+                max_delegation_secs
+            )));
+        }
+        if delegation.scope != "*" && delegation.scope != request.path {
+            return Err(AuthError::Invalid(format!("Session key is not delegated for {}", request.path)));
+        }
+
+        let account = address
+            .parse::<Address>()
+            .map_err(|e| AuthError::Invalid(format!("Invalid address format: {}", e)))?;
+        let message = delegation_message(address, &delegation.session.to_string(), delegation.expires, delegation.scope);
+        if recover_signer(&message, delegation.signature) != Ok(account) {
+            return Err(AuthError::Invalid("Delegation was not signed by the account".to_string()));
+        }
+
+        Ok(Some(delegation.session))
+    }
+
+    /// Body hash algorithm the client signed with, if this gateway accepts it
+    fn body_hash_algorithm(&self, headers: &HeaderMap) -> Result<BodyHashAlgorithm, AuthError> {
+        body_hash_algorithm(headers, &self.body_hash_algorithms).map_err(AuthError::Invalid)
+    }
+
+    /// Body form the client hashed, if this gateway accepts it
+    fn body_canonicalization(&self, headers: &HeaderMap) -> Result<BodyCanonicalization, AuthError> {
+        body_canonicalization(headers, &self.body_canonicalizations).map_err(AuthError::Invalid)
+    }
+}
+
+/// Body hash algorithm named in X-Auth-Body-Hash-Algorithm (keccak256 if absent), if it is `accepted`
+pub fn body_hash_algorithm(headers: &HeaderMap, accepted: &[BodyHashAlgorithm]) -> Result<BodyHashAlgorithm, String> {
+    let algorithm = match headers.get("x-auth-body-hash-algorithm") {
+        Some(value) => value
+            .to_str()
+            .map_err(|_| "Invalid X-Auth-Body-Hash-Algorithm header".to_string())?
+            .parse()?,
+        None => BodyHashAlgorithm::Keccak256,
+    };
+    if !accepted.contains(&algorithm) {
+        return Err(format!("Body hash algorithm {:?} is not accepted", algorithm));
+    }
+    Ok(algorithm)
+}
+
+/// Body form named in X-Auth-Body-Canonicalization (exact if absent), if it is `accepted`
+pub fn body_canonicalization(headers: &HeaderMap, accepted: &[BodyCanonicalization]) -> Result<BodyCanonicalization, String> {
+    let canonicalization = match headers.get("x-auth-body-canonicalization") {
+        Some(value) => value
+            .to_str()
+            .map_err(|_| "Invalid X-Auth-Body-Canonicalization header".to_string())?
+            .parse()?,
+        None => BodyCanonicalization::Exact,
+    };
+    if !accepted.contains(&canonicalization) {
+        return Err(format!("Body canonicalization {:?} is not accepted", canonicalization));
+    }
+    Ok(canonicalization)
+}
+
+impl AuthScheme for SignatureAuth {
+    fn authenticate(&self, request: &AuthRequest<'_>) -> Result<Authenticated, AuthError> {
+        let (address, signature, timestamp) = extract_auth_headers(request.headers).ok_or(AuthError::Missing)?;
+
+        // Check if signature has been used before (replay attack)
+        if self.signature_cache.is_replay(&signature) {
+            return Err(AuthError::Replay);
+        }
+
+        let algorithm = self.body_hash_algorithm(request.headers)?;
+        let canonicalization = self.body_canonicalization(request.headers)?;
+        let future_window_secs = self.clock.lock().unwrap().future_window_secs(TIMESTAMP_WINDOW_SECS);
+        let now = self.block_clock.as_ref().map_or_else(now_secs, |clock| clock.now_secs());
+        check_timestamp(timestamp, now, future_window_secs).map_err(AuthError::Invalid)?;
+
+        // Under a delegation a session key signs the request, which is still billed to the account
+        let session_key = self.delegated_signer(&address, now, request)?;
+        let address = verify_signature(&address, session_key, &signature, timestamp, algorithm, canonicalization, request)
+            .map_err(AuthError::Invalid)?;
+
+        Ok(Authenticated {
+            address,
+            timestamp,
+            signature: Some(signature),
+        })
+    }
+}
+
+/// Extract authentication headers from request
+/// Returns (address, signature, timestamp) if all headers are present
+fn extract_auth_headers(headers: &HeaderMap) -> Option<(String, String, u64)> {
+    let address = headers.get("x-auth-address")?.to_str().ok()?.to_string();
+    let signature = headers.get("x-auth-signature")?.to_str().ok()?.to_string();
+    let timestamp = headers.get("x-auth-timestamp")?
+        .to_str().ok()?
+        .parse::<u64>().ok()?;
+
+    Some((address, signature, timestamp))
+}
+
+/// Message a client signs for a request, its body hashed with `algorithm` in its `canonicalization` form
+/// Format: address + timestamp + method + path + body_hash
+/// Binding method and path prevents replaying a signature against another endpoint
+pub fn signed_message_with(
+    address: &str,
+    timestamp: u64,
+    method: &str,
+    path: &str,
+    body: &[u8],
+    algorithm: BodyHashAlgorithm,
+    canonicalization: BodyCanonicalization,
+) -> Result<String, String> {
+    let body = signed_body(canonicalization, body)?;
+    Ok(message_with_body_hash(address, timestamp, method, path, &body_hash(algorithm, &body)))
+}
+
+/// Hex digest of a request body
+pub fn body_hash(algorithm: BodyHashAlgorithm, body: &[u8]) -> String {
+    match algorithm {
+        BodyHashAlgorithm::Keccak256 => hex::encode(keccak256(body)),
+        BodyHashAlgorithm::Sha256 => hex::encode(Sha256::digest(body)),
+    }
+}
+
+/// The bytes of `body` that are hashed into the signed message
+/// Canonical JSON sorts object keys and drops whitespace; must match the transport's `canonical_json`
+pub fn signed_body(canonicalization: BodyCanonicalization, body: &[u8]) -> Result<Cow<'_, [u8]>, String> {
+    match canonicalization {
+        BodyCanonicalization::Exact => Ok(Cow::Borrowed(body)),
+        BodyCanonicalization::CanonicalJson => {
+            let value: serde_json::Value = serde_json::from_slice(body)
+                .map_err(|_| "request body is not valid JSON, so it can't be canonicalized".to_string())?;
+            Ok(Cow::Owned(canonical_json(value).to_string().into_bytes()))
+        }
+    }
+}
+
+/// `value` with every object's keys in sorted order
+fn canonical_json(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(entries.into_iter().map(|(key, value)| (key, canonical_json(value))).collect())
+        }
+        serde_json::Value::Array(values) => serde_json::Value::Array(values.into_iter().map(canonical_json).collect()),
+        other => other,
+    }
+}
+
+/// Signed message for a body known only by its hex hash
+fn message_with_body_hash(address: &str, timestamp: u64, method: &str, path: &str, body_hash: &str) -> String {
+    format!("{}{}{}{}{}", address, timestamp, method, path, body_hash)
+}
+
+/// Address whose key signed the keccak256 hash of `message`
+pub fn recover_signer(message: &str, signature: &str) -> Result<Address, String> {
+    let sig = Signature::from_str(signature)
+        .map_err(|e| format!("Invalid signature format: {}", e))?;
+
+    sig.recover_address_from_prehash(&keccak256(message.as_bytes()))
+        .map_err(|e| format!("Failed to recover address: {}", e))
+}
+
+/// Check a request timestamp is within the window around server time `now`
+/// `future_window_secs` is how far ahead of `now` it may be (see `ClockMonitor`)
+fn check_timestamp(timestamp: u64, now: u64, future_window_secs: u64) -> Result<(), String> {
+    let window = if timestamp > now { future_window_secs } else { TIMESTAMP_WINDOW_SECS };
+    if now.abs_diff(timestamp) > window {
+        return Err(format!(
+            "Timestamp outside window: {} seconds drift",
+            now.abs_diff(timestamp)
+        ));
+    }
+    Ok(())
+}
+
+/// Verify the request signature, returning the verified address
+/// The signer must be the account itself, or `session_key` if the account delegated to one
+fn verify_signature(
+    address: &str,
+    session_key: Option<Address>,
+    signature: &str,
+    timestamp: u64,
+    algorithm: BodyHashAlgorithm,
+    canonicalization: BodyCanonicalization,
+    request: &AuthRequest<'_>,
+) -> Result<Address, String> {
+    // Reconstruct the message that was signed and recover its signer
+    let method = request.method.as_str();
+    let body = signed_body(canonicalization, request.body).map_err(|e| format!("Signature verification failed: {}", e))?;
+    let body_hash = body_hash(algorithm, &body);
+    let message = message_with_body_hash(address, timestamp, method, request.path, &body_hash);
+    let recovered_address = recover_signer(&message, signature)?;
+
+    let claimed_address = address.parse::<Address>()
+        .map_err(|e| format!("Invalid address format: {}", e))?;
+    let expected_signer = session_key.unwrap_or(claimed_address);
+
+    if recovered_address != expected_signer {
+        // If the signature holds over the body hash the client says it signed (X-Auth-Body-Hash),
+        // the body was changed in transit rather than signed by someone else
+        let signed_body_hash = request
+            .headers
+            .get("x-auth-body-hash")
+            .and_then(|value| value.to_str().ok())
+            .map(|hash| hash.trim_start_matches("0x").to_lowercase());
+        if let Some(signed_body_hash) = signed_body_hash {
+            let original = message_with_body_hash(address, timestamp, method, request.path, &signed_body_hash);
+            if signed_body_hash != body_hash && recover_signer(&original, signature) == Ok(expected_signer) {
+                return Err("Signature verification failed: request body does not match signature".to_string());
+            }
+        }
+        return Err("Signature verification failed: address mismatch".to_string());
+    }
+
+    Ok(claimed_address)
+}
+
+/// A session key an account authorized to sign its requests, sent as X-Auth-Delegation:
+/// `<session address>:<expiry>:<scope>:<account signature over delegation_message>`
+struct Delegation<'a> {
+    session: Address,
+    /// Unix time the delegation is accepted until
+    expires: u64,
+    /// The one path the session key may sign for, or `*` for every path
+    scope: &'a str,
+    signature: &'a str,
+}
+
+impl<'a> Delegation<'a> {
+    fn parse(value: &'a str) -> Option<Self> {
+        let (rest, signature) = value.rsplit_once(':')?;
+        let mut parts = rest.splitn(3, ':');
+        Some(Self {
+            session: parts.next()?.parse().ok()?,
+            expires: parts.next()?.parse().ok()?,
+            scope: parts.next()?,
+            signature,
+        })
+    }
+}
+
+/// Message an account signs to let `session` sign its requests for `scope` until `expires`
+pub fn delegation_message(account: &str, session: &str, expires: u64, scope: &str) -> String {
+    format!("x402-delegation:{}:{}:{}:{}", account.to_lowercase(), session.to_lowercase(), expires, scope)
+}
+
+/// Static API key sent as X-Api-Key, for clients that can't sign EVM messages
+pub struct ApiKeyAuth {
+    /// Account address by keccak256 digest of the key
+    addresses: HashMap<B256, Address>,
+}
+
+impl ApiKeyAuth {
+    /// `keys` maps each key to its account address (validated at config load)
+    pub fn new(keys: &HashMap<String, String>) -> Self {
+        let addresses = keys
+            .iter()
+            .map(|(key, address)| {
+                let address = Address::from_str(address).expect("Invalid api_keys address");
+                (keccak256(key.as_bytes()), address)
+            })
+            .collect();
+        Self { addresses }
+    }
+}
+
+impl AuthScheme for ApiKeyAuth {
+    fn authenticate(&self, request: &AuthRequest<'_>) -> Result<Authenticated, AuthError> {
+        let key = request
+            .headers
+            .get("x-api-key")
+            .ok_or(AuthError::Missing)?
+            .to_str()
+            .map_err(|_| AuthError::Invalid("Invalid API key".to_string()))?;
+
+        // Look keys up by digest so lookup time doesn't depend on the key contents
+        let address = self
+            .addresses
+            .get(&keccak256(key.as_bytes()))
+            .ok_or_else(|| AuthError::Invalid("Invalid API key".to_string()))?;
+
+        Ok(Authenticated {
+            address: *address,
+            timestamp: now_secs(),
+            signature: None,
+        })
+    }
+}
+
+/// Verified TLS client certificate, mapped by subject common name to an account
+pub struct ClientCertAuth {
+    /// Account address by certificate common name
+    accounts: HashMap<String, Address>,
+}
+
+impl ClientCertAuth {
+    /// `accounts` maps each subject common name to its account address (validated at config load)
+    pub fn new(accounts: &HashMap<String, String>) -> Self {
+        let accounts = accounts
+            .iter()
+            .map(|(subject, address)| {
+                (subject.clone(), Address::from_str(address).expect("Invalid mtls.accounts address"))
+            })
+            .collect();
+        Self { accounts }
+    }
+}
+
+impl AuthScheme for ClientCertAuth {
+    fn authenticate(&self, request: &AuthRequest<'_>) -> Result<Authenticated, AuthError> {
+        let subject = request.client_subject.ok_or(AuthError::Missing)?;
+
+        // The certificate was verified during the handshake; an unmapped one just isn't an account
+        let Some(address) = self.accounts.get(subject) else {
+            tracing::debug!(subject = %subject, "Client certificate subject is not mapped to an account");
+            return Err(AuthError::Missing);
+        };
+
+        Ok(Authenticated {
+            address: *address,
+            timestamp: now_secs(),
+            signature: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use alloy::signers::local::PrivateKeySigner;
+    use alloy::signers::SignerSync;
+    use axum::http::HeaderValue;
+
+    const API_KEY: &str = "internal-service-key-0123456789abcdef";
+    const API_KEY_ADDRESS: &str = "0x00000000000000000000000000000000000000AA";
+
+    fn signature_auth() -> SignatureAuth {
+        SignatureAuth::new(
+            Arc::new(SignatureCache::new()),
+            Arc::new(Mutex::new(ClockMonitor::new(None))),
+            None,
+            vec![BodyHashAlgorithm::Keccak256, BodyHashAlgorithm::Sha256],
+            vec![BodyCanonicalization::Exact, BodyCanonicalization::CanonicalJson],
+            Some(3600),
+        )
+    }
+
+    fn api_key_auth() -> ApiKeyAuth {
+        ApiKeyAuth::new(&HashMap::from([(API_KEY.to_string(), API_KEY_ADDRESS.to_string())]))
+    }
+
+    fn request<'a>(path: &'a str, headers: &'a HeaderMap, body: &'a [u8]) -> AuthRequest<'a> {
+        AuthRequest { method: &Method::POST, path, headers, body, client_subject: None }
+    }
+
+    fn api_key_headers(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_str(key).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_signature_scheme_authenticates_signer() {
+        let signer = PrivateKeySigner::random();
+        let body = rpc_call("eth_chainId", 1);
+        let signed = signed_request("/relay", &signer, &body);
+        let scheme = signature_auth();
+
+        let auth = scheme
+            .authenticate(&request("/relay", signed.headers(), body.as_bytes()))
+            .unwrap();
+        assert_eq!(auth.address, signer.address());
+        assert!(auth.signature.is_some());
+
+        // Bound to the path it was signed for
+        let error = scheme
+            .authenticate(&request("/other", signed.headers(), body.as_bytes()))
+            .unwrap_err();
+        assert!(matches!(error, AuthError::Invalid(_)));
+
+        // A used signature is a replay
+        scheme.signature_cache.add(auth.signature.as_ref().unwrap());
+        let error = scheme
+            .authenticate(&request("/relay", signed.headers(), body.as_bytes()))
+            .unwrap_err();
+        assert_eq!(error, AuthError::Replay);
+    }
+
+    #[test]
+    fn test_backward_chain_time_does_not_reopen_evicted_signatures() {
+        let signer = PrivateKeySigner::random();
+        let body = rpc_call("eth_chainId", 1);
+        let wall = super::now_secs();
+        let block_clock = Arc::new(BlockClock::new(300));
+        let scheme = |signature_cache: Arc<SignatureCache>| {
+            SignatureAuth::new(
+                signature_cache,
+                Arc::new(Mutex::new(ClockMonitor::new(None))),
+                Some(block_clock.clone()),
+                vec![BodyHashAlgorithm::Keccak256],
+                vec![BodyCanonicalization::Exact],
+                None,
+            )
+        };
+
+        // Signed and accepted at chain time
+        block_clock.observe(wall - 200);
+        let signed = signed_request_at("/relay", &signer, &body, wall - 200);
+        scheme(Arc::new(SignatureCache::new()))
+            .authenticate(&request("/relay", signed.headers(), body.as_bytes()))
+            .unwrap();
+
+        // Chain time moves on, with requests judged against it, and the signature leaves the cache...
+        block_clock.observe(wall);
+        let scheme = scheme(Arc::new(SignatureCache::new()));
+        let later = signed_request_at("/relay", &signer, &body, wall);
+        scheme.authenticate(&request("/relay", later.headers(), body.as_bytes())).unwrap();
+
+        // ...so re-anchoring to a lagging node must not bring its timestamp back into the window
+        block_clock.observe(wall - 200);
+        let error = scheme
+            .authenticate(&request("/relay", signed.headers(), body.as_bytes()))
+            .unwrap_err();
+        assert!(matches!(error, AuthError::Invalid(_)));
+    }
+
+    #[test]
+    fn test_session_key_signs_for_delegating_account() {
+        let account = PrivateKeySigner::random();
+        let session = PrivateKeySigner::random();
+        let body = rpc_call("eth_chainId", 1);
+        let now = super::now_secs();
+        let scheme = signature_auth();
+
+        // A request to `path` signed by the session key, carrying a delegation signed by `delegator`
+        let delegated = |delegator: &PrivateKeySigner, expires: u64, scope: &str, path: &str| {
+            let account = account.address().to_string();
+            let message = delegation_message(&account, &session.address().to_string(), expires, scope);
+            let delegation = delegator.sign_hash_sync(&keccak256(message.as_bytes())).unwrap();
+            let header = format!("{}:{}:{}:{}", session.address(), expires, scope, delegation);
+            let mut signed = signed_request_as(path, &session, &account, &body, now);
+            signed.headers_mut().insert("x-auth-delegation", HeaderValue::from_str(&header).unwrap());
+            signed
+        };
+
+        // The account is billed for what its session key signs
+        let signed = delegated(&account, now + 600, "/relay", "/relay");
+        let auth = scheme.authenticate(&request("/relay", signed.headers(), body.as_bytes())).unwrap();
+        assert_eq!(auth.address, account.address());
+        let signed = delegated(&account, now + 600, "*", "/eth");
+        assert!(scheme.authenticate(&request("/eth", signed.headers(), body.as_bytes())).is_ok());
+
+        // Without a delegation the session key signs only for itself
+        let mut undelegated = delegated(&account, now + 600, "/relay", "/relay");
+        undelegated.headers_mut().remove("x-auth-delegation");
+        let error = scheme.authenticate(&request("/relay", undelegated.headers(), body.as_bytes())).unwrap_err();
+        assert_eq!(error, AuthError::Invalid("Signature verification failed: address mismatch".to_string()));
+
+        // Expired
+        let signed = delegated(&account, now - 1, "/relay", "/relay");
+        let error = scheme.authenticate(&request("/relay", signed.headers(), body.as_bytes())).unwrap_err();
+        assert_eq!(error, AuthError::Invalid("Delegation expired".to_string()));
+
+        // Beyond its scope: another path, or longer than the gateway accepts
+        let signed = delegated(&account, now + 600, "/relay", "/admin/adjust");
+        let error = scheme.authenticate(&request("/admin/adjust", signed.headers(), body.as_bytes())).unwrap_err();
+        assert_eq!(error, AuthError::Invalid("Session key is not delegated for /admin/adjust".to_string()));
+        let signed = delegated(&account, now + 7200, "/relay", "/relay");
+        let error = scheme.authenticate(&request("/relay", signed.headers(), body.as_bytes())).unwrap_err();
+        assert!(matches!(error, AuthError::Invalid(reason) if reason.contains("runs longer")));
+
+        // A session key can't delegate to itself
+        let signed = delegated(&session, now + 600, "/relay", "/relay");
+        let error = scheme.authenticate(&request("/relay", signed.headers(), body.as_bytes())).unwrap_err();
+        assert_eq!(error, AuthError::Invalid("Delegation was not signed by the account".to_string()));
+    }
+
+    #[test]
+    fn test_tampered_body_reported_apart_from_wrong_signer() {
+        let signer = PrivateKeySigner::random();
+        let body = rpc_call("eth_chainId", 1);
+        let mut signed = signed_request("/relay", &signer, &body);
+        let body_hash = hex::encode(keccak256(body.as_bytes()));
+        signed.headers_mut().insert("x-auth-body-hash", HeaderValue::from_str(&body_hash).unwrap());
+        let scheme = signature_auth();
+
+        // A proxy rewrote the body after it was signed
+        let rewritten = rpc_call("eth_chainId", 2);
+        let error = scheme
+            .authenticate(&request("/relay", signed.headers(), rewritten.as_bytes()))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            AuthError::Invalid("Signature verification failed: request body does not match signature".to_string())
+        );
+
+        // Someone else's key signed the request
+        let impostor = PrivateKeySigner::random();
+        let timestamp = super::now_secs();
+        let address = signer.address().to_string();
+        let mut forged = signed_request_as("/relay", &impostor, &address, &body, timestamp);
+        forged.headers_mut().insert("x-auth-body-hash", HeaderValue::from_str(&body_hash).unwrap());
+        let error = scheme
+            .authenticate(&request("/relay", forged.headers(), body.as_bytes()))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            AuthError::Invalid("Signature verification failed: address mismatch".to_string())
+        );
+
+        // The untouched body still verifies with the hash header present
+        let auth = scheme
+            .authenticate(&request("/relay", signed.headers(), body.as_bytes()))
+            .unwrap();
+        assert_eq!(auth.address, signer.address());
+    }
+
+    #[test]
+    fn test_each_body_hash_algorithm_round_trips() {
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        let body = rpc_call("eth_chainId", 1);
+        let scheme = signature_auth();
+
+        for (name, algorithm) in [("keccak256", BodyHashAlgorithm::Keccak256), ("sha256", BodyHashAlgorithm::Sha256)] {
+            let timestamp = super::now_secs();
+            let message = message_with_body_hash(&address, timestamp, "POST", "/relay", &body_hash(algorithm, body.as_bytes()));
+            let signature = signer.sign_hash_sync(&keccak256(message.as_bytes())).unwrap().to_string();
+
+            let mut headers = HeaderMap::new();
+            headers.insert("x-auth-address", HeaderValue::from_str(&address).unwrap());
+            headers.insert("x-auth-signature", HeaderValue::from_str(&signature).unwrap());
+            headers.insert("x-auth-timestamp", HeaderValue::from(timestamp));
+            headers.insert("x-auth-body-hash-algorithm", HeaderValue::from_static(name));
+
+            let auth = scheme.authenticate(&request("/relay", &headers, body.as_bytes())).unwrap();
+            assert_eq!(auth.address, signer.address(), "{}", name);
+
+            // Verifying with the other algorithm doesn't reconstruct the same message
+            let other = if name == "sha256" { "keccak256" } else { "sha256" };
+            headers.insert("x-auth-body-hash-algorithm", HeaderValue::from_static(other));
+            let error = scheme.authenticate(&request("/relay", &headers, body.as_bytes())).unwrap_err();
+            assert!(matches!(error, AuthError::Invalid(_)), "{}", name);
+        }
+        assert_eq!(body_hash(BodyHashAlgorithm::Sha256, b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+
+        // Only configured algorithms are accepted
+        let keccak_only = SignatureAuth::new(
+            Arc::new(SignatureCache::new()),
+            Arc::new(Mutex::new(ClockMonitor::new(None))),
+            None,
+            vec![BodyHashAlgorithm::Keccak256],
+            vec![BodyCanonicalization::Exact],
+            None,
+        );
+        let mut signed = signed_request("/relay", &signer, &body);
+        signed.headers_mut().insert("x-auth-body-hash-algorithm", HeaderValue::from_static("sha256"));
+        let error = keccak_only.authenticate(&request("/relay", signed.headers(), body.as_bytes())).unwrap_err();
+        assert_eq!(error, AuthError::Invalid("Body hash algorithm Sha256 is not accepted".to_string()));
+    }
+
+    /// Headers for `body` signed over its `canonicalization` form
+    fn canonicalized_headers(signer: &PrivateKeySigner, body: &str, canonicalization: &'static str) -> HeaderMap {
+        let address = signer.address().to_string();
+        let timestamp = super::now_secs();
+        let signed = signed_body(canonicalization.parse().unwrap(), body.as_bytes()).unwrap();
+        let message = message_with_body_hash(&address, timestamp, "POST", "/relay", &body_hash(BodyHashAlgorithm::Keccak256, &signed));
+        let signature = signer.sign_hash_sync(&keccak256(message.as_bytes())).unwrap().to_string();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-auth-address", HeaderValue::from_str(&address).unwrap());
+        headers.insert("x-auth-signature", HeaderValue::from_str(&signature).unwrap());
+        headers.insert("x-auth-timestamp", HeaderValue::from(timestamp));
+        headers.insert("x-auth-body-canonicalization", HeaderValue::from_static(canonicalization));
+        headers
+    }
+
+    #[test]
+    fn test_canonical_json_body_survives_reserialization() {
+        let signer = PrivateKeySigner::random();
+        let body = r#"{"jsonrpc":"2.0","method":"eth_chainId","params":[],"id":1}"#;
+        let reserialized = "{\n  \"id\": 1,\n  \"jsonrpc\": \"2.0\",\n  \"method\": \"eth_chainId\",\n  \"params\": [ ]\n}";
+        let scheme = signature_auth();
+
+        // Same vector as the transport's canonical_json test, so both sides hash the same bytes
+        assert_eq!(
+            signed_body(BodyCanonicalization::CanonicalJson, body.as_bytes()).unwrap().as_ref(),
+            br#"{"id":1,"jsonrpc":"2.0","method":"eth_chainId","params":[]}"#
+        );
+
+        let headers = canonicalized_headers(&signer, body, "canonical_json");
+        let auth = scheme.authenticate(&request("/relay", &headers, reserialized.as_bytes())).unwrap();
+        assert_eq!(auth.address, signer.address());
+
+        // Exact mode holds the signature to the bytes as sent
+        let headers = canonicalized_headers(&signer, body, "exact");
+        let auth = scheme.authenticate(&request("/relay", &headers, body.as_bytes())).unwrap();
+        assert_eq!(auth.address, signer.address());
+        let headers = canonicalized_headers(&signer, body, "exact");
+        let error = scheme.authenticate(&request("/relay", &headers, reserialized.as_bytes())).unwrap_err();
+        assert!(matches!(error, AuthError::Invalid(_)));
+
+        // A canonical signature doesn't cover a different JSON value, nor a body that isn't JSON
+        let headers = canonicalized_headers(&signer, body, "canonical_json");
+        let changed = body.replace("eth_chainId", "eth_blockNumber");
+        let error = scheme.authenticate(&request("/relay", &headers, changed.as_bytes())).unwrap_err();
+        assert!(matches!(error, AuthError::Invalid(_)));
+        let headers = canonicalized_headers(&signer, body, "canonical_json");
+        let error = scheme.authenticate(&request("/relay", &headers, b"not json")).unwrap_err();
+        assert_eq!(
+            error,
+            AuthError::Invalid(
+                "Signature verification failed: request body is not valid JSON, so it can't be canonicalized".to_string()
+            )
+        );
+
+        // Canonical JSON must be enabled
+        let strict = SignatureAuth::new(
+            Arc::new(SignatureCache::new()),
+            Arc::new(Mutex::new(ClockMonitor::new(None))),
+            None,
+            vec![BodyHashAlgorithm::Keccak256],
+            vec![BodyCanonicalization::Exact],
+            None,
+        );
+        let headers = canonicalized_headers(&signer, body, "canonical_json");
+        let error = strict.authenticate(&request("/relay", &headers, body.as_bytes())).unwrap_err();
+        assert_eq!(error, AuthError::Invalid("Body canonicalization CanonicalJson is not accepted".to_string()));
+    }
+
+    #[test]
+    fn test_display_address_is_checksummed() {
+        // EIP-55 test vector
+        let checksummed = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+        assert_eq!(display_address(&checksummed.to_lowercase()), checksummed);
+        assert_eq!(display_address(checksummed), checksummed);
+        assert_eq!(display_address("not-an-address"), "not-an-address");
+    }
+
+    #[test]
+    fn test_api_key_scheme_maps_key_to_address() {
+        let scheme = api_key_auth();
+
+        let headers = api_key_headers(API_KEY);
+        let auth = scheme.authenticate(&request("/relay", &headers, b"")).unwrap();
+        assert_eq!(account_key(&auth.address), API_KEY_ADDRESS.to_lowercase());
+        assert_eq!(auth.signature, None);
+
+        let headers = api_key_headers("wrong-key");
+        let error = scheme.authenticate(&request("/relay", &headers, b"")).unwrap_err();
+        assert!(matches!(error, AuthError::Invalid(_)));
+    }
+
+    #[test]
+    fn test_schemes_are_tried_in_order() {
+        let schemes: Vec<Box<dyn AuthScheme>> = vec![Box::new(signature_auth()), Box::new(api_key_auth())];
+        let signer = PrivateKeySigner::random();
+        let signed = signed_request("/relay", &signer, "");
+        let headers = api_key_headers(API_KEY);
+
+        let auth = authenticate(&schemes, &request("/relay", &headers, b"")).unwrap();
+        assert_eq!(account_key(&auth.address), API_KEY_ADDRESS.to_lowercase());
+
+        let auth = authenticate(&schemes, &request("/relay", signed.headers(), b"")).unwrap();
+        assert_eq!(auth.address, signer.address());
+
+        let headers = HeaderMap::new();
+        let error = authenticate(&schemes, &request("/relay", &headers, b"")).unwrap_err();
+        assert_eq!(error, AuthError::Missing);
+    }
+
+    #[test]
+    fn test_client_cert_scheme_maps_subject_to_address() {
+        let scheme = ClientCertAuth::new(&HashMap::from([(
+            "billing-service".to_string(),
+            API_KEY_ADDRESS.to_lowercase(),
+        )]));
+        let headers = HeaderMap::new();
+        let with_subject = |subject| AuthRequest { client_subject: subject, ..request("/relay", &headers, b"") };
+
+        let auth = scheme.authenticate(&with_subject(Some("billing-service"))).unwrap();
+        assert_eq!(account_key(&auth.address), API_KEY_ADDRESS.to_lowercase());
+
+        assert_eq!(scheme.authenticate(&with_subject(Some("other-service"))).unwrap_err(), AuthError::Missing);
+        assert_eq!(scheme.authenticate(&with_subject(None)).unwrap_err(), AuthError::Missing);
+    }
+}
@@ -0,0 +1,159 @@
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+
+use crate::config::BlockTimeConfig;
+
+/// Fetch the node's latest block timestamp (unix seconds)
+pub async fn fetch_latest_block_timestamp(client: &Client, node_url: &str) -> Option<u64> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "method": "eth_getBlockByNumber",
+        "params": ["latest", false],
+        "id": 1,
+    });
+
+    let response: Value = match client.post(node_url).json(&request).send().await {
+        Ok(response) => response.json().await.ok()?,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to fetch latest block");
+            return None;
+        }
+    };
+
+    let timestamp = response.get("result")?.get("timestamp")?.as_str()?;
+    u64::from_str_radix(timestamp.strip_prefix("0x")?, 16).ok()
+}
+
+fn wall_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Server time anchored to chain time: the latest block timestamp seen, advanced by the
+/// monotonic time since it was fetched. Falls back to the wall clock until a block is seen
+/// or when chain time strays more than `max_skew_secs` from it (e.g. a stalled node).
+/// Never goes backward, so timestamps it has moved past can't come back into the window
+pub struct BlockClock {
+    /// Latest block timestamp and when it was fetched
+    anchor: Mutex<Option<(u64, Instant)>>,
+    max_skew_secs: u64,
+    /// Latest time returned
+    latest: AtomicU64,
+}
+
+impl BlockClock {
+    pub fn new(max_skew_secs: u64) -> Self {
+        Self {
+            anchor: Mutex::new(None),
+            max_skew_secs,
+            latest: AtomicU64::new(0),
+        }
+    }
+
+    /// Current time in unix seconds, held at the latest time returned if the anchor or
+    /// the fallback to the wall clock would step it back
+    pub fn now_secs(&self) -> u64 {
+        let now = self.anchored_secs();
+        self.latest.fetch_max(now, Ordering::Relaxed).max(now)
+    }
+
+    /// Chain time, or the wall clock if there's no usable anchor
+    fn anchored_secs(&self) -> u64 {
+        let wall = wall_secs();
+        let Some((block_timestamp, fetched_at)) = *self.anchor.lock().unwrap() else {
+            return wall;
+        };
+        let chain = block_timestamp + fetched_at.elapsed().as_secs();
+        if chain.abs_diff(wall) > self.max_skew_secs {
+            return wall;
+        }
+        chain
+    }
+
+    /// Anchor to a block timestamp just fetched
+    pub fn observe(&self, block_timestamp: u64) {
+        let wall = wall_secs();
+        if block_timestamp.abs_diff(wall) > self.max_skew_secs {
+            tracing::warn!(
+                block_timestamp,
+                skew_secs = block_timestamp.abs_diff(wall),
+                max_skew_secs = self.max_skew_secs,
+                "Block time too far from the wall clock, using the wall clock"
+            );
+        }
+        *self.anchor.lock().unwrap() = Some((block_timestamp, Instant::now()));
+    }
+
+    /// Fetch the latest block from `node_url` and anchor to it; the previous anchor is kept on failure
+    pub async fn refresh(&self, client: &Client, node_url: &str) {
+        if let Some(block_timestamp) = fetch_latest_block_timestamp(client, node_url).await {
+            self.observe(block_timestamp);
+        }
+    }
+}
+
+/// Spawn the background task refreshing `clock` from `node_url` until `shutdown` is cancelled
+pub fn spawn(
+    clock: Arc<BlockClock>,
+    client: Client,
+    node_url: String,
+    config: BlockTimeConfig,
+    shutdown: CancellationToken,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.refresh_secs));
+
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = interval.tick() => {}
+            }
+            clock.refresh(&client, &node_url).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_time_used_within_max_skew() {
+        let wall = wall_secs();
+        let clock = BlockClock::new(300);
+        assert!(clock.now_secs().abs_diff(wall) <= 1);
+
+        let clock = BlockClock::new(300);
+        clock.observe(wall - 120);
+        assert!(clock.now_secs().abs_diff(wall - 120) <= 1);
+
+        // A node stuck far in the past doesn't drag server time with it
+        clock.observe(wall - 3600);
+        assert!(clock.now_secs().abs_diff(wall) <= 1);
+    }
+
+    #[test]
+    fn test_never_goes_backward() {
+        let wall = wall_secs();
+        let clock = BlockClock::new(300);
+        clock.observe(wall);
+        let latest = clock.now_secs();
+
+        // Re-anchoring to an older block (a lagging node) holds time where it was
+        clock.observe(wall - 200);
+        assert!(clock.now_secs() >= latest);
+
+        // As does falling back to a wall clock behind chain time
+        let clock = BlockClock::new(300);
+        clock.observe(wall + 200);
+        let latest = clock.now_secs();
+        clock.observe(wall + 400);
+        assert!(clock.now_secs() >= latest);
+    }
+}
@@ -0,0 +1,186 @@
+use axum::http::HeaderMap;
+use serde_json::Value;
+use std::collections::HashSet;
+
+use crate::config::BodyLoggingConfig;
+
+/// Headers carrying credentials, which are never logged whatever the configuration
+const SECRET_HEADERS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "x-api-key",
+    "x-auth-delegation",
+    "x-auth-signature",
+    "x-payment",
+];
+
+const REDACTED: &str = "[redacted]";
+
+/// Logs relayed request and response bodies with sensitive parts redacted
+#[derive(Debug, Clone)]
+pub struct BodyLogger {
+    /// Methods whose params (and results) are redacted
+    redacted_methods: HashSet<String>,
+    /// Headers logged as redacted, beyond the credential headers (lowercase)
+    redacted_headers: HashSet<String>,
+    max_logged_bytes: usize,
+}
+
+impl BodyLogger {
+    pub fn new(config: &BodyLoggingConfig) -> Self {
+        Self {
+            redacted_methods: config.redacted_methods.iter().cloned().collect(),
+            redacted_headers: config.redacted_headers.iter().map(|name| name.to_ascii_lowercase()).collect(),
+            max_logged_bytes: config.max_logged_bytes,
+        }
+    }
+
+    /// Log a relay request's headers and body
+    pub fn log_request(&self, headers: &HeaderMap, body: &[u8]) {
+        tracing::info!(
+            headers = %self.redact_headers(headers),
+            body = %self.truncate(self.redact_request(body)),
+            "Relay request body"
+        );
+    }
+
+    /// Log the node's response to `request`
+    pub fn log_response(&self, request: &[u8], response: &[u8]) {
+        tracing::info!(body = %self.truncate(self.redact_response(request, response)), "Relay response body");
+    }
+
+    /// Headers as `name: value` pairs, credentials and configured headers redacted
+    fn redact_headers(&self, headers: &HeaderMap) -> String {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                let name = name.as_str();
+                let value = if SECRET_HEADERS.contains(&name) || self.redacted_headers.contains(name) {
+                    REDACTED
+                } else {
+                    value.to_str().unwrap_or("[binary]")
+                };
+                format!("{}: {}", name, value)
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Request body with the params of redacted methods replaced
+    /// A body that isn't JSON can't be inspected, so it isn't logged at all
+    fn redact_request(&self, body: &[u8]) -> String {
+        let Ok(mut request) = serde_json::from_slice::<Value>(body) else {
+            return format!("[{} bytes, not JSON]", body.len());
+        };
+        for call in calls_mut(&mut request) {
+            if self.is_redacted(call) {
+                if let Some(params) = call.get_mut("params") {
+                    *params = Value::String(REDACTED.to_string());
+                }
+            }
+        }
+        request.to_string()
+    }
+
+    /// Response body with the results of redacted calls in `request` replaced, matched by id
+    fn redact_response(&self, request: &[u8], response: &[u8]) -> String {
+        let Ok(mut reply) = serde_json::from_slice::<Value>(response) else {
+            return String::from_utf8_lossy(response).into_owned();
+        };
+        let mut request = serde_json::from_slice::<Value>(request).unwrap_or(Value::Null);
+        let redacted_ids: Vec<Value> = calls_mut(&mut request)
+            .into_iter()
+            .filter(|call| self.is_redacted(call))
+            .filter_map(|call| call.get("id").cloned())
+            .collect();
+
+        for item in calls_mut(&mut reply) {
+            if item.get("id").is_some_and(|id| redacted_ids.contains(id)) {
+                if let Some(result) = item.get_mut("result") {
+                    *result = Value::String(REDACTED.to_string());
+                }
+            }
+        }
+        reply.to_string()
+    }
+
+    fn is_redacted(&self, call: &Value) -> bool {
+        call.get("method").and_then(Value::as_str).is_some_and(|method| self.redacted_methods.contains(method))
+    }
+
+    /// At most `max_logged_bytes` of `text`, cut at a character boundary
+    fn truncate(&self, mut text: String) -> String {
+        if text.len() > self.max_logged_bytes {
+            let mut end = self.max_logged_bytes;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            let total = text.len();
+            text.truncate(end);
+            text.push_str(&format!("... ({} bytes)", total));
+        }
+        text
+    }
+}
+
+/// The calls of a single request or batch (or replies of a response)
+fn calls_mut(value: &mut Value) -> Vec<&mut Value> {
+    match value {
+        Value::Array(calls) => calls.iter_mut().collect(),
+        other => vec![other],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+
+    fn logger() -> BodyLogger {
+        BodyLogger::new(&BodyLoggingConfig {
+            redacted_methods: vec!["eth_sendRawTransaction".to_string(), "eth_sign".to_string()],
+            redacted_headers: vec!["X-Forwarded-For".to_string()],
+            max_logged_bytes: 4096,
+        })
+    }
+
+    #[test]
+    fn test_sensitive_params_and_headers_redacted() {
+        let logger = logger();
+        let request = json!([
+            {"jsonrpc": "2.0", "method": "eth_sendRawTransaction", "params": ["0x02f8secret"], "id": 7},
+            {"jsonrpc": "2.0", "method": "eth_getBalance", "params": ["0xabc", "latest"], "id": "b"}
+        ])
+        .to_string();
+
+        let logged = logger.redact_request(request.as_bytes());
+        assert!(!logged.contains("0x02f8secret"), "{}", logged);
+        assert!(logged.contains("eth_sendRawTransaction") && logged.contains("\"id\":7"), "{}", logged);
+        assert!(logged.contains("\"params\":[\"0xabc\",\"latest\"]"), "{}", logged);
+
+        let response = json!([
+            {"jsonrpc": "2.0", "id": 7, "result": "0xtxhash"},
+            {"jsonrpc": "2.0", "id": "b", "result": "0x10"}
+        ])
+        .to_string();
+        let logged = logger.redact_response(request.as_bytes(), response.as_bytes());
+        assert!(!logged.contains("0xtxhash") && logged.contains("\"id\":7"), "{}", logged);
+        assert!(logged.contains("0x10"), "{}", logged);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-auth-signature", HeaderValue::from_static("0xsig"));
+        headers.insert("x-forwarded-for", HeaderValue::from_static("10.0.0.1"));
+        headers.insert("x-auth-address", HeaderValue::from_static("0xaddr"));
+        let logged = logger.redact_headers(&headers);
+        assert!(!logged.contains("0xsig") && !logged.contains("10.0.0.1"), "{}", logged);
+        assert!(logged.contains("x-auth-address: 0xaddr"), "{}", logged);
+    }
+
+    #[test]
+    fn test_unparseable_and_long_bodies_kept_out_of_logs() {
+        let logger = BodyLogger { max_logged_bytes: 10, ..logger() };
+        assert_eq!(logger.redact_request(b"raw 0xsecret"), "[12 bytes, not JSON]");
+        assert_eq!(logger.truncate("é".repeat(8)), format!("{}... (16 bytes)", "é".repeat(5)));
+    }
+}
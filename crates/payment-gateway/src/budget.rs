@@ -0,0 +1,109 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::GlobalBudgetConfig;
+
+/// Requests and spend counted in the current window
+struct Window {
+    start: Instant,
+    requests: u64,
+    spent: f64,
+}
+
+/// Gateway-wide cap on paid requests and spend per window, shared by every account
+/// Distinct from balances: an account with funds is still turned away once the gateway's budget is gone
+pub struct GlobalBudget {
+    max_requests: Option<u64>,
+    max_spend: Option<f64>,
+    window: Duration,
+    current: Mutex<Window>,
+}
+
+impl GlobalBudget {
+    pub fn new(config: &GlobalBudgetConfig) -> Self {
+        Self {
+            max_requests: config.max_requests,
+            max_spend: config.max_spend,
+            window: Duration::from_secs(config.window_secs),
+            current: Mutex::new(Window { start: Instant::now(), requests: 0, spent: 0.0 }),
+        }
+    }
+
+    /// Count a request costing `amount` against the current window, returning the window's start
+    /// for a later refund, or how long until the next window if it would exceed the budget
+    pub fn try_spend(&self, amount: f64, now: Instant) -> Result<Instant, Duration> {
+        let mut current = self.current.lock().unwrap();
+        if now.duration_since(current.start) >= self.window {
+            *current = Window { start: now, requests: 0, spent: 0.0 };
+        }
+
+        let over_requests = self.max_requests.is_some_and(|max| current.requests >= max);
+        let over_spend = self.max_spend.is_some_and(|max| current.spent + amount > max);
+        if over_requests || over_spend {
+            return Err(self.window.saturating_sub(now.duration_since(current.start)));
+        }
+        current.requests += 1;
+        current.spent += amount;
+        Ok(current.start)
+    }
+
+    /// Return a request that was counted but not charged (e.g. the account couldn't pay)
+    /// A request counted in a window that has since ended is not taken off the new one
+    pub fn refund(&self, amount: f64, window: Instant) {
+        let mut current = self.current.lock().unwrap();
+        if current.start != window {
+            return;
+        }
+        current.requests = current.requests.saturating_sub(1);
+        current.spent = (current.spent - amount).max(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(extra: &str) -> GlobalBudget {
+        GlobalBudget::new(&toml::from_str(extra).unwrap())
+    }
+
+    #[test]
+    fn test_spend_capped_per_window() {
+        let budget = budget("max_spend = 1.0\nwindow_secs = 60");
+        let now = Instant::now();
+        let window = budget.try_spend(0.5, now).unwrap();
+        assert!(budget.try_spend(0.5, now).is_ok());
+        assert!(budget.try_spend(0.5, now).is_err());
+
+        // Refunded spend can be used again
+        budget.refund(0.5, window);
+        assert!(budget.try_spend(0.5, now).is_ok());
+
+        // A new window starts from nothing
+        let retry_after = budget.try_spend(0.5, now + Duration::from_secs(30)).unwrap_err();
+        assert!(retry_after <= Duration::from_secs(30) && retry_after > Duration::from_secs(29));
+        assert!(budget.try_spend(0.5, now + Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn test_refund_from_previous_window_ignored() {
+        let budget = budget("max_spend = 1.0\nwindow_secs = 60");
+        let now = Instant::now();
+        let previous = budget.try_spend(0.5, now).unwrap();
+
+        // The window rolls over before the refund; the new window's spend is left alone
+        let later = now + Duration::from_secs(60);
+        assert!(budget.try_spend(1.0, later).is_ok());
+        budget.refund(0.5, previous);
+        assert!(budget.try_spend(0.5, later).is_err());
+    }
+
+    #[test]
+    fn test_requests_capped_per_window() {
+        let budget = budget("max_requests = 2\nwindow_secs = 60");
+        let now = Instant::now();
+        assert!(budget.try_spend(0.0, now).is_ok());
+        assert!(budget.try_spend(0.0, now).is_ok());
+        assert!(budget.try_spend(0.0, now).is_err());
+    }
+}
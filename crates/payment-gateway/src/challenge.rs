@@ -0,0 +1,79 @@
+use alloy::primitives::keccak256;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Challenges kept before the oldest is dropped, since 402s are issued to unauthenticated clients
+const MAX_CHALLENGES: usize = 100_000;
+
+/// Single-use nonces handed out with 402 responses, which a deposit must echo back
+/// so a payment is tied to a challenge this gateway issued recently
+pub struct ChallengeStore {
+    /// Maps challenge -> when it was issued
+    entries: HashMap<String, Instant>,
+    /// How long a challenge can be redeemed for
+    ttl: Duration,
+}
+
+impl ChallengeStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            ttl,
+        }
+    }
+
+    /// Issue a fresh random challenge
+    pub fn issue(&mut self, now: Instant) -> String {
+        if self.entries.len() >= MAX_CHALLENGES {
+            self.entries.retain(|_, issued_at| now.duration_since(*issued_at) < self.ttl);
+        }
+        if self.entries.len() >= MAX_CHALLENGES {
+            if let Some(oldest) = self.entries.iter().min_by_key(|(_, issued_at)| **issued_at).map(|(challenge, _)| challenge.clone()) {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        let challenge = unique_challenge();
+        self.entries.insert(challenge.clone(), now);
+        challenge
+    }
+
+    /// Redeem a challenge, which succeeds once and only before it expires
+    pub fn consume(&mut self, challenge: &str, now: Instant) -> bool {
+        self.entries
+            .remove(challenge)
+            .is_some_and(|issued_at| now.duration_since(issued_at) < self.ttl)
+    }
+}
+
+/// Challenge value; only challenges in the store are redeemable, so it needs to be unique, not secret
+fn unique_challenge() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let seed = format!("challenge:{}:{}:{}", nanos, std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+    keccak256(seed.as_bytes()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenges_redeemed_once_before_expiry() {
+        let mut store = ChallengeStore::new(Duration::from_secs(60));
+        let now = Instant::now();
+
+        let challenge = store.issue(now);
+        assert_ne!(store.issue(now), challenge);
+        assert!(store.consume(&challenge, now));
+        assert!(!store.consume(&challenge, now));
+        assert!(!store.consume("0xunknown", now));
+
+        let expiring = store.issue(now);
+        assert!(!store.consume(&expiring, now + Duration::from_secs(60)));
+    }
+}
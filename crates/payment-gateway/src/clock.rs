@@ -0,0 +1,100 @@
+use std::time::{Duration, Instant, SystemTime};
+
+/// Wall clock moves smaller than this relative to the monotonic clock are ordinary drift
+const JUMP_THRESHOLD: Duration = Duration::from_secs(2);
+
+/// Most extra seconds ever accepted after a jump (signature cache retention covers this much)
+pub const MAX_WIDENING_SECS: u64 = 60;
+
+/// Watches the wall clock against the monotonic clock to spot NTP steps
+pub struct ClockMonitor {
+    /// Wall and monotonic readings from the previous observation
+    last: (SystemTime, Instant),
+    /// Extra seconds of timestamp drift accepted, and until when, after a backward jump
+    widened: Option<(u64, Instant)>,
+    /// How long to widen the window after a backward jump (never widened if None)
+    grace: Option<Duration>,
+}
+
+impl ClockMonitor {
+    pub fn new(grace: Option<Duration>) -> Self {
+        Self {
+            last: (SystemTime::now(), Instant::now()),
+            widened: None,
+            grace,
+        }
+    }
+
+    /// How far ahead of server time a client timestamp may be right now, given the normal window
+    /// After a backward jump the server lags its clients, so only the future side is widened
+    pub fn future_window_secs(&mut self, base: u64) -> u64 {
+        let now = Instant::now();
+        self.observe(SystemTime::now(), now);
+
+        match self.widened {
+            Some((extra, until)) if now < until => base + extra,
+            _ => base,
+        }
+    }
+
+    /// Compare a new pair of readings against the previous one
+    fn observe(&mut self, wall: SystemTime, monotonic: Instant) {
+        let (last_wall, last_monotonic) = self.last;
+        let expected_wall = last_wall + monotonic.saturating_duration_since(last_monotonic);
+        self.last = (wall, monotonic);
+
+        if let Ok(backward) = expected_wall.duration_since(wall) {
+            if backward > JUMP_THRESHOLD {
+                let extra = (backward.as_secs() + 1).min(MAX_WIDENING_SECS);
+                tracing::warn!(
+                    backward_secs = backward.as_secs(),
+                    widening = self.grace.is_some(),
+                    "Wall clock jumped backward; client timestamps may look like they're from the future"
+                );
+                if let Some(grace) = self.grace {
+                    self.widened = Some((extra, monotonic + grace));
+                }
+            }
+        } else if let Ok(forward) = wall.duration_since(expected_wall) {
+            if forward > JUMP_THRESHOLD {
+                tracing::warn!(forward_secs = forward.as_secs(), "Wall clock jumped forward");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backward_jump_widens_window_for_grace_period() {
+        let mut monitor = ClockMonitor::new(Some(Duration::from_secs(30)));
+        let (wall, monotonic) = monitor.last;
+
+        // Small drift is ignored
+        monitor.observe(wall + Duration::from_millis(500), monotonic);
+        assert!(monitor.widened.is_none());
+
+        // Wall clock stepped back 10s while 1s really passed
+        let later = monotonic + Duration::from_secs(1);
+        monitor.observe(wall - Duration::from_secs(9), later);
+        let (extra, until) = monitor.widened.unwrap();
+        assert_eq!(extra, 11);
+        assert_eq!(until, later + Duration::from_secs(30));
+
+        // Widening is capped
+        monitor.observe(wall - Duration::from_secs(3600), later);
+        assert_eq!(monitor.widened.unwrap().0, MAX_WIDENING_SECS);
+    }
+
+    #[test]
+    fn test_backward_jump_without_grace_only_logs() {
+        let mut monitor = ClockMonitor::new(None);
+        let (wall, monotonic) = monitor.last;
+
+        monitor.observe(wall - Duration::from_secs(60), monotonic);
+        assert!(monitor.widened.is_none());
+        assert_eq!(monitor.future_window_secs(60), 60);
+    }
+}
@@ -1,8 +1,17 @@
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue, InvalidHeaderValue};
 use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
 use std::path::Path;
 use thiserror::Error;
+use x402_rs::network::Network;
+
+use crate::ip_filter::IpFilter;
+use crate::key_source::KeySourceError;
+use crate::pricing::RoundingMode;
+use crate::requirements::PreparedRequirements;
+use crate::sanitizer::ErrorSanitizer;
 
 #[derive(Error, Debug)]
 pub enum ConfigError {
@@ -22,30 +31,727 @@ pub enum ConfigError {
 /// Settings loaded from config.toml
 #[derive(Debug, Deserialize)]
 struct TomlConfig {
-    node_url: String,
-    price_per_request: f64,
+    node_url: Option<String>,
+    #[serde(default)]
+    fallback_node_urls: Vec<String>,
+    price_per_request: Option<f64>,
+    relay_path: Option<String>,
+    #[serde(default = "default_network")]
+    network: Network,
     port: u16,
     facilitator_url: String,
+    facilitator_auth: Option<FacilitatorAuthConfig>,
     database_path: String,
     database_type: String,
     dynamodb_table_name: Option<String>,
+    dynamodb_audit_table_name: Option<String>,
+    dynamodb_spend_table_name: Option<String>,
+    dynamodb_history_table_name: Option<String>,
+    #[serde(default)]
+    dynamodb_create_tables: bool,
+    dynamodb_table_wait_secs: Option<u64>,
+    #[serde(default)]
+    record_spend: bool,
+    #[serde(default)]
+    free_requests: u32,
+    #[serde(default)]
+    resources: Vec<ResourceConfig>,
+    idempotency_window_secs: Option<u64>,
+    #[serde(default)]
+    replayable_methods: Vec<String>,
+    payment_challenge_ttl_secs: Option<u64>,
+    #[serde(default)]
+    address_pricing: HashMap<String, f64>,
+    #[serde(default)]
+    price_rounding: RoundingMode,
+    #[serde(default)]
+    rocksdb: RocksDbConfig,
+    error_sanitizer: Option<ErrorSanitizerConfig>,
+    body_logging: Option<BodyLoggingConfig>,
+    #[serde(default = "default_signature_cache_max_entries")]
+    signature_cache_max_entries: usize,
+    #[serde(default = "default_max_concurrent_settlements")]
+    max_concurrent_settlements: usize,
+    settlement_timeout_secs: Option<u64>,
+    #[serde(default)]
+    async_settlement: bool,
+    #[serde(default)]
+    on_deduction_failure: DeductionFailurePolicy,
+    min_deposit: Option<f64>,
+    #[serde(default)]
+    payment_assets: Vec<PaymentAssetConfig>,
+    #[serde(default)]
+    payment_addresses: HashMap<Network, String>,
+    #[serde(default)]
+    previous_payment_addresses: Vec<PreviousPaymentAddress>,
+    #[serde(default = "default_shutdown_drain_timeout_secs")]
+    shutdown_drain_timeout_secs: u64,
+    max_concurrent_relays: Option<usize>,
+    global_budget: Option<GlobalBudgetConfig>,
+    #[serde(default)]
+    jsonrpc_error_status: HashMap<String, u16>,
+    #[serde(default)]
+    normalize_jsonrpc_status: bool,
+    #[serde(default)]
+    transient_jsonrpc_errors: TransientJsonRpcErrors,
+    #[serde(default = "default_max_batch_size")]
+    max_batch_size: usize,
+    max_node_response_bytes: Option<usize>,
+    #[serde(default)]
+    partial_batch: bool,
+    #[serde(default)]
+    reject_unfunded_before_auth: bool,
+    #[serde(default)]
+    require_json_content_type: bool,
+    #[serde(default)]
+    precheck_raw_transactions: bool,
+    sweeper: Option<SweeperConfig>,
+    reconciliation: Option<ReconciliationConfig>,
+    request_history: Option<RequestHistoryConfig>,
+    #[serde(default)]
+    rewrite_jsonrpc_ids: bool,
+    #[serde(default)]
+    normalize_jsonrpc_ids: bool,
+    finalized_cache: Option<FinalizedCacheConfig>,
+    #[serde(default)]
+    require_tls: bool,
+    slow_request_threshold_ms: Option<u64>,
+    #[serde(default)]
+    upstream_time_header: bool,
+    #[serde(default)]
+    node_http2: bool,
+    #[serde(default = "default_node_pool_max_idle_per_host")]
+    node_pool_max_idle_per_host: usize,
+    #[serde(default = "default_node_connect_timeout_ms")]
+    node_connect_timeout_ms: u64,
+    #[serde(default = "default_node_timeout_ms")]
+    node_timeout_ms: u64,
+    #[serde(default)]
+    debug_endpoints: bool,
+    #[serde(default)]
+    maintenance: bool,
+    #[serde(default)]
+    monotonic_timestamps: bool,
+    #[serde(default)]
+    check_payment_address_funding: bool,
+    #[serde(default = "default_deposits_enabled")]
+    deposits_enabled: bool,
+    #[serde(default)]
+    accept_permit: bool,
+    #[serde(default)]
+    require_deposit_network_match: bool,
+    clock_jump_grace_secs: Option<u64>,
+    block_time: Option<BlockTimeConfig>,
+    balance_alert: Option<BalanceAlertConfig>,
+    upstream_payment: Option<UpstreamPaymentConfig>,
+    bandwidth_billing: Option<BandwidthBillingConfig>,
+    #[serde(default = "default_auth_schemes")]
+    auth_schemes: Vec<AuthSchemeKind>,
+    #[serde(default = "default_body_hash_algorithms")]
+    body_hash_algorithms: Vec<BodyHashAlgorithm>,
+    #[serde(default = "default_body_canonicalizations")]
+    body_canonicalizations: Vec<BodyCanonicalization>,
+    max_delegation_secs: Option<u64>,
+    #[serde(default)]
+    api_keys: HashMap<String, String>,
+    mtls: Option<MtlsConfig>,
+    ip_filter: Option<IpFilterConfig>,
 }
 
-/// Complete application configuration
-#[derive(Debug, Clone)]
-pub struct Config {
-    /// URL of the Ethereum node to relay requests to
+fn default_body_hash_algorithms() -> Vec<BodyHashAlgorithm> {
+    vec![BodyHashAlgorithm::Keccak256]
+}
+
+fn default_body_canonicalizations() -> Vec<BodyCanonicalization> {
+    vec![BodyCanonicalization::Exact]
+}
+
+fn default_auth_schemes() -> Vec<AuthSchemeKind> {
+    vec![AuthSchemeKind::Signature]
+}
+
+fn default_shutdown_drain_timeout_secs() -> u64 {
+    30
+}
+
+fn default_node_pool_max_idle_per_host() -> usize {
+    10
+}
+
+fn default_node_connect_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_node_timeout_ms() -> u64 {
+    30_000
+}
+
+fn default_max_batch_size() -> usize {
+    100
+}
+
+fn default_deposits_enabled() -> bool {
+    true
+}
+
+fn default_max_concurrent_settlements() -> usize {
+    16
+}
+
+fn default_signature_cache_max_entries() -> usize {
+    crate::signature_cache::DEFAULT_MAX_ENTRIES
+}
+
+/// Paths served by the gateway itself that resources can't be mounted on
+const RESERVED_PATHS: &[&str] = &["/health", "/quote", "/balance", "/metrics", "/time", "/admin", "/.well-known", "/debug", "/auth-state", "/status", "/history"];
+
+/// 0x-prefixed 20-byte hex address
+fn is_address(address: &str) -> bool {
+    address.len() == 42
+        && address.starts_with("0x")
+        && address[2..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn default_network() -> Network {
+    Network::BaseSepolia
+}
+
+/// A relay target mounted at its own path with its own node and price
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResourceConfig {
+    /// Name of the resource, used in logs and payment descriptions
+    pub name: String,
+
+    /// Route path the resource is served on (e.g. "/eth")
+    pub path: String,
+
+    /// URL of the node to relay requests to
     pub node_url: String,
 
+    /// Nodes tried in order when the previous one fails or answers with a transient JSON-RPC error
+    #[serde(default)]
+    pub fallback_node_urls: Vec<String>,
+
     /// Price per RPC request in USDC
     pub price_per_request: f64,
 
+    /// Network payments for this resource are accepted on
+    #[serde(default = "default_network")]
+    pub network: Network,
+}
+
+/// A token accepted for deposits on one network, with its own top-up bounds
+/// Tokens are priced by the pricing oracle, so they must be dollar stablecoins
+#[derive(Debug, Clone, Deserialize)]
+pub struct PaymentAssetConfig {
+    /// Network the token is accepted on
+    pub network: Network,
+
+    /// Token contract address on that network
+    pub address: String,
+
+    /// EIP-712 domain name of the token contract (e.g. "USDC" on Base Sepolia, "USD Coin" on Base)
+    pub name: String,
+
+    /// EIP-712 domain version of the token contract
+    #[serde(default = "default_payment_asset_version")]
+    pub version: String,
+
+    /// Decimals of the token's smallest unit
+    pub decimals: u32,
+
+    /// Smallest top-up requested in this token, in whole tokens (unbounded if unset)
+    pub min_amount: Option<f64>,
+
+    /// Largest top-up requested in this token, in whole tokens (unbounded if unset)
+    pub max_amount: Option<f64>,
+
+    /// How long a payment authorization for this token may take to settle
+    #[serde(default = "default_payment_asset_max_timeout_seconds")]
+    pub max_timeout_seconds: u64,
+}
+
+fn default_payment_asset_version() -> String {
+    "2".to_string()
+}
+
+fn default_payment_asset_max_timeout_seconds() -> u64 {
+    300
+}
+
+/// Most decimals a payment asset may have, so a whole token still fits in a u128 of smallest units
+const MAX_PAYMENT_ASSET_DECIMALS: u32 = 36;
+
+/// JSON-RPC errors worth retrying on a resource's next node (e.g. a syncing or rate-limited node)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TransientJsonRpcErrors {
+    /// Error codes treated as transient
+    pub codes: Vec<i64>,
+
+    /// Case-insensitive substrings of error messages treated as transient
+    pub messages: Vec<String>,
+}
+
+/// RocksDB durability vs throughput settings
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RocksDbConfig {
+    /// fsync instead of fdatasync when flushing files
+    pub use_fsync: bool,
+
+    /// Sync the WAL on every balance write (highest durability, lowest throughput)
+    pub sync_writes: bool,
+
+    /// Skip the WAL entirely (writes since the last flush are lost on crash)
+    pub disable_wal: bool,
+
+    /// Memtable size in MiB before flushing to disk
+    pub write_buffer_size_mb: usize,
+
+    /// Buffer balance deductions for up to this long and write them as one batch (disabled if unset)
+    /// A crash loses at most this window of deductions; credits are always written immediately
+    pub coalesce_window_ms: Option<u64>,
+
+    /// Most accounts deposits may open, bounding storage against floods of tiny deposits
+    /// (unlimited if unset); existing accounts keep working at the cap
+    pub max_accounts: Option<u64>,
+}
+
+impl Default for RocksDbConfig {
+    fn default() -> Self {
+        Self {
+            use_fsync: false,
+            sync_writes: false,
+            disable_wal: false,
+            write_buffer_size_mb: 64,
+            coalesce_window_ms: None,
+            max_accounts: None,
+        }
+    }
+}
+
+/// Rewriting of JSON-RPC error messages returned by the node
+#[derive(Debug, Clone, Deserialize)]
+pub struct ErrorSanitizerConfig {
+    /// Regular expressions matching text to hide (provider names, internal IPs, ...)
+    pub patterns: Vec<String>,
+
+    /// Text substituted for each match
+    #[serde(default = "default_sanitizer_replacement")]
+    pub replacement: String,
+}
+
+fn default_sanitizer_replacement() -> String {
+    "[redacted]".to_string()
+}
+
+/// Logging of relayed request and response bodies, with sensitive parts redacted
+/// Credential headers (X-Auth-Signature, X-Payment, ...) are always redacted
+#[derive(Debug, Clone, Deserialize)]
+pub struct BodyLoggingConfig {
+    /// Methods whose params, and the results replying to them, are redacted
+    #[serde(default = "default_redacted_methods")]
+    pub redacted_methods: Vec<String>,
+
+    /// Further headers whose values are redacted
+    #[serde(default)]
+    pub redacted_headers: Vec<String>,
+
+    /// Longest body logged; longer ones are truncated
+    #[serde(default = "default_max_logged_bytes")]
+    pub max_logged_bytes: usize,
+}
+
+fn default_redacted_methods() -> Vec<String> {
+    ["eth_sendRawTransaction", "eth_sendTransaction", "eth_sign", "eth_signTransaction", "eth_signTypedData_v4", "personal_sign"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+fn default_max_logged_bytes() -> usize {
+    4096
+}
+
+/// Background pruning of empty, inactive accounts
+#[derive(Debug, Clone, Deserialize)]
+pub struct SweeperConfig {
+    /// How long an empty account must be inactive before it is deleted
+    pub retention_secs: u64,
+
+    /// How often to scan for stale accounts
+    #[serde(default = "default_sweeper_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_sweeper_interval_secs() -> u64 {
+    3600
+}
+
+/// Background check of every balance against its ledger of deposits, adjustments and spend
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReconciliationConfig {
+    /// How often to reconcile
+    #[serde(default = "default_reconciliation_interval_secs")]
+    pub interval_secs: u64,
+
+    /// Difference in USDC tolerated before an account is reported as drifted
+    #[serde(default = "default_reconciliation_tolerance")]
+    pub tolerance: f64,
+}
+
+fn default_reconciliation_interval_secs() -> u64 {
+    3600
+}
+
+fn default_reconciliation_tolerance() -> f64 {
+    0.000001
+}
+
+/// Per-account log of relayed requests, served by `GET /history`
+#[derive(Debug, Clone, Deserialize)]
+pub struct RequestHistoryConfig {
+    /// How long entries are kept
+    #[serde(default = "default_request_history_retention_secs")]
+    pub retention_secs: u64,
+
+    /// Most entries returned per page
+    #[serde(default = "default_request_history_max_page_size")]
+    pub max_page_size: usize,
+}
+
+fn default_request_history_retention_secs() -> u64 {
+    30 * 24 * 3600
+}
+
+fn default_request_history_max_page_size() -> usize {
+    100
+}
+
+/// Payment address rotated out, still accepted for deposits during a grace period so
+/// payments made against challenges issued before the rotation go through
+#[derive(Debug, Clone, Deserialize)]
+pub struct PreviousPaymentAddress {
+    pub address: String,
+
+    /// Unix seconds from which payments to the address are refused
+    pub accepted_until: u64,
+
+    /// Network the address received payments on (every network if unset)
+    pub network: Option<Network>,
+}
+
+/// Gateway-wide cap on paid requests and spend per window (e.g. during a subsidized promotion)
+#[derive(Debug, Clone, Deserialize)]
+pub struct GlobalBudgetConfig {
+    /// Most paid requests relayed across all accounts per window
+    pub max_requests: Option<u64>,
+
+    /// Most USDC charged across all accounts per window
+    pub max_spend: Option<f64>,
+
+    /// Length of a budget window
+    #[serde(default = "default_global_budget_window_secs")]
+    pub window_secs: u64,
+}
+
+fn default_global_budget_window_secs() -> u64 {
+    3600
+}
+
+/// Server time taken from a node's latest block timestamp, for clients synced to chain time
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockTimeConfig {
+    /// Resource whose node is followed (the first resource if unset)
+    pub resource: Option<String>,
+
+    /// How often the latest block is fetched
+    #[serde(default = "default_block_time_refresh_secs")]
+    pub refresh_secs: u64,
+
+    /// Most seconds chain time may differ from the wall clock before the wall clock is used instead
+    #[serde(default = "default_block_time_max_skew_secs")]
+    pub max_skew_secs: u64,
+}
+
+fn default_block_time_refresh_secs() -> u64 {
+    12
+}
+
+fn default_block_time_max_skew_secs() -> u64 {
+    300
+}
+
+/// Caching of reads at finalized blocks
+#[derive(Debug, Clone, Deserialize)]
+pub struct FinalizedCacheConfig {
+    /// Maximum cached responses before evicting the oldest
+    #[serde(default = "default_finalized_cache_max_entries")]
+    pub max_entries: usize,
+
+    /// How long the node's finalized block number is reused before querying it again
+    #[serde(default = "default_finalized_refresh_secs")]
+    pub refresh_secs: u64,
+
+    /// Blocks a read must also be below the node's latest block to be cached, guarding against
+    /// reorgs deeper than finality (only finality is required if unset)
+    pub reorg_safety_depth: Option<u64>,
+
+    /// How long a cached response is served before the node is asked again (kept until evicted if unset)
+    pub ttl_secs: Option<u64>,
+}
+
+/// Ways a client can authenticate requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthSchemeKind {
+    /// ECDSA signature over each request
+    Signature,
+    /// Static X-Api-Key mapped to an address in `api_keys`
+    ApiKey,
+    /// Verified TLS client certificate mapped to an address in `mtls.accounts`
+    ClientCert,
+}
+
+/// Handling of a request whose deposit was credited but whose charge couldn't be written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeductionFailurePolicy {
+    /// Answer 500 without relaying; the deposit stays credited for a retry to spend
+    #[default]
+    Fail,
+    /// Relay the request unpaid
+    Proceed,
+}
+
+/// Hash of the request body included in the signed message
+/// Clients pick one with X-Auth-Body-Hash-Algorithm (keccak256 if absent)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BodyHashAlgorithm {
+    Keccak256,
+    /// For clients outside EVM tooling
+    Sha256,
+}
+
+impl std::str::FromStr for BodyHashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "keccak256" => Ok(Self::Keccak256),
+            "sha256" => Ok(Self::Sha256),
+            _ => Err(format!("unknown body hash algorithm '{}'", s)),
+        }
+    }
+}
+
+/// Form of the request body that is hashed into the signed message
+/// Clients pick one with X-Auth-Body-Canonicalization (exact if absent)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BodyCanonicalization {
+    /// The body bytes as received, so any change to them (even whitespace) fails verification
+    Exact,
+    /// The body's JSON with object keys sorted and no whitespace, so an intermediary
+    /// re-serializing the JSON doesn't break the signature
+    CanonicalJson,
+}
+
+impl std::str::FromStr for BodyCanonicalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "exact" => Ok(Self::Exact),
+            "canonical_json" => Ok(Self::CanonicalJson),
+            _ => Err(format!("unknown body canonicalization '{}'", s)),
+        }
+    }
+}
+
+/// How the gateway authenticates to a facilitator that requires it
+/// The credential itself comes from FACILITATOR_AUTH_TOKEN
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FacilitatorAuthConfig {
+    /// `Authorization: Bearer <token>`
+    Bearer,
+    /// The token sent verbatim in `header`
+    ApiKey {
+        #[serde(default = "default_facilitator_api_key_header")]
+        header: String,
+    },
+}
+
+fn default_facilitator_api_key_header() -> String {
+    "X-API-Key".to_string()
+}
+
+/// A credential that is never printed, even in Debug output
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+/// Serving over TLS with optional client certificate authentication
+#[derive(Debug, Clone, Deserialize)]
+pub struct MtlsConfig {
+    /// PEM certificate chain presented by the gateway
+    pub cert_path: String,
+
+    /// PEM (PKCS#8, PKCS#1 or SEC1) private key for `cert_path`
+    pub key_path: String,
+
+    /// PEM CA certificates client certificates must chain to
+    pub client_ca_path: String,
+
+    /// Client certificate subject common name to the address it bills
+    #[serde(default)]
+    pub accounts: HashMap<String, String>,
+}
+
+/// Address blocking and rate limiting applied before authentication
+#[derive(Debug, Clone, Deserialize)]
+pub struct IpFilterConfig {
+    /// Addresses or CIDR ranges refused with 403
+    #[serde(default)]
+    pub blocklist: Vec<String>,
+
+    /// Requests allowed per address in each window; further requests get 429 (unlimited if unset)
+    pub requests_per_window: Option<u32>,
+
+    /// Length of the rate limit window
+    #[serde(default = "default_ip_filter_window_secs")]
+    pub window_secs: u64,
+
+    /// Header carrying the client address when the peer is a trusted proxy (e.g. X-Forwarded-For)
+    pub client_ip_header: Option<String>,
+
+    /// Proxy addresses or CIDR ranges whose client_ip_header is believed
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+}
+
+fn default_ip_filter_window_secs() -> u64 {
+    1
+}
+
+/// Webhook notified when an account's balance drops below a threshold
+#[derive(Debug, Clone, Deserialize)]
+pub struct BalanceAlertConfig {
+    /// URL the low_balance event is POSTed to
+    pub webhook_url: String,
+
+    /// Balance in USDC below which an account is considered low
+    pub threshold: f64,
+
+    /// Delivery attempts per event before it is dropped
+    #[serde(default = "default_balance_alert_max_attempts")]
+    pub max_attempts: u32,
+
+    /// Least time between two alerts for the same account (every crossing is alerted if 0)
+    #[serde(default)]
+    pub cooldown_secs: u64,
+
+    /// Collect alerts for this long and deliver them in one webhook call (each delivered on its own if unset)
+    pub batch_interval_ms: Option<u64>,
+}
+
+/// Paying nodes that themselves charge via x402
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpstreamPaymentConfig {
+    /// Most USDC paid to a node for a single request
+    pub max_amount: f64,
+
+    /// Where the signing key is read from (UPSTREAM_PRIVATE_KEY by default)
+    #[serde(default = "default_upstream_key_source")]
+    pub key_source: KeySource,
+}
+
+fn default_upstream_key_source() -> KeySource {
+    KeySource::Env { var: "UPSTREAM_PRIVATE_KEY".to_string() }
+}
+
+/// Where a private key is read from
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KeySource {
+    /// Environment variable holding the hex key
+    Env { var: String },
+    /// File holding the hex key, which must not be accessible to other users
+    File { path: String },
+    /// Command printing the hex key, such as a secret manager CLI
+    Command { command: String },
+}
+
+fn default_balance_alert_max_attempts() -> u32 {
+    3
+}
+
+/// Charging for response size on top of the per-request price
+#[derive(Debug, Clone, Deserialize)]
+pub struct BandwidthBillingConfig {
+    /// Price in USDC per response byte
+    pub per_byte_price: f64,
+
+    /// Largest response size billed; this much is reserved up front and the unused part refunded
+    pub max_billed_bytes: u64,
+}
+
+impl BandwidthBillingConfig {
+    /// Amount held on top of the request price before relaying
+    pub fn reserve(&self) -> f64 {
+        self.charge(self.max_billed_bytes as usize)
+    }
+
+    /// Bandwidth charge for a response of `response_bytes`
+    pub fn charge(&self, response_bytes: usize) -> f64 {
+        self.per_byte_price * (response_bytes as u64).min(self.max_billed_bytes) as f64
+    }
+}
+
+fn default_finalized_cache_max_entries() -> usize {
+    crate::finalized_cache::DEFAULT_MAX_ENTRIES
+}
+
+fn default_finalized_refresh_secs() -> u64 {
+    12
+}
+
+/// Complete application configuration
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Relay targets served by this gateway. Balances are shared across all of them.
+    pub resources: Vec<ResourceConfig>,
+
     /// Port to bind the server to
     pub port: u16,
 
     /// x402 facilitator URL
     pub facilitator_url: String,
 
+    /// Authentication sent to the facilitator (none if unset)
+    pub facilitator_auth: Option<FacilitatorAuthConfig>,
+
+    /// Facilitator credential, from FACILITATOR_AUTH_TOKEN (required with facilitator_auth)
+    pub facilitator_token: Option<Secret>,
+
     /// EVM address to receive payments
     pub payment_address: String,
 
@@ -57,6 +763,221 @@ pub struct Config {
 
     /// DynamoDB table name (required if database_type is "dynamodb")
     pub dynamodb_table_name: Option<String>,
+
+    /// DynamoDB audit ledger table name (defaults to "<dynamodb_table_name>-audit")
+    pub dynamodb_audit_table_name: Option<String>,
+
+    /// DynamoDB spend events table name (defaults to "<dynamodb_table_name>-spend")
+    pub dynamodb_spend_table_name: Option<String>,
+
+    /// DynamoDB request history table name (defaults to "<dynamodb_table_name>-history")
+    pub dynamodb_history_table_name: Option<String>,
+
+    /// Create missing DynamoDB tables at startup
+    pub dynamodb_create_tables: bool,
+
+    /// Longest startup waits for DynamoDB tables to become active (not waited for if unset,
+    /// unless tables are created)
+    pub dynamodb_table_wait_secs: Option<u64>,
+
+    /// Record every charge so spend over a period can be reported per account
+    pub record_spend: bool,
+
+    /// Requests a brand-new account may make before it needs a balance (0 disables)
+    pub free_requests: u32,
+
+    /// RocksDB durability settings (used if database_type is "rocksdb")
+    pub rocksdb: RocksDbConfig,
+
+    /// Optional rewriting of node error messages (disabled if unset)
+    pub error_sanitizer: Option<ErrorSanitizerConfig>,
+
+    /// Logging of relayed bodies with redaction (disabled if unset)
+    pub body_logging: Option<BodyLoggingConfig>,
+
+    /// Maximum signatures held for replay protection before evicting the oldest
+    pub signature_cache_max_entries: usize,
+
+    /// Maximum concurrent settlement calls to the facilitator (further deposits queue)
+    pub max_concurrent_settlements: usize,
+
+    /// Longest a deposit waits for settlement before failing (unbounded if unset)
+    pub settlement_timeout_secs: Option<u64>,
+
+    /// Credit deposits once verified and settle in the background, debiting back on failure
+    pub async_settlement: bool,
+
+    /// What happens to a deposit's request when the deposit is credited but charging for it fails
+    pub on_deduction_failure: DeductionFailurePolicy,
+
+    /// Smallest deposit accepted, in USDC; smaller ones are refused before settling
+    pub min_deposit: Option<f64>,
+
+    /// Tokens deposits are requested in, per network (USDC on every network if empty)
+    pub payment_assets: Vec<PaymentAssetConfig>,
+
+    /// Receiving address per network, overriding `payment_address` there
+    pub payment_addresses: HashMap<Network, String>,
+
+    /// Rotated-out receiving addresses deposits may still pay until their grace period ends
+    pub previous_payment_addresses: Vec<PreviousPaymentAddress>,
+
+    /// Longest shutdown waits for background settlements and webhooks to finish
+    pub shutdown_drain_timeout_secs: u64,
+
+    /// Most relay requests in flight at once; further requests get 503 (unlimited if unset)
+    pub max_concurrent_relays: Option<usize>,
+
+    /// Gateway-wide request and spend cap per window; paid requests beyond it get 503 (None when unlimited)
+    pub global_budget: Option<GlobalBudgetConfig>,
+
+    /// HTTP status returned for node JSON-RPC errors by code (unmapped codes stay 200)
+    pub jsonrpc_error_status: HashMap<i64, u16>,
+
+    /// Answer node JSON-RPC responses with 200 whatever the node's HTTP status, and other
+    /// non-2xx node responses with 502
+    pub normalize_jsonrpc_status: bool,
+
+    /// JSON-RPC errors retried on a resource's fallback nodes instead of being returned
+    pub transient_jsonrpc_errors: TransientJsonRpcErrors,
+
+    /// Most calls accepted in one JSON-RPC batch request
+    pub max_batch_size: usize,
+
+    /// Largest node response relayed; batch responses over it have their largest replies
+    /// replaced with "response too large" errors
+    pub max_node_response_bytes: Option<usize>,
+
+    /// Bill batches per call, relaying the leading calls the balance covers and failing the rest
+    pub partial_batch: bool,
+
+    /// Answer signed requests from accounts that can't afford one request with 402
+    /// before verifying their signature
+    pub reject_unfunded_before_auth: bool,
+
+    /// Answer relay requests without `Content-Type: application/json` with 415, unbilled
+    pub require_json_content_type: bool,
+
+    /// Ask the node whether eth_sendRawTransaction calls would obviously fail (stale nonce, fee
+    /// below the base fee, failed gas estimate) and answer 400 without billing if so
+    pub precheck_raw_transactions: bool,
+
+    /// Pruning of stale zero-balance accounts (disabled if unset)
+    pub sweeper: Option<SweeperConfig>,
+
+    /// Periodic check of balances against their ledger (disabled if unset); deposits are
+    /// recorded in the audit ledger while enabled
+    pub reconciliation: Option<ReconciliationConfig>,
+
+    /// Per-account request history (not recorded if unset)
+    pub request_history: Option<RequestHistoryConfig>,
+
+    /// Replace client JSON-RPC ids with gateway-unique ids upstream, restoring them on responses
+    pub rewrite_jsonrpc_ids: bool,
+
+    /// Send integral numeric ids (1.0, 1e2) to the node as integers
+    pub normalize_jsonrpc_ids: bool,
+
+    /// Caching of responses for reads at finalized blocks (disabled if unset)
+    pub finalized_cache: Option<FinalizedCacheConfig>,
+
+    /// How long responses are replayed for a repeated X-Idempotency-Key (disabled if unset)
+    pub idempotency_window_secs: Option<u64>,
+
+    /// Idempotent methods whose replayed signed requests get the original response back
+    /// instead of a replay error (replays always refused if empty)
+    pub replayable_methods: Vec<String>,
+
+    /// How long a challenge issued with a 402 can be echoed back in X-Payment-Challenge;
+    /// deposits without a live, unused challenge are refused (not required if unset)
+    pub payment_challenge_ttl_secs: Option<u64>,
+
+    /// Negotiated per-request prices keyed by lowercase address, overriding resource prices
+    pub address_pricing: HashMap<String, f64>,
+
+    /// How prices between smallest asset units are rounded to whole units
+    pub price_rounding: RoundingMode,
+
+    /// Accept on-chain x402 deposits (disable when balances are credited externally)
+    pub deposits_enabled: bool,
+
+    /// Also accept EIP-2612 permit deposits alongside ERC-3009 authorizations
+    pub accept_permit: bool,
+
+    /// Refuse deposits paid on a different network than the resource they're made on relays to
+    pub require_deposit_network_match: bool,
+
+    /// After the server clock steps backward, accept timestamps further ahead for this long (disabled if unset)
+    pub clock_jump_grace_secs: Option<u64>,
+
+    /// Judge request timestamps against chain time from a node's latest block (wall clock if unset)
+    pub block_time: Option<BlockTimeConfig>,
+
+    /// Relay requests slower than this are logged as warnings (disabled if unset)
+    pub slow_request_threshold_ms: Option<u64>,
+
+    /// Report the node call duration to clients in X-Upstream-Time-Ms
+    pub upstream_time_header: bool,
+
+    /// Offer HTTP/2 to https nodes during the TLS handshake, multiplexing requests over fewer connections
+    pub node_http2: bool,
+
+    /// Idle connections kept open to each node for reuse
+    pub node_pool_max_idle_per_host: usize,
+
+    /// Longest wait for a connection to a node to open
+    pub node_connect_timeout_ms: u64,
+
+    /// Longest a node request may take, connecting and reading the response included
+    pub node_timeout_ms: u64,
+
+    /// Serve unauthenticated client debugging endpoints such as POST /debug/verify
+    pub debug_endpoints: bool,
+
+    /// Start in maintenance mode, failing readiness until POST /admin/maintenance lifts it
+    pub maintenance: bool,
+
+    /// Reject signed requests not timestamped after the address's last one (tracked in the database)
+    pub monotonic_timestamps: bool,
+
+    /// Warn at startup if the payment address holds no ETH on a resource's network
+    pub check_payment_address_funding: bool,
+
+    /// Low balance webhook notifications (disabled if unset)
+    pub balance_alert: Option<BalanceAlertConfig>,
+
+    /// Paying nodes that answer 402 (node 402s are passed through if unset)
+    pub upstream_payment: Option<UpstreamPaymentConfig>,
+
+    /// Key paying upstream nodes, from the upstream_payment key source (required with upstream_payment)
+    pub upstream_private_key: Option<String>,
+
+    /// Per-byte charging for relayed responses (flat per-request pricing if unset)
+    pub bandwidth_billing: Option<BandwidthBillingConfig>,
+
+    /// Enabled authentication schemes, tried in order
+    pub auth_schemes: Vec<AuthSchemeKind>,
+
+    /// Body hash algorithms signed requests may use
+    pub body_hash_algorithms: Vec<BodyHashAlgorithm>,
+
+    /// Body forms signed requests may hash (raw bytes only unless canonical JSON is enabled)
+    pub body_canonicalizations: Vec<BodyCanonicalization>,
+
+    /// Longest a session key delegation may run for; delegations are refused if unset
+    pub max_delegation_secs: Option<u64>,
+
+    /// API key to the lowercase address it bills (used by the api_key scheme)
+    pub api_keys: HashMap<String, String>,
+
+    /// Serve over TLS, verifying client certificates (plain HTTP if unset)
+    pub mtls: Option<MtlsConfig>,
+
+    /// Address blocklist and rate limit checked before authentication (disabled if unset)
+    pub ip_filter: Option<IpFilterConfig>,
+
+    /// Bearer token for admin endpoints (admin endpoints are disabled if unset)
+    pub admin_token: Option<String>,
 }
 
 impl Config {
@@ -69,6 +990,53 @@ impl Config {
         let payment_address = env::var("PAYMENT_ADDRESS")
             .map_err(|_| ConfigError::MissingEnvVar("PAYMENT_ADDRESS".to_string()))?;
 
+        // Load config.toml (settings)
+        let config_path = env::var("CONFIG_PATH").unwrap_or_else(|_| "config.toml".to_string());
+        let toml_config = Self::load_toml(&config_path)?;
+
+        let mut config = Self::from_toml(toml_config, payment_address)?;
+
+        // Load admin token from environment (optional)
+        if let Ok(admin_token) = env::var("ADMIN_TOKEN") {
+            if admin_token.len() < 32 {
+                return Err(ConfigError::Invalid(
+                    "ADMIN_TOKEN must be at least 32 characters".to_string(),
+                ));
+            }
+            config.admin_token = Some(admin_token);
+        }
+
+        // Load the facilitator credential from environment (required with facilitator_auth)
+        if config.facilitator_auth.is_some() {
+            let token = env::var("FACILITATOR_AUTH_TOKEN")
+                .map_err(|_| ConfigError::MissingEnvVar("FACILITATOR_AUTH_TOKEN".to_string()))?;
+            config.facilitator_token = Some(Secret::new(token));
+            if config.facilitator_headers().is_err() {
+                return Err(ConfigError::Invalid(
+                    "FACILITATOR_AUTH_TOKEN is not a valid header value".to_string(),
+                ));
+            }
+        }
+
+        // Load the key paying upstream nodes from its source (required with upstream_payment)
+        if let Some(upstream) = &config.upstream_payment {
+            let key = upstream.key_source.read().map_err(|e| match e {
+                KeySourceError::MissingEnvVar(var) => ConfigError::MissingEnvVar(var),
+                e => ConfigError::Invalid(format!("Failed to load the upstream payment key: {}", e)),
+            })?;
+            if key.parse::<alloy::signers::local::PrivateKeySigner>().is_err() {
+                return Err(ConfigError::Invalid(
+                    "The upstream payment key must be a hex-encoded secp256k1 private key".to_string(),
+                ));
+            }
+            config.upstream_private_key = Some(key);
+        }
+
+        Ok(config)
+    }
+
+    /// Validate parsed settings and assemble the final configuration
+    fn from_toml(toml_config: TomlConfig, payment_address: String) -> Result<Self, ConfigError> {
         // Validate payment address format (basic check for 0x prefix and length)
         if !payment_address.starts_with("0x") || payment_address.len() != 42 {
             return Err(ConfigError::Invalid(
@@ -76,21 +1044,64 @@ impl Config {
             ));
         }
 
-        // Load config.toml (settings)
-        let config_path = env::var("CONFIG_PATH").unwrap_or_else(|_| "config.toml".to_string());
-        let toml_config = Self::load_toml(&config_path)?;
-
-        // Validate node URL
-        if toml_config.node_url.is_empty() {
-            return Err(ConfigError::Invalid("node_url cannot be empty".to_string()));
+        // Validate payment address isn't the zero address, where payments would be unrecoverable
+        if payment_address[2..].bytes().all(|b| b == b'0') {
+            return Err(ConfigError::Invalid(
+                "PAYMENT_ADDRESS must not be the zero address".to_string(),
+            ));
         }
 
-        // Validate price
-        if toml_config.price_per_request < 0.0 {
+        // Without explicit resources, the top-level node settings form a single resource,
+        // served at relay_path (default /relay)
+        if toml_config.relay_path.is_some() && !toml_config.resources.is_empty() {
+            return Err(ConfigError::Invalid(
+                "relay_path applies only without [[resources]]; set each resource's path instead".to_string(),
+            ));
+        }
+        if !toml_config.fallback_node_urls.is_empty() && !toml_config.resources.is_empty() {
             return Err(ConfigError::Invalid(
-                "price_per_request cannot be negative".to_string(),
+                "fallback_node_urls applies only without [[resources]]; set each resource's fallback_node_urls instead".to_string(),
             ));
         }
+        let resources = if toml_config.resources.is_empty() {
+            let node_url = toml_config.node_url.ok_or_else(|| {
+                ConfigError::Invalid("node_url is required when no resources are configured".to_string())
+            })?;
+            let price_per_request = toml_config.price_per_request.ok_or_else(|| {
+                ConfigError::Invalid(
+                    "price_per_request is required when no resources are configured".to_string(),
+                )
+            })?;
+
+            vec![ResourceConfig {
+                name: "default".to_string(),
+                path: toml_config.relay_path.unwrap_or_else(|| "/relay".to_string()),
+                node_url,
+                fallback_node_urls: toml_config.fallback_node_urls,
+                price_per_request,
+                network: toml_config.network,
+            }]
+        } else {
+            toml_config.resources
+        };
+
+        Self::validate_resources(&resources)?;
+
+        // Require https:// for the facilitator and every node if configured
+        if toml_config.require_tls {
+            let urls = std::iter::once(("facilitator_url", toml_config.facilitator_url.as_str()))
+                .chain(resources.iter().map(|resource| ("node_url", resource.node_url.as_str())))
+                .chain(resources.iter().flat_map(|resource| &resource.fallback_node_urls).map(|url| ("fallback_node_urls", url.as_str())))
+                .chain(toml_config.balance_alert.iter().map(|alert| ("balance_alert.webhook_url", alert.webhook_url.as_str())));
+            for (name, url) in urls {
+                if !url.to_ascii_lowercase().starts_with("https://") {
+                    return Err(ConfigError::Invalid(format!(
+                        "{} must use https:// when require_tls is set (got '{}')",
+                        name, url
+                    )));
+                }
+            }
+        }
 
         // Validate database type
         if toml_config.database_type != "rocksdb" && toml_config.database_type != "dynamodb" {
@@ -106,16 +1117,654 @@ impl Config {
             ));
         }
 
-        Ok(Config {
-            node_url: toml_config.node_url,
-            price_per_request: toml_config.price_per_request,
+        // Validate DynamoDB table wait
+        if toml_config.dynamodb_table_wait_secs == Some(0) {
+            return Err(ConfigError::Invalid(
+                "dynamodb_table_wait_secs must be greater than 0".to_string(),
+            ));
+        }
+
+        // Validate RocksDB settings
+        if !(1..=4096).contains(&toml_config.rocksdb.write_buffer_size_mb) {
+            return Err(ConfigError::Invalid(
+                "rocksdb.write_buffer_size_mb must be between 1 and 4096".to_string(),
+            ));
+        }
+
+        if matches!(toml_config.rocksdb.coalesce_window_ms, Some(window) if !(1..=1000).contains(&window)) {
+            return Err(ConfigError::Invalid(
+                "rocksdb.coalesce_window_ms must be between 1 and 1000".to_string(),
+            ));
+        }
+
+        if toml_config.rocksdb.max_accounts == Some(0) {
+            return Err(ConfigError::Invalid(
+                "rocksdb.max_accounts must be greater than 0".to_string(),
+            ));
+        }
+
+        if toml_config.rocksdb.sync_writes && toml_config.rocksdb.disable_wal {
+            return Err(ConfigError::Invalid(
+                "rocksdb.sync_writes requires the WAL (disable_wal must be false)".to_string(),
+            ));
+        }
+
+        // Validate body logging
+        if toml_config.body_logging.as_ref().is_some_and(|logging| logging.max_logged_bytes == 0) {
+            return Err(ConfigError::Invalid(
+                "body_logging.max_logged_bytes must be greater than 0".to_string(),
+            ));
+        }
+
+        // Validate error sanitizer patterns
+        if let Some(sanitizer) = &toml_config.error_sanitizer {
+            ErrorSanitizer::new(sanitizer).map_err(|e| {
+                ConfigError::Invalid(format!("error_sanitizer pattern is invalid: {}", e))
+            })?;
+        }
+
+        // Validate IP filter
+        if let Some(filter) = &toml_config.ip_filter {
+            if filter.requests_per_window == Some(0) || filter.window_secs == 0 {
+                return Err(ConfigError::Invalid(
+                    "ip_filter.requests_per_window and ip_filter.window_secs must be greater than 0".to_string(),
+                ));
+            }
+            if filter.client_ip_header.is_some() && filter.trusted_proxies.is_empty() {
+                return Err(ConfigError::Invalid(
+                    "ip_filter.client_ip_header requires trusted_proxies; any client could set it otherwise".to_string(),
+                ));
+            }
+            IpFilter::new(filter).map_err(|e| ConfigError::Invalid(format!("ip_filter is invalid: {}", e)))?;
+        }
+
+        // Validate signature cache bound
+        if toml_config.signature_cache_max_entries == 0 {
+            return Err(ConfigError::Invalid(
+                "signature_cache_max_entries must be greater than 0".to_string(),
+            ));
+        }
+
+        // Validate settlement concurrency
+        if toml_config.max_concurrent_settlements == 0 {
+            return Err(ConfigError::Invalid(
+                "max_concurrent_settlements must be greater than 0".to_string(),
+            ));
+        }
+        if toml_config.settlement_timeout_secs == Some(0) {
+            return Err(ConfigError::Invalid(
+                "settlement_timeout_secs must be greater than 0".to_string(),
+            ));
+        }
+
+        // Validate minimum deposit
+        if let Some(min_deposit) = toml_config.min_deposit {
+            if !min_deposit.is_finite() || min_deposit <= 0.0 {
+                return Err(ConfigError::Invalid(
+                    "min_deposit must be a positive amount".to_string(),
+                ));
+            }
+            // Deposits are credited at most the top-up amount, so a higher minimum refuses them all
+            if min_deposit > crate::handlers::TOPUP_AMOUNT_USDC {
+                return Err(ConfigError::Invalid(format!(
+                    "min_deposit must not exceed the top-up amount of {} USDC",
+                    crate::handlers::TOPUP_AMOUNT_USDC
+                )));
+            }
+        }
+
+        // Validate per-network payment addresses like PAYMENT_ADDRESS
+        for (network, address) in &toml_config.payment_addresses {
+            if !is_address(address) || address[2..].bytes().all(|b| b == b'0') {
+                return Err(ConfigError::Invalid(format!(
+                    "payment_addresses entry for network '{}' must be a non-zero address, got '{}'",
+                    network, address
+                )));
+            }
+        }
+
+        // Validate previous payment addresses the same way
+        for previous in &toml_config.previous_payment_addresses {
+            if !is_address(&previous.address) || previous.address[2..].bytes().all(|b| b == b'0') {
+                return Err(ConfigError::Invalid(format!(
+                    "previous_payment_addresses entry must be a non-zero address, got '{}'",
+                    previous.address
+                )));
+            }
+        }
+
+        // Validate payment assets: each listed once per network, with sane decimals and bounds
+        let mut payment_assets = HashSet::new();
+        for asset in &toml_config.payment_assets {
+            if !is_address(&asset.address) {
+                return Err(ConfigError::Invalid(format!(
+                    "payment_assets address '{}' is not an address",
+                    asset.address
+                )));
+            }
+            if !payment_assets.insert((asset.network.to_string(), asset.address.to_lowercase())) {
+                return Err(ConfigError::Invalid(format!(
+                    "payment asset {} is listed more than once for network '{}'",
+                    asset.address, asset.network
+                )));
+            }
+            if asset.decimals > MAX_PAYMENT_ASSET_DECIMALS {
+                return Err(ConfigError::Invalid(format!(
+                    "payment asset {} has more than {} decimals",
+                    asset.address, MAX_PAYMENT_ASSET_DECIMALS
+                )));
+            }
+            let bounds = [asset.min_amount, asset.max_amount];
+            if bounds.iter().flatten().any(|amount| !amount.is_finite() || *amount <= 0.0)
+                || asset.min_amount.zip(asset.max_amount).is_some_and(|(min, max)| min > max)
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "payment asset {} needs positive amounts with min_amount no greater than max_amount",
+                    asset.address
+                )));
+            }
+            if asset.max_timeout_seconds == 0 {
+                return Err(ConfigError::Invalid(format!(
+                    "payment asset {} max_timeout_seconds must be greater than 0",
+                    asset.address
+                )));
+            }
+        }
+
+        // Validate relay concurrency
+        if toml_config.max_concurrent_relays == Some(0) {
+            return Err(ConfigError::Invalid(
+                "max_concurrent_relays must be greater than 0".to_string(),
+            ));
+        }
+
+        // Validate global budget: a window and at least one positive limit
+        if let Some(budget) = &toml_config.global_budget {
+            if budget.max_requests.is_none() && budget.max_spend.is_none() {
+                return Err(ConfigError::Invalid(
+                    "global_budget needs max_requests, max_spend or both".to_string(),
+                ));
+            }
+            if budget.max_requests == Some(0)
+                || budget.max_spend.is_some_and(|max| !max.is_finite() || max <= 0.0)
+                || budget.window_secs == 0
+            {
+                return Err(ConfigError::Invalid(
+                    "global_budget limits and window_secs must be greater than 0".to_string(),
+                ));
+            }
+        }
+
+        // Validate batch size limit
+        if toml_config.max_batch_size == 0 {
+            return Err(ConfigError::Invalid(
+                "max_batch_size must be greater than 0".to_string(),
+            ));
+        }
+
+        // Validate node response size limit
+        if toml_config.max_node_response_bytes == Some(0) {
+            return Err(ConfigError::Invalid(
+                "max_node_response_bytes must be greater than 0".to_string(),
+            ));
+        }
+
+        // Validate node timeouts
+        if toml_config.node_connect_timeout_ms == 0 || toml_config.node_timeout_ms == 0 {
+            return Err(ConfigError::Invalid(
+                "node_connect_timeout_ms and node_timeout_ms must be greater than 0".to_string(),
+            ));
+        }
+
+        // Validate JSON-RPC error status mapping
+        let mut jsonrpc_error_status = HashMap::new();
+        for (code, status) in toml_config.jsonrpc_error_status {
+            let code = code.parse::<i64>().map_err(|_| {
+                ConfigError::Invalid(format!("jsonrpc_error_status key '{}' must be an integer error code", code))
+            })?;
+            if !(100..=599).contains(&status) {
+                return Err(ConfigError::Invalid(format!(
+                    "jsonrpc_error_status for code {} must be a valid HTTP status",
+                    code
+                )));
+            }
+            jsonrpc_error_status.insert(code, status);
+        }
+
+        // Validate block time settings: a refresh interval and a resource to follow
+        if let Some(block_time) = &toml_config.block_time {
+            if block_time.refresh_secs == 0 {
+                return Err(ConfigError::Invalid(
+                    "block_time.refresh_secs must be greater than 0".to_string(),
+                ));
+            }
+            if let Some(name) = &block_time.resource {
+                if !resources.iter().any(|resource| &resource.name == name) {
+                    return Err(ConfigError::Invalid(format!(
+                        "block_time.resource '{}' is not a configured resource",
+                        name
+                    )));
+                }
+            }
+        }
+
+        // Validate sweeper settings
+        if let Some(sweeper) = &toml_config.sweeper {
+            // Retention must comfortably exceed the signature timestamp window
+            if sweeper.retention_secs < 3600 || sweeper.interval_secs == 0 {
+                return Err(ConfigError::Invalid(
+                    "sweeper.retention_secs must be at least 3600 and sweeper.interval_secs greater than 0".to_string(),
+                ));
+            }
+        }
+
+        // Validate request history settings
+        if let Some(history) = &toml_config.request_history {
+            if history.retention_secs == 0 || history.max_page_size == 0 {
+                return Err(ConfigError::Invalid(
+                    "request_history.retention_secs and request_history.max_page_size must be greater than 0".to_string(),
+                ));
+            }
+        }
+
+        // Validate reconciliation settings; balances are reconciled against recorded spend
+        if let Some(reconciliation) = &toml_config.reconciliation {
+            if reconciliation.interval_secs == 0 || !reconciliation.tolerance.is_finite() || reconciliation.tolerance < 0.0 {
+                return Err(ConfigError::Invalid(
+                    "reconciliation.interval_secs must be greater than 0 and reconciliation.tolerance non-negative".to_string(),
+                ));
+            }
+            if !toml_config.record_spend {
+                return Err(ConfigError::Invalid(
+                    "reconciliation requires record_spend".to_string(),
+                ));
+            }
+        }
+
+        // Validate finalized cache settings
+        if let Some(cache) = &toml_config.finalized_cache {
+            if cache.max_entries == 0 || cache.refresh_secs == 0 {
+                return Err(ConfigError::Invalid(
+                    "finalized_cache.max_entries and finalized_cache.refresh_secs must be greater than 0".to_string(),
+                ));
+            }
+            if cache.ttl_secs == Some(0) {
+                return Err(ConfigError::Invalid(
+                    "finalized_cache.ttl_secs must be greater than 0".to_string(),
+                ));
+            }
+        }
+
+        // Validate balance alert settings
+        if let Some(alert) = &toml_config.balance_alert {
+            if reqwest::Url::parse(&alert.webhook_url).is_err() {
+                return Err(ConfigError::Invalid(format!(
+                    "balance_alert.webhook_url '{}' is not a valid URL",
+                    alert.webhook_url
+                )));
+            }
+            if !alert.threshold.is_finite() || alert.threshold <= 0.0 || alert.max_attempts == 0 {
+                return Err(ConfigError::Invalid(
+                    "balance_alert.threshold and balance_alert.max_attempts must be greater than 0".to_string(),
+                ));
+            }
+            if alert.batch_interval_ms == Some(0) {
+                return Err(ConfigError::Invalid(
+                    "balance_alert.batch_interval_ms must be greater than 0".to_string(),
+                ));
+            }
+        }
+
+        // Validate facilitator authentication
+        if let Some(FacilitatorAuthConfig::ApiKey { header }) = &toml_config.facilitator_auth {
+            if reqwest::header::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                return Err(ConfigError::Invalid(format!(
+                    "facilitator_auth.header '{}' is not a valid header name",
+                    header
+                )));
+            }
+        }
+
+        // Validate upstream payment settings
+        if let Some(upstream) = &toml_config.upstream_payment {
+            if !upstream.max_amount.is_finite() || upstream.max_amount <= 0.0 {
+                return Err(ConfigError::Invalid(
+                    "upstream_payment.max_amount must be greater than 0".to_string(),
+                ));
+            }
+            let reference = match &upstream.key_source {
+                KeySource::Env { var } => var,
+                KeySource::File { path } => path,
+                KeySource::Command { command } => command,
+            };
+            if reference.trim().is_empty() {
+                return Err(ConfigError::Invalid(
+                    "upstream_payment.key_source must name a variable, path or command".to_string(),
+                ));
+            }
+        }
+
+        // Validate bandwidth billing settings
+        if let Some(billing) = &toml_config.bandwidth_billing {
+            if !billing.per_byte_price.is_finite() || billing.per_byte_price <= 0.0 || billing.max_billed_bytes == 0 {
+                return Err(ConfigError::Invalid(
+                    "bandwidth_billing.per_byte_price and bandwidth_billing.max_billed_bytes must be greater than 0".to_string(),
+                ));
+            }
+        }
+
+        // Validate body hash algorithms
+        if toml_config.body_hash_algorithms.is_empty() {
+            return Err(ConfigError::Invalid(
+                "body_hash_algorithms must list at least one algorithm".to_string(),
+            ));
+        }
+
+        // Validate body canonicalizations
+        if toml_config.body_canonicalizations.is_empty() {
+            return Err(ConfigError::Invalid(
+                "body_canonicalizations must list at least one canonicalization".to_string(),
+            ));
+        }
+
+        // Validate session key delegation lifetime
+        if toml_config.max_delegation_secs == Some(0) {
+            return Err(ConfigError::Invalid(
+                "max_delegation_secs must be greater than 0".to_string(),
+            ));
+        }
+
+        // Validate authentication schemes
+        let mut schemes = HashSet::new();
+        if toml_config.auth_schemes.is_empty() || !toml_config.auth_schemes.iter().all(|scheme| schemes.insert(*scheme)) {
+            return Err(ConfigError::Invalid(
+                "auth_schemes must list at least one scheme, each at most once".to_string(),
+            ));
+        }
+
+        // Validate and normalize API keys
+        if schemes.contains(&AuthSchemeKind::ApiKey) && toml_config.api_keys.is_empty() {
+            return Err(ConfigError::Invalid(
+                "api_keys must not be empty when the api_key scheme is enabled".to_string(),
+            ));
+        }
+        let mut api_keys = HashMap::new();
+        for (key, address) in toml_config.api_keys {
+            // Same minimum as ADMIN_TOKEN so keys can't be guessed
+            if key.len() < 32 {
+                return Err(ConfigError::Invalid("api_keys keys must be at least 32 characters".to_string()));
+            }
+            if !is_address(&address) {
+                return Err(ConfigError::Invalid(format!(
+                    "api_keys address '{}' must be a valid EVM address",
+                    address
+                )));
+            }
+            api_keys.insert(key, address.to_lowercase());
+        }
+
+        // Validate and normalize client certificate accounts
+        let mut mtls = toml_config.mtls;
+        if schemes.contains(&AuthSchemeKind::ClientCert)
+            && mtls.as_ref().is_none_or(|mtls| mtls.accounts.is_empty())
+        {
+            return Err(ConfigError::Invalid(
+                "mtls.accounts must not be empty when the client_cert scheme is enabled".to_string(),
+            ));
+        }
+        if let Some(mtls) = &mut mtls {
+            for address in mtls.accounts.values_mut() {
+                if !is_address(address) {
+                    return Err(ConfigError::Invalid(format!(
+                        "mtls.accounts address '{}' must be a valid EVM address",
+                        address
+                    )));
+                }
+                *address = address.to_lowercase();
+            }
+        }
+
+        // Validate idempotency window
+        if toml_config.idempotency_window_secs == Some(0) {
+            return Err(ConfigError::Invalid(
+                "idempotency_window_secs must be greater than 0".to_string(),
+            ));
+        }
+
+        // Validate payment challenge lifetime
+        if toml_config.payment_challenge_ttl_secs == Some(0) {
+            return Err(ConfigError::Invalid(
+                "payment_challenge_ttl_secs must be greater than 0".to_string(),
+            ));
+        }
+
+        // Validate and normalize per-address pricing
+        let mut address_pricing = HashMap::new();
+        for (address, price) in toml_config.address_pricing {
+            if !address.starts_with("0x") || address.len() != 42 {
+                return Err(ConfigError::Invalid(format!(
+                    "address_pricing key '{}' must be a valid EVM address",
+                    address
+                )));
+            }
+            if price < 0.0 {
+                return Err(ConfigError::Invalid(format!(
+                    "address_pricing price for '{}' cannot be negative",
+                    address
+                )));
+            }
+            address_pricing.insert(address.to_lowercase(), price);
+        }
+
+        let config = Config {
+            resources,
             port: toml_config.port,
             facilitator_url: toml_config.facilitator_url,
+            facilitator_auth: toml_config.facilitator_auth,
+            facilitator_token: None,
             payment_address,
             database_path: toml_config.database_path,
             database_type: toml_config.database_type,
+            dynamodb_audit_table_name: toml_config
+                .dynamodb_audit_table_name
+                .or_else(|| toml_config.dynamodb_table_name.as_ref().map(|table| format!("{}-audit", table))),
+            dynamodb_spend_table_name: toml_config
+                .dynamodb_spend_table_name
+                .or_else(|| toml_config.dynamodb_table_name.as_ref().map(|table| format!("{}-spend", table))),
+            dynamodb_history_table_name: toml_config
+                .dynamodb_history_table_name
+                .or_else(|| toml_config.dynamodb_table_name.as_ref().map(|table| format!("{}-history", table))),
+            record_spend: toml_config.record_spend,
+            free_requests: toml_config.free_requests,
+            dynamodb_create_tables: toml_config.dynamodb_create_tables,
+            dynamodb_table_wait_secs: toml_config.dynamodb_table_wait_secs,
             dynamodb_table_name: toml_config.dynamodb_table_name,
-        })
+            rocksdb: toml_config.rocksdb,
+            error_sanitizer: toml_config.error_sanitizer,
+            body_logging: toml_config.body_logging,
+            signature_cache_max_entries: toml_config.signature_cache_max_entries,
+            max_concurrent_settlements: toml_config.max_concurrent_settlements,
+            settlement_timeout_secs: toml_config.settlement_timeout_secs,
+            async_settlement: toml_config.async_settlement,
+            on_deduction_failure: toml_config.on_deduction_failure,
+            min_deposit: toml_config.min_deposit,
+            payment_assets: toml_config.payment_assets,
+            payment_addresses: toml_config.payment_addresses,
+            previous_payment_addresses: toml_config.previous_payment_addresses,
+            shutdown_drain_timeout_secs: toml_config.shutdown_drain_timeout_secs,
+            max_concurrent_relays: toml_config.max_concurrent_relays,
+            global_budget: toml_config.global_budget,
+            jsonrpc_error_status,
+            normalize_jsonrpc_status: toml_config.normalize_jsonrpc_status,
+            transient_jsonrpc_errors: toml_config.transient_jsonrpc_errors,
+            max_batch_size: toml_config.max_batch_size,
+            max_node_response_bytes: toml_config.max_node_response_bytes,
+            partial_batch: toml_config.partial_batch,
+            reject_unfunded_before_auth: toml_config.reject_unfunded_before_auth,
+            require_json_content_type: toml_config.require_json_content_type,
+            precheck_raw_transactions: toml_config.precheck_raw_transactions,
+            sweeper: toml_config.sweeper,
+            reconciliation: toml_config.reconciliation,
+            request_history: toml_config.request_history,
+            rewrite_jsonrpc_ids: toml_config.rewrite_jsonrpc_ids,
+            normalize_jsonrpc_ids: toml_config.normalize_jsonrpc_ids,
+            finalized_cache: toml_config.finalized_cache,
+            idempotency_window_secs: toml_config.idempotency_window_secs,
+            replayable_methods: toml_config.replayable_methods,
+            payment_challenge_ttl_secs: toml_config.payment_challenge_ttl_secs,
+            address_pricing,
+            price_rounding: toml_config.price_rounding,
+            deposits_enabled: toml_config.deposits_enabled,
+            accept_permit: toml_config.accept_permit,
+            require_deposit_network_match: toml_config.require_deposit_network_match,
+            clock_jump_grace_secs: toml_config.clock_jump_grace_secs,
+            block_time: toml_config.block_time,
+            slow_request_threshold_ms: toml_config.slow_request_threshold_ms,
+            upstream_time_header: toml_config.upstream_time_header,
+            node_http2: toml_config.node_http2,
+            node_pool_max_idle_per_host: toml_config.node_pool_max_idle_per_host,
+            node_connect_timeout_ms: toml_config.node_connect_timeout_ms,
+            node_timeout_ms: toml_config.node_timeout_ms,
+            debug_endpoints: toml_config.debug_endpoints,
+            maintenance: toml_config.maintenance,
+            monotonic_timestamps: toml_config.monotonic_timestamps,
+            check_payment_address_funding: toml_config.check_payment_address_funding,
+            balance_alert: toml_config.balance_alert,
+            upstream_payment: toml_config.upstream_payment,
+            upstream_private_key: None,
+            bandwidth_billing: toml_config.bandwidth_billing,
+            auth_schemes: toml_config.auth_schemes,
+            body_hash_algorithms: toml_config.body_hash_algorithms,
+            body_canonicalizations: toml_config.body_canonicalizations,
+            max_delegation_secs: toml_config.max_delegation_secs,
+            api_keys,
+            mtls,
+            ip_filter: toml_config.ip_filter,
+            admin_token: None,
+        };
+
+        // Validate that payment requirements can be built from the addresses and paths above
+        PreparedRequirements::new(&config).map_err(ConfigError::Invalid)?;
+
+        Ok(config)
+    }
+
+    /// Headers authenticating requests to the facilitator, marked sensitive so they aren't logged
+    pub fn facilitator_headers(&self) -> Result<HeaderMap, InvalidHeaderValue> {
+        let mut headers = HeaderMap::new();
+        let (Some(auth), Some(token)) = (&self.facilitator_auth, &self.facilitator_token) else {
+            return Ok(headers);
+        };
+
+        let (name, mut value) = match auth {
+            FacilitatorAuthConfig::Bearer => (
+                header::AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {}", token.expose()))?,
+            ),
+            FacilitatorAuthConfig::ApiKey { header } => (
+                HeaderName::from_bytes(header.as_bytes()).expect("Header name validated at load"),
+                HeaderValue::from_str(token.expose())?,
+            ),
+        };
+        value.set_sensitive(true);
+        headers.insert(name, value);
+        Ok(headers)
+    }
+
+    /// Node followed for chain time, if block time is configured
+    pub fn block_time_node_url(&self) -> Option<&str> {
+        let block_time = self.block_time.as_ref()?;
+        self.resources
+            .iter()
+            .find(|resource| block_time.resource.as_ref().is_none_or(|name| &resource.name == name))
+            .map(|resource| resource.node_url.as_str())
+    }
+
+    /// Address deposits on `network` are paid to
+    pub fn payment_address_for(&self, network: Network) -> &str {
+        self.payment_addresses
+            .get(&network)
+            .unwrap_or(&self.payment_address)
+    }
+
+    /// Address a deposit naming `pay_to` on `network` is verified against at `now`: `pay_to`
+    /// itself if it's a previous payment address still in its grace period, the current one otherwise
+    pub fn accepted_payment_address<'a>(&'a self, network: Network, pay_to: &'a str, now: u64) -> &'a str {
+        let accepted = self.previous_payment_addresses.iter().any(|previous| {
+            previous.address.eq_ignore_ascii_case(pay_to)
+                && now < previous.accepted_until
+                && previous.network.is_none_or(|previous_network| previous_network == network)
+        });
+        if accepted {
+            pay_to
+        } else {
+            self.payment_address_for(network)
+        }
+    }
+
+    /// Price per request for an authenticated address on a resource
+    pub fn price_for(&self, resource: &ResourceConfig, address: &str) -> f64 {
+        self.address_pricing
+            .get(&address.to_lowercase())
+            .copied()
+            .unwrap_or(resource.price_per_request)
+    }
+
+    fn validate_resources(resources: &[ResourceConfig]) -> Result<(), ConfigError> {
+        let mut names = HashSet::new();
+        let mut paths = HashSet::new();
+
+        for resource in resources {
+            // Validate node URL
+            if resource.node_url.is_empty() {
+                return Err(ConfigError::Invalid(format!(
+                    "node_url cannot be empty (resource '{}')",
+                    resource.name
+                )));
+            }
+            if resource.fallback_node_urls.iter().any(String::is_empty) {
+                return Err(ConfigError::Invalid(format!(
+                    "fallback_node_urls cannot contain empty URLs (resource '{}')",
+                    resource.name
+                )));
+            }
+
+            // Validate price
+            if resource.price_per_request < 0.0 {
+                return Err(ConfigError::Invalid(format!(
+                    "price_per_request cannot be negative (resource '{}')",
+                    resource.name
+                )));
+            }
+
+            // Validate route path
+            let reserved = RESERVED_PATHS.iter().any(|reserved| {
+                resource.path == *reserved || resource.path.starts_with(&format!("{}/", reserved))
+            });
+            if !resource.path.starts_with('/') || reserved {
+                return Err(ConfigError::Invalid(format!(
+                    "path must start with '/' and not collide with {} (resource '{}')",
+                    RESERVED_PATHS.join(", "),
+                    resource.name
+                )));
+            }
+
+            if !names.insert(resource.name.as_str()) {
+                return Err(ConfigError::Invalid(format!(
+                    "duplicate resource name '{}'",
+                    resource.name
+                )));
+            }
+
+            if !paths.insert(resource.path.as_str()) {
+                return Err(ConfigError::Invalid(format!(
+                    "duplicate resource path '{}'",
+                    resource.path
+                )));
+            }
+        }
+
+        Ok(())
     }
 
     fn load_toml(path: &str) -> Result<TomlConfig, ConfigError> {
@@ -124,5 +1773,279 @@ impl Config {
         let config: TomlConfig = toml::from_str(&contents)?;
         Ok(config)
     }
+
+    /// Parse and validate configuration from TOML text (for tests)
+    #[cfg(test)]
+    pub fn from_toml_str(contents: &str, payment_address: &str) -> Result<Self, ConfigError> {
+        let toml_config: TomlConfig = toml::from_str(contents)?;
+        Self::from_toml(toml_config, payment_address.to_string())
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYMENT_ADDRESS: &str = "0x1234567890abcdef1234567890abcdef12345678";
+
+    const BASE: &str = r#"
+        port = 3000
+        facilitator_url = "https://x402.org/facilitator"
+        database_path = "./data/gateway.db"
+        database_type = "rocksdb"
+    "#;
+
+    #[test]
+    fn test_single_node_config_becomes_relay_resource() {
+        let contents = format!("node_url = \"http://localhost:8545\"\nprice_per_request = 0.001\n{}", BASE);
+        let config = Config::from_toml_str(&contents, PAYMENT_ADDRESS).unwrap();
+
+        assert_eq!(config.resources.len(), 1);
+        assert_eq!(config.resources[0].path, "/relay");
+        assert_eq!(config.resources[0].node_url, "http://localhost:8545");
+        assert_eq!(config.resources[0].network, Network::BaseSepolia);
+    }
+
+    #[test]
+    fn test_zero_payment_address_rejected() {
+        let contents = format!("node_url = \"http://localhost:8545\"\nprice_per_request = 0.001\n{}", BASE);
+        let error = Config::from_toml_str(&contents, "0x0000000000000000000000000000000000000000").unwrap_err();
+        assert!(error.to_string().contains("zero address"), "{}", error);
+
+        assert!(Config::from_toml_str(&contents, "0x0000000000000000000000000000000000000001").is_ok());
+    }
+
+    #[test]
+    fn test_min_deposit_bounded_by_topup_amount() {
+        let contents = |min_deposit: f64| {
+            format!("node_url = \"http://localhost:8545\"\nprice_per_request = 0.001\nmin_deposit = {}\n{}", min_deposit, BASE)
+        };
+        assert!(Config::from_toml_str(&contents(0.5), PAYMENT_ADDRESS).is_ok());
+        assert!(Config::from_toml_str(&contents(crate::handlers::TOPUP_AMOUNT_USDC), PAYMENT_ADDRESS).is_ok());
+
+        let error = Config::from_toml_str(&contents(2.0), PAYMENT_ADDRESS).unwrap_err();
+        assert!(error.to_string().contains("top-up amount"), "{}", error);
+        assert!(Config::from_toml_str(&contents(0.0), PAYMENT_ADDRESS).is_err());
+    }
+
+    #[test]
+    fn test_payment_addresses_per_network() {
+        let contents = |address: &str| {
+            format!(
+                "node_url = \"http://localhost:8545\"\nprice_per_request = 0.001\n{}\n[payment_addresses]\nbase = \"{}\"\n",
+                BASE, address
+            )
+        };
+        let treasury = "0x00000000000000000000000000000000000000b0";
+        let config = Config::from_toml_str(&contents(treasury), PAYMENT_ADDRESS).unwrap();
+        assert_eq!(config.payment_address_for(Network::Base), treasury);
+        assert_eq!(config.payment_address_for(Network::BaseSepolia), PAYMENT_ADDRESS);
+
+        for invalid in ["0x1234", "0x0000000000000000000000000000000000000000"] {
+            let error = Config::from_toml_str(&contents(invalid), PAYMENT_ADDRESS).unwrap_err();
+            assert!(error.to_string().contains("payment_addresses"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_previous_payment_addresses_accepted_until_grace_ends() {
+        let previous = "0x00000000000000000000000000000000000000c1";
+        let contents = format!(
+            "node_url = \"http://localhost:8545\"\nprice_per_request = 0.001\n{}\n[[previous_payment_addresses]]\naddress = \"{}\"\naccepted_until = 1000\nnetwork = \"base\"\n",
+            BASE, previous
+        );
+        let config = Config::from_toml_str(&contents, PAYMENT_ADDRESS).unwrap();
+
+        assert_eq!(config.accepted_payment_address(Network::Base, previous, 999), previous);
+        assert_eq!(config.accepted_payment_address(Network::Base, previous, 1000), PAYMENT_ADDRESS);
+        // Only on the network it was used on
+        assert_eq!(config.accepted_payment_address(Network::BaseSepolia, previous, 999), PAYMENT_ADDRESS);
+        // Any other recipient is verified against the current address
+        let other = "0x00000000000000000000000000000000000000c2";
+        assert_eq!(config.accepted_payment_address(Network::Base, other, 999), PAYMENT_ADDRESS);
+    }
+
+    #[test]
+    fn test_upstream_key_source_parsed() {
+        let contents = |upstream: &str| {
+            format!("node_url = \"http://localhost:8545\"\nprice_per_request = 0.001\n{}\n[upstream_payment]\nmax_amount = 0.01\n{}", BASE, upstream)
+        };
+        let config = Config::from_toml_str(&contents(""), "0x1234567890abcdef1234567890abcdef12345678").unwrap();
+        let upstream = config.upstream_payment.unwrap();
+        assert_eq!(upstream.key_source, KeySource::Env { var: "UPSTREAM_PRIVATE_KEY".to_string() });
+
+        let config = Config::from_toml_str(
+            &contents(r#"key_source = { type = "file", path = "/run/secrets/upstream.key" }"#),
+            "0x1234567890abcdef1234567890abcdef12345678",
+        )
+        .unwrap();
+        let upstream = config.upstream_payment.unwrap();
+        assert_eq!(upstream.key_source, KeySource::File { path: "/run/secrets/upstream.key".to_string() });
+
+        let error = Config::from_toml_str(
+            &contents(r#"key_source = { type = "command", command = " " }"#),
+            "0x1234567890abcdef1234567890abcdef12345678",
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("key_source"), "{}", error);
+    }
+
+    #[test]
+    fn test_ip_filter_validated() {
+        let contents = |filter: &str| {
+            format!("node_url = \"http://localhost:8545\"\nprice_per_request = 0.001\n{}\n[ip_filter]\n{}", BASE, filter)
+        };
+
+        let config = Config::from_toml_str(&contents("blocklist = [\"203.0.113.0/24\"]\nrequests_per_window = 10"), PAYMENT_ADDRESS).unwrap();
+        assert_eq!(config.ip_filter.unwrap().window_secs, 1);
+
+        for filter in [
+            "blocklist = [\"203.0.113.0/33\"]",
+            "requests_per_window = 0",
+            "window_secs = 0",
+            "client_ip_header = \"X-Forwarded-For\"",
+            "client_ip_header = \"bad header\"\ntrusted_proxies = [\"10.0.0.0/8\"]",
+        ] {
+            assert!(Config::from_toml_str(&contents(filter), PAYMENT_ADDRESS).is_err(), "{}", filter);
+        }
+    }
+
+    #[test]
+    fn test_relay_path_validated() {
+        let contents = |relay_path: &str| {
+            format!("node_url = \"http://localhost:8545\"\nprice_per_request = 0.001\nrelay_path = \"{}\"\n{}", relay_path, BASE)
+        };
+
+        for path in ["/", "/rpc"] {
+            let config = Config::from_toml_str(&contents(path), PAYMENT_ADDRESS).unwrap();
+            assert_eq!(config.resources[0].path, path);
+        }
+        for path in ["rpc", "/health", "/health/rpc", "/history"] {
+            assert!(Config::from_toml_str(&contents(path), PAYMENT_ADDRESS).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_multiple_resources() {
+        let contents = format!(
+            r#"{}
+            [[resources]]
+            name = "eth"
+            path = "/eth"
+            node_url = "http://eth-node:8545"
+            price_per_request = 0.002
+
+            [[resources]]
+            name = "base"
+            path = "/base"
+            node_url = "http://base-node:8545"
+            price_per_request = 0.001
+            network = "base"
+            "#,
+            BASE
+        );
+        let config = Config::from_toml_str(&contents, PAYMENT_ADDRESS).unwrap();
+
+        assert_eq!(config.resources.len(), 2);
+        assert_eq!(config.resources[0].price_per_request, 0.002);
+        assert_eq!(config.resources[1].network, Network::Base);
+    }
+
+    #[test]
+    fn test_address_pricing_overrides_default_price() {
+        let contents = format!(
+            r#"node_url = "http://localhost:8545"
+            price_per_request = 0.001
+            {}
+            [address_pricing]
+            "0xABCDEFabcdefABCDEFabcdefABCDEFabcdefABCD" = 0.0005
+            "#,
+            BASE
+        );
+        let config = Config::from_toml_str(&contents, PAYMENT_ADDRESS).unwrap();
+        let resource = &config.resources[0];
+
+        // Lookup is case-insensitive
+        assert_eq!(config.price_for(resource, "0xabcdefabcdefabcdefabcdefabcdefabcdefabcd"), 0.0005);
+        assert_eq!(config.price_for(resource, PAYMENT_ADDRESS), 0.001);
+    }
+
+    #[test]
+    fn test_rocksdb_settings_validated() {
+        let single = "node_url = \"http://localhost:8545\"\nprice_per_request = 0.001\n";
+
+        let contents = format!("{}{}\n[rocksdb]\nsync_writes = true\nuse_fsync = true\n", single, BASE);
+        let config = Config::from_toml_str(&contents, PAYMENT_ADDRESS).unwrap();
+        assert!(config.rocksdb.sync_writes);
+        assert_eq!(config.rocksdb.write_buffer_size_mb, 64);
+
+        let contents = format!("{}{}\n[rocksdb]\nsync_writes = true\ndisable_wal = true\n", single, BASE);
+        assert!(Config::from_toml_str(&contents, PAYMENT_ADDRESS).is_err());
+
+        let contents = format!("{}{}\n[rocksdb]\nwrite_buffer_size_mb = 0\n", single, BASE);
+        assert!(Config::from_toml_str(&contents, PAYMENT_ADDRESS).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_resource_path_rejected() {
+        let contents = format!(
+            r#"{}
+            [[resources]]
+            name = "a"
+            path = "/rpc"
+            node_url = "http://a:8545"
+            price_per_request = 0.001
+
+            [[resources]]
+            name = "b"
+            path = "/rpc"
+            node_url = "http://b:8545"
+            price_per_request = 0.001
+            "#,
+            BASE
+        );
+        assert!(Config::from_toml_str(&contents, PAYMENT_ADDRESS).is_err());
+    }
+
+    #[test]
+    fn test_facilitator_credential_is_sensitive() {
+        let contents = |auth: &str| {
+            format!("node_url = \"http://localhost:8545\"\nprice_per_request = 0.001\n{}\n[facilitator_auth]\n{}", BASE, auth)
+        };
+        let mut config = Config::from_toml_str(&contents("type = \"api_key\""), PAYMENT_ADDRESS).unwrap();
+        config.facilitator_token = Some(Secret::new("facilitator-secret".to_string()));
+
+        let headers = config.facilitator_headers().unwrap();
+        assert_eq!(headers["x-api-key"], "facilitator-secret");
+        assert!(headers["x-api-key"].is_sensitive());
+        assert!(!format!("{:?}", config).contains("facilitator-secret"));
+        assert!(!format!("{:?}", headers).contains("facilitator-secret"));
+
+        let invalid = contents("type = \"api_key\"\nheader = \"bad header\"");
+        assert!(Config::from_toml_str(&invalid, PAYMENT_ADDRESS).is_err());
+    }
+
+    #[test]
+    fn test_require_tls_rejects_plaintext_urls() {
+        let config = |require_tls: bool, facilitator_url: &str, node_url: &str| {
+            let contents = format!(
+                r#"
+                require_tls = {}
+                port = 3000
+                facilitator_url = "{}"
+                database_path = "./data/gateway.db"
+                database_type = "rocksdb"
+                node_url = "{}"
+                price_per_request = 0.001
+                "#,
+                require_tls, facilitator_url, node_url
+            );
+            Config::from_toml_str(&contents, PAYMENT_ADDRESS)
+        };
+
+        assert!(config(true, "https://x402.org/facilitator", "https://eth-node").is_ok());
+        assert!(config(true, "https://x402.org/facilitator", "http://eth-node").is_err());
+        assert!(config(true, "http://facilitator.local", "https://eth-node").is_err());
+        assert!(config(false, "http://facilitator.local", "http://eth-node").is_ok());
+    }
+}
@@ -1,32 +1,255 @@
-use super::{DatabaseError, DatabaseTrait, UserData};
+use super::{
+    history_position, AuditEntry, ConsistencyLevel, DatabaseError, DatabaseTrait, RequestPage, RequestRecord,
+    Reservation, ReservationId, UserData, EMPTY_BALANCE_THRESHOLD, MAX_HISTORY_POSITION, NANOS_PER_SEC,
+};
 use async_trait::async_trait;
-use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use aws_sdk_dynamodb::types::{
+    AttributeDefinition, AttributeValue, BillingMode, KeySchemaElement, KeyType, KeysAndAttributes, ReturnValue,
+    ScalarAttributeType, TableStatus,
+};
 use aws_sdk_dynamodb::Client;
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Most keys BatchGetItem accepts per request
+const BATCH_GET_MAX_KEYS: usize = 100;
+
+/// BatchGetItem calls per chunk before giving up on keys DynamoDB keeps leaving unprocessed
+const BATCH_GET_MAX_ATTEMPTS: u32 = 5;
+
+/// How often table status is polled while waiting for tables to become active
+const TABLE_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
 /// DynamoDB implementation of DatabaseTrait
 #[derive(Clone)]
 pub struct DynamoDbDatabase {
     client: Client,
     table_name: String,
+    /// Audit ledger table (partition key "address", sort key "recorded_at")
+    audit_table_name: String,
+    /// Spend events table (partition key "address", sort key "spent_at")
+    spend_table_name: String,
+    /// Request history table (partition key "address", sort key "position"), if history is kept
+    history_table_name: Option<String>,
 }
 
 impl DynamoDbDatabase {
     /// Create a new DynamoDB database instance
-    pub async fn new(table_name: String) -> Result<Self, DatabaseError> {
+    pub async fn new(table_name: String, audit_table_name: String, spend_table_name: String) -> Result<Self, DatabaseError> {
         let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
             .load()
             .await;
         let client = Client::new(&config);
 
-        tracing::info!(table = %table_name, "DynamoDB client initialized");
+        tracing::info!(
+            table = %table_name,
+            audit_table = %audit_table_name,
+            spend_table = %spend_table_name,
+            "DynamoDB client initialized"
+        );
+
+        Ok(Self::from_client(client, table_name, audit_table_name, spend_table_name))
+    }
+
+    /// Create a DynamoDB database instance from an already configured client
+    pub fn from_client(client: Client, table_name: String, audit_table_name: String, spend_table_name: String) -> Self {
+        Self {
+            client,
+            table_name,
+            audit_table_name,
+            spend_table_name,
+            history_table_name: None,
+        }
+    }
+
+    /// Keep request history in `history_table_name`
+    /// Its entries carry an "expires_at" attribute for DynamoDB's time to live to remove them by
+    pub fn with_history_table(mut self, history_table_name: String) -> Self {
+        self.history_table_name = Some(history_table_name);
+        self
+    }
+
+    /// Each table with its sort key; all are partitioned by "address"
+    fn tables(&self) -> Vec<(&str, Option<&'static str>)> {
+        let mut tables = vec![
+            (self.table_name.as_str(), None),
+            (self.audit_table_name.as_str(), Some("recorded_at")),
+            (self.spend_table_name.as_str(), Some("spent_at")),
+        ];
+        if let Some(history_table_name) = &self.history_table_name {
+            tables.push((history_table_name.as_str(), Some("position")));
+        }
+        tables
+    }
+
+    fn history_table(&self) -> Result<&str, DatabaseError> {
+        self.history_table_name
+            .as_deref()
+            .ok_or_else(|| DatabaseError::DynamoDB("no request history table configured".to_string()))
+    }
+
+    /// Request history entry from a DynamoDB item, with its position
+    fn parse_request(item: &HashMap<String, AttributeValue>) -> Result<(u128, RequestRecord), DatabaseError> {
+        let number = |name: &str| {
+            item.get(name)
+                .and_then(|v| v.as_n().ok())
+                .ok_or_else(|| DatabaseError::AttributeNotFound(name.to_string()))
+        };
+        let parse_error = |name: &str| DatabaseError::ParseError(name.to_string());
+
+        let position = number("position")?.parse::<u128>().map_err(|_| parse_error("position"))?;
+        let record = RequestRecord {
+            method: item
+                .get("method")
+                .and_then(|v| v.as_s().ok())
+                .ok_or_else(|| DatabaseError::AttributeNotFound("method".to_string()))?
+                .clone(),
+            price: number("price")?.parse::<f64>().map_err(|_| parse_error("price"))?,
+            timestamp: number("timestamp")?.parse::<u64>().map_err(|_| parse_error("timestamp"))?,
+            success: item.get("success").and_then(|v| v.as_bool().ok()).copied().unwrap_or(false),
+        };
+        Ok((position, record))
+    }
+
+    /// Wait up to `timeout` for every table to become ACTIVE, creating missing ones if `create` is set
+    /// A freshly created table rejects reads and writes until then
+    pub async fn wait_for_tables(&self, create: bool, timeout: Duration) -> Result<(), DatabaseError> {
+        let deadline = Instant::now() + timeout;
+
+        for (table, sort_key) in self.tables() {
+            loop {
+                match self.client.describe_table().table_name(table).send().await {
+                    Ok(output) => {
+                        let status = output.table().and_then(|table| table.table_status());
+                        if status == Some(&TableStatus::Active) {
+                            break;
+                        }
+                        tracing::info!(table, status = ?status, "Waiting for DynamoDB table to become active");
+                    }
+                    Err(e) if e.as_service_error().is_some_and(|se| se.is_resource_not_found_exception()) => {
+                        if !create {
+                            return Err(DatabaseError::DynamoDB(format!(
+                                "table {} does not exist (set dynamodb_create_tables to create it)",
+                                table
+                            )));
+                        }
+                        self.create_table(table, sort_key).await?;
+                    }
+                    Err(e) => return Err(DatabaseError::DynamoDB(e.to_string())),
+                }
+
+                if Instant::now() >= deadline {
+                    return Err(DatabaseError::DynamoDB(format!(
+                        "table {} is not active after {}s",
+                        table,
+                        timeout.as_secs()
+                    )));
+                }
+                tokio::time::sleep(TABLE_POLL_INTERVAL).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Create an on-demand table partitioned by "address", sorted by the numeric `sort_key` if any
+    async fn create_table(&self, table: &str, sort_key: Option<&str>) -> Result<(), DatabaseError> {
+        let invalid = |e: aws_sdk_dynamodb::error::BuildError| DatabaseError::DynamoDB(e.to_string());
+        let attribute = |name: &str, attribute_type| {
+            AttributeDefinition::builder().attribute_name(name).attribute_type(attribute_type).build().map_err(invalid)
+        };
+        let key = |name: &str, key_type| {
+            KeySchemaElement::builder().attribute_name(name).key_type(key_type).build().map_err(invalid)
+        };
+
+        let mut request = self
+            .client
+            .create_table()
+            .table_name(table)
+            .billing_mode(BillingMode::PayPerRequest)
+            .attribute_definitions(attribute("address", ScalarAttributeType::S)?)
+            .key_schema(key("address", KeyType::Hash)?);
+        if let Some(sort_key) = sort_key {
+            request = request
+                .attribute_definitions(attribute(sort_key, ScalarAttributeType::N)?)
+                .key_schema(key(sort_key, KeyType::Range)?);
+        }
+
+        match request.send().await {
+            Ok(_) => {
+                tracing::info!(table, "DynamoDB table created");
+                Ok(())
+            }
+            // Another instance starting at the same time created it first
+            Err(e) if e.as_service_error().is_some_and(|se| se.is_resource_in_use_exception()) => Ok(()),
+            Err(e) => Err(DatabaseError::DynamoDB(e.to_string())),
+        }
+    }
+
+    /// Whether an item is an account; one holding only a signed timestamp isn't until a deposit
+    /// or free request opens it, just as RocksDB keeps signed timestamps apart from accounts
+    fn is_account(item: &HashMap<String, AttributeValue>) -> bool {
+        item.contains_key("balance")
+    }
+
+    /// Parse a user record from a DynamoDB item
+    fn parse_user(item: &HashMap<String, AttributeValue>) -> Result<UserData, DatabaseError> {
+        let balance = item
+            .get("balance")
+            .and_then(|v| v.as_n().ok())
+            .and_then(|s| s.parse::<f64>().ok())
+            .ok_or_else(|| DatabaseError::AttributeNotFound("balance".to_string()))?;
+
+        let latest_timestamp = item
+            .get("latest_timestamp")
+            .and_then(|v| v.as_n().ok())
+            .and_then(|s| s.parse::<u64>().ok())
+            .ok_or_else(|| {
+                DatabaseError::AttributeNotFound("latest_timestamp".to_string())
+            })?;
+
+        // Accounts written before suspension existed have no flag
+        let suspended = item
+            .get("suspended")
+            .and_then(|v| v.as_bool().ok())
+            .copied()
+            .unwrap_or(false);
+
+        // Nor do accounts written before free requests were counted
+        let free_requests_used = item
+            .get("free_requests_used")
+            .and_then(|v| v.as_n().ok())
+            .and_then(|s| s.parse::<u32>().ok())
+            .unwrap_or(0);
+
+        Ok(UserData {
+            suspended,
+            free_requests_used,
+            ..UserData::new(balance, latest_timestamp)
+        })
+    }
+
+    /// Balance from the attributes returned by an update
+    fn returned_balance(attributes: Option<HashMap<String, AttributeValue>>) -> Result<f64, DatabaseError> {
+        attributes
+            .and_then(|attrs| attrs.get("balance").cloned())
+            .and_then(|v| v.as_n().ok().and_then(|n| n.parse::<f64>().ok()))
+            .ok_or_else(|| DatabaseError::AttributeNotFound("balance".to_string()))
+    }
 
-        Ok(Self { client, table_name })
+    /// Item attribute recording the amount a reservation holds until it is settled
+    fn hold_attribute(id: &ReservationId) -> String {
+        format!("hold_{}", id)
     }
 }
 
 #[async_trait]
 impl DatabaseTrait for DynamoDbDatabase {
-    async fn get_user(&self, address: &str) -> Result<Option<UserData>, DatabaseError> {
+    async fn get_user_consistent(
+        &self,
+        address: &str,
+        consistency: ConsistencyLevel,
+    ) -> Result<Option<UserData>, DatabaseError> {
         let key = address.to_lowercase();
 
         let result = self
@@ -34,30 +257,81 @@ impl DatabaseTrait for DynamoDbDatabase {
             .get_item()
             .table_name(&self.table_name)
             .key("address", AttributeValue::S(key.clone()))
+            .consistent_read(consistency == ConsistencyLevel::Strong)
             .send()
             .await
             .map_err(|e| DatabaseError::DynamoDB(e.to_string()))?;
 
         match result.item {
-            Some(item) => {
-                let balance = item
-                    .get("balance")
-                    .and_then(|v| v.as_n().ok())
-                    .and_then(|s| s.parse::<f64>().ok())
-                    .ok_or_else(|| DatabaseError::AttributeNotFound("balance".to_string()))?;
+            Some(item) if Self::is_account(&item) => Ok(Some(Self::parse_user(&item)?)),
+            _ => Ok(None),
+        }
+    }
 
-                let latest_timestamp = item
-                    .get("latest_timestamp")
-                    .and_then(|v| v.as_n().ok())
-                    .and_then(|s| s.parse::<u64>().ok())
-                    .ok_or_else(|| {
-                        DatabaseError::AttributeNotFound("latest_timestamp".to_string())
-                    })?;
+    async fn get_user_batch(&self, addresses: &[String]) -> Result<Vec<(String, Option<UserData>)>, DatabaseError> {
+        let keys: Vec<String> = addresses.iter().map(|address| address.to_lowercase()).collect();
+
+        // BatchGetItem rejects duplicate keys, and takes at most 100 per request
+        let mut unique = keys.clone();
+        unique.sort();
+        unique.dedup();
+
+        let mut found = HashMap::new();
+        for chunk in unique.chunks(BATCH_GET_MAX_KEYS) {
+            let request = KeysAndAttributes::builder()
+                .set_keys(Some(
+                    chunk
+                        .iter()
+                        .map(|key| HashMap::from([("address".to_string(), AttributeValue::S(key.clone()))]))
+                        .collect(),
+                ))
+                .consistent_read(true)
+                .build()
+                .map_err(|e| DatabaseError::DynamoDB(e.to_string()))?;
+            let mut request_items = HashMap::from([(self.table_name.clone(), request)]);
+
+            // Throttled reads come back as unprocessed keys; retry them with backoff
+            let mut delay = Duration::from_millis(50);
+            for attempt in 1..=BATCH_GET_MAX_ATTEMPTS {
+                let result = self
+                    .client
+                    .batch_get_item()
+                    .set_request_items(Some(request_items))
+                    .send()
+                    .await
+                    .map_err(|e| DatabaseError::DynamoDB(e.to_string()))?;
+
+                let items = result
+                    .responses
+                    .and_then(|mut responses| responses.remove(&self.table_name))
+                    .unwrap_or_default();
+                for item in items.iter().filter(|item| Self::is_account(item)) {
+                    let address = item
+                        .get("address")
+                        .and_then(|v| v.as_s().ok())
+                        .ok_or_else(|| DatabaseError::AttributeNotFound("address".to_string()))?;
+                    found.insert(address.clone(), Self::parse_user(item)?);
+                }
 
-                Ok(Some(UserData::new(balance, latest_timestamp)))
+                request_items = result.unprocessed_keys.unwrap_or_default();
+                if request_items.is_empty() {
+                    break;
+                }
+                if attempt == BATCH_GET_MAX_ATTEMPTS {
+                    return Err(DatabaseError::DynamoDB(
+                        "BatchGetItem left keys unprocessed after retries".to_string(),
+                    ));
+                }
+                tokio::time::sleep(delay).await;
+                delay *= 2;
             }
-            None => Ok(None),
         }
+
+        Ok(addresses
+            .iter()
+            .zip(&keys)
+            .map(|(address, key)| (address.clone(), found.get(key).cloned()))
+            .collect())
     }
 
     async fn update_user(&self, address: &str, data: UserData) -> Result<(), DatabaseError> {
@@ -72,6 +346,8 @@ impl DatabaseTrait for DynamoDbDatabase {
                 "latest_timestamp",
                 AttributeValue::N(data.latest_timestamp.to_string()),
             )
+            .item("suspended", AttributeValue::Bool(data.suspended))
+            .item("free_requests_used", AttributeValue::N(data.free_requests_used.to_string()))
             .send()
             .await
             .map_err(|e| DatabaseError::DynamoDB(e.to_string()))?;
@@ -179,5 +455,797 @@ impl DatabaseTrait for DynamoDbDatabase {
 
         Ok(remaining_balance)
     }
+
+    async fn deposit_and_deduct(
+        &self,
+        address: &str,
+        credit: f64,
+        amount: f64,
+        timestamp: u64,
+    ) -> Result<f64, DatabaseError> {
+        let key = address.to_lowercase();
+
+        // Apply the net delta in one conditional update, so no read ever sees the credit without the deduction
+        let mut update = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("address", AttributeValue::S(key.clone()))
+            .update_expression("SET balance = if_not_exists(balance, :zero) + :delta, latest_timestamp = :ts")
+            .expression_attribute_values(":delta", AttributeValue::N((credit - amount).to_string()))
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+            .expression_attribute_values(":ts", AttributeValue::N(timestamp.to_string()))
+            .return_values(ReturnValue::AllNew);
+        if amount > credit {
+            update = update
+                .condition_expression("balance >= :shortfall")
+                .expression_attribute_values(":shortfall", AttributeValue::N((amount - credit).to_string()));
+        }
+
+        let result = update
+            .send()
+            .await
+            .map_err(|e| {
+                let error_str = e.to_string();
+                if error_str.contains("ConditionalCheckFailedException") {
+                    DatabaseError::InsufficientBalance {
+                        has: credit,
+                        need: amount,
+                    }
+                } else {
+                    DatabaseError::DynamoDB(error_str)
+                }
+            })?;
+
+        let remaining = Self::returned_balance(result.attributes)?;
+
+        tracing::info!(
+            address = %key,
+            added = credit,
+            deducted = amount,
+            remaining,
+            "Deposit credited and request deducted"
+        );
+
+        Ok(remaining)
+    }
+
+    async fn reserve_balance(
+        &self,
+        address: &str,
+        amount: f64,
+        timestamp: u64,
+    ) -> Result<Reservation, DatabaseError> {
+        let key = address.to_lowercase();
+        let id = ReservationId::new();
+
+        // Deduct and record the hold on the item in one conditional update
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("address", AttributeValue::S(key.clone()))
+            .update_expression("SET balance = balance - :amount, latest_timestamp = :ts, #hold = :amount")
+            .condition_expression("attribute_exists(balance) AND balance >= :amount")
+            .expression_attribute_names("#hold", Self::hold_attribute(&id))
+            .expression_attribute_values(":amount", AttributeValue::N(amount.to_string()))
+            .expression_attribute_values(":ts", AttributeValue::N(timestamp.to_string()))
+            .return_values(ReturnValue::AllNew)
+            .send()
+            .await
+            .map_err(|e| {
+                let error_str = e.to_string();
+                if error_str.contains("ConditionalCheckFailedException") {
+                    DatabaseError::InsufficientBalance {
+                        has: 0.0,
+                        need: amount,
+                    }
+                } else {
+                    DatabaseError::DynamoDB(error_str)
+                }
+            })?;
+
+        let remaining = Self::returned_balance(result.attributes)?;
+
+        tracing::debug!(address = %key, reservation = %id, held = amount, remaining, "Balance reserved");
+
+        Ok(Reservation { id, address: key, amount, remaining })
+    }
+
+    async fn commit_reservation(&self, reservation: &Reservation, cost: f64) -> Result<f64, DatabaseError> {
+        let refund = reservation.amount - cost.clamp(0.0, reservation.amount);
+
+        // Refund and clear the hold together; the condition makes a second settlement fail
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("address", AttributeValue::S(reservation.address.clone()))
+            .update_expression("SET balance = balance + :refund REMOVE #hold")
+            .condition_expression("#hold = :amount")
+            .expression_attribute_names("#hold", Self::hold_attribute(&reservation.id))
+            .expression_attribute_values(":refund", AttributeValue::N(refund.to_string()))
+            .expression_attribute_values(":amount", AttributeValue::N(reservation.amount.to_string()))
+            .return_values(ReturnValue::AllNew)
+            .send()
+            .await
+            .map_err(|e| {
+                let error_str = e.to_string();
+                if error_str.contains("ConditionalCheckFailedException") {
+                    DatabaseError::UnknownReservation(reservation.id.to_string())
+                } else {
+                    DatabaseError::DynamoDB(error_str)
+                }
+            })?;
+
+        let balance = Self::returned_balance(result.attributes)?;
+
+        tracing::debug!(
+            address = %reservation.address,
+            reservation = %reservation.id,
+            refund,
+            balance,
+            "Reservation settled"
+        );
+
+        Ok(balance)
+    }
+
+    async fn get_signed_timestamp(&self, address: &str) -> Result<Option<u64>, DatabaseError> {
+        let result = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("address", AttributeValue::S(address.to_lowercase()))
+            .projection_expression("signed_timestamp")
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(|e| DatabaseError::DynamoDB(e.to_string()))?;
+
+        Ok(result
+            .item
+            .and_then(|item| item.get("signed_timestamp").cloned())
+            .and_then(|v| v.as_n().ok().and_then(|n| n.parse::<u64>().ok())))
+    }
+
+    async fn advance_signed_timestamp(&self, address: &str, timestamp: u64) -> Result<bool, DatabaseError> {
+        let key = address.to_lowercase();
+
+        // Conditional update so concurrent gateways agree on which request came first;
+        // only the timestamp is written, so a fresh item doesn't open an account (and a new
+        // address keeps its free requests)
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("address", AttributeValue::S(key.clone()))
+            .update_expression("SET signed_timestamp = :ts")
+            .condition_expression("attribute_not_exists(signed_timestamp) OR signed_timestamp < :ts")
+            .expression_attribute_values(":ts", AttributeValue::N(timestamp.to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                let error_str = e.to_string();
+                if error_str.contains("ConditionalCheckFailedException") {
+                    tracing::debug!(address = %key, timestamp, "Signed timestamp not after the latest");
+                    Ok(false)
+                } else {
+                    Err(DatabaseError::DynamoDB(error_str))
+                }
+            }
+        }
+    }
+
+    async fn list_users(&self) -> Result<Vec<(String, UserData)>, DatabaseError> {
+        let mut users = Vec::new();
+        let mut exclusive_start_key = None;
+
+        // Scan is paginated; follow last_evaluated_key until exhausted
+        loop {
+            let result = self
+                .client
+                .scan()
+                .table_name(&self.table_name)
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await
+                .map_err(|e| DatabaseError::DynamoDB(e.to_string()))?;
+
+            for item in result.items().iter().filter(|item| Self::is_account(item)) {
+                let address = item
+                    .get("address")
+                    .and_then(|v| v.as_s().ok())
+                    .ok_or_else(|| DatabaseError::AttributeNotFound("address".to_string()))?;
+                users.push((address.clone(), Self::parse_user(item)?));
+            }
+
+            exclusive_start_key = result.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                break;
+            }
+        }
+
+        Ok(users)
+    }
+
+    async fn use_free_request(&self, address: &str, quota: u32, timestamp: u64) -> Result<bool, DatabaseError> {
+        let key = address.to_lowercase();
+
+        // Opens the account if there is none (an item holding only a signed timestamp is none);
+        // only accounts a free request opened have a quota
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("address", AttributeValue::S(key.clone()))
+            .update_expression(
+                "SET free_requests_used = if_not_exists(free_requests_used, :zero) + :one, \
+                 balance = if_not_exists(balance, :zero), latest_timestamp = :ts",
+            )
+            .condition_expression(
+                "attribute_not_exists(balance) OR (free_requests_used > :zero AND free_requests_used < :quota)",
+            )
+            .expression_attribute_values(":zero", AttributeValue::N("0".to_string()))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":quota", AttributeValue::N(quota.to_string()))
+            .expression_attribute_values(":ts", AttributeValue::N(timestamp.to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => {
+                tracing::debug!(address = %key, quota, "Free request used");
+                Ok(true)
+            }
+            Err(e) => {
+                let condition_failed = e
+                    .as_service_error()
+                    .is_some_and(|se| se.is_conditional_check_failed_exception());
+                if condition_failed {
+                    Ok(false)
+                } else {
+                    Err(DatabaseError::DynamoDB(e.to_string()))
+                }
+            }
+        }
+    }
+
+    async fn set_suspended(&self, address: &str, suspended: bool) -> Result<bool, DatabaseError> {
+        let key = address.to_lowercase();
+
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("address", AttributeValue::S(key.clone()))
+            .update_expression("SET suspended = :suspended")
+            .condition_expression("attribute_exists(balance)")
+            .expression_attribute_values(":suspended", AttributeValue::Bool(suspended))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => {
+                tracing::info!(address = %key, suspended, "Account suspension updated");
+                Ok(true)
+            }
+            Err(e) => {
+                let condition_failed = e
+                    .as_service_error()
+                    .is_some_and(|se| se.is_conditional_check_failed_exception());
+                if condition_failed {
+                    Ok(false)
+                } else {
+                    Err(DatabaseError::DynamoDB(e.to_string()))
+                }
+            }
+        }
+    }
+
+    async fn delete_user(&self, address: &str, stale_before: u64) -> Result<bool, DatabaseError> {
+        let key = address.to_lowercase();
+
+        // Conditional delete: a deposit, request or suspension since the scan keeps the account
+        let result = self
+            .client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("address", AttributeValue::S(key.clone()))
+            .condition_expression(
+                "balance <= :empty AND latest_timestamp < :stale_before \
+                 AND (attribute_not_exists(suspended) OR suspended = :not_suspended)",
+            )
+            .expression_attribute_values(":empty", AttributeValue::N(EMPTY_BALANCE_THRESHOLD.to_string()))
+            .expression_attribute_values(":not_suspended", AttributeValue::Bool(false))
+            .expression_attribute_values(":stale_before", AttributeValue::N(stale_before.to_string()))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => {
+                tracing::debug!(address = %key, "User deleted");
+                Ok(true)
+            }
+            Err(e) => {
+                let condition_failed = e
+                    .as_service_error()
+                    .is_some_and(|se| se.is_conditional_check_failed_exception());
+                if condition_failed {
+                    Ok(false)
+                } else {
+                    Err(DatabaseError::DynamoDB(e.to_string()))
+                }
+            }
+        }
+    }
+
+    async fn append_audit(&self, entry: &AuditEntry) -> Result<(), DatabaseError> {
+        let recorded_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+
+        self.client
+            .put_item()
+            .table_name(&self.audit_table_name)
+            .item("address", AttributeValue::S(entry.address.to_lowercase()))
+            .item("recorded_at", AttributeValue::N(recorded_at.to_string()))
+            .item("actor", AttributeValue::S(entry.actor.clone()))
+            .item("delta", AttributeValue::N(entry.delta.to_string()))
+            .item("reason", AttributeValue::S(entry.reason.clone()))
+            .item("timestamp", AttributeValue::N(entry.timestamp.to_string()))
+            .send()
+            .await
+            .map_err(|e| DatabaseError::DynamoDB(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn list_audit(&self, address: &str) -> Result<Vec<AuditEntry>, DatabaseError> {
+        let key = address.to_lowercase();
+        let mut entries = Vec::new();
+        let mut exclusive_start_key = None;
+
+        // Query is paginated; the sort key keeps entries oldest first
+        loop {
+            let result = self
+                .client
+                .query()
+                .table_name(&self.audit_table_name)
+                .key_condition_expression("address = :address")
+                .expression_attribute_values(":address", AttributeValue::S(key.clone()))
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await
+                .map_err(|e| DatabaseError::DynamoDB(e.to_string()))?;
+
+            for item in result.items() {
+                let string = |name: &str| {
+                    item.get(name)
+                        .and_then(|v| v.as_s().ok())
+                        .cloned()
+                        .ok_or_else(|| DatabaseError::AttributeNotFound(name.to_string()))
+                };
+                let number = |name: &str| {
+                    item.get(name)
+                        .and_then(|v| v.as_n().ok())
+                        .cloned()
+                        .ok_or_else(|| DatabaseError::AttributeNotFound(name.to_string()))
+                };
+
+                entries.push(AuditEntry {
+                    address: key.clone(),
+                    actor: string("actor")?,
+                    delta: number("delta")?
+                        .parse()
+                        .map_err(|_| DatabaseError::ParseError("delta".to_string()))?,
+                    reason: string("reason")?,
+                    timestamp: number("timestamp")?
+                        .parse()
+                        .map_err(|_| DatabaseError::ParseError("timestamp".to_string()))?,
+                });
+            }
+
+            exclusive_start_key = result.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                break;
+            }
+        }
+
+        Ok(entries)
+    }
+
+    async fn record_spend(&self, address: &str, amount: u64, timestamp: u64) -> Result<(), DatabaseError> {
+        // The sort key is the spend time in nanoseconds; the sub-second part of the time of
+        // recording keeps events in the same second apart
+        let recorded_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let spent_at = u128::from(timestamp) * 1_000_000_000 + u128::from(recorded_at.subsec_nanos());
+
+        self.client
+            .put_item()
+            .table_name(&self.spend_table_name)
+            .item("address", AttributeValue::S(address.to_lowercase()))
+            .item("spent_at", AttributeValue::N(spent_at.to_string()))
+            .item("amount", AttributeValue::N(amount.to_string()))
+            .send()
+            .await
+            .map_err(|e| DatabaseError::DynamoDB(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn spend_total(&self, address: &str, from_ts: u64, to_ts: u64) -> Result<u64, DatabaseError> {
+        if to_ts <= from_ts {
+            return Ok(0);
+        }
+        let from = u128::from(from_ts) * 1_000_000_000;
+        let to = u128::from(to_ts) * 1_000_000_000 - 1;
+        let mut total: u64 = 0;
+        let mut exclusive_start_key = None;
+
+        // Query is paginated; only the amounts are read
+        loop {
+            let result = self
+                .client
+                .query()
+                .table_name(&self.spend_table_name)
+                .key_condition_expression("address = :address AND spent_at BETWEEN :from AND :to")
+                .expression_attribute_values(":address", AttributeValue::S(address.to_lowercase()))
+                .expression_attribute_values(":from", AttributeValue::N(from.to_string()))
+                .expression_attribute_values(":to", AttributeValue::N(to.to_string()))
+                .projection_expression("amount")
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await
+                .map_err(|e| DatabaseError::DynamoDB(e.to_string()))?;
+
+            for item in result.items() {
+                let amount = item
+                    .get("amount")
+                    .and_then(|v| v.as_n().ok())
+                    .ok_or_else(|| DatabaseError::AttributeNotFound("amount".to_string()))?
+                    .parse::<u64>()
+                    .map_err(|_| DatabaseError::ParseError("amount".to_string()))?;
+                total = total.saturating_add(amount);
+            }
+
+            exclusive_start_key = result.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                break;
+            }
+        }
+
+        Ok(total)
+    }
+
+    async fn record_request(&self, address: &str, record: &RequestRecord, retention_secs: u64) -> Result<(), DatabaseError> {
+        self.client
+            .put_item()
+            .table_name(self.history_table()?)
+            .item("address", AttributeValue::S(address.to_lowercase()))
+            .item("position", AttributeValue::N(history_position(record.timestamp).to_string()))
+            .item("method", AttributeValue::S(record.method.clone()))
+            .item("price", AttributeValue::N(record.price.to_string()))
+            .item("timestamp", AttributeValue::N(record.timestamp.to_string()))
+            .item("success", AttributeValue::Bool(record.success))
+            .item("expires_at", AttributeValue::N(record.timestamp.saturating_add(retention_secs).to_string()))
+            .send()
+            .await
+            .map_err(|e| DatabaseError::DynamoDB(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn list_requests(
+        &self,
+        address: &str,
+        since: u64,
+        before: Option<u128>,
+        limit: usize,
+    ) -> Result<RequestPage, DatabaseError> {
+        // Time to live removes expired entries lazily, so `since` bounds the query as well
+        let from = u128::from(since) * NANOS_PER_SEC;
+        // Numbers are capped at 38 digits, so the open end is the largest real position
+        let to = before.map_or(MAX_HISTORY_POSITION, |before| before.saturating_sub(1));
+        if to < from || limit == 0 {
+            return Ok(RequestPage::default());
+        }
+
+        let result = self
+            .client
+            .query()
+            .table_name(self.history_table()?)
+            .key_condition_expression("address = :address AND #position BETWEEN :from AND :to")
+            .expression_attribute_names("#position", "position")
+            .expression_attribute_values(":address", AttributeValue::S(address.to_lowercase()))
+            .expression_attribute_values(":from", AttributeValue::N(from.to_string()))
+            .expression_attribute_values(":to", AttributeValue::N(to.to_string()))
+            .scan_index_forward(false)
+            .limit(i32::try_from(limit).unwrap_or(i32::MAX))
+            .send()
+            .await
+            .map_err(|e| DatabaseError::DynamoDB(e.to_string()))?;
+
+        let mut page = RequestPage::default();
+        let mut last_position = None;
+        for item in result.items() {
+            let (position, record) = Self::parse_request(item)?;
+            page.records.push(record);
+            last_position = Some(position);
+        }
+        if result.last_evaluated_key.is_some() {
+            page.next = last_position;
+        }
+
+        Ok(page)
+    }
+
+    async fn health_check(&self) -> Result<(), DatabaseError> {
+        // describe_table is cheap and fails on missing tables, bad credentials and throttling
+        self.client
+            .describe_table()
+            .table_name(&self.table_name)
+            .send()
+            .await
+            .map_err(|e| DatabaseError::DynamoDB(e.to_string()))?;
+
+        Ok(())
+    }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::spawn_server;
+    use aws_sdk_dynamodb::config::{BehaviorVersion, Credentials, Region};
+    use axum::{extract::State, http::{HeaderMap, StatusCode}, routing::post, Router};
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    /// Key counts of the BatchGetItem requests a mock table received
+    type Requests = Arc<Mutex<Vec<usize>>>;
+
+    /// Mock DynamoDB endpoint answering BatchGetItem for accounts holding their index as balance;
+    /// the first request leaves its first key unprocessed, as a throttled read would
+    async fn spawn_mock_table(accounts: Vec<String>) -> (DynamoDbDatabase, Requests) {
+        let requests: Requests = Arc::default();
+        let app = Router::new()
+            .route(
+                "/",
+                post(|State((accounts, requests)): State<(Arc<Vec<String>>, Requests)>, headers: HeaderMap, body: String| async move {
+                    let target = headers.get("x-amz-target").unwrap().to_str().unwrap();
+                    assert_eq!(target, "DynamoDB_20120810.BatchGetItem");
+                    let request: Value = serde_json::from_str(&body).unwrap();
+                    let keys = request["RequestItems"]["balances"]["Keys"].as_array().unwrap().clone();
+                    assert_eq!(request["RequestItems"]["balances"]["ConsistentRead"], true);
+
+                    let first = {
+                        let mut requests = requests.lock().unwrap();
+                        requests.push(keys.len());
+                        requests.len() == 1
+                    };
+                    let (unprocessed, keys) = if first { keys.split_at(1) } else { keys.split_at(0) };
+
+                    let items: Vec<Value> = keys
+                        .iter()
+                        .filter_map(|key| {
+                            let address = key["address"]["S"].as_str().unwrap();
+                            let index = accounts.iter().position(|account| account == address)?;
+                            Some(json!({
+                                "address": {"S": address},
+                                "balance": {"N": index.to_string()},
+                                "latest_timestamp": {"N": "0"},
+                            }))
+                        })
+                        .collect();
+                    let unprocessed = if unprocessed.is_empty() {
+                        json!({})
+                    } else {
+                        json!({"balances": {"Keys": unprocessed, "ConsistentRead": true}})
+                    };
+                    (
+                        [("content-type", "application/x-amz-json-1.0")],
+                        json!({"Responses": {"balances": items}, "UnprocessedKeys": unprocessed}).to_string(),
+                    )
+                }),
+            )
+            .with_state((Arc::new(accounts), requests.clone()));
+        let url = spawn_server(app).await;
+
+        let config = aws_sdk_dynamodb::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .endpoint_url(url)
+            .build();
+        let database = DynamoDbDatabase::from_client(
+            Client::from_conf(config),
+            "balances".to_string(),
+            "balances-audit".to_string(),
+            "balances-spend".to_string(),
+        );
+        (database, requests)
+    }
+
+    #[tokio::test]
+    async fn test_get_user_batch_chunks_and_preserves_order() {
+        let address = |i: usize| format!("0x{:040x}", i);
+        let accounts: Vec<String> = (0..150).map(address).collect();
+        let (database, requests) = spawn_mock_table(accounts).await;
+
+        // Existing accounts interleaved with missing ones, in reverse order, with a duplicate
+        let mut addresses: Vec<String> = (0..250).rev().map(address).collect();
+        addresses.push(address(7).to_uppercase().replace("0X", "0x"));
+        let users = database.get_user_batch(&addresses).await.unwrap();
+
+        assert_eq!(users.len(), addresses.len());
+        for (i, (address, user)) in users.iter().enumerate() {
+            assert_eq!(address, &addresses[i]);
+            let index = usize::from_str_radix(&address[2..], 16).unwrap();
+            match user {
+                Some(user) => assert_eq!(user.balance, index as f64),
+                None => assert!(index >= 150, "{} should exist", address),
+            }
+        }
+
+        // 250 unique keys in chunks of 100, plus a retry of the key left unprocessed
+        assert_eq!(*requests.lock().unwrap(), vec![100, 1, 100, 50]);
+    }
+
+    /// Key schemas of the tables a mock endpoint has created, and how often each was described
+    type Tables = Arc<Mutex<HashMap<String, (Value, usize)>>>;
+
+    /// Mock DynamoDB endpoint starting with no tables; a created table is CREATING the first
+    /// time it is described and ACTIVE after that
+    async fn spawn_mock_tables() -> (DynamoDbDatabase, Tables) {
+        let tables: Tables = Arc::default();
+        let app = Router::new()
+            .route(
+                "/",
+                post(|State(tables): State<Tables>, headers: HeaderMap, body: String| async move {
+                    let target = headers.get("x-amz-target").unwrap().to_str().unwrap().to_string();
+                    let request: Value = serde_json::from_str(&body).unwrap();
+                    let name = request["TableName"].as_str().unwrap().to_string();
+                    let mut tables = tables.lock().unwrap();
+
+                    let reply = match (target.as_str(), tables.get_mut(&name)) {
+                        ("DynamoDB_20120810.CreateTable", _) => {
+                            tables.insert(name.clone(), (request["KeySchema"].clone(), 0));
+                            Ok(json!({"TableDescription": {"TableName": name, "TableStatus": "CREATING"}}))
+                        }
+                        ("DynamoDB_20120810.DescribeTable", Some((_, described))) => {
+                            *described += 1;
+                            let status = if *described == 1 { "CREATING" } else { "ACTIVE" };
+                            Ok(json!({"Table": {"TableName": name, "TableStatus": status}}))
+                        }
+                        ("DynamoDB_20120810.GetItem", Some((_, described))) if *described > 1 => Ok(json!({})),
+                        _ => Err("ResourceNotFoundException"),
+                    };
+
+                    let content_type = [("content-type", "application/x-amz-json-1.0")];
+                    match reply {
+                        Ok(reply) => (StatusCode::OK, content_type, reply.to_string()),
+                        Err(error) => (
+                            StatusCode::BAD_REQUEST,
+                            content_type,
+                            json!({"__type": format!("com.amazonaws.dynamodb.v20120810#{}", error), "message": name}).to_string(),
+                        ),
+                    }
+                }),
+            )
+            .with_state(tables.clone());
+        let url = spawn_server(app).await;
+
+        let config = aws_sdk_dynamodb::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .endpoint_url(url)
+            .build();
+        let database = DynamoDbDatabase::from_client(
+            Client::from_conf(config),
+            "balances".to_string(),
+            "balances-audit".to_string(),
+            "balances-spend".to_string(),
+        );
+        (database, tables)
+    }
+
+    #[tokio::test]
+    async fn test_missing_tables_created_and_usable() {
+        let (database, tables) = spawn_mock_tables().await;
+        let address = "0x00000000000000000000000000000000000000aa";
+
+        // Missing tables are an error unless creating them is allowed
+        let error = database.wait_for_tables(false, Duration::from_secs(5)).await.unwrap_err();
+        assert!(error.to_string().contains("table balances does not exist"), "{}", error);
+        assert!(database.get_user(address).await.is_err());
+
+        database.wait_for_tables(true, Duration::from_secs(10)).await.unwrap();
+        {
+            let tables = tables.lock().unwrap();
+            let key_schema = |table: &str| tables[table].0.clone();
+            assert_eq!(key_schema("balances"), json!([{"AttributeName": "address", "KeyType": "HASH"}]));
+            assert_eq!(
+                key_schema("balances-audit"),
+                json!([{"AttributeName": "address", "KeyType": "HASH"}, {"AttributeName": "recorded_at", "KeyType": "RANGE"}])
+            );
+            assert_eq!(
+                key_schema("balances-spend"),
+                json!([{"AttributeName": "address", "KeyType": "HASH"}, {"AttributeName": "spent_at", "KeyType": "RANGE"}])
+            );
+        }
+
+        // Once active the tables serve requests
+        assert!(database.get_user(address).await.unwrap().is_none());
+    }
+
+    /// Mock DynamoDB endpoint holding one item, updated only by setting signed timestamps
+    async fn spawn_mock_item() -> DynamoDbDatabase {
+        let item: Arc<Mutex<serde_json::Map<String, Value>>> = Arc::default();
+        let app = Router::new()
+            .route(
+                "/",
+                post(|State(item): State<Arc<Mutex<serde_json::Map<String, Value>>>>, headers: HeaderMap, body: String| async move {
+                    let target = headers.get("x-amz-target").unwrap().to_str().unwrap().to_string();
+                    let request: Value = serde_json::from_str(&body).unwrap();
+                    let mut item = item.lock().unwrap();
+
+                    let reply = match target.as_str() {
+                        "DynamoDB_20120810.UpdateItem" => {
+                            assert_eq!(request["UpdateExpression"], "SET signed_timestamp = :ts");
+                            item.insert("address".to_string(), request["Key"]["address"].clone());
+                            item.insert("signed_timestamp".to_string(), request["ExpressionAttributeValues"][":ts"].clone());
+                            json!({})
+                        }
+                        "DynamoDB_20120810.GetItem" => json!({"Item": (*item).clone()}),
+                        "DynamoDB_20120810.Scan" => json!({"Items": [(*item).clone()], "Count": 1, "ScannedCount": 1}),
+                        other => panic!("unexpected request {}", other),
+                    };
+                    (StatusCode::OK, [("content-type", "application/x-amz-json-1.0")], reply.to_string())
+                }),
+            )
+            .with_state(item);
+        let url = spawn_server(app).await;
+
+        let config = aws_sdk_dynamodb::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .endpoint_url(url)
+            .build();
+        DynamoDbDatabase::from_client(
+            Client::from_conf(config),
+            "balances".to_string(),
+            "balances-audit".to_string(),
+            "balances-spend".to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_signed_timestamp_does_not_open_account() {
+        let database = spawn_mock_item().await;
+        let address = "0x00000000000000000000000000000000000000aa";
+
+        // Under monotonic timestamps the timestamp is recorded before any account exists;
+        // the address must still read as new, so it keeps its free requests
+        assert!(database.advance_signed_timestamp(address, 5).await.unwrap());
+        assert_eq!(database.get_signed_timestamp(address).await.unwrap(), Some(5));
+        assert!(database.get_user(address).await.unwrap().is_none());
+        assert!(database.list_users().await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_open_history_bound_fits_dynamodb_number() {
+        // DynamoDB rejects numbers with more than 38 significant digits
+        assert!(MAX_HISTORY_POSITION.to_string().len() <= 38);
+        assert!(MAX_HISTORY_POSITION >= history_position(u64::MAX));
+    }
+}
@@ -1,5 +1,7 @@
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
 pub mod rocksdb;
@@ -24,8 +26,23 @@ pub enum DatabaseError {
 
     #[error("Parse error: {0}")]
     ParseError(String),
+
+    #[error("Reservation {0} is not held (already settled or never made)")]
+    UnknownReservation(String),
+
+    #[error("Account limit of {0} reached, no new accounts can be opened")]
+    AccountLimitReached(u64),
 }
 
+/// Balances at or below this are treated as empty (absorbs floating point dust)
+pub const EMPTY_BALANCE_THRESHOLD: f64 = 1e-9;
+
+/// Version byte leading each serialized user record
+const USER_RECORD_VERSION: u8 = 2;
+
+/// Length of a record written before records were versioned: balance (f64) and timestamp (u64)
+const UNVERSIONED_USER_RECORD_LEN: usize = 16;
+
 /// User account data stored in database
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserData {
@@ -33,6 +50,25 @@ pub struct UserData {
     pub balance: f64,
     /// Last successful request timestamp (unix seconds)
     pub latest_timestamp: u64,
+    /// Suspended by an admin: requests are refused but the balance is kept
+    pub suspended: bool,
+    /// Free requests made, for accounts opened by one (see `free_requests`)
+    pub free_requests_used: u32,
+}
+
+/// User record layout before versioning
+#[derive(Deserialize)]
+struct UnversionedUserData {
+    balance: f64,
+    latest_timestamp: u64,
+}
+
+/// User record layout of version 1, before free requests were counted
+#[derive(Deserialize)]
+struct UserDataV1 {
+    balance: f64,
+    latest_timestamp: u64,
+    suspended: bool,
 }
 
 impl UserData {
@@ -40,15 +76,153 @@ impl UserData {
         Self {
             balance,
             latest_timestamp: timestamp,
+            suspended: false,
+            free_requests_used: 0,
+        }
+    }
+
+    /// Whether the account is empty, not suspended and hasn't made a request since `stale_before`
+    /// (a suspension must outlive the balance, so suspended accounts are never stale)
+    pub fn is_stale(&self, stale_before: u64) -> bool {
+        self.balance <= EMPTY_BALANCE_THRESHOLD && self.latest_timestamp < stale_before && !self.suspended
+    }
+
+    /// Serialize for storage, led by the record version
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DatabaseError> {
+        let mut bytes = vec![USER_RECORD_VERSION];
+        bincode::serialize_into(&mut bytes, self).map_err(|e| DatabaseError::Serialization(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    /// Deserialize a stored record of any version, unversioned ones included
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DatabaseError> {
+        let serialization = |e: bincode::Error| DatabaseError::Serialization(e.to_string());
+        if bytes.len() == UNVERSIONED_USER_RECORD_LEN {
+            let user: UnversionedUserData = bincode::deserialize(bytes).map_err(serialization)?;
+            return Ok(Self::new(user.balance, user.latest_timestamp));
+        }
+        match bytes.split_first() {
+            Some((&USER_RECORD_VERSION, record)) => bincode::deserialize(record).map_err(serialization),
+            Some((1, record)) => {
+                let user: UserDataV1 = bincode::deserialize(record).map_err(serialization)?;
+                Ok(Self { suspended: user.suspended, ..Self::new(user.balance, user.latest_timestamp) })
+            }
+            Some((version, _)) => Err(DatabaseError::Serialization(format!("unknown user record version {}", version))),
+            None => Err(DatabaseError::Serialization("empty user record".to_string())),
         }
     }
 }
 
+/// Read consistency for user lookups
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsistencyLevel {
+    /// Reflects every completed write (used wherever a balance is about to change)
+    Strong,
+    /// May lag recent writes slightly; cheaper and served by replicas (display-only reads)
+    Eventual,
+}
+
+/// Identifies a balance reservation; unique across gateway instances
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ReservationId(String);
+
+impl ReservationId {
+    fn new() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        Self(format!("{:x}-{:x}-{:x}", nanos, std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)))
+    }
+}
+
+impl std::fmt::Display for ReservationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Funds held from a balance while the request they pay for is relayed
+#[derive(Debug, Clone)]
+pub struct Reservation {
+    pub id: ReservationId,
+    /// Account the funds are held from (lowercase)
+    pub address: String,
+    /// Amount held in USDC
+    pub amount: f64,
+    /// Balance left after the hold
+    pub remaining: f64,
+}
+
+/// Manual balance adjustment recorded in the audit ledger
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Address whose balance was adjusted
+    pub address: String,
+    /// Who made the adjustment
+    pub actor: String,
+    /// Signed amount applied to the balance in USDC
+    pub delta: f64,
+    /// Why the adjustment was made (e.g. refund for a failed settlement)
+    pub reason: String,
+    /// When the adjustment was made (unix seconds)
+    pub timestamp: u64,
+}
+
+/// Relayed request recorded in an account's request history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestRecord {
+    /// JSON-RPC method called (see `jsonrpc::method_summary`)
+    pub method: String,
+    /// Amount charged in USDC
+    pub price: f64,
+    /// When the request was relayed (unix seconds)
+    pub timestamp: u64,
+    /// Whether the node answered successfully
+    pub success: bool,
+}
+
+/// Nanoseconds in a second, the unit request history positions are kept in
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Largest position `history_position` can produce, the last nanosecond of `u64::MAX` seconds
+const MAX_HISTORY_POSITION: u128 = (u64::MAX as u128 + 1) * NANOS_PER_SEC - 1;
+
+/// Position of a request history entry: the request time in nanoseconds, with the sub-second
+/// part taken from the time of recording to keep requests made in the same second apart
+fn history_position(timestamp: u64) -> u128 {
+    let recorded_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    u128::from(timestamp) * NANOS_PER_SEC + u128::from(recorded_at.subsec_nanos())
+}
+
+/// Page of an account's request history, newest first
+#[derive(Debug, Clone, Default)]
+pub struct RequestPage {
+    pub records: Vec<RequestRecord>,
+    /// Cursor for the next (older) page, None on the last page
+    pub next: Option<u128>,
+}
+
 /// Database trait for persistent user data storage
 #[async_trait]
 pub trait DatabaseTrait: Send + Sync {
-    /// Get user data by address
-    async fn get_user(&self, address: &str) -> Result<Option<UserData>, DatabaseError>;
+    /// Get user data by address (strongly consistent)
+    async fn get_user(&self, address: &str) -> Result<Option<UserData>, DatabaseError> {
+        self.get_user_consistent(address, ConsistencyLevel::Strong).await
+    }
+
+    /// Get user data by address with the given read consistency
+    async fn get_user_consistent(
+        &self,
+        address: &str,
+        consistency: ConsistencyLevel,
+    ) -> Result<Option<UserData>, DatabaseError>;
+
+    /// Get many users in one round trip (strongly consistent), in the order of `addresses`
+    async fn get_user_batch(&self, addresses: &[String]) -> Result<Vec<(String, Option<UserData>)>, DatabaseError>;
 
     /// Update user data
     async fn update_user(&self, address: &str, data: UserData) -> Result<(), DatabaseError>;
@@ -65,5 +239,97 @@ pub trait DatabaseTrait: Send + Sync {
         amount: f64,
         timestamp: u64,
     ) -> Result<f64, DatabaseError>;
+
+    /// Credit a deposit and deduct the price of the request it came with in one atomic write,
+    /// updating the timestamp. Fails with nothing credited if the credited balance can't cover `amount`
+    /// Returns the remaining balance
+    async fn deposit_and_deduct(
+        &self,
+        address: &str,
+        credit: f64,
+        amount: f64,
+        timestamp: u64,
+    ) -> Result<f64, DatabaseError>;
+
+    /// Hold `amount` from the balance and update the timestamp, failing if the balance can't cover it
+    async fn reserve_balance(
+        &self,
+        address: &str,
+        amount: f64,
+        timestamp: u64,
+    ) -> Result<Reservation, DatabaseError>;
+
+    /// Settle a reservation at `cost` (capped at the amount held), refunding the rest
+    /// Each reservation can be settled once; returns the resulting balance
+    async fn commit_reservation(&self, reservation: &Reservation, cost: f64) -> Result<f64, DatabaseError>;
+
+    /// Release a reservation, refunding everything held
+    async fn cancel_reservation(&self, reservation: &Reservation) -> Result<f64, DatabaseError> {
+        self.commit_reservation(reservation, 0.0).await
+    }
+
+    /// Count a free request against the quota of `quota` free requests for new accounts,
+    /// updating the timestamp. An unknown address is opened as an account with a quota;
+    /// returns false (changing nothing) for accounts opened otherwise or whose quota is used up
+    async fn use_free_request(&self, address: &str, quota: u32, timestamp: u64) -> Result<bool, DatabaseError>;
+
+    /// Whether crediting an address would be accepted: false if it would open a new account
+    /// past the configured account cap
+    async fn accepts_credit(&self, _address: &str) -> Result<bool, DatabaseError> {
+        Ok(true)
+    }
+
+    /// Latest signed request time recorded for an address (None if none was recorded)
+    async fn get_signed_timestamp(&self, address: &str) -> Result<Option<u64>, DatabaseError>;
+
+    /// Record `timestamp` as the latest signed request time for an address if it is later
+    /// than the one recorded before; returns false (recording nothing) for equal or older times
+    async fn advance_signed_timestamp(&self, address: &str, timestamp: u64) -> Result<bool, DatabaseError>;
+
+    /// List all user accounts (for maintenance tasks)
+    async fn list_users(&self) -> Result<Vec<(String, UserData)>, DatabaseError>;
+
+    /// Suspend or reinstate an account, keeping its balance
+    /// Returns false (changing nothing) if the account doesn't exist
+    async fn set_suspended(&self, address: &str, suspended: bool) -> Result<bool, DatabaseError>;
+
+    /// Delete a user account if it is still stale (empty balance, last request before
+    /// `stale_before`) at the time of deletion
+    /// Returns whether the account was deleted
+    async fn delete_user(&self, address: &str, stale_before: u64) -> Result<bool, DatabaseError>;
+
+    /// Append a manual adjustment to the audit ledger
+    async fn append_audit(&self, entry: &AuditEntry) -> Result<(), DatabaseError>;
+
+    /// Audit ledger entries for an address, oldest first
+    async fn list_audit(&self, address: &str) -> Result<Vec<AuditEntry>, DatabaseError>;
+
+    /// Record that an address was charged `amount` smallest USDC units at `timestamp` (unix seconds)
+    async fn record_spend(&self, address: &str, amount: u64, timestamp: u64) -> Result<(), DatabaseError>;
+
+    /// Smallest USDC units an address was charged from `from_ts` up to, but not including, `to_ts`
+    async fn spend_total(&self, address: &str, from_ts: u64, to_ts: u64) -> Result<u64, DatabaseError>;
+
+    /// Record a relayed request in an address's history; entries older than `retention_secs`
+    /// before it are dropped (or left to expire)
+    async fn record_request(&self, address: &str, record: &RequestRecord, retention_secs: u64) -> Result<(), DatabaseError>;
+
+    /// Up to `limit` history entries for an address recorded from `since` (unix seconds) and
+    /// before the `before` cursor of a previous page, newest first
+    async fn list_requests(
+        &self,
+        address: &str,
+        since: u64,
+        before: Option<u128>,
+        limit: usize,
+    ) -> Result<RequestPage, DatabaseError>;
+
+    /// Write out any buffered updates (called on shutdown, after in-flight work has drained)
+    async fn flush(&self) -> Result<(), DatabaseError> {
+        Ok(())
+    }
+
+    /// Check that the database is reachable (used by the readiness probe)
+    async fn health_check(&self) -> Result<(), DatabaseError>;
 }
 
@@ -1,17 +1,67 @@
-use super::{DatabaseError, DatabaseTrait, UserData};
+use super::{
+    history_position, AuditEntry, ConsistencyLevel, DatabaseError, DatabaseTrait, RequestPage, RequestRecord, Reservation,
+    ReservationId, UserData, NANOS_PER_SEC,
+};
+use crate::config::RocksDbConfig;
 use async_trait::async_trait;
-use rocksdb::{Options, DB};
-use std::sync::Arc;
+use rocksdb::{
+    ColumnFamily, ColumnFamilyDescriptor, DBCompressionType, Direction, IteratorMode, Options, WriteBatch,
+    WriteOptions, DB,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Column family holding user balances, kept small so balance reads stay fast
+const BALANCES_CF: &str = "balances";
+
+/// Column family holding the audit ledger, kept apart from user balances (compressed: it only grows)
+const AUDIT_CF: &str = "audit";
+
+/// Column family holding spend events keyed by address, then time, so a period is one range scan
+const SPENDS_CF: &str = "spends";
+
+/// Column family holding request history keyed by address, then request time (compressed: it only grows)
+const HISTORY_CF: &str = "history";
+
+/// Column family holding each address's latest signed request timestamp (big-endian u64)
+const SIGNED_TIMESTAMPS_CF: &str = "signed_timestamps";
+
+/// Column family holding bookkeeping about the other column families
+const META_CF: &str = "meta";
+
+/// Key in the meta column family holding the number of accounts (big-endian u64)
+const ACCOUNT_COUNT_KEY: &[u8] = b"account_count";
+
+/// Coalesced balance updates not yet written, keyed by (lowercase) address
+type Pending = HashMap<String, UserData>;
 
 /// RocksDB implementation of DatabaseTrait
 #[derive(Clone)]
 pub struct RocksDbDatabase {
     db: Arc<DB>,
+    write_opts: Arc<WriteOptions>,
+    /// Serializes read-modify-write cycles and guards coalesced updates
+    write_lock: Arc<PendingWrites>,
+    /// Whether deductions are buffered and flushed in batches
+    coalesce: bool,
+    /// Number of writes issued to RocksDB (a batch counts once)
+    writes: Arc<AtomicU64>,
+    /// Reservations not yet settled (held funds are simply kept if the process restarts)
+    holds: Arc<Mutex<HashSet<ReservationId>>>,
+    /// Most accounts credits may open (unlimited if unset)
+    max_accounts: Option<u64>,
 }
 
 impl RocksDbDatabase {
-    /// Open or create a RocksDB database at the specified path
+    /// Open or create a RocksDB database at the specified path with default settings
     pub fn open(path: &str) -> Result<Self, DatabaseError> {
+        Self::open_with_config(path, &RocksDbConfig::default())
+    }
+
+    /// Open or create a RocksDB database with the given durability settings
+    pub fn open_with_config(path: &str, config: &RocksDbConfig) -> Result<Self, DatabaseError> {
         // Create parent directories if they don't exist
         if let Some(parent) = std::path::Path::new(path).parent() {
             std::fs::create_dir_all(parent).ok();
@@ -19,40 +69,367 @@ impl RocksDbDatabase {
 
         let mut opts = Options::default();
         opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        opts.set_use_fsync(config.use_fsync);
+        opts.set_write_buffer_size(config.write_buffer_size_mb * 1024 * 1024);
 
-        let db = DB::open(&opts, path)
+        let mut write_opts = WriteOptions::default();
+        write_opts.set_sync(config.sync_writes);
+        write_opts.disable_wal(config.disable_wal);
+
+        let column_family = |name: &str, compression: Option<DBCompressionType>| {
+            let mut cf_opts = opts.clone();
+            if let Some(compression) = compression {
+                cf_opts.set_compression_type(compression);
+            }
+            ColumnFamilyDescriptor::new(name, cf_opts)
+        };
+        let column_families = [
+            column_family(BALANCES_CF, None),
+            column_family(AUDIT_CF, Some(DBCompressionType::Zstd)),
+            column_family(SIGNED_TIMESTAMPS_CF, None),
+            column_family(SPENDS_CF, Some(DBCompressionType::Zstd)),
+            column_family(HISTORY_CF, Some(DBCompressionType::Zstd)),
+            column_family(META_CF, None),
+        ];
+        let db = DB::open_cf_descriptors(&opts, path, column_families)
             .map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
 
-        tracing::info!(path = %path, "RocksDB opened successfully");
+        let migrated = migrate_default_cf(&db, &write_opts)?;
+        if migrated > 0 {
+            tracing::info!(path = %path, accounts = migrated, "Moved balances into their own column family");
+        }
 
-        Ok(Self { db: Arc::new(db) })
+        let accounts = init_account_count(&db, &write_opts)?;
+
+        tracing::info!(
+            path = %path,
+            use_fsync = config.use_fsync,
+            sync_writes = config.sync_writes,
+            disable_wal = config.disable_wal,
+            write_buffer_size_mb = config.write_buffer_size_mb,
+            accounts,
+            max_accounts = config.max_accounts,
+            "RocksDB opened successfully"
+        );
+
+        let db = Arc::new(db);
+        let write_opts = Arc::new(write_opts);
+        let writes = Arc::new(AtomicU64::new(0));
+        let flusher = Flusher { db: db.clone(), write_opts: write_opts.clone(), writes: writes.clone() };
+
+        let database = Self {
+            db,
+            write_opts,
+            write_lock: Arc::new(PendingWrites { pending: Mutex::new(Pending::new()), flusher }),
+            coalesce: config.coalesce_window_ms.is_some(),
+            writes,
+            holds: Arc::new(Mutex::new(HashSet::new())),
+            max_accounts: config.max_accounts,
+        };
+
+        if let Some(window_ms) = config.coalesce_window_ms {
+            tracing::info!(window_ms, "Coalescing balance deductions");
+            database.spawn_flusher(Duration::from_millis(window_ms));
+        }
+
+        Ok(database)
+    }
+
+    /// Flush coalesced updates every `window` until the last handle is dropped
+    /// (dropping it flushes whatever is still pending)
+    fn spawn_flusher(&self, window: Duration) {
+        let writes: Weak<PendingWrites> = Arc::downgrade(&self.write_lock);
+
+        std::thread::spawn(move || loop {
+            std::thread::sleep(window);
+            let Some(writes) = writes.upgrade() else {
+                break;
+            };
+            let mut pending = writes.pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Err(e) = writes.flusher.flush(&mut pending) {
+                tracing::error!(error = %e, "Failed to flush coalesced balance updates");
+            }
+        });
     }
 }
 
-#[async_trait]
-impl DatabaseTrait for RocksDbDatabase {
-    async fn get_user(&self, address: &str) -> Result<Option<UserData>, DatabaseError> {
-        let key = address.to_lowercase();
+/// Balances column family of an open database
+fn balances_cf(db: &DB) -> &ColumnFamily {
+    db.cf_handle(BALANCES_CF).expect("Balances column family is created at open")
+}
+
+/// Databases written before balances had their own column family keep them in the default one;
+/// move them over in a single batch. Returns the number of accounts moved
+fn migrate_default_cf(db: &DB, write_opts: &WriteOptions) -> Result<usize, DatabaseError> {
+    let mut batch = WriteBatch::default();
+    for entry in db.iterator(IteratorMode::Start) {
+        let (key, value) = entry.map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
+        batch.put_cf(balances_cf(db), &key, &value);
+        batch.delete(&key);
+    }
+
+    // Each account is a put and a delete
+    let moved = batch.len() / 2;
+    if moved > 0 {
+        db.write_opt(batch, write_opts)
+            .map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
+    }
+    Ok(moved)
+}
+
+/// Number of accounts stored, counting them once if the database predates the count
+fn init_account_count(db: &DB, write_opts: &WriteOptions) -> Result<u64, DatabaseError> {
+    let meta = db.cf_handle(META_CF).expect("Meta column family is created at open");
+    if let Some(value) = db.get_cf(meta, ACCOUNT_COUNT_KEY).map_err(|e| DatabaseError::RocksDB(e.to_string()))? {
+        return decode_account_count(&value);
+    }
+
+    let mut accounts = 0;
+    for entry in db.iterator_cf(balances_cf(db), IteratorMode::Start) {
+        entry.map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
+        accounts += 1;
+    }
+    db.put_cf_opt(meta, ACCOUNT_COUNT_KEY, u64::to_be_bytes(accounts), write_opts)
+        .map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
+    Ok(accounts)
+}
+
+fn decode_account_count(value: &[u8]) -> Result<u64, DatabaseError> {
+    let bytes = value.try_into()
+        .map_err(|_| DatabaseError::Serialization("Invalid account count".to_string()))?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// What's needed to write coalesced updates, shareable with the flusher thread
+struct Flusher {
+    db: Arc<DB>,
+    write_opts: Arc<WriteOptions>,
+    writes: Arc<AtomicU64>,
+}
+
+impl Flusher {
+    /// Write all pending updates in a single batch
+    /// Updates stay pending if the write fails so they're retried on the next flush
+    fn flush(&self, pending: &mut Pending) -> Result<(), DatabaseError> {
+        self.flush_with(pending, WriteBatch::default())
+    }
+
+    /// Write all pending updates in the same batch as `batch`
+    fn flush_with(&self, pending: &mut Pending, mut batch: WriteBatch) -> Result<(), DatabaseError> {
+        if pending.is_empty() && batch.is_empty() {
+            return Ok(());
+        }
+
+        for (key, data) in pending.iter() {
+            let value = data.to_bytes()?;
+            batch.put_cf(balances_cf(&self.db), key.as_bytes(), value);
+        }
 
-        match self.db.get(key.as_bytes())
+        self.db.write_opt(batch, &self.write_opts)
+            .map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
+        self.writes.fetch_add(1, Ordering::Relaxed);
+
+        tracing::debug!(updates = pending.len(), "Flushed coalesced balance updates");
+        pending.clear();
+
+        Ok(())
+    }
+}
+
+/// Coalesced updates shared by every handle, written out when the last one is dropped
+struct PendingWrites {
+    pending: Mutex<Pending>,
+    flusher: Flusher,
+}
+
+impl Drop for PendingWrites {
+    fn drop(&mut self) {
+        let pending = self.pending.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(e) = self.flusher.flush(pending) {
+            tracing::error!(error = %e, updates = pending.len(), "Failed to flush coalesced balance updates on close");
+        }
+    }
+}
+
+impl RocksDbDatabase {
+    /// Read a user record by its (lowercase) key, preferring a coalesced update not yet written
+    fn read_user(&self, pending: &Pending, key: &str) -> Result<Option<UserData>, DatabaseError> {
+        if let Some(user_data) = pending.get(key) {
+            return Ok(Some(user_data.clone()));
+        }
+
+        match self.db.get_cf(balances_cf(&self.db), key.as_bytes())
             .map_err(|e| DatabaseError::RocksDB(e.to_string()))?
         {
             Some(bytes) => {
-                let user_data: UserData = bincode::deserialize(&bytes)
-                    .map_err(|e| DatabaseError::Serialization(e.to_string()))?;
+                let user_data = UserData::from_bytes(&bytes)?;
                 Ok(Some(user_data))
             }
             None => Ok(None),
         }
     }
 
+    /// Write a user record by its (lowercase) key
+    /// Any coalesced updates are written in the same batch so nothing older overwrites it later
+    fn write_user(&self, pending: &mut Pending, key: &str, data: &UserData) -> Result<(), DatabaseError> {
+        self.write_account(pending, key, data, false)
+    }
+
+    /// Write a user record, counting it in the same batch if it opens a new account
+    fn write_account(&self, pending: &mut Pending, key: &str, data: &UserData, opens_account: bool) -> Result<(), DatabaseError> {
+        let mut batch = WriteBatch::default();
+        if opens_account {
+            batch.put_cf(self.meta_cf(), ACCOUNT_COUNT_KEY, u64::to_be_bytes(self.account_count()? + 1));
+        }
+        let previous = pending.insert(key.to_string(), data.clone());
+
+        let result = self.flusher().flush_with(pending, batch);
+        if result.is_err() {
+            // The caller sees the failure, so this update must not surface or be retried later
+            match previous {
+                Some(previous) => pending.insert(key.to_string(), previous),
+                None => pending.remove(key),
+            };
+        }
+        result
+    }
+
+    /// Buffer a deduction when coalescing, otherwise write it straight away
+    fn write_deduction(&self, pending: &mut Pending, key: &str, data: &UserData) -> Result<(), DatabaseError> {
+        if self.coalesce {
+            pending.insert(key.to_string(), data.clone());
+            Ok(())
+        } else {
+            self.write_user(pending, key, data)
+        }
+    }
+
+    fn flusher(&self) -> &Flusher {
+        &self.write_lock.flusher
+    }
+
+    fn meta_cf(&self) -> &ColumnFamily {
+        self.db.cf_handle(META_CF).expect("Meta column family is created at open")
+    }
+
+    /// Number of accounts stored
+    fn account_count(&self) -> Result<u64, DatabaseError> {
+        match self.db.get_cf(self.meta_cf(), ACCOUNT_COUNT_KEY)
+            .map_err(|e| DatabaseError::RocksDB(e.to_string()))?
+        {
+            Some(value) => decode_account_count(&value),
+            None => Ok(0),
+        }
+    }
+
+    /// Refuse to open another account once `max_accounts` exist
+    fn check_account_cap(&self) -> Result<(), DatabaseError> {
+        match self.max_accounts {
+            Some(max) if self.account_count()? >= max => Err(DatabaseError::AccountLimitReached(max)),
+            _ => Ok(()),
+        }
+    }
+
+    fn audit_cf(&self) -> &ColumnFamily {
+        self.db.cf_handle(AUDIT_CF).expect("Audit column family is created at open")
+    }
+
+    fn spends_cf(&self) -> &ColumnFamily {
+        self.db.cf_handle(SPENDS_CF).expect("Spends column family is created at open")
+    }
+
+    fn history_cf(&self) -> &ColumnFamily {
+        self.db.cf_handle(HISTORY_CF).expect("History column family is created at open")
+    }
+
+    /// Add deletions of an address's history entries positioned before `end` to `batch`
+    fn delete_history_before(&self, batch: &mut WriteBatch, address: &str, end: &str) -> Result<(), DatabaseError> {
+        let start = format!("{}:", address);
+        let mode = IteratorMode::From(start.as_bytes(), Direction::Forward);
+        for entry in self.db.iterator_cf(self.history_cf(), mode) {
+            let (key, _) = entry.map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
+            if key.as_ref() >= end.as_bytes() {
+                break;
+            }
+            batch.delete_cf(self.history_cf(), key);
+        }
+        Ok(())
+    }
+
+    fn signed_timestamps_cf(&self) -> &ColumnFamily {
+        self.db
+            .cf_handle(SIGNED_TIMESTAMPS_CF)
+            .expect("Signed timestamps column family is created at open")
+    }
+
+    /// Latest signed request timestamp stored for a (lowercase) key
+    fn read_signed_timestamp(&self, key: &str) -> Result<Option<u64>, DatabaseError> {
+        self.db.get_cf(self.signed_timestamps_cf(), key.as_bytes())
+            .map_err(|e| DatabaseError::RocksDB(e.to_string()))?
+            .map(|value| {
+                let bytes = value.try_into()
+                    .map_err(|_| DatabaseError::Serialization("Invalid signed timestamp".to_string()))?;
+                Ok(u64::from_be_bytes(bytes))
+            })
+            .transpose()
+    }
+
+    /// Serialize read-modify-write cycles so concurrent updates aren't lost
+    fn lock(&self) -> MutexGuard<'_, Pending> {
+        self.write_lock.pending.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Number of writes issued to RocksDB so far
+    #[cfg(test)]
+    fn write_count(&self) -> u64 {
+        self.writes.load(Ordering::Relaxed)
+    }
+}
+
+#[async_trait]
+impl DatabaseTrait for RocksDbDatabase {
+    async fn get_user_consistent(
+        &self,
+        address: &str,
+        _consistency: ConsistencyLevel,
+    ) -> Result<Option<UserData>, DatabaseError> {
+        // A single local instance always sees its own writes
+        let pending = self.lock();
+        self.read_user(&pending, &address.to_lowercase())
+    }
+
+    async fn get_user_batch(&self, addresses: &[String]) -> Result<Vec<(String, Option<UserData>)>, DatabaseError> {
+        let pending = self.lock();
+        let keys: Vec<String> = addresses.iter().map(|address| address.to_lowercase()).collect();
+
+        // Coalesced updates not yet flushed are newer than what's stored
+        let unflushed: Vec<&String> = keys.iter().filter(|key| !pending.contains_key(*key)).collect();
+        let balances = balances_cf(&self.db);
+        let stored = self.db.multi_get_cf(unflushed.iter().map(|key| (balances, key.as_bytes())));
+
+        let mut found = HashMap::new();
+        for (key, value) in unflushed.into_iter().zip(stored) {
+            if let Some(bytes) = value.map_err(|e| DatabaseError::RocksDB(e.to_string()))? {
+                let user_data = UserData::from_bytes(&bytes)?;
+                found.insert(key.as_str(), user_data);
+            }
+        }
+
+        Ok(addresses
+            .iter()
+            .zip(&keys)
+            .map(|(address, key)| (address.clone(), pending.get(key).or(found.get(key.as_str())).cloned()))
+            .collect())
+    }
+
     async fn update_user(&self, address: &str, data: UserData) -> Result<(), DatabaseError> {
         let key = address.to_lowercase();
-        let value = bincode::serialize(&data)
-            .map_err(|e| DatabaseError::Serialization(e.to_string()))?;
+        let mut pending = self.lock();
 
-        self.db.put(key.as_bytes(), value)
-            .map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
+        // Direct writes (maintenance, self-test probes) may exceed the cap but are still counted
+        let opens_account = self.read_user(&pending, &key)?.is_none();
+        self.write_account(&mut pending, &key, &data, opens_account)?;
 
         tracing::debug!(
             address = %key,
@@ -66,14 +443,19 @@ impl DatabaseTrait for RocksDbDatabase {
 
     async fn add_balance(&self, address: &str, amount: f64) -> Result<f64, DatabaseError> {
         let key = address.to_lowercase();
+        let mut pending = self.lock();
 
-        let mut user_data = self.get_user(&key).await?.unwrap_or_else(|| {
-            UserData::new(0.0, 0)
-        });
+        let existing = self.read_user(&pending, &key)?;
+        let opens_account = existing.is_none();
+        if opens_account {
+            self.check_account_cap()?;
+        }
+        let mut user_data = existing.unwrap_or_else(|| UserData::new(0.0, 0));
 
         user_data.balance += amount;
 
-        self.update_user(&key, user_data.clone()).await?;
+        // Credits are never coalesced: a settled deposit must survive a crash
+        self.write_account(&mut pending, &key, &user_data, opens_account)?;
 
         tracing::info!(
             address = %key,
@@ -92,10 +474,11 @@ impl DatabaseTrait for RocksDbDatabase {
         timestamp: u64,
     ) -> Result<f64, DatabaseError> {
         let key = address.to_lowercase();
+        let mut pending = self.lock();
 
-        let mut user_data = self.get_user(&key).await?.unwrap_or_else(|| {
-            UserData::new(0.0, 0)
-        });
+        let existing = self.read_user(&pending, &key)?;
+        let opens_account = existing.is_none();
+        let mut user_data = existing.unwrap_or_else(|| UserData::new(0.0, 0));
 
         if user_data.balance < amount {
             return Err(DatabaseError::InsufficientBalance {
@@ -107,7 +490,13 @@ impl DatabaseTrait for RocksDbDatabase {
         user_data.balance -= amount;
         user_data.latest_timestamp = timestamp;
 
-        self.update_user(&key, user_data.clone()).await?;
+        // A free request from an unknown address opens an account: counted, but not capped
+        // since no deposit is involved
+        if opens_account {
+            self.write_account(&mut pending, &key, &user_data, true)?;
+        } else {
+            self.write_deduction(&mut pending, &key, &user_data)?;
+        }
 
         tracing::debug!(
             address = %key,
@@ -118,6 +507,341 @@ impl DatabaseTrait for RocksDbDatabase {
 
         Ok(user_data.balance)
     }
+
+    async fn deposit_and_deduct(
+        &self,
+        address: &str,
+        credit: f64,
+        amount: f64,
+        timestamp: u64,
+    ) -> Result<f64, DatabaseError> {
+        let key = address.to_lowercase();
+        let mut pending = self.lock();
+
+        let existing = self.read_user(&pending, &key)?;
+        let opens_account = existing.is_none();
+        if opens_account {
+            self.check_account_cap()?;
+        }
+        let mut user_data = existing.unwrap_or_else(|| UserData::new(0.0, 0));
+
+        if user_data.balance + credit < amount {
+            return Err(DatabaseError::InsufficientBalance {
+                has: user_data.balance + credit,
+                need: amount,
+            });
+        }
+
+        user_data.balance += credit - amount;
+        user_data.latest_timestamp = timestamp;
+
+        // Carries a credit, so never coalesced
+        self.write_account(&mut pending, &key, &user_data, opens_account)?;
+
+        tracing::info!(
+            address = %key,
+            added = credit,
+            deducted = amount,
+            remaining = user_data.balance,
+            "Deposit credited and request deducted"
+        );
+
+        Ok(user_data.balance)
+    }
+
+    async fn reserve_balance(
+        &self,
+        address: &str,
+        amount: f64,
+        timestamp: u64,
+    ) -> Result<Reservation, DatabaseError> {
+        let remaining = self.deduct_balance(address, amount, timestamp).await?;
+        let reservation = Reservation {
+            id: ReservationId::new(),
+            address: address.to_lowercase(),
+            amount,
+            remaining,
+        };
+        self.holds
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(reservation.id.clone());
+
+        Ok(reservation)
+    }
+
+    async fn commit_reservation(&self, reservation: &Reservation, cost: f64) -> Result<f64, DatabaseError> {
+        let held = self.holds
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .remove(&reservation.id);
+        if !held {
+            return Err(DatabaseError::UnknownReservation(reservation.id.to_string()));
+        }
+
+        let refund = reservation.amount - cost.clamp(0.0, reservation.amount);
+        if refund <= 0.0 {
+            let pending = self.lock();
+            let user_data = self.read_user(&pending, &reservation.address)?;
+            return Ok(user_data.map_or(0.0, |user| user.balance));
+        }
+
+        self.add_balance(&reservation.address, refund).await
+    }
+
+    async fn accepts_credit(&self, address: &str) -> Result<bool, DatabaseError> {
+        let pending = self.lock();
+        if self.read_user(&pending, &address.to_lowercase())?.is_some() {
+            return Ok(true);
+        }
+
+        match self.check_account_cap() {
+            Ok(()) => Ok(true),
+            Err(DatabaseError::AccountLimitReached(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get_signed_timestamp(&self, address: &str) -> Result<Option<u64>, DatabaseError> {
+        self.read_signed_timestamp(&address.to_lowercase())
+    }
+
+    async fn advance_signed_timestamp(&self, address: &str, timestamp: u64) -> Result<bool, DatabaseError> {
+        let key = address.to_lowercase();
+        let _pending = self.lock();
+
+        let latest = self.read_signed_timestamp(&key)?;
+        if latest.is_some_and(|latest| timestamp <= latest) {
+            return Ok(false);
+        }
+
+        self.db.put_cf_opt(self.signed_timestamps_cf(), key.as_bytes(), timestamp.to_be_bytes(), &self.write_opts)
+            .map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
+        self.writes.fetch_add(1, Ordering::Relaxed);
+
+        Ok(true)
+    }
+
+    async fn list_users(&self) -> Result<Vec<(String, UserData)>, DatabaseError> {
+        let mut users = Vec::new();
+
+        // Write out coalesced updates so the scan sees current balances
+        self.flusher().flush(&mut self.lock())?;
+
+        for entry in self.db.iterator_cf(balances_cf(&self.db), IteratorMode::Start) {
+            let (key, value) = entry.map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
+            let user_data = UserData::from_bytes(&value)?;
+            users.push((String::from_utf8_lossy(&key).into_owned(), user_data));
+        }
+
+        Ok(users)
+    }
+
+    async fn use_free_request(&self, address: &str, quota: u32, timestamp: u64) -> Result<bool, DatabaseError> {
+        let key = address.to_lowercase();
+        let mut pending = self.lock();
+
+        let existing = self.read_user(&pending, &key)?;
+        let opens_account = existing.is_none();
+        if opens_account && self.check_account_cap().is_err() {
+            return Ok(false);
+        }
+        let mut user_data = existing.unwrap_or_else(|| UserData::new(0.0, 0));
+
+        // Only accounts a free request opened have a quota
+        if (!opens_account && user_data.free_requests_used == 0) || user_data.free_requests_used >= quota {
+            return Ok(false);
+        }
+
+        user_data.free_requests_used += 1;
+        user_data.latest_timestamp = timestamp;
+        self.write_account(&mut pending, &key, &user_data, opens_account)?;
+
+        tracing::debug!(address = %key, used = user_data.free_requests_used, quota, "Free request used");
+
+        Ok(true)
+    }
+
+    async fn set_suspended(&self, address: &str, suspended: bool) -> Result<bool, DatabaseError> {
+        let key = address.to_lowercase();
+        let mut pending = self.lock();
+
+        let Some(mut user_data) = self.read_user(&pending, &key)? else {
+            return Ok(false);
+        };
+        user_data.suspended = suspended;
+        self.write_user(&mut pending, &key, &user_data)?;
+
+        tracing::info!(address = %key, suspended, "Account suspension updated");
+
+        Ok(true)
+    }
+
+    async fn delete_user(&self, address: &str, stale_before: u64) -> Result<bool, DatabaseError> {
+        let key = address.to_lowercase();
+        let mut pending = self.lock();
+
+        // Re-check under the lock: a deposit or request since the scan keeps the account
+        let Some(user_data) = self.read_user(&pending, &key)? else {
+            return Ok(false);
+        };
+
+        if !user_data.is_stale(stale_before) {
+            return Ok(false);
+        }
+
+        let mut batch = WriteBatch::default();
+        batch.delete_cf(balances_cf(&self.db), key.as_bytes());
+        batch.put_cf(self.meta_cf(), ACCOUNT_COUNT_KEY, u64::to_be_bytes(self.account_count()?.saturating_sub(1)));
+        // History only expires as requests are recorded, so it goes with the account
+        self.delete_history_before(&mut batch, &key, &format!("{};", key))?;
+        self.db.write_opt(batch, &self.write_opts)
+            .map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
+        pending.remove(&key);
+
+        tracing::debug!(address = %key, "User deleted");
+
+        Ok(true)
+    }
+
+    async fn append_audit(&self, entry: &AuditEntry) -> Result<(), DatabaseError> {
+        // Keys sort by address, then time of recording
+        let recorded_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let key = format!("{}:{:039}", entry.address.to_lowercase(), recorded_at);
+
+        let value = bincode::serialize(entry)
+            .map_err(|e| DatabaseError::Serialization(e.to_string()))?;
+
+        self.db.put_cf_opt(self.audit_cf(), key.as_bytes(), value, &self.write_opts)
+            .map_err(|e| DatabaseError::RocksDB(e.to_string()))
+    }
+
+    async fn list_audit(&self, address: &str) -> Result<Vec<AuditEntry>, DatabaseError> {
+        let prefix = format!("{}:", address.to_lowercase());
+        let mut entries = Vec::new();
+
+        let mode = IteratorMode::From(prefix.as_bytes(), Direction::Forward);
+        for entry in self.db.iterator_cf(self.audit_cf(), mode) {
+            let (key, value) = entry.map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            entries.push(
+                bincode::deserialize(&value)
+                    .map_err(|e| DatabaseError::Serialization(e.to_string()))?,
+            );
+        }
+
+        Ok(entries)
+    }
+
+    async fn record_spend(&self, address: &str, amount: u64, timestamp: u64) -> Result<(), DatabaseError> {
+        // Keys sort by address, then spend time; the time of recording keeps them unique
+        let recorded_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let key = format!("{}:{:020}:{:039}", address.to_lowercase(), timestamp, recorded_at);
+
+        self.db.put_cf_opt(self.spends_cf(), key.as_bytes(), amount.to_be_bytes(), &self.write_opts)
+            .map_err(|e| DatabaseError::RocksDB(e.to_string()))
+    }
+
+    async fn spend_total(&self, address: &str, from_ts: u64, to_ts: u64) -> Result<u64, DatabaseError> {
+        let address = address.to_lowercase();
+        let start = format!("{}:{:020}", address, from_ts);
+        let end = format!("{}:{:020}", address, to_ts);
+        let mut total: u64 = 0;
+
+        let mode = IteratorMode::From(start.as_bytes(), Direction::Forward);
+        for entry in self.db.iterator_cf(self.spends_cf(), mode) {
+            let (key, value) = entry.map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
+            if key.as_ref() >= end.as_bytes() {
+                break;
+            }
+            let bytes = value.as_ref().try_into()
+                .map_err(|_| DatabaseError::Serialization("Invalid spend amount".to_string()))?;
+            total = total.saturating_add(u64::from_be_bytes(bytes));
+        }
+
+        Ok(total)
+    }
+
+    async fn record_request(&self, address: &str, record: &RequestRecord, retention_secs: u64) -> Result<(), DatabaseError> {
+        // Keys sort by address, then position (request time in nanoseconds)
+        let address = address.to_lowercase();
+        let key = format!("{}:{:039}", address, history_position(record.timestamp));
+        let value = bincode::serialize(record)
+            .map_err(|e| DatabaseError::Serialization(e.to_string()))?;
+
+        // Entries past retention lead the address's range and are dropped by the same write
+        let mut batch = WriteBatch::default();
+        batch.put_cf(self.history_cf(), key.as_bytes(), value);
+        let cutoff = u128::from(record.timestamp.saturating_sub(retention_secs)) * NANOS_PER_SEC;
+        self.delete_history_before(&mut batch, &address, &format!("{}:{:039}", address, cutoff))?;
+
+        self.db.write_opt(batch, &self.write_opts)
+            .map_err(|e| DatabaseError::RocksDB(e.to_string()))
+    }
+
+    async fn list_requests(
+        &self,
+        address: &str,
+        since: u64,
+        before: Option<u128>,
+        limit: usize,
+    ) -> Result<RequestPage, DatabaseError> {
+        let address = address.to_lowercase();
+        let floor = format!("{}:{:039}", address, u128::from(since) * NANOS_PER_SEC);
+        // ';' follows ':', so without a cursor the scan starts past the address's last entry
+        let start = match before {
+            Some(before) => format!("{}:{:039}", address, before),
+            None => format!("{};", address),
+        };
+        let mut page = RequestPage::default();
+        let mut last_position = None;
+
+        let mode = IteratorMode::From(start.as_bytes(), Direction::Reverse);
+        for entry in self.db.iterator_cf(self.history_cf(), mode) {
+            let (key, value) = entry.map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
+            if key.as_ref() < floor.as_bytes() {
+                break;
+            }
+            // The cursor is the last entry of the previous page
+            if key.as_ref() >= start.as_bytes() {
+                continue;
+            }
+            if page.records.len() == limit {
+                page.next = last_position;
+                break;
+            }
+            let position = std::str::from_utf8(&key[address.len() + 1..])
+                .ok()
+                .and_then(|position| position.parse::<u128>().ok())
+                .ok_or_else(|| DatabaseError::Serialization("Invalid history key".to_string()))?;
+            let record = bincode::deserialize(&value)
+                .map_err(|e| DatabaseError::Serialization(e.to_string()))?;
+            page.records.push(record);
+            last_position = Some(position);
+        }
+
+        Ok(page)
+    }
+
+    async fn flush(&self) -> Result<(), DatabaseError> {
+        self.flusher().flush(&mut self.lock())
+    }
+
+    async fn health_check(&self) -> Result<(), DatabaseError> {
+        // A trivial read proves the database handle is usable
+        self.db.get_cf(balances_cf(&self.db), b"__health_check")
+            .map_err(|e| DatabaseError::RocksDB(e.to_string()))?;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -155,5 +879,401 @@ mod tests {
         let result = db.deduct_balance(address, 10.0, 1234567891).await;
         assert!(result.is_err());
     }
-}
 
+    #[tokio::test]
+    async fn test_deposit_and_deduct_is_one_write() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = RocksDbDatabase::open(db_path.to_str().unwrap()).unwrap();
+        let address = "0x1234567890ABCDEF1234567890abcdef12345678";
+
+        let writes = db.write_count();
+        let remaining = db.deposit_and_deduct(address, 1.0, 0.001, 100).await.unwrap();
+        assert_eq!(remaining, 1.0 - 0.001);
+        assert_eq!(db.write_count(), writes + 1);
+
+        let user = db.get_user(address).await.unwrap().unwrap();
+        assert_eq!(user.balance, 1.0 - 0.001);
+        assert_eq!(user.latest_timestamp, 100);
+
+        // A deposit that can't cover the request credits nothing
+        let result = db.deposit_and_deduct(address, 0.5, 2.0, 101).await;
+        assert!(matches!(result, Err(DatabaseError::InsufficientBalance { .. })));
+        assert_eq!(db.get_user(address).await.unwrap().unwrap().balance, 1.0 - 0.001);
+    }
+
+    #[tokio::test]
+    async fn test_get_user_batch_preserves_order() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let config = RocksDbConfig { coalesce_window_ms: Some(60_000), ..RocksDbConfig::default() };
+        let db = RocksDbDatabase::open_with_config(db_path.to_str().unwrap(), &config).unwrap();
+
+        let stored = "0x000000000000000000000000000000000000000a";
+        let coalesced = "0x000000000000000000000000000000000000000b";
+        let missing = "0x000000000000000000000000000000000000000c";
+        db.add_balance(stored, 1.0).await.unwrap();
+        db.add_balance(coalesced, 2.0).await.unwrap();
+        db.deduct_balance(coalesced, 0.5, 100).await.unwrap();
+
+        let addresses = vec![
+            missing.to_string(),
+            coalesced.to_string(),
+            stored.to_uppercase().replace("0X", "0x"),
+        ];
+        let users = db.get_user_batch(&addresses).await.unwrap();
+
+        assert_eq!(users.len(), 3);
+        assert_eq!(users[0].0, missing);
+        assert!(users[0].1.is_none());
+        assert_eq!(users[1].0, coalesced);
+        assert_eq!(users[1].1.as_ref().unwrap().balance, 1.5);
+        assert_eq!(users[2].0, addresses[2]);
+        assert_eq!(users[2].1.as_ref().unwrap().balance, 1.0);
+        assert!(db.get_user_batch(&[]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_spend_total_sums_spends_in_period() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = RocksDbDatabase::open(temp_dir.path().join("test.db").to_str().unwrap()).unwrap();
+        let address = "0x00000000000000000000000000000000000000aa";
+        let other = "0x00000000000000000000000000000000000000bb";
+
+        for (amount, timestamp) in [(100, 1_000), (250, 1_500), (250, 1_500), (400, 2_000)] {
+            db.record_spend(address, amount, timestamp).await.unwrap();
+        }
+        db.record_spend(other, 999, 1_500).await.unwrap();
+
+        assert_eq!(db.spend_total(address, 0, u64::MAX).await.unwrap(), 1_000);
+        // The start of a period is included and its end is not
+        assert_eq!(db.spend_total(address, 1_000, 2_000).await.unwrap(), 600);
+        assert_eq!(db.spend_total(address, 1_500, 2_001).await.unwrap(), 900);
+        assert_eq!(db.spend_total(&address.to_uppercase().replace("0X", "0x"), 1_001, 1_501).await.unwrap(), 500);
+        assert_eq!(db.spend_total(address, 2_001, 3_000).await.unwrap(), 0);
+        assert_eq!(db.spend_total(other, 0, u64::MAX).await.unwrap(), 999);
+    }
+
+    #[tokio::test]
+    async fn test_request_history_paginated_and_pruned() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = RocksDbDatabase::open(temp_dir.path().join("test.db").to_str().unwrap()).unwrap();
+        let address = "0x00000000000000000000000000000000000000aa";
+        let other = "0x00000000000000000000000000000000000000bb";
+        let record = |method: &str, timestamp| RequestRecord {
+            method: method.to_string(),
+            price: 0.001,
+            timestamp,
+            success: true,
+        };
+
+        for (method, timestamp) in [("eth_chainId", 1_000), ("eth_call", 1_500), ("eth_getLogs", 1_500), ("eth_blockNumber", 2_000)] {
+            db.record_request(address, &record(method, timestamp), 3_600).await.unwrap();
+        }
+        db.record_request(other, &record("eth_gasPrice", 1_500), 3_600).await.unwrap();
+
+        // Newest first, a page at a time
+        let first = db.list_requests(address, 0, None, 3).await.unwrap();
+        let methods: Vec<&str> = first.records.iter().map(|record| record.method.as_str()).collect();
+        assert_eq!(methods, ["eth_blockNumber", "eth_getLogs", "eth_call"]);
+        let second = db.list_requests(address, 0, first.next, 3).await.unwrap();
+        assert_eq!(second.records, [record("eth_chainId", 1_000)]);
+        assert!(second.next.is_none());
+
+        // Entries from before `since` aren't listed
+        assert_eq!(db.list_requests(address, 1_001, None, 10).await.unwrap().records.len(), 3);
+
+        // Recording drops entries past retention
+        db.record_request(address, &record("eth_chainId", 5_000), 3_600).await.unwrap();
+        let page = db.list_requests(address, 0, None, 10).await.unwrap();
+        let timestamps: Vec<u64> = page.records.iter().map(|record| record.timestamp).collect();
+        assert_eq!(timestamps, [5_000, 2_000, 1_500, 1_500]);
+        assert_eq!(db.list_requests(other, 0, None, 10).await.unwrap().records.len(), 1);
+    }
+
+    #[test]
+    fn test_user_records_versioned() {
+        let mut user = UserData::new(2.5, 100);
+        user.suspended = true;
+        let decoded = UserData::from_bytes(&user.to_bytes().unwrap()).unwrap();
+        assert_eq!((decoded.balance, decoded.latest_timestamp, decoded.suspended), (2.5, 100, true));
+
+        // Records from before versioning read as not suspended
+        let unversioned = bincode::serialize(&(2.5f64, 100u64)).unwrap();
+        let decoded = UserData::from_bytes(&unversioned).unwrap();
+        assert_eq!((decoded.balance, decoded.latest_timestamp, decoded.suspended), (2.5, 100, false));
+
+        assert!(UserData::from_bytes(&[9; 18]).is_err());
+
+        // Version 1 records have used no free requests
+        let mut v1 = vec![1];
+        bincode::serialize_into(&mut v1, &(2.5f64, 100u64, true)).unwrap();
+        let decoded = UserData::from_bytes(&v1).unwrap();
+        assert_eq!((decoded.balance, decoded.suspended, decoded.free_requests_used), (2.5, true, 0));
+    }
+
+    #[tokio::test]
+    async fn test_free_requests_only_for_new_accounts() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = RocksDbDatabase::open(temp_dir.path().join("test.db").to_str().unwrap()).unwrap();
+        let new = "0x000000000000000000000000000000000000000a";
+        let funded = "0x000000000000000000000000000000000000000b";
+
+        assert!(db.use_free_request(new, 2, 100).await.unwrap());
+        assert!(db.use_free_request(new, 2, 101).await.unwrap());
+        assert!(!db.use_free_request(new, 2, 102).await.unwrap());
+        let user = db.get_user(new).await.unwrap().unwrap();
+        assert_eq!((user.free_requests_used, user.latest_timestamp, user.balance), (2, 101, 0.0));
+        assert_eq!(db.account_count().unwrap(), 1);
+
+        // An account opened by a deposit never had a quota
+        db.add_balance(funded, 1.0).await.unwrap();
+        assert!(!db.use_free_request(funded, 2, 100).await.unwrap());
+        assert_eq!(db.get_user(funded).await.unwrap().unwrap().free_requests_used, 0);
+    }
+
+    #[tokio::test]
+    async fn test_records_land_in_their_column_families() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let address = "0x1234567890abcdef1234567890abcdef12345678";
+
+        // A database from before balances had their own column family (and records a version)
+        let legacy = (2.5f64, 100u64);
+        {
+            let mut opts = Options::default();
+            opts.create_if_missing(true);
+            opts.create_missing_column_families(true);
+            let db = DB::open_cf(&opts, &db_path, [AUDIT_CF]).unwrap();
+            db.put(address, bincode::serialize(&legacy).unwrap()).unwrap();
+        }
+
+        let db = RocksDbDatabase::open(db_path.to_str().unwrap()).unwrap();
+        let user = db.get_user(address).await.unwrap().unwrap();
+        assert_eq!((user.balance, user.latest_timestamp), (2.5, 100));
+        assert_eq!(db.list_users().await.unwrap().len(), 1);
+        assert!(db.db.get(address).unwrap().is_none());
+
+        // New writes go to the balances and audit column families, never the default one
+        db.deduct_balance(address, 0.5, 101).await.unwrap();
+        db.append_audit(&AuditEntry {
+            address: address.to_string(),
+            actor: "admin".to_string(),
+            delta: 1.0,
+            reason: "refund".to_string(),
+            timestamp: 101,
+        })
+        .await
+        .unwrap();
+        assert!(db.db.get_cf(balances_cf(&db.db), address).unwrap().is_some());
+        assert_eq!(db.db.iterator_cf(db.audit_cf(), IteratorMode::Start).count(), 1);
+        assert_eq!(db.db.iterator(IteratorMode::Start).count(), 0);
+        assert_eq!(db.list_audit(address).await.unwrap().len(), 1);
+
+        // Reopening finds nothing left to migrate and keeps the balance
+        drop(db);
+        let db = RocksDbDatabase::open(db_path.to_str().unwrap()).unwrap();
+        assert_eq!(db.get_user(address).await.unwrap().unwrap().balance, 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_reservation_lifecycle() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = RocksDbDatabase::open(db_path.to_str().unwrap()).unwrap();
+        let address = "0x1234567890ABCDEF1234567890abcdef12345678";
+        db.add_balance(address, 10.0).await.unwrap();
+
+        // Committing charges the actual cost and refunds the rest of the hold
+        let reservation = db.reserve_balance(address, 4.0, 100).await.unwrap();
+        assert_eq!(reservation.remaining, 6.0);
+        assert_eq!(db.get_user(address).await.unwrap().unwrap().balance, 6.0);
+        assert_eq!(db.commit_reservation(&reservation, 1.5).await.unwrap(), 8.5);
+        assert!(matches!(
+            db.commit_reservation(&reservation, 0.0).await,
+            Err(DatabaseError::UnknownReservation(_))
+        ));
+
+        // Cancelling refunds everything held
+        let reservation = db.reserve_balance(address, 8.0, 101).await.unwrap();
+        assert_eq!(reservation.remaining, 0.5);
+        assert_eq!(db.cancel_reservation(&reservation).await.unwrap(), 8.5);
+        assert_eq!(db.get_user(address).await.unwrap().unwrap().latest_timestamp, 101);
+
+        // Nothing is held when the balance can't cover the reservation
+        match db.reserve_balance(address, 9.0, 102).await {
+            Err(DatabaseError::InsufficientBalance { has, need }) => assert_eq!((has, need), (8.5, 9.0)),
+            other => panic!("expected insufficient balance, got {:?}", other),
+        }
+        assert_eq!(db.get_user(address).await.unwrap().unwrap().balance, 8.5);
+    }
+
+    #[tokio::test]
+    async fn test_signed_timestamps_only_advance() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = RocksDbDatabase::open(db_path.to_str().unwrap()).unwrap();
+        let address = "0x1234567890ABCDEF1234567890abcdef12345678";
+
+        assert_eq!(db.get_signed_timestamp(address).await.unwrap(), None);
+        assert!(db.advance_signed_timestamp(address, 100).await.unwrap());
+        assert!(db.advance_signed_timestamp(address, 101).await.unwrap());
+        assert_eq!(db.get_signed_timestamp(address).await.unwrap(), Some(101));
+
+        // Equal and older timestamps are rejected, whatever the address spelling
+        assert!(!db.advance_signed_timestamp(address, 101).await.unwrap());
+        assert!(!db.advance_signed_timestamp(&address.to_lowercase(), 100).await.unwrap());
+        assert!(db.advance_signed_timestamp(&address.to_lowercase(), 102).await.unwrap());
+
+        // Addresses advance independently, and signed timestamps aren't user accounts
+        assert!(db.advance_signed_timestamp("0xabc", 1).await.unwrap());
+        assert!(db.list_users().await.unwrap().is_empty());
+
+        // The latest timestamp survives a restart
+        drop(db);
+        let db = RocksDbDatabase::open(db_path.to_str().unwrap()).unwrap();
+        assert!(!db.advance_signed_timestamp(address, 102).await.unwrap());
+        assert!(db.advance_signed_timestamp(address, 103).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_open_with_durability_settings() {
+        let settings = [
+            RocksDbConfig::default(),
+            RocksDbConfig { use_fsync: true, sync_writes: true, ..Default::default() },
+            RocksDbConfig { disable_wal: true, write_buffer_size_mb: 8, ..Default::default() },
+        ];
+
+        for (i, config) in settings.iter().enumerate() {
+            let temp_dir = tempfile::tempdir().unwrap();
+            let db_path = temp_dir.path().join(format!("test-{}.db", i));
+            let db = RocksDbDatabase::open_with_config(db_path.to_str().unwrap(), config).unwrap();
+
+            let address = "0x1234567890abcdef1234567890abcdef12345678";
+            assert_eq!(db.add_balance(address, 1.5).await.unwrap(), 1.5);
+            assert_eq!(db.get_user(address).await.unwrap().unwrap().balance, 1.5);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_only_removes_stale_empty_accounts() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = RocksDbDatabase::open(db_path.to_str().unwrap()).unwrap();
+
+        let stale = "0x000000000000000000000000000000000000000a";
+        let active = "0x000000000000000000000000000000000000000b";
+        let funded = "0x000000000000000000000000000000000000000c";
+        db.update_user(stale, UserData::new(0.0, 100)).await.unwrap();
+        db.update_user(active, UserData::new(0.0, 5_000)).await.unwrap();
+        db.update_user(funded, UserData::new(2.0, 100)).await.unwrap();
+
+        assert_eq!(db.list_users().await.unwrap().len(), 3);
+
+        assert!(db.delete_user(stale, 1_000).await.unwrap());
+        assert!(!db.delete_user(active, 1_000).await.unwrap());
+        assert!(!db.delete_user(funded, 1_000).await.unwrap());
+
+        assert!(db.get_user(stale).await.unwrap().is_none());
+        assert!(db.get_user(active).await.unwrap().is_some());
+        assert!(db.get_user(funded).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_account_cap_refuses_only_new_accounts() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let config = RocksDbConfig { max_accounts: Some(2), ..Default::default() };
+        let db = RocksDbDatabase::open_with_config(db_path.to_str().unwrap(), &config).unwrap();
+
+        let first = "0x000000000000000000000000000000000000000a";
+        let second = "0x000000000000000000000000000000000000000b";
+        let third = "0x000000000000000000000000000000000000000c";
+        db.add_balance(first, 1.0).await.unwrap();
+        db.deposit_and_deduct(second, 0.01, 0.01, 100).await.unwrap();
+        assert_eq!(db.account_count().unwrap(), 2);
+
+        // At the cap, new accounts are refused by every credit
+        assert!(matches!(db.add_balance(third, 1.0).await, Err(DatabaseError::AccountLimitReached(2))));
+        assert!(matches!(db.deposit_and_deduct(third, 1.0, 0.1, 100).await, Err(DatabaseError::AccountLimitReached(2))));
+        assert!(db.get_user(third).await.unwrap().is_none());
+        assert!(!db.accepts_credit(third).await.unwrap());
+
+        // Existing accounts keep depositing and transacting
+        assert!(db.accepts_credit(first).await.unwrap());
+        assert_eq!(db.add_balance(first, 1.0).await.unwrap(), 2.0);
+        assert_eq!(db.deduct_balance(first, 0.5, 100).await.unwrap(), 1.5);
+        assert_eq!(db.account_count().unwrap(), 2);
+
+        // Sweeping a stale account makes room, and the count survives a restart
+        assert!(db.delete_user(second, 1_000).await.unwrap());
+        drop(db);
+        let db = RocksDbDatabase::open_with_config(db_path.to_str().unwrap(), &config).unwrap();
+        assert_eq!(db.account_count().unwrap(), 1);
+        assert_eq!(db.add_balance(third, 1.0).await.unwrap(), 1.0);
+        assert!(!db.accepts_credit(second).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_coalescing_reduces_writes_without_losing_deductions() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let address = "0x1234567890abcdef1234567890abcdef12345678";
+        let burst = 500;
+
+        let mut writes = Vec::new();
+        for coalesce_window_ms in [None, Some(50)] {
+            let db_path = temp_dir.path().join(format!("test-{:?}.db", coalesce_window_ms));
+            let config = RocksDbConfig { coalesce_window_ms, ..Default::default() };
+            let db = RocksDbDatabase::open_with_config(db_path.to_str().unwrap(), &config).unwrap();
+
+            db.add_balance(address, burst as f64).await.unwrap();
+            for i in 0..burst {
+                db.deduct_balance(address, 1.0, i).await.unwrap();
+            }
+
+            // Reads see buffered deductions immediately
+            assert_eq!(db.get_user(address).await.unwrap().unwrap().balance, 0.0);
+            assert!(db.deduct_balance(address, 1.0, burst).await.is_err());
+
+            // ...and they reach RocksDB once the window passes
+            tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+            let stored = db.db.get_cf(balances_cf(&db.db), address).unwrap().unwrap();
+            let stored = UserData::from_bytes(&stored).unwrap();
+            assert_eq!(stored.balance, 0.0);
+            assert_eq!(stored.latest_timestamp, burst - 1);
+
+            writes.push(db.write_count());
+        }
+
+        assert_eq!(writes[0], burst + 1);
+        assert!(writes[1] <= 3, "coalesced writes: {}", writes[1]);
+    }
+
+    #[tokio::test]
+    async fn test_pending_deductions_written_on_close() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let address = "0x1234567890abcdef1234567890abcdef12345678";
+        // A window long enough that only closing can write the deductions
+        let config = RocksDbConfig { coalesce_window_ms: Some(60_000), ..Default::default() };
+
+        let db = RocksDbDatabase::open_with_config(db_path.to_str().unwrap(), &config).unwrap();
+        db.add_balance(address, 10.0).await.unwrap();
+        db.deduct_balance(address, 1.0, 1).await.unwrap();
+        let clone = db.clone();
+        drop(db);
+        clone.deduct_balance(address, 2.0, 2).await.unwrap();
+        drop(clone);
+
+        let db = RocksDbDatabase::open_with_config(db_path.to_str().unwrap(), &config).unwrap();
+        let user = db.get_user(address).await.unwrap().unwrap();
+        assert_eq!(user.balance, 7.0);
+        assert_eq!(user.latest_timestamp, 2);
+
+        // An explicit flush writes them without closing
+        db.deduct_balance(address, 3.0, 3).await.unwrap();
+        db.flush().await.unwrap();
+        let stored = db.db.get_cf(balances_cf(&db.db), address).unwrap().unwrap();
+        assert_eq!(UserData::from_bytes(&stored).unwrap().balance, 4.0);
+    }
+}
@@ -0,0 +1,174 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+/// ERC-3009 `transferWithAuthorization` fields
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TransferAuthorization {
+    pub from: String,
+    pub value: String,
+    pub nonce: String,
+}
+
+/// EIP-2612 `permit` fields
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Permit {
+    pub owner: String,
+    pub spender: String,
+    pub value: String,
+    pub nonce: String,
+    pub deadline: String,
+}
+
+/// How a deposit payment authorizes moving the payer's tokens
+#[derive(Debug, Clone, PartialEq)]
+pub enum DepositAuthorization {
+    Transfer(TransferAuthorization),
+    Permit(Permit),
+}
+
+impl DepositAuthorization {
+    /// Parse the authorization out of a serialized x402 payment payload
+    pub fn from_payment(payment: &Value) -> Result<Self, String> {
+        let payload = payment
+            .get("payload")
+            .ok_or_else(|| "payment has no payload".to_string())?;
+
+        if let Some(authorization) = payload.get("authorization") {
+            return serde_json::from_value(authorization.clone())
+                .map(Self::Transfer)
+                .map_err(|e| format!("invalid transfer authorization: {}", e));
+        }
+
+        if let Some(permit) = payload.get("permit") {
+            return serde_json::from_value(permit.clone())
+                .map(Self::Permit)
+                .map_err(|e| format!("invalid permit: {}", e));
+        }
+
+        Err("payload has neither an authorization nor a permit".to_string())
+    }
+
+    /// Address whose tokens are moved
+    pub fn payer(&self) -> &str {
+        match self {
+            Self::Transfer(authorization) => &authorization.from,
+            Self::Permit(permit) => &permit.owner,
+        }
+    }
+
+    /// Amount in the token's smallest unit
+    pub fn value(&self) -> Result<u128, String> {
+        let value = match self {
+            Self::Transfer(authorization) => &authorization.value,
+            Self::Permit(permit) => &permit.value,
+        };
+        value.parse().map_err(|_| format!("invalid value '{}'", value))
+    }
+
+    /// Nonce making the authorization single-use
+    pub fn nonce(&self) -> &str {
+        match self {
+            Self::Transfer(authorization) => &authorization.nonce,
+            Self::Permit(permit) => &permit.nonce,
+        }
+    }
+
+    /// Address a serialized x402 payment pays: the transfer's recipient or the permit's spender
+    pub fn recipient(payment: &Value) -> Option<&str> {
+        let payload = payment.get("payload")?;
+        payload
+            .get("authorization")
+            .and_then(|authorization| authorization.get("to"))
+            .or_else(|| payload.get("permit")?.get("spender"))?
+            .as_str()
+    }
+
+    /// Check a permit is accepted, approves `pay_to` and hasn't expired at `now`
+    /// Transfer authorizations are validated by the facilitator
+    pub fn validate(&self, accept_permit: bool, pay_to: &str, now: u64) -> Result<(), String> {
+        let Self::Permit(permit) = self else {
+            return Ok(());
+        };
+
+        if !accept_permit {
+            return Err("permit payments are not accepted".to_string());
+        }
+
+        if !permit.spender.eq_ignore_ascii_case(pay_to) {
+            return Err(format!("permit spender {} is not the payment address", permit.spender));
+        }
+
+        let deadline: u64 = permit
+            .deadline
+            .parse()
+            .map_err(|_| format!("invalid permit deadline '{}'", permit.deadline))?;
+        if deadline <= now {
+            return Err(format!("permit expired at {}", deadline));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const PAY_TO: &str = "0x1234567890abcdef1234567890abcdef12345678";
+    const OWNER: &str = "0x00000000000000000000000000000000000000aa";
+
+    fn permit_payment(deadline: u64) -> Value {
+        json!({
+            "x402Version": 1,
+            "scheme": "exact",
+            "network": "base-sepolia",
+            "payload": {
+                "signature": "0x00",
+                "permit": {
+                    "owner": OWNER,
+                    "spender": PAY_TO,
+                    "value": "1000000",
+                    "nonce": "7",
+                    "deadline": deadline.to_string(),
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn test_permit_payload_parsed_and_validated() {
+        let authorization = DepositAuthorization::from_payment(&permit_payment(2_000)).unwrap();
+
+        assert!(matches!(authorization, DepositAuthorization::Permit(_)));
+        assert_eq!(authorization.payer(), OWNER);
+        assert_eq!(authorization.value(), Ok(1_000_000));
+        assert_eq!(DepositAuthorization::recipient(&permit_payment(2_000)), Some(PAY_TO));
+        assert_eq!(authorization.nonce(), "7");
+        assert!(authorization.validate(true, PAY_TO, 1_000).is_ok());
+
+        // Only accepted when enabled
+        assert!(authorization.validate(false, PAY_TO, 1_000).is_err());
+    }
+
+    #[test]
+    fn test_expired_permit_rejected() {
+        let authorization = DepositAuthorization::from_payment(&permit_payment(1_000)).unwrap();
+        let error = authorization.validate(true, PAY_TO, 1_000).unwrap_err();
+        assert!(error.contains("expired"));
+    }
+
+    #[test]
+    fn test_transfer_authorization_parsed() {
+        let payment = json!({
+            "payload": {
+                "authorization": {"from": OWNER, "to": PAY_TO, "value": "500000", "nonce": "0xabc"}
+            }
+        });
+        let authorization = DepositAuthorization::from_payment(&payment).unwrap();
+
+        assert_eq!(authorization.payer(), OWNER);
+        assert_eq!(authorization.value(), Ok(500_000));
+        assert!(authorization.validate(false, PAY_TO, 0).is_ok());
+    }
+}
@@ -0,0 +1,204 @@
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Default cap on cached responses
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+/// Position of the block parameter for methods whose result never changes once that block is final
+fn block_param_index(method: &str) -> Option<usize> {
+    match method {
+        "eth_getBlockByNumber"
+        | "eth_getBlockReceipts"
+        | "eth_getBlockTransactionCountByNumber"
+        | "eth_getTransactionByBlockNumberAndIndex"
+        | "eth_getUncleCountByBlockNumber" => Some(0),
+        "eth_getBalance" | "eth_getCode" | "eth_getTransactionCount" | "eth_call" => Some(1),
+        "eth_getStorageAt" | "eth_getProof" => Some(2),
+        _ => None,
+    }
+}
+
+/// Block number a single JSON-RPC call reads at, if it is a cacheable method naming an explicit block
+/// Tags like "latest" or "finalized" move over time and are never cacheable
+pub fn requested_block(call: &Value) -> Option<u64> {
+    let index = block_param_index(call.get("method")?.as_str()?)?;
+    let block = call.get("params")?.get(index)?.as_str()?;
+    u64::from_str_radix(block.strip_prefix("0x")?, 16).ok()
+}
+
+/// Block the node reports at the head of its chain or as finalized
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Head {
+    Latest,
+    Finalized,
+}
+
+impl Head {
+    fn tag(self) -> &'static str {
+        match self {
+            Self::Latest => "latest",
+            Self::Finalized => "finalized",
+        }
+    }
+}
+
+/// Ask the node for the number of its latest or finalized block
+pub async fn fetch_head_block(client: &Client, node_url: &str, head: Head) -> Option<u64> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "method": "eth_getBlockByNumber",
+        "params": [head.tag(), false],
+        "id": 1,
+    });
+
+    let response: Value = match client.post(node_url).json(&request).send().await {
+        Ok(response) => response.json().await.ok()?,
+        Err(e) => {
+            tracing::warn!(error = %e, head = head.tag(), "Failed to fetch head block");
+            return None;
+        }
+    };
+
+    let number = response.get("result")?.get("number")?.as_str()?;
+    u64::from_str_radix(number.strip_prefix("0x")?, 16).ok()
+}
+
+/// Cache of node responses for reads at finalized blocks, which can never change
+pub struct FinalizedCache {
+    /// Maps "resource:method:params" -> response without its id and when it was cached
+    entries: HashMap<String, (Instant, Value)>,
+    /// Keys in insertion order (oldest first) for eviction
+    order: VecDeque<String>,
+    /// Maximum number of responses kept; the oldest is evicted beyond this
+    max_entries: usize,
+    /// Latest and finalized block per resource and when they were fetched
+    heads: HashMap<(String, Head), (Instant, u64)>,
+    /// How long a fetched head block number is trusted before refetching
+    refresh: Duration,
+    /// How long a response is served from the cache (None keeps it until evicted)
+    ttl: Option<Duration>,
+}
+
+impl FinalizedCache {
+    /// Create a new cache holding at most `max_entries` responses, each for at most `ttl`
+    pub fn new(max_entries: usize, refresh: Duration, ttl: Option<Duration>) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            max_entries,
+            heads: HashMap::new(),
+            refresh,
+            ttl,
+        }
+    }
+
+    /// Look up a cached response for this call, carrying the call's own id
+    pub fn get(&self, resource: &str, call: &Value) -> Option<Value> {
+        let (cached_at, response) = self.entries.get(&Self::entry_key(resource, call)?)?;
+        if self.ttl.is_some_and(|ttl| cached_at.elapsed() >= ttl) {
+            return None;
+        }
+        let mut response = response.clone();
+        response["id"] = call.get("id").cloned().unwrap_or(Value::Null);
+        Some(response)
+    }
+
+    /// Store the response for this call
+    /// Evicts the oldest response if the cache is full
+    pub fn insert(&mut self, resource: &str, call: &Value, mut response: Value) {
+        let Some(key) = Self::entry_key(resource, call) else {
+            return;
+        };
+        // An expired response is replaced in place, keeping its eviction position
+        if let Some((cached_at, _)) = self.entries.get(&key) {
+            if self.ttl.is_none_or(|ttl| cached_at.elapsed() < ttl) {
+                return;
+            }
+            self.entries.remove(&key);
+            self.order.retain(|existing| existing != &key);
+        }
+
+        while self.entries.len() >= self.max_entries {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+
+        if let Some(response) = response.as_object_mut() {
+            response.remove("id");
+        }
+        self.order.push_back(key.clone());
+        self.entries.insert(key, (Instant::now(), response));
+
+        tracing::debug!(
+            resource = %resource,
+            cache_size = self.entries.len(),
+            "Finalized response cached"
+        );
+    }
+
+    /// Latest or finalized block for a resource, if fetched recently enough
+    pub fn head_block(&self, resource: &str, head: Head) -> Option<u64> {
+        self.heads
+            .get(&(resource.to_string(), head))
+            .filter(|(fetched_at, _)| fetched_at.elapsed() < self.refresh)
+            .map(|(_, block)| *block)
+    }
+
+    /// Record the latest or finalized block for a resource
+    pub fn set_head_block(&mut self, resource: &str, head: Head, block: u64) {
+        self.heads.insert((resource.to_string(), head), (Instant::now(), block));
+    }
+
+    /// Calls are identified by resource, method and params (ids differ between clients)
+    fn entry_key(resource: &str, call: &Value) -> Option<String> {
+        let method = call.get("method")?.as_str()?;
+        let params = call.get("params").cloned().unwrap_or(Value::Null);
+        Some(format!("{}:{}:{}", resource, method, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_explicit_blocks_are_cacheable() {
+        let at = |method: &str, params: Value| json!({"method": method, "params": params, "id": 1});
+
+        assert_eq!(requested_block(&at("eth_getBalance", json!(["0xabc", "0x10"]))), Some(16));
+        assert_eq!(requested_block(&at("eth_getStorageAt", json!(["0xabc", "0x0", "0x20"]))), Some(32));
+        assert_eq!(requested_block(&at("eth_getBalance", json!(["0xabc", "latest"]))), None);
+        assert_eq!(requested_block(&at("eth_blockNumber", json!([]))), None);
+    }
+
+    #[test]
+    fn test_cached_response_takes_callers_id_and_oldest_is_evicted() {
+        let mut cache = FinalizedCache::new(1, Duration::from_secs(12), None);
+        let call = |id: u64, block: &str| {
+            json!({"method": "eth_getBalance", "params": ["0xabc", block], "id": id})
+        };
+
+        cache.insert("eth", &call(1, "0x1"), json!({"jsonrpc": "2.0", "id": 1, "result": "0x5"}));
+        let cached = cache.get("eth", &call(7, "0x1")).unwrap();
+        assert_eq!(cached["id"], 7);
+        assert_eq!(cached["result"], "0x5");
+        assert!(cache.get("base", &call(7, "0x1")).is_none());
+
+        cache.insert("eth", &call(2, "0x2"), json!({"jsonrpc": "2.0", "id": 2, "result": "0x6"}));
+        assert!(cache.get("eth", &call(1, "0x1")).is_none());
+        assert!(cache.get("eth", &call(1, "0x2")).is_some());
+    }
+
+    #[test]
+    fn test_expired_responses_not_served() {
+        let mut cache = FinalizedCache::new(10, Duration::from_secs(12), Some(Duration::ZERO));
+        let call = json!({"method": "eth_getBalance", "params": ["0xabc", "0x1"], "id": 1});
+
+        cache.insert("eth", &call, json!({"jsonrpc": "2.0", "id": 1, "result": "0x5"}));
+        assert!(cache.get("eth", &call).is_none());
+    }
+}
@@ -1,40 +1,68 @@
 use axum::{
     body::Bytes,
-    extract::State,
-    http::{header, HeaderMap, StatusCode},
+    extract::{ConnectInfo, Extension, Query, State},
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri},
     response::{IntoResponse, Response},
 };
+use std::future::Future;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tracing::instrument;
+use serde::Deserialize;
 use serde_json::json;
+use thiserror::Error;
 use alloy::primitives::{Address, Signature};
-use x402_axum::layer::X402Paygate;
-use x402_rs::types::{EvmAddress, MixedAddress, PaymentRequiredResponse, PaymentRequirements, Scheme, TokenAmount, X402Version};
-use x402_rs::network::Network;
+use x402_rs::types::{MixedAddress, PaymentRequiredResponse, PaymentRequirements, Scheme, TokenAmount, X402Version};
 use once_cell::sync::Lazy;
+use tokio::sync::Semaphore;
 
+use crate::asset::{self, USDC};
+use crate::auth::{self, AuthError, AuthRequest, Authenticated};
+use crate::config::{BodyHashAlgorithm, DeductionFailurePolicy, PaymentAssetConfig, ResourceConfig};
+use crate::database::{AuditEntry, ConsistencyLevel, DatabaseError, RequestRecord, Reservation, UserData, EMPTY_BALANCE_THRESHOLD};
+use crate::deposit::DepositAuthorization;
+use crate::finalized_cache::{self, FinalizedCache, Head};
+use crate::jsonrpc;
+use crate::paygate::PaygateError;
+use crate::reconciliation;
+use crate::settlement::{self, PendingDeposit};
 use crate::state::AppState;
+use crate::tls::TlsPeer;
+use crate::tx_precheck;
+use crate::upstream_payment::UpstreamPayer;
 
 /// Top-up amount in USDC for prepayments
-const TOPUP_AMOUNT_USDC: f64 = 1.0;
+pub const TOPUP_AMOUNT_USDC: f64 = 1.0;
+
+/// Most accounts one admin balance lookup may ask for
+const MAX_ADMIN_LOOKUP_ADDRESSES: usize = 1000;
 
-/// Timestamp window in seconds - requests must be within this time
-const TIMESTAMP_WINDOW_SECS: u64 = 60;
 
 static ERR_PAYMENT_HEADER_REQUIRED: Lazy<String> =
     Lazy::new(|| "X-PAYMENT header is required".to_string());
     
-/// Extract authentication headers from request
-/// Returns (address, signature, timestamp) if all headers are present
-fn extract_auth_headers(headers: &HeaderMap) -> Option<(String, String, u64)> {
-    let address = headers.get("x-auth-address")?.to_str().ok()?.to_string();
-    let signature = headers.get("x-auth-signature")?.to_str().ok()?.to_string();
-    let timestamp = headers.get("x-auth-timestamp")?
-        .to_str().ok()?
-        .parse::<u64>().ok()?;
-    
-    Some((address, signature, timestamp))
+/// Extract the optional client-supplied idempotency key
+fn extract_idempotency_key(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("x-idempotency-key")?
+        .to_str()
+        .ok()
+        .map(|s| s.to_string())
+}
+
+/// Replay cache entry for a signed request to replayable methods only: the claimed address,
+/// and the signature, path and body, so only a byte-identical retry gets the response back
+fn replay_key(state: &AppState, path: &str, headers: &HeaderMap, body: &[u8]) -> Option<(String, String)> {
+    state.replay_cache.as_ref()?;
+    let request = serde_json::from_slice::<serde_json::Value>(body).ok()?;
+    if !jsonrpc::only_methods(&request, &state.config.replayable_methods) {
+        return None;
+    }
+    let address = headers.get("x-auth-address")?.to_str().ok()?;
+    let signature = headers.get("x-auth-signature")?.to_str().ok()?;
+    let body_hash = auth::body_hash(BodyHashAlgorithm::Keccak256, body);
+    Some((address.to_string(), format!("{}:{}:{}", signature, path, body_hash)))
 }
 
 /// Check if request has an X-Payment header (indicates payment attempt)
@@ -42,360 +70,5294 @@ fn has_payment_header(headers: &HeaderMap) -> bool {
     headers.contains_key("X-Payment")
 }
 
-/// Create payment requirements for top-up
-fn create_payment_requirements(state: &AppState) -> Vec<PaymentRequirements> {
-    let amount_smallest_unit = (TOPUP_AMOUNT_USDC * 1_000_000.0) as u64;
-    
-    vec![PaymentRequirements {
+/// Why a request has to be paid for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PaymentReason {
+    /// The request carries no usable credentials
+    Unauthenticated,
+    /// The caller is authenticated but can't cover the price
+    InsufficientBalance { balance: f64, required: f64 },
+}
+
+/// Requirements for a top-up on `resource`'s network, one per token accepted there
+/// Errors if no token is configured for the network or the top-up is outside a token's bounds
+fn create_payment_requirements(state: &AppState, resource: &ResourceConfig) -> Result<Vec<PaymentRequirements>, String> {
+    let amount_smallest_unit = state.pricing_oracle.usd_to_asset_units(TOPUP_AMOUNT_USDC, state.config.price_rounding);
+
+    // USDC on every network unless tokens are configured
+    if state.config.payment_assets.is_empty() {
+        let usdc = state.prepared_requirements.usdc();
+        return Ok(vec![payment_requirement(state, resource, usdc, USDC.symbol, "2", u128::from(amount_smallest_unit), 300)]);
+    }
+
+    let assets: Vec<(&PaymentAssetConfig, &MixedAddress)> = state
+        .config
+        .payment_assets
+        .iter()
+        .zip(state.prepared_requirements.assets())
+        .filter(|(asset, _)| asset.network == resource.network)
+        .collect();
+    if assets.is_empty() {
+        return Err(format!("no payment asset is configured for network '{}'", resource.network));
+    }
+
+    // The oracle prices in the built-in asset; configured tokens are dollar stablecoins too
+    let topup = USDC.to_tokens(u128::from(amount_smallest_unit));
+    assets
+        .into_iter()
+        .map(|(asset, contract)| {
+            let below = asset.min_amount.is_some_and(|min| topup + 1e-9 < min);
+            let above = asset.max_amount.is_some_and(|max| topup > max + 1e-9);
+            if below || above {
+                return Err(format!(
+                    "top-up of {} is outside the bounds configured for {} on network '{}'",
+                    topup, asset.address, asset.network
+                ));
+            }
+            let amount = asset::tokens_to_units(topup, asset.decimals, state.config.price_rounding);
+            Ok(payment_requirement(state, resource, contract, &asset.name, &asset.version, amount, asset.max_timeout_seconds))
+        })
+        .collect()
+}
+
+/// Requirement to pay `amount` smallest units of the token at `asset`
+fn payment_requirement(
+    state: &AppState,
+    resource: &ResourceConfig,
+    asset: &MixedAddress,
+    name: &str,
+    version: &str,
+    amount: u128,
+    max_timeout_seconds: u64,
+) -> PaymentRequirements {
+    // Advertise EIP-2612 permit support alongside ERC-3009 if enabled
+    let mut extra = json!({
+        "name": name,
+        "version": version
+    });
+    if state.config.accept_permit {
+        extra["permit"] = json!(true);
+    }
+
+    PaymentRequirements {
         scheme: Scheme::Exact,
-        network: Network::BaseSepolia,
-        max_amount_required: TokenAmount::from(amount_smallest_unit),
-        resource: format!("http://localhost:{}/relay", state.config.port)
-            .parse()
-            .unwrap(),
-        description: "Top up your RPC access balance with $1 USDC".to_string(),
+        network: resource.network,
+        max_amount_required: TokenAmount::from(amount),
+        resource: state.prepared_requirements.resource(&resource.path).clone(),
+        description: format!("Top up your RPC access balance with ${} {}", TOPUP_AMOUNT_USDC, name),
         mime_type: "application/json".to_string(),
-        pay_to: MixedAddress::Evm(EvmAddress::from_str(&state.config.payment_address).unwrap()),
-        max_timeout_seconds: 300,
-        asset: MixedAddress::Evm(EvmAddress::from_str("0x036CbD53842c5426634e7929541eC2318f3dCF7e").unwrap()),
-        extra: Some(json!({
-            "name": "USDC",
-            "version": "2"
-        })),
+        pay_to: state.prepared_requirements.pay_to(resource.network).clone(),
+        max_timeout_seconds,
+        asset: asset.clone(),
+        extra: Some(extra),
         output_schema: None,
-    }]
+    }
 }
 
-/// Verify cryptographic signature and timestamp
-fn verify_signature(
-    address: &str,
-    signature: &str,
-    timestamp: u64,
-    body: &[u8],
-) -> Result<(), String> {
-    // Check timestamp is within acceptable window
-    let now = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    
-    if now.abs_diff(timestamp) > TIMESTAMP_WINDOW_SECS {
-        return Err(format!(
-            "Timestamp outside window: {} seconds drift",
-            now.abs_diff(timestamp)
-        ));
-    }
+/// Whole tokens for `units` of the token a payment was verified against
+fn verified_tokens(state: &AppState, requirements: &PaymentRequirements, units: u128) -> f64 {
+    let decimals = state
+        .config
+        .payment_assets
+        .iter()
+        .zip(state.prepared_requirements.assets())
+        .find(|(asset, contract)| asset.network == requirements.network && requirements.asset == **contract)
+        .map_or(USDC.decimals, |(asset, _)| asset.decimals);
+    asset::units_to_tokens(units, decimals)
+}
 
-    // Reconstruct the message that was signed
-    // Format: address + timestamp + body_hash
-    let body_hash = alloy::primitives::keccak256(body);
-    let message = format!("{}{}{}", address, timestamp, hex::encode(body_hash));
-    let message_hash = alloy::primitives::keccak256(message.as_bytes());
+/// Smallest units to credit for a verified payment
+/// The facilitator verified the authorization against `requirements`, so their amount is
+/// authoritative: a payload claiming more is credited only the verified amount
+fn verified_amount(requirements: &PaymentRequirements, claimed: u128) -> Result<u128, String> {
+    let verified: u128 = requirements
+        .max_amount_required
+        .to_string()
+        .parse()
+        .map_err(|_| format!("invalid verified amount '{}'", requirements.max_amount_required))?;
+    Ok(claimed.min(verified))
+}
+
+/// USDC charged for a USD price at the pricing oracle's current rate,
+/// rounded to whole units with the configured rounding mode
+fn asset_price(state: &AppState, usd_price: f64) -> f64 {
+    let units = state.pricing_oracle.usd_to_asset_units(usd_price, state.config.price_rounding);
+    USDC.to_tokens(u128::from(units))
+}
+
+/// Message a payer signs to credit a deposit to another account
+/// Bound to the payment authorization nonce so it can't be reused for other payments
+fn credit_authorization_message(credit_address: &str, authorization_nonce: &str) -> String {
+    format!("x402-credit:{}:{}", credit_address.to_lowercase(), authorization_nonce)
+}
+
+/// Determine which account a deposit is credited to, as its canonical account key
+/// Without X-Credit-Address the payer is credited; otherwise X-Credit-Signature must be
+/// the payer's signature over the credit authorization message
+fn resolve_credit_address(
+    headers: &HeaderMap,
+    payer_address: &str,
+    authorization_nonce: &str,
+) -> Result<String, String> {
+    let payer = payer_address.parse::<Address>()
+        .map_err(|e| format!("Invalid payer address format: {}", e))?;
+
+    let Some(credit_address) = headers.get("x-credit-address") else {
+        return Ok(auth::account_key(&payer));
+    };
+
+    let credit_address = credit_address
+        .to_str()
+        .map_err(|_| "X-Credit-Address is not valid text".to_string())?;
+
+    let credited = credit_address.parse::<Address>()
+        .map_err(|e| format!("Invalid credit address format: {}", e))?;
+
+    let signature = headers
+        .get("x-credit-signature")
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| "X-Credit-Signature header is required".to_string())?;
 
-    // Parse and verify signature
     let sig = Signature::from_str(signature)
         .map_err(|e| format!("Invalid signature format: {}", e))?;
 
+    let message = credit_authorization_message(credit_address, authorization_nonce);
+    let message_hash = alloy::primitives::keccak256(message.as_bytes());
+
     let recovered_address = sig.recover_address_from_prehash(&message_hash)
         .map_err(|e| format!("Failed to recover address: {}", e))?;
 
-    let claimed_address = address.parse::<Address>()
-        .map_err(|e| format!("Invalid address format: {}", e))?;
-
-    if recovered_address != claimed_address {
-        return Err("Signature verification failed: address mismatch".to_string());
+    if recovered_address != payer {
+        return Err("credit address was not authorized by the payer".to_string());
     }
 
-    Ok(())
+    Ok(auth::account_key(&credited))
 }
 
 /// Return 402 Payment Required with x402 payment requirements
-fn request_payment(state: &AppState) -> Response {
-    let payment_required_response = PaymentRequiredResponse {
-        error: ERR_PAYMENT_HEADER_REQUIRED.clone(),
-        accepts: create_payment_requirements(state),
-        x402_version: X402Version::V1,
+fn request_payment(state: &AppState, resource: &ResourceConfig, reason: PaymentReason) -> Response {
+    let mut body = if state.config.deposits_enabled {
+        let accepts = match create_payment_requirements(state, resource) {
+            Ok(accepts) => accepts,
+            Err(e) => {
+                tracing::error!(resource = %resource.name, error = %e, "Cannot request payment");
+                return (
+                    StatusCode::PAYMENT_REQUIRED,
+                    format!("Insufficient balance (deposits are not accepted for this resource: {})", e),
+                ).into_response();
+            }
+        };
+        let payment_required_response = PaymentRequiredResponse {
+            error: ERR_PAYMENT_HEADER_REQUIRED.clone(),
+            accepts,
+            x402_version: X402Version::V1,
+        };
+        let mut body = serde_json::to_value(&payment_required_response).unwrap();
+        with_challenge(state, &mut body);
+        body
+    } else if reason == PaymentReason::Unauthenticated {
+        return (
+            StatusCode::PAYMENT_REQUIRED,
+            "Insufficient balance (deposits are disabled on this gateway)",
+        ).into_response();
+    } else {
+        json!({})
     };
 
+    // Tell an authenticated client how far short it is, so it knows to top up rather than sign
+    if let PaymentReason::InsufficientBalance { balance, required } = reason {
+        body["error"] = json!(if state.config.deposits_enabled {
+            "Insufficient balance"
+        } else {
+            "Insufficient balance (deposits are disabled on this gateway)"
+        });
+        body["balance"] = json!(balance);
+        body["required"] = json!(required);
+        body["shortfall"] = json!(required - balance);
+    }
+
     (
         StatusCode::PAYMENT_REQUIRED,
         [(header::CONTENT_TYPE, "application/json")],
-        serde_json::to_string(&payment_required_response).unwrap(),
+        body.to_string(),
     ).into_response()
 }
 
-/// Forward request to RPC node
-async fn relay_to_node(state: &AppState, body: Bytes) -> Response {
-    let response = match state
-        .client
-        .post(&state.config.node_url)
-        .header(header::CONTENT_TYPE, "application/json")
-        .body(body)
-        .send()
-        .await
-    {
-        Ok(resp) => resp,
+/// Add a fresh payment challenge to a 402 body if deposits must redeem one
+fn with_challenge(state: &AppState, body: &mut serde_json::Value) {
+    if let Some(challenges) = &state.payment_challenges {
+        body["challenge"] = json!(challenges.lock().unwrap().issue(Instant::now()));
+    }
+}
+
+/// Redeem the X-Payment-Challenge a deposit echoes back, if challenges are required
+fn redeem_challenge(state: &AppState, headers: &HeaderMap) -> Result<(), String> {
+    let Some(challenges) = &state.payment_challenges else {
+        return Ok(());
+    };
+    let challenge = headers
+        .get("x-payment-challenge")
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| "X-Payment-Challenge is required".to_string())?;
+    if !challenges.lock().unwrap().consume(challenge, Instant::now()) {
+        return Err("payment challenge is unknown, expired or already used".to_string());
+    }
+    Ok(())
+}
+
+/// Settlement failed after the payment was verified, so the payer's funds may or may not have moved
+#[derive(Debug, Error)]
+#[error("payment from {payer} for {amount_usdc} USDC (crediting {credited}) verified but settlement failed: {reason}")]
+pub struct SettlementError {
+    pub payer: String,
+    pub credited: String,
+    pub amount_usdc: f64,
+    pub reason: String,
+}
+
+impl IntoResponse for SettlementError {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::BAD_GATEWAY,
+            axum::Json(json!({
+                "error": "Payment verified but settlement failed, contact support",
+                "payer": self.payer,
+                "amount": self.amount_usdc,
+            })),
+        ).into_response()
+    }
+}
+
+/// Writing a deposit and the charge for its request failed
+#[derive(Debug, Error)]
+enum CreditError {
+    #[error("{0}")]
+    Deposit(DatabaseError),
+    /// The deposit was credited but the request wasn't charged
+    #[error("deposit credited but the request could not be charged: {0}")]
+    Deduction(DatabaseError),
+}
+
+/// 402 for a signed request whose claimed account can't cover one request, checked before
+/// the signature is verified. Missing and empty accounts take the same path and get the same
+/// response as an unauthenticated request, so neither existence nor balance is revealed
+async fn reject_unfunded(state: &AppState, resource: &ResourceConfig, headers: &HeaderMap) -> Result<(), Response> {
+    // Only the signature scheme names its account up front
+    let Some(address) = headers
+        .get("x-auth-address")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| Address::from_str(value).ok())
+    else {
+        return Ok(());
+    };
+    let address = auth::account_key(&address);
+
+    let price = asset_price(state, state.config.price_for(resource, &address));
+    let user = match state.database.get_user_consistent(&address, ConsistencyLevel::Eventual).await {
+        Ok(user) => user,
+        // Leave the decision to the usual path rather than failing the request here
         Err(e) => {
-            tracing::error!(error = %e, "Failed to relay request to node");
-            return (
-                StatusCode::BAD_GATEWAY,
-                [(header::CONTENT_TYPE, "application/json")],
-                format!(
-                    r#"{{"jsonrpc":"2.0","error":{{"code":-32603,"message":"Failed to connect to node: {}"}},"id":null}}"#,
-                    e
-                ),
-            ).into_response();
+            tracing::warn!(address = %address, error = %e, "Failed to read balance for unfunded check");
+            return Ok(());
         }
     };
 
-    let status = response.status();
-    let response_body = match response.bytes().await {
-        Ok(bytes) => bytes,
+    // Accounts with free requests left needn't be funded
+    if has_free_requests(state, user.as_ref()) {
+        return Ok(());
+    }
+    let balance = user.map_or(0.0, |user| user.balance);
+
+    if balance + EMPTY_BALANCE_THRESHOLD < price {
+        tracing::debug!(address = %address, "Rejecting unfunded account before authentication");
+        return Err(request_payment(state, resource, PaymentReason::Unauthenticated));
+    }
+    Ok(())
+}
+
+/// Whether an account (None if there is none yet) may still make free requests
+fn has_free_requests(state: &AppState, user: Option<&UserData>) -> bool {
+    let quota = state.config.free_requests;
+    quota > 0 && user.is_none_or(|user| (1..quota).contains(&user.free_requests_used))
+}
+
+/// Count a request against a new account's free requests; true if it is free
+/// Only called for accounts `has_free_requests` found eligible, so accounts that can't qualify
+/// are billed without a write; a failure is logged and the request billed as usual
+async fn use_free_request(state: &AppState, address: &str, timestamp: u64) -> bool {
+    match state.database.use_free_request(address, state.config.free_requests, timestamp).await {
+        Ok(free) => free,
         Err(e) => {
-            tracing::error!(error = %e, "Failed to read response from node");
-            return (
-                StatusCode::BAD_GATEWAY,
-                [(header::CONTENT_TYPE, "application/json")],
-                format!(
-                    r#"{{"jsonrpc":"2.0","error":{{"code":-32603,"message":"Failed to read node response: {}"}},"id":null}}"#,
-                    e
-                ),
-            ).into_response();
+            tracing::error!(address = %address, error = %e, "Failed to count free request");
+            false
+        }
+    }
+}
+
+/// 403 for an account an admin has suspended, otherwise the account as read (None if there is none)
+/// Read eventually consistent: suspensions are rare and needn't take effect on the very next request
+async fn refuse_if_suspended(state: &AppState, address: &str) -> Result<Option<UserData>, Response> {
+    match state.database.get_user_consistent(address, ConsistencyLevel::Eventual).await {
+        Ok(Some(user)) if user.suspended => {
+            tracing::warn!(address = %address, "Refusing suspended account");
+            Err((StatusCode::FORBIDDEN, "Account suspended").into_response())
+        }
+        Ok(user) => Ok(user),
+        Err(e) => {
+            tracing::error!(address = %address, error = %e, "Failed to check account suspension");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response())
         }
+    }
+}
+
+/// Refuse deposits below the configured minimum, which would cost more gas to settle than they're worth
+fn check_min_deposit(min_deposit: Option<f64>, amount_usdc: f64) -> Result<(), String> {
+    match min_deposit {
+        Some(min_deposit) if amount_usdc + 1e-9 < min_deposit => Err(format!(
+            "Deposit of {} USDC is below the minimum of {} USDC",
+            amount_usdc, min_deposit
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// 402 for a payment the facilitator rejected; nothing moved, so the client can retry with another payment
+fn verification_failed(state: &AppState, resource: &ResourceConfig, reason: &str) -> Response {
+    let payment_required_response = PaymentRequiredResponse {
+        error: format!("Payment verification failed: {}", reason),
+        // Deposits only get this far once requirements were created for the resource
+        accepts: create_payment_requirements(state, resource).unwrap_or_default(),
+        x402_version: X402Version::V1,
     };
+    let mut body = serde_json::to_value(&payment_required_response).unwrap();
+    with_challenge(state, &mut body);
 
     (
-        status,
+        StatusCode::PAYMENT_REQUIRED,
         [(header::CONTENT_TYPE, "application/json")],
-        response_body,
+        body.to_string(),
     ).into_response()
 }
 
-/// Main relay endpoint - handles both payments and authenticated requests
-#[instrument(skip_all, fields(body_size))]
-pub async fn relay(
-    State(state): State<Arc<AppState>>,
-    headers: HeaderMap,
+/// Response relayed back from the node (or a gateway-generated JSON-RPC error)
+#[derive(Debug, Clone)]
+pub struct NodeResponse {
+    pub status: StatusCode,
+    pub body: Bytes,
+}
+
+impl NodeResponse {
+    fn new(status: StatusCode, body: impl Into<Bytes>) -> Self {
+        Self { status, body: body.into() }
+    }
+}
+
+impl IntoResponse for NodeResponse {
+    fn into_response(self) -> Response {
+        if self.status == StatusCode::NO_CONTENT {
+            return StatusCode::NO_CONTENT.into_response();
+        }
+        (
+            self.status,
+            [(header::CONTENT_TYPE, "application/json")],
+            self.body,
+        ).into_response()
+    }
+}
+
+/// Pay a node's x402 requirements and resend the request with the payment attached
+async fn pay_node(
+    state: &AppState,
+    payer: &UpstreamPayer,
+    node_url: &str,
     body: Bytes,
-) -> Response {
-    tracing::Span::current().record("body_size", body.len());
+    payment_required: &[u8],
+) -> Result<(StatusCode, Bytes), String> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let payment = payer.payment_header(payment_required, now)?;
 
-    // Check if this is a payment/top-up request (has X-Payment header)
-    if has_payment_header(&headers) {
-        return handle_payment_with_paygate(state, headers, body).await;
+    state.metrics.node_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let response = state
+        .client
+        .post(node_url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("X-PAYMENT", payment)
+        .body(body.clone())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    let status = response.status();
+    let body = read_node_body(state, node_url, response, &body).await.map_err(|e| e.to_string())?;
+
+    if status == StatusCode::PAYMENT_REQUIRED {
+        return Err(format!("node rejected the payment: {}", String::from_utf8_lossy(&body)));
     }
+    tracing::info!(node = %node_url, "Paid upstream node");
+    Ok((status, body))
+}
 
-    // Not a payment - check for authentication headers
-    let (address, signature, timestamp) = match extract_auth_headers(&headers) {
-        Some(auth) => auth,
-        None => {
-            tracing::debug!("No authentication headers found");
-            return request_payment(&state);
+/// Forward request to the resource's RPC node
+/// Returns the response and the time spent waiting on the node (zero when served from cache)
+async fn relay_to_node(state: &AppState, resource: &ResourceConfig, body: Bytes) -> (NodeResponse, Duration) {
+    let notification = jsonrpc::is_notification(&body);
+    let (response, node_latency) = match &state.body_logger {
+        Some(logger) => {
+            let (response, node_latency) = forward_to_node(state, resource, body.clone()).await;
+            logger.log_response(&body, &response.body);
+            (response, node_latency)
         }
+        None => forward_to_node(state, resource, body).await,
     };
 
-    // Check if signature has been used before (replay attack)
-    {
-        let mut cache = state.signature_cache.lock().unwrap();
-        if cache.is_replay(&signature) {
-            tracing::warn!(
-                address = %address,
-                signature = %signature,
-                "Replay detected"
-            );
-            return (
-                StatusCode::UNAUTHORIZED,
-                "Replay detected: signature already used",
-            ).into_response();
-        }
+    // Notifications get no reply, not even a synthetic error; only the HTTP status reports failures
+    if notification {
+        let status = if response.status.is_success() { StatusCode::NO_CONTENT } else { response.status };
+        return (NodeResponse::new(status, Bytes::new()), node_latency);
     }
+    (response, node_latency)
+}
 
-    // Verify signature
-    if let Err(e) = verify_signature(&address, &signature, timestamp, &body) {
-        tracing::warn!(
-            address = %address,
-            error = %e,
-            "Signature verification failed"
-        );
-        return (
-            StatusCode::UNAUTHORIZED,
-            format!("Authentication failed: {}", e),
-        ).into_response();
+/// Send a request to the node, applying the configured caching, id handling and error mapping
+async fn forward_to_node(state: &AppState, resource: &ResourceConfig, body: Bytes) -> (NodeResponse, Duration) {
+    // Serve reads at finalized blocks from cache if configured
+    let cacheable_call = state.finalized_cache.as_ref().and_then(|_| {
+        serde_json::from_slice::<serde_json::Value>(&body)
+            .ok()
+            .filter(|call| call.get("id").is_some() && finalized_cache::requested_block(call).is_some())
+    });
+
+    if let (Some(cache), Some(call)) = (&state.finalized_cache, &cacheable_call) {
+        let cached = cache.lock().unwrap().get(&resource.name, call);
+        if let Some(response) = cached {
+            tracing::debug!(resource = %resource.name, "Serving finalized read from cache");
+            return (NodeResponse::new(StatusCode::OK, response.to_string()), Duration::ZERO);
+        }
     }
 
-    // Check user balance
-    let price = state.config.price_per_request;
-    
-    match state.database.deduct_balance(&address, price, timestamp).await {
-        Ok(remaining_balance) => {
-            // Add signature to cache to prevent replay
-            {
-                let mut cache = state.signature_cache.lock().unwrap();
-                cache.add(&signature);
+    // Normalize numeric ids and swap client ids for gateway-unique ones if configured
+    // (unparseable bodies pass through)
+    let mut original_ids = None;
+    let body = if state.config.normalize_jsonrpc_ids || state.config.rewrite_jsonrpc_ids {
+        match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(mut request) => {
+                if state.config.normalize_jsonrpc_ids {
+                    jsonrpc::normalize_ids(&mut request);
+                }
+                if state.config.rewrite_jsonrpc_ids {
+                    original_ids = Some(jsonrpc::rewrite_ids(&mut request, &state.next_jsonrpc_id));
+                }
+                Bytes::from(request.to_string())
             }
+            Err(_) => body,
+        }
+    } else {
+        body
+    };
 
-            tracing::info!(
-                address = %address,
-                deducted = price,
-                remaining = remaining_balance,
-                "Request authorized, balance deducted"
-            );
+    // Try the resource's nodes in order, moving on when one fails or answers with a
+    // transient JSON-RPC error; the last node's answer stands whatever it is
+    let node_started = Instant::now();
+    let nodes: Vec<&String> = std::iter::once(&resource.node_url).chain(&resource.fallback_node_urls).collect();
+    let mut nodes = nodes.into_iter().peekable();
+    let (status, response_body) = loop {
+        let node_url = nodes.next().expect("resources have at least one node");
+        let result = call_node(state, resource, node_url, body.clone()).await;
+        if nodes.peek().is_none() {
+            match result {
+                Ok(response) => break response,
+                Err(response) => return (response, node_started.elapsed()),
+            }
+        }
+        match result {
+            Ok((status, response_body)) if !is_transient(state, &response_body) => break (status, response_body),
+            Ok(_) => tracing::warn!(resource = %resource.name, node = %node_url, "Transient node error, trying next node"),
+            Err(_) => tracing::warn!(resource = %resource.name, node = %node_url, "Node failed, trying next node"),
+        }
+    };
 
-            // Forward to RPC node
-            relay_to_node(&state, body).await
+    let node_latency = node_started.elapsed();
+    state.metrics.node_latency.record(node_latency);
+
+    // Give the client back the ids it sent
+    let response_body = match original_ids {
+        Some(original_ids) => match serde_json::from_slice::<serde_json::Value>(&response_body) {
+            Ok(mut reply) => {
+                jsonrpc::restore_ids(&mut reply, &original_ids);
+                Bytes::from(reply.to_string())
+            }
+            Err(_) => response_body,
+        },
+        None => response_body,
+    };
+
+    // Strip sensitive details from node error messages if configured
+    let response_body = match &state.error_sanitizer {
+        Some(sanitizer) => sanitizer.sanitize(&response_body).unwrap_or(response_body),
+        None => response_body,
+    };
+
+    // Follow the JSON-RPC convention of HTTP 200 for replies if configured; anything else
+    // the node failed with is a bad gateway
+    let status = if state.config.normalize_jsonrpc_status && !status.is_success() {
+        if jsonrpc::is_response(&response_body) {
+            StatusCode::OK
+        } else {
+            StatusCode::BAD_GATEWAY
         }
-        Err(e) => {
-            tracing::info!(
-                address = %address,
-                error = %e,
-                required = price,
-                "Insufficient balance or database error"
-            );
-            request_payment(&state)
+    } else {
+        status
+    };
+
+    // Map JSON-RPC error codes to HTTP statuses if configured (single responses only)
+    let status = if status == StatusCode::OK && !state.config.jsonrpc_error_status.is_empty() {
+        serde_json::from_slice(&response_body)
+            .ok()
+            .and_then(|response| jsonrpc::error_code(&response))
+            .and_then(|code| state.config.jsonrpc_error_status.get(&code))
+            .and_then(|mapped| StatusCode::from_u16(*mapped).ok())
+            .unwrap_or(status)
+    } else {
+        status
+    };
+
+    if let Some(call) = &cacheable_call {
+        if status == StatusCode::OK {
+            cache_if_finalized(state, resource, call, &response_body).await;
         }
     }
+
+    (NodeResponse::new(status, response_body), node_latency)
 }
 
-/// Handle payment/deposit request using X402Paygate
-async fn handle_payment_with_paygate(
-    state: Arc<AppState>,
-    headers: HeaderMap,
+/// Exchange a request with one node, paying it first if it charges via x402
+/// Failures come back as the bad gateway response to relay
+async fn call_node(
+    state: &AppState,
+    resource: &ResourceConfig,
+    node_url: &str,
     body: Bytes,
-) -> Response {
-    // Create payment requirements for top-up
-    let payment_requirements = create_payment_requirements(&state);
-    
-    // Create X402Paygate to verify and settle payment
-    let paygate = X402Paygate {
-        facilitator: state.facilitator.clone(),
-        payment_requirements: Arc::new(payment_requirements),
-        settle_before_execution: false, // Settle after we add balance
+) -> Result<(StatusCode, Bytes), NodeResponse> {
+    state.metrics.node_requests.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let response = match state
+        .client
+        .post(node_url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.clone())
+        .send()
+        .await
+    {
+        Ok(resp) => resp,
+        Err(e) => return Err(node_failure(state, node_url, &e, "Failed to relay request to node")),
     };
 
-    // Extract and verify payment
-    let payment_payload = match paygate.extract_payment_payload(&headers).await {
-        Ok(payload) => payload,
-        Err(err) => {
-            tracing::warn!("Payment extraction failed");
-            return err.into_response();
+    let status = response.status();
+    let response_body = match read_node_body(state, node_url, response, &body).await {
+        Ok(bytes) => bytes,
+        Err(NodeBodyError::TooLarge(limit)) => {
+            tracing::warn!(node = %node_url, limit, "Node response too large");
+            return Err(response_too_large());
         }
+        Err(NodeBodyError::Read(e)) => return Err(node_failure(state, node_url, &e, "Failed to read node response")),
     };
 
-    // Verify payment with facilitator
-    let verify_request = match paygate.verify_payment(payment_payload).await {
-        Ok(request) => request,
-        Err(err) => {
-            tracing::warn!("Payment verification failed");
-            return err.into_response();
+    // Pay nodes that charge via x402 and send the request again with the payment
+    match &state.upstream_payer {
+        Some(payer) if status == StatusCode::PAYMENT_REQUIRED => {
+            match pay_node(state, payer, node_url, body, &response_body).await {
+                Ok(paid) => Ok(paid),
+                Err(e) => {
+                    tracing::error!(node = %resource.name, error = %e, "Failed to pay upstream node");
+                    let error = json!({
+                        "jsonrpc": "2.0",
+                        "error": {"code": -32603, "message": format!("Upstream payment failed: {}", e)},
+                        "id": null,
+                    });
+                    Err(NodeResponse::new(StatusCode::BAD_GATEWAY, error.to_string()))
+                }
+            }
         }
-    };
+        _ => Ok((status, response_body)),
+    }
+}
 
-    // Extract user address and amount from verified payment
-    // Convert PaymentPayload to JSON to extract fields
-    let payment_json = match serde_json::to_value(&verify_request.payment_payload) {
-        Ok(json) => json,
-        Err(e) => {
-            tracing::error!("Failed to serialize payment payload: {}", e);
-            return (
-                StatusCode::BAD_REQUEST,
-                "Invalid payment format",
-            ).into_response();
-        }
-    };
-    
-    // Extract from address - the payment payload should have an EVM authorization
-    let user_address = payment_json
-        .get("payload")
-        .and_then(|p| p.get("authorization"))
-        .and_then(|auth| auth.get("from"))
-        .and_then(|from| from.as_str())
-        .map(|s| s.to_string())
-        .unwrap_or_default();
+/// How an exchange with a node failed, as told to the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeFailure {
+    /// No connection could be opened within `node_connect_timeout_ms`
+    ConnectTimeout,
+    /// The connection was refused or couldn't be opened (DNS, TLS, reset)
+    Unreachable,
+    /// The node didn't answer within `node_timeout_ms`
+    Timeout,
+    /// Anything else, described by the caller
+    Other,
+}
 
-    if user_address.is_empty() {
-        tracing::error!("Failed to extract user address from payment");
-        return (
-            StatusCode::BAD_REQUEST,
-            "Invalid payment format",
-        ).into_response();
+impl NodeFailure {
+    fn classify(e: &reqwest::Error) -> Self {
+        // A connect timeout is both a connect error and a timeout
+        match (e.is_connect(), e.is_timeout()) {
+            (true, true) => Self::ConnectTimeout,
+            (true, false) => Self::Unreachable,
+            (false, true) => Self::Timeout,
+            (false, false) => Self::Other,
+        }
     }
 
-    // Extract amount
-    let amount_raw = payment_json
-        .get("payload")
-        .and_then(|p| p.get("authorization"))
-        .and_then(|auth| auth.get("value"))
-        .and_then(|val| val.as_str())
-        .map(|s| s.to_string())
-        .unwrap_or_else(|| "0".to_string());
+    /// Timeouts are a gateway timeout, everything else a bad gateway
+    fn status(self) -> StatusCode {
+        match self {
+            Self::ConnectTimeout | Self::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            Self::Unreachable | Self::Other => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
 
-    // Convert from string to u64 to f64 USDC (6 decimals)
-    let amount_usdc = amount_raw.parse::<u64>()
-        .map(|v| v as f64 / 1_000_000.0)
-        .unwrap_or(0.0);
+/// Response for a failed exchange with a node, counted in metrics by kind of failure
+/// The error itself is only logged: it may name the node's URL, credentials included
+fn node_failure(state: &AppState, node_url: &str, e: &reqwest::Error, other: &str) -> NodeResponse {
+    let failure = NodeFailure::classify(e);
+    let message = match failure {
+        NodeFailure::ConnectTimeout => "Timed out connecting to node",
+        NodeFailure::Unreachable => "Node unreachable",
+        NodeFailure::Timeout => "Node did not respond in time",
+        NodeFailure::Other => other,
+    };
+    match failure {
+        NodeFailure::ConnectTimeout | NodeFailure::Timeout => {
+            state.metrics.node_timeouts.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+        NodeFailure::Unreachable => {
+            state.metrics.node_connect_failures.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+        NodeFailure::Other => {}
+    }
+    tracing::error!(node = %node_url, failure = ?failure, error = %e, "{}", message);
 
-    tracing::info!(
-        address = %user_address,
-        amount = amount_usdc,
-        "Payment verified, settling and adding to balance"
-    );
+    let error = json!({
+        "jsonrpc": "2.0",
+        "error": {"code": -32603, "message": message},
+        "id": null,
+    });
+    NodeResponse::new(failure.status(), error.to_string())
+}
 
-    // Settle payment on-chain
-    match paygate.settle_payment(&verify_request).await {
-        Ok(_settlement) => {
-            tracing::info!(
-                address = %user_address,
-                "Payment settled successfully"
-            );
+/// Why a node response body couldn't be read
+#[derive(Debug, Error)]
+enum NodeBodyError {
+    #[error("response exceeds {0} bytes")]
+    TooLarge(usize),
+    #[error("{0}")]
+    Read(#[from] reqwest::Error),
+}
 
-            // Add balance to user account
-            match state.database.add_balance(&user_address, amount_usdc).await {
-                Ok(new_balance) => {
-                    tracing::info!(
-                        address = %user_address,
-                        new_balance = new_balance,
-                        "Balance updated successfully"
-                    );
-
-                    // Deduct the price for this request
-                    let price = state.config.price_per_request;
-                    let timestamp = std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_secs();
-
-                    if let Err(e) = state.database.deduct_balance(&user_address, price, timestamp).await {
-                        tracing::error!(
-                            address = %user_address,
-                            error = %e,
-                            "Failed to deduct balance after deposit"
-                        );
-                    }
+/// Read a node response to `request` chunk by chunk, giving up once it passes
+/// `max_node_response_bytes`. Batch replies are split as they arrive, so a reply too large
+/// becomes a "response too large" error, as do the largest replies until the batch fits
+async fn read_node_body(
+    state: &AppState,
+    node_url: &str,
+    mut response: reqwest::Response,
+    request: &[u8],
+) -> Result<Bytes, NodeBodyError> {
+    let Some(limit) = state.config.max_node_response_bytes else {
+        return Ok(response.bytes().await?);
+    };
 
-                    // Process the original request
-                    relay_to_node(&state, body).await
-                }
-                Err(e) => {
-                    tracing::error!(
-                        address = %user_address,
-                        error = %e,
-                        "Failed to add balance"
-                    );
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        format!("Failed to process payment: {}", e),
-                    ).into_response()
-                }
-            }
+    // Kept whole while it fits, and split into replies too if it may be a batch
+    let mut body = Some(Vec::new());
+    let mut batch = jsonrpc::batch_size(request).map(|_| jsonrpc::BatchReader::new(limit));
+    while let Some(chunk) = response.chunk().await? {
+        if batch.as_mut().is_some_and(|reader| reader.feed(&chunk).is_err()) {
+            batch = None;
         }
-        Err(err) => {
-            tracing::error!("Payment settlement failed");
-            err.into_response()
+        body = body.filter(|body| body.len() + chunk.len() <= limit).map(|mut body| {
+            body.extend_from_slice(&chunk);
+            body
+        });
+        if body.is_none() && batch.is_none() {
+            return Err(NodeBodyError::TooLarge(limit));
         }
     }
+
+    if let Some(body) = body {
+        return Ok(Bytes::from(body));
+    }
+    let Some(Ok((replies, mut replaced))) = batch.map(jsonrpc::BatchReader::finish) else {
+        return Err(NodeBodyError::TooLarge(limit));
+    };
+    let mut response = serde_json::Value::Array(replies);
+    replaced += jsonrpc::fit_batch(&mut response, limit);
+    tracing::warn!(node = %node_url, limit, replaced, "Batch response too large, replaced its largest replies");
+    Ok(Bytes::from(response.to_string()))
 }
 
-/// Health check endpoint (not paywalled)
+/// Bad gateway reply for a node response over `max_node_response_bytes`
+fn response_too_large() -> NodeResponse {
+    let (code, message) = jsonrpc::RESPONSE_TOO_LARGE;
+    let error = json!({"jsonrpc": "2.0", "error": {"code": code, "message": message}, "id": null});
+    NodeResponse::new(StatusCode::BAD_GATEWAY, error.to_string())
+}
+
+/// 400 for a request carrying a raw transaction that fails `tx_precheck::precheck`
+/// Pre-checks cost node calls, so they're only made for accounts whose balance covers `reserved`
+/// (others are refused for want of funds afterwards), and for at most
+/// `tx_precheck::MAX_PRECHECKED_TRANSACTIONS` transactions per request
+async fn precheck_raw_transactions(
+    state: &AppState,
+    resource: &ResourceConfig,
+    address: &str,
+    reserved: f64,
+    body: &[u8],
+) -> Result<(), Response> {
+    let Ok(request) = serde_json::from_slice::<serde_json::Value>(body) else {
+        return Ok(());
+    };
+    let raw_transactions = tx_precheck::raw_transactions(&request);
+    if raw_transactions.is_empty() {
+        return Ok(());
+    }
+
+    if reserved > 0.0 {
+        match state.database.get_user(address).await {
+            Ok(user) if user.as_ref().is_some_and(|user| user.balance >= reserved) => {}
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                tracing::warn!(address = %address, error = %e, "Failed to read balance, skipping transaction pre-check");
+                return Ok(());
+            }
+        }
+    }
+
+    for raw in raw_transactions.into_iter().take(tx_precheck::MAX_PRECHECKED_TRANSACTIONS) {
+        if let Err(reason) = tx_precheck::precheck(&state.client, &resource.node_url, raw).await {
+            tracing::info!(address = %address, reason = %reason, "Raw transaction failed pre-check, not billed");
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("Transaction rejected before relaying: {}", reason),
+            ).into_response());
+        }
+    }
+    Ok(())
+}
+
+/// Whether a node's reply is a JSON-RPC error configured as worth retrying elsewhere
+fn is_transient(state: &AppState, response_body: &[u8]) -> bool {
+    serde_json::from_slice(response_body)
+        .is_ok_and(|response| jsonrpc::is_transient_error(&response, &state.config.transient_jsonrpc_errors))
+}
+
+/// Cache a successful response if the block it read is at or below the node's finalized block
+/// and, with `reorg_safety_depth`, at least that many blocks below its latest block
+async fn cache_if_finalized(state: &AppState, resource: &ResourceConfig, call: &serde_json::Value, response_body: &Bytes) {
+    let (Some(cache), Some(block)) = (&state.finalized_cache, finalized_cache::requested_block(call)) else {
+        return;
+    };
+
+    // Errors and null results (e.g. unknown block) aren't worth pinning
+    let Ok(response) = serde_json::from_slice::<serde_json::Value>(response_body) else {
+        return;
+    };
+    if response.get("result").is_none_or(|result| result.is_null()) {
+        return;
+    }
+
+    let Some(finalized) = head_block(state, cache, resource, Head::Finalized).await else {
+        return;
+    };
+    if block > finalized {
+        return;
+    }
+
+    let reorg_safety_depth = state.config.finalized_cache.as_ref().and_then(|config| config.reorg_safety_depth);
+    if let Some(depth) = reorg_safety_depth {
+        let Some(latest) = head_block(state, cache, resource, Head::Latest).await else {
+            return;
+        };
+        if block.saturating_add(depth) > latest {
+            return;
+        }
+    }
+
+    cache.lock().unwrap().insert(&resource.name, call, response);
+}
+
+/// The resource node's latest or finalized block, fetched when not known recently enough
+async fn head_block(
+    state: &AppState,
+    cache: &Mutex<FinalizedCache>,
+    resource: &ResourceConfig,
+    head: Head,
+) -> Option<u64> {
+    let known = cache.lock().unwrap().head_block(&resource.name, head);
+    if known.is_some() {
+        return known;
+    }
+
+    let block = finalized_cache::fetch_head_block(&state.client, &resource.node_url, head).await?;
+    cache.lock().unwrap().set_head_block(&resource.name, head, block);
+    Some(block)
+}
+
+/// Record relay latency and warn about requests slower than the configured threshold
+fn record_relay_latency(
+    state: &AppState,
+    resource: &ResourceConfig,
+    address: &str,
+    total: Duration,
+    node_latency: Duration,
+) {
+    state.metrics.relay_latency.record(total);
+
+    let Some(threshold_ms) = state.config.slow_request_threshold_ms else {
+        return;
+    };
+    if total > Duration::from_millis(threshold_ms) {
+        tracing::warn!(
+            resource = %resource.name,
+            address = %address,
+            total_ms = total.as_millis() as u64,
+            node_ms = node_latency.as_millis() as u64,
+            overhead_ms = total.saturating_sub(node_latency).as_millis() as u64,
+            threshold_ms,
+            "Slow relay request"
+        );
+    }
+}
+
+/// Add X-Upstream-Time-Ms with the node call duration if configured (0 when served from cache)
+fn with_upstream_time(state: &AppState, mut response: Response, node_latency: Duration) -> Response {
+    if state.config.upstream_time_header {
+        response
+            .headers_mut()
+            .insert("x-upstream-time-ms", HeaderValue::from(node_latency.as_millis() as u64));
+    }
+    response
+}
+
+/// Connection info inserted when serving over TLS (absent on plain HTTP)
+type Peer = Option<Extension<ConnectInfo<TlsPeer>>>;
+
+/// Common name of the verified client certificate on this connection, if any
+fn client_subject(peer: &Peer) -> Option<&str> {
+    peer.as_ref()?.0.0.client_subject.as_deref()
+}
+
+/// Mark a request signature as used so it can't be replayed
+fn consume_signature(state: &AppState, signature: Option<&str>) {
+    if let Some(signature) = signature {
+        state.signature_cache.add(signature);
+    }
+}
+
+/// Under monotonic timestamps, accept a signed request only if it is later than the
+/// address's last one; the signature cache stays as the in-memory fast path
+async fn advance_timestamp(state: &AppState, auth: &Authenticated) -> Result<(), Response> {
+    if !state.config.monotonic_timestamps || auth.signature.is_none() {
+        return Ok(());
+    }
+
+    let address = auth::account_key(&auth.address);
+    match state.database.advance_signed_timestamp(&address, auth.timestamp).await {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            let e = AuthError::StaleTimestamp;
+            tracing::warn!(address = %address, timestamp = auth.timestamp, error = %e, "Authentication failed");
+            Err((e.status(), e.to_string()).into_response())
+        }
+        Err(e) => {
+            tracing::error!(address = %address, error = %e, "Failed to record signed timestamp");
+            Err((StatusCode::INTERNAL_SERVER_ERROR, "Failed to verify request timestamp").into_response())
+        }
+    }
+}
+
+/// Amount held before relaying: the request price plus the bandwidth reserve, if billed
+fn reservation(state: &AppState, price: f64) -> f64 {
+    price + state.config.bandwidth_billing.as_ref().map_or(0.0, |billing| billing.reserve())
+}
+
+/// Take payment before relaying. Under bandwidth billing the cost depends on the response,
+/// so the most it can be is held and settled afterwards; a fixed price is simply deducted
+/// Returns the balance left and the reservation to settle, if any
+async fn take_payment(
+    state: &AppState,
+    address: &str,
+    amount: f64,
+    timestamp: u64,
+) -> Result<(f64, Option<Reservation>), DatabaseError> {
+    match &state.config.bandwidth_billing {
+        Some(_) => state.database
+            .reserve_balance(address, amount, timestamp)
+            .await
+            .map(|reservation| (reservation.remaining, Some(reservation))),
+        None => state.database
+            .deduct_balance(address, amount, timestamp)
+            .await
+            .map(|remaining| (remaining, None)),
+    }
+}
+
+/// Settle a payment from `take_payment` against the actual response size, refunding the unused part
+/// Returns the amount finally charged and the balance left
+async fn settle_payment(
+    state: &AppState,
+    price: f64,
+    (remaining_balance, reservation): (f64, Option<Reservation>),
+    reserved: f64,
+    response_bytes: usize,
+) -> (f64, f64) {
+    let (Some(billing), Some(reservation)) = (&state.config.bandwidth_billing, reservation) else {
+        return (reserved, remaining_balance);
+    };
+
+    let charged = (price + billing.charge(response_bytes)).min(reservation.amount);
+    match state.database.commit_reservation(&reservation, charged).await {
+        Ok(balance) => {
+            tracing::debug!(
+                address = %reservation.address,
+                response_bytes,
+                charged,
+                refund = reservation.amount - charged,
+                "Bandwidth reservation settled"
+            );
+            (charged, balance)
+        }
+        Err(e) => {
+            // The user keeps paying the full reservation, which is never more than the cap
+            tracing::error!(address = %reservation.address, error = %e, "Failed to settle bandwidth reservation");
+            (reservation.amount, reservation.remaining)
+        }
+    }
+}
+
+/// Record a charge for spend reports if configured; a failure is logged, not passed on to the request
+async fn record_spend(state: &AppState, address: &str, charged: f64) {
+    if !state.config.record_spend || charged <= EMPTY_BALANCE_THRESHOLD {
+        return;
+    }
+    let amount = u64::try_from(USDC.to_units(charged, state.config.price_rounding)).unwrap_or(u64::MAX);
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if let Err(e) = state.database.record_spend(address, amount, timestamp).await {
+        tracing::error!(address = %address, amount, error = %e, "Failed to record spend");
+    }
+}
+
+/// Record a relayed request in the account's history if configured; a failure is logged, not
+/// passed on to the request
+async fn record_request(state: &AppState, address: &str, body: &[u8], charged: f64, response: &NodeResponse) {
+    let Some(history) = &state.config.request_history else {
+        return;
+    };
+    let record = RequestRecord {
+        method: jsonrpc::method_summary(body),
+        price: charged,
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+        success: response.status.is_success(),
+    };
+    if let Err(e) = state.database.record_request(address, &record, history.retention_secs).await {
+        tracing::error!(address = %address, method = %record.method, error = %e, "Failed to record request history");
+    }
+}
+
+/// JSON-RPC error code for batch calls left unpaid in partial batch mode
+const UNPAID_CALL_CODE: i64 = -402;
+
+/// Leading calls of a batch that `balance` covers at `reserved` per call
+/// Returns the batch to relay, how many calls it holds and the unpaid remainder
+fn split_batch(body: &[u8], balance: f64, reserved: f64) -> Option<(Bytes, usize, Vec<serde_json::Value>)> {
+    let mut calls: Vec<serde_json::Value> = serde_json::from_slice(body).ok()?;
+    // At least one call is kept so an empty balance still gets the usual 402
+    let affordable = ((balance / reserved) + 1e-9).floor() as usize;
+    let paid = affordable.max(1).min(calls.len());
+    let unpaid = calls.split_off(paid);
+    Some((Bytes::from(serde_json::Value::Array(calls).to_string()), paid, unpaid))
+}
+
+/// Price of the relayed batch calls the node sent no reply for, at `call_price` each
+fn unanswered_refund(state: &AppState, relayed: &[u8], response: &NodeResponse, call_price: f64) -> f64 {
+    let (Ok(mut request), Ok(replies)) = (
+        serde_json::from_slice::<serde_json::Value>(relayed),
+        serde_json::from_slice::<serde_json::Value>(&response.body),
+    ) else {
+        return 0.0;
+    };
+    // Replies carry the ids the node saw
+    if state.config.normalize_jsonrpc_ids {
+        jsonrpc::normalize_ids(&mut request);
+    }
+    call_price * jsonrpc::unanswered_calls(&request, &replies) as f64
+}
+
+/// Append payment-required errors for unpaid calls to a relayed batch response
+fn with_unpaid_calls(mut response: NodeResponse, unpaid: &[serde_json::Value]) -> NodeResponse {
+    // Unpaid notifications get no error reply
+    let errors: Vec<_> = unpaid
+        .iter()
+        .filter_map(|call| jsonrpc::error_reply(call, UNPAID_CALL_CODE, "Insufficient balance"))
+        .collect();
+    if errors.is_empty() {
+        return response;
+    }
+    // A relayed part made only of notifications has no replies to append to
+    let relayed_replies = if response.status == StatusCode::NO_CONTENT {
+        response.status = StatusCode::OK;
+        Ok(serde_json::Value::Array(Vec::new()))
+    } else {
+        serde_json::from_slice(&response.body)
+    };
+    if let Ok(serde_json::Value::Array(mut replies)) = relayed_replies {
+        replies.extend(errors);
+        response.body = Bytes::from(serde_json::Value::Array(replies).to_string());
+    }
+    response
+}
+
+/// Run a settlement while holding a permit, queueing when the limit is reached
+async fn with_settlement_permit<F: Future>(permits: &Semaphore, settle: F) -> F::Output {
+    let _permit = permits
+        .acquire()
+        .await
+        .expect("Settlement semaphore is never closed");
+    settle.await
+}
+
+/// Main relay endpoint - handles both payments and authenticated requests
+#[instrument(skip_all, fields(resource = %resource.name, body_size))]
+pub async fn relay(
+    State(state): State<Arc<AppState>>,
+    Extension(resource): Extension<Arc<ResourceConfig>>,
+    peer: Peer,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let started = Instant::now();
+    tracing::Span::current().record("body_size", body.len());
+
+    // Catch misconfigured clients before anything is paid for or relayed if configured
+    if state.config.require_json_content_type && !is_json_content_type(&headers) {
+        tracing::warn!(content_type = ?headers.get(header::CONTENT_TYPE), "Relay request is not JSON");
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content-Type must be application/json",
+        ).into_response();
+    }
+
+    // Reject oversized batches before anything is paid for or relayed
+    if let Some(calls) = jsonrpc::batch_size(&body) {
+        if calls > state.config.max_batch_size {
+            tracing::warn!(calls, max_batch_size = state.config.max_batch_size, "Batch too large");
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Batch of {} calls exceeds the limit of {}", calls, state.config.max_batch_size),
+            ).into_response();
+        }
+    }
+
+    // Reject ids that aren't a string, number or null before anything is paid for or relayed
+    if let Ok(request) = serde_json::from_slice::<serde_json::Value>(&body) {
+        if let Some(id) = jsonrpc::invalid_id(&request) {
+            tracing::warn!(id = %id, "Invalid JSON-RPC id type");
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid JSON-RPC id {}: must be a string, number or null", id),
+            ).into_response();
+        }
+    }
+
+    // Shed load once the gateway-wide relay limit is reached; the permit is held until the response
+    let _permit = match &state.relay_permits {
+        Some(permits) => match permits.clone().try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) => {
+                tracing::warn!("Relay concurrency limit reached, shedding request");
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(header::RETRY_AFTER, "1")],
+                    "Gateway is at capacity, retry shortly",
+                ).into_response();
+            }
+        },
+        None => None,
+    };
+
+    // Log the body for debugging if configured, with sensitive params and headers redacted
+    if let Some(logger) = &state.body_logger {
+        logger.log_request(&headers, &body);
+    }
+
+    // Check if this is a payment/top-up request (has X-Payment header)
+    if has_payment_header(&headers) {
+        return handle_payment_with_paygate(state, &resource, headers, body).await;
+    }
+
+    // Turn away accounts that can't afford a request before the costly signature check if configured
+    if state.config.reject_unfunded_before_auth {
+        if let Err(response) = reject_unfunded(&state, &resource, &headers).await {
+            return response;
+        }
+    }
+
+    // Not a payment - authenticate with the first enabled scheme the request uses
+    let replay_key = replay_key(&state, uri.path(), &headers, &body);
+    state.metrics.authentications.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let auth = match auth::authenticate(&state.auth_schemes, &AuthRequest {
+        method: &method,
+        path: uri.path(),
+        headers: &headers,
+        body: &body,
+        client_subject: client_subject(&peer),
+    }) {
+        Ok(auth) => auth,
+        Err(AuthError::Missing) => {
+            tracing::debug!("No authentication headers found");
+            return request_payment(&state, &resource, PaymentReason::Unauthenticated);
+        }
+        Err(e) => {
+            // A retried read whose response was lost gets that response rather than a replay error
+            if let (AuthError::Replay, Some(cache), Some((address, key))) = (&e, &state.replay_cache, &replay_key) {
+                let cached = cache.lock().unwrap().get(address, key);
+                if let Some(response) = cached {
+                    tracing::info!(address = %address, "Returning cached response for replayed read");
+                    return response.into_response();
+                }
+            }
+            tracing::warn!(error = %e, "Authentication failed");
+            return (e.status(), e.to_string()).into_response();
+        }
+    };
+
+    // Under monotonic timestamps, the request must be later than the address's last one
+    if let Err(response) = advance_timestamp(&state, &auth).await {
+        return response;
+    }
+    let Authenticated { address, timestamp, signature } = auth;
+
+    // Bill the verified identity under its canonical key, never the raw header string
+    let address = auth::account_key(&address);
+
+    // Suspended accounts keep their balance but can't spend it
+    let user = match refuse_if_suspended(&state, &address).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+
+    // A repeated idempotency key replays the earlier response without billing again
+    let idempotency_key = state
+        .idempotency_cache
+        .as_ref()
+        .and(extract_idempotency_key(&headers));
+
+    if let (Some(cache), Some(key)) = (&state.idempotency_cache, &idempotency_key) {
+        let cached = cache.lock().unwrap().get(&address, key);
+        if let Some(response) = cached {
+            // The signature is still consumed so it can't be replayed
+            consume_signature(&state, signature.as_deref());
+
+            tracing::info!(
+                address = %address,
+                idempotency_key = %key,
+                "Returning cached response for idempotency key"
+            );
+            return response.into_response();
+        }
+    }
+
+    // Brand-new accounts make their first requests for free if configured; the free request is
+    // only counted once the checks below pass, so a refused request doesn't use one up
+    let free = has_free_requests(&state, user.as_ref());
+
+    // Check user balance, reserving for the largest billable response under bandwidth billing
+    let mut price = if free { 0.0 } else { asset_price(&state, state.config.price_for(&resource, &address)) };
+    let mut reserved = if free { 0.0 } else { reservation(&state, price) };
+
+    // In partial batch mode batches are billed per call, and only the leading calls
+    // the balance covers are relayed
+    let mut body = body;
+    let mut unpaid = Vec::new();
+    let mut call_price = None;
+    if !free && state.config.partial_batch && jsonrpc::batch_size(&body).is_some() {
+        let balance = match state.database.get_user(&address).await {
+            Ok(user) => user.map_or(0.0, |user| user.balance),
+            Err(e) => {
+                tracing::error!(address = %address, error = %e, "Failed to read balance for partial batch");
+                return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to read balance").into_response();
+            }
+        };
+        if let Some((paid_body, paid, rest)) = split_batch(&body, balance, reserved) {
+            if !rest.is_empty() {
+                tracing::info!(address = %address, paid, unpaid = rest.len(), "Partially paid batch");
+            }
+            body = paid_body;
+            call_price = Some(price);
+            price *= paid as f64;
+            reserved *= paid as f64;
+            unpaid = rest;
+        }
+    }
+
+    // Refuse raw transactions the node would obviously reject before billing for them if configured
+    if state.config.precheck_raw_transactions {
+        if let Err(response) = precheck_raw_transactions(&state, &resource, &address, reserved, &body).await {
+            return response;
+        }
+    }
+
+    // Shed paid requests once the gateway-wide budget for this window is spent
+    let mut budget_window = match spend_global_budget(&state, &address, price) {
+        Ok(window) => window,
+        Err(response) => return response,
+    };
+
+    if free {
+        if use_free_request(&state, &address, timestamp).await {
+            tracing::info!(address = %address, "Free request for new account");
+        } else {
+            // A concurrent request took the last free request, so this one is billed as usual
+            refund_global_budget(&state, price, budget_window);
+            price = asset_price(&state, state.config.price_for(&resource, &address));
+            reserved = reservation(&state, price);
+            budget_window = match spend_global_budget(&state, &address, price) {
+                Ok(window) => window,
+                Err(response) => return response,
+            };
+        }
+    }
+
+    let payment = take_payment(&state, &address, reserved, timestamp).await;
+    if payment.is_err() {
+        refund_global_budget(&state, price, budget_window);
+    }
+    match payment {
+        Ok(payment) => {
+            // Add signature to cache to prevent replay
+            consume_signature(&state, signature.as_deref());
+
+            tracing::info!(
+                address = %address,
+                deducted = reserved,
+                remaining = payment.0,
+                "Request authorized, balance deducted"
+            );
+
+            // Forward to RPC node
+            let (response, node_latency) = relay_to_node(&state, &resource, body.clone()).await;
+            record_relay_latency(&state, &resource, &address, started.elapsed(), node_latency);
+
+            // Calls billed per call but left unanswered by the node are refunded: taken off the
+            // charge under bandwidth billing, credited back otherwise
+            let refund = call_price.map_or(0.0, |call_price| unanswered_refund(&state, &body, &response, call_price));
+            let (charged, balance) = settle_payment(&state, price - refund, payment, reserved, response.body.len()).await;
+            let (charged, balance) = if refund > 0.0 && state.config.bandwidth_billing.is_none() {
+                match state.database.add_balance(&address, refund).await {
+                    Ok(balance) => (charged - refund, balance),
+                    Err(e) => {
+                        tracing::error!(address = %address, refund, error = %e, "Failed to refund unanswered batch calls");
+                        (charged, balance)
+                    }
+                }
+            } else {
+                (charged, balance)
+            };
+            if refund > 0.0 {
+                tracing::info!(address = %address, refund, "Refunded batch calls the node left unanswered");
+            }
+            if let Some(alerts) = &state.balance_alerts {
+                alerts.observe_deduction(&address, charged, balance);
+            }
+            record_spend(&state, &address, charged).await;
+            record_request(&state, &address, &body, charged, &response).await;
+            let response = with_unpaid_calls(response, &unpaid);
+
+            if let (Some(cache), Some(key)) = (&state.idempotency_cache, &idempotency_key) {
+                if response.status.is_success() {
+                    cache.lock().unwrap().insert(&address, key, response.clone());
+                }
+            }
+            if let (Some(cache), Some((claimed, key))) = (&state.replay_cache, &replay_key) {
+                if response.status.is_success() {
+                    cache.lock().unwrap().insert(claimed, key, response.clone());
+                }
+            }
+
+            with_upstream_time(&state, response.into_response(), node_latency)
+        }
+        Err(DatabaseError::InsufficientBalance { has, need }) => {
+            tracing::info!(
+                address = %address,
+                balance = has,
+                required = need,
+                "Insufficient balance"
+            );
+            request_payment(&state, &resource, PaymentReason::InsufficientBalance { balance: has, required: need })
+        }
+        Err(e) => {
+            tracing::info!(
+                address = %address,
+                error = %e,
+                required = reserved,
+                "Balance deduction failed"
+            );
+            request_payment(&state, &resource, PaymentReason::Unauthenticated)
+        }
+    }
+}
+
+/// Count a request costing `price` against the gateway-wide budget, if one is configured
+/// Returns the window it was counted in, or a 503 once this window's budget is spent
+fn spend_global_budget(state: &AppState, address: &str, price: f64) -> Result<Option<Instant>, Response> {
+    let Some(budget) = &state.global_budget else {
+        return Ok(None);
+    };
+    match budget.try_spend(price, Instant::now()) {
+        Ok(window) => Ok(Some(window)),
+        Err(retry_after) => {
+            tracing::warn!(address = %address, price, "Global budget exhausted, shedding request");
+            let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+            Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                "Gateway budget exhausted, retry later",
+            ).into_response())
+        }
+    }
+}
+
+/// Give back a request counted against the global budget that ended up not being charged
+fn refund_global_budget(state: &AppState, price: f64, window: Option<Instant>) {
+    if let (Some(budget), Some(window)) = (&state.global_budget, window) {
+        budget.refund(price, window);
+    }
+}
+
+/// Whether the request declares a JSON body; parameters such as charset are ignored
+fn is_json_content_type(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Address the X-Payment header's payment pays, if the header can be read
+/// Only used to pick the address to verify against; the facilitator checks the payment itself
+fn payment_recipient(headers: &HeaderMap) -> Option<String> {
+    use base64::Engine;
+
+    let header = headers.get("X-Payment")?.to_str().ok()?;
+    let decoded = base64::engine::general_purpose::STANDARD.decode(header).ok()?;
+    let payment: serde_json::Value = serde_json::from_slice(&decoded).ok()?;
+    DepositAuthorization::recipient(&payment).map(str::to_string)
+}
+
+/// Handle payment/deposit request through the paygate
+async fn handle_payment_with_paygate(
+    state: Arc<AppState>,
+    resource: &ResourceConfig,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    // Balances are credited externally when deposits are disabled
+    let Some(paygate) = state.paygate.clone() else {
+        return (StatusCode::FORBIDDEN, "Deposits are disabled on this gateway").into_response();
+    };
+
+    // Create payment requirements for top-up
+    let mut payment_requirements = match create_payment_requirements(&state, resource) {
+        Ok(requirements) => requirements,
+        Err(e) => {
+            tracing::warn!(resource = %resource.name, error = %e, "Deposit for unsupported asset or network");
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Deposits are not accepted for this resource: {}", e),
+            ).into_response();
+        }
+    };
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    // Challenges advertise the current payment address, but a payment to a rotated-out one
+    // still in its grace period is verified against that address
+    let recipient = payment_recipient(&headers);
+    let pay_to = state.config.accepted_payment_address(resource.network, recipient.as_deref().unwrap_or_default(), now);
+    if pay_to != state.config.payment_address_for(resource.network) {
+        tracing::info!(resource = %resource.name, pay_to = %pay_to, "Deposit to a previous payment address");
+        if let Some(previous) = state.prepared_requirements.previous_pay_to(pay_to) {
+            for requirement in &mut payment_requirements {
+                requirement.pay_to = previous.clone();
+            }
+        }
+    }
+
+    // Extract the payment and verify it with the facilitator
+    let verify_request = match paygate.verify(&headers, payment_requirements).await {
+        Ok(request) => request,
+        Err(PaygateError::Extraction(response)) => {
+            tracing::warn!("Payment extraction failed");
+            return response;
+        }
+        Err(PaygateError::Verification(err)) => {
+            tracing::warn!(error = %err, "Payment verification failed");
+            return verification_failed(&state, resource, &err);
+        }
+    };
+
+    // Extract user address and amount from verified payment
+    // Convert PaymentPayload to JSON to extract fields
+    let payment_json = match serde_json::to_value(&verify_request.payment_payload) {
+        Ok(json) => json,
+        Err(e) => {
+            tracing::error!("Failed to serialize payment payload: {}", e);
+            return (
+                StatusCode::BAD_REQUEST,
+                "Invalid payment format",
+            ).into_response();
+        }
+    };
+
+    // Balances are spent on any resource, so keep chains apart if configured: the payment must be
+    // on the network this resource relays to
+    if state.config.require_deposit_network_match {
+        let payment_network = payment_json.get("network").and_then(|network| network.as_str()).unwrap_or_default();
+        if payment_network != resource.network.to_string() {
+            tracing::warn!(
+                resource = %resource.name,
+                network = %payment_network,
+                relay_network = %resource.network,
+                "Payment on a different network than the resource relays to"
+            );
+            return (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Payment is on network '{}' but this resource relays to '{}'",
+                    payment_network, resource.network
+                ),
+            ).into_response();
+        }
+    }
+
+    // Extract payer, amount and nonce from the ERC-3009 authorization or EIP-2612 permit
+    let authorization = match DepositAuthorization::from_payment(&payment_json) {
+        Ok(authorization) => authorization,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to parse payment authorization");
+            return (
+                StatusCode::BAD_REQUEST,
+                "Invalid payment format",
+            ).into_response();
+        }
+    };
+
+    // A permit must name the address the payment was verified against
+    if let Err(e) = authorization.validate(state.config.accept_permit, pay_to, now) {
+        tracing::warn!(payer = %authorization.payer(), error = %e, "Payment authorization rejected");
+        return verification_failed(&state, resource, &e);
+    }
+
+    let payer_address = authorization.payer().to_string();
+
+    // Credit what the facilitator verified, not what the payload claims,
+    // converted from the token's smallest unit to USDC
+    let verified = authorization
+        .value()
+        .and_then(|claimed| Ok((claimed, verified_amount(&verify_request.payment_requirements, claimed)?)));
+    let amount_usdc = match verified {
+        Ok((claimed, verified)) => {
+            if claimed > verified {
+                tracing::warn!(
+                    payer = %authorization.payer(),
+                    claimed = %claimed,
+                    verified = %verified,
+                    "Payment payload claims more than the facilitator verified, crediting the verified amount"
+                );
+            }
+            verified_tokens(&state, &verify_request.payment_requirements, verified)
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "Invalid payment amount");
+            return (
+                StatusCode::BAD_REQUEST,
+                "Invalid payment format",
+            ).into_response();
+        }
+    };
+
+    // Refuse dust deposits before settling, so no gas is spent on them
+    if let Err(e) = check_min_deposit(state.config.min_deposit, amount_usdc) {
+        tracing::warn!(payer = %payer_address, amount = amount_usdc, "Deposit below minimum");
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+
+    // Credit the payer, or a different account the payer has signed off on
+    let authorization_nonce = authorization.nonce();
+
+    let user_address = match resolve_credit_address(&headers, &payer_address, authorization_nonce) {
+        Ok(address) => address,
+        Err(e) => {
+            tracing::warn!(
+                payer = %payer_address,
+                error = %e,
+                "Credit address authorization failed"
+            );
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid credit address authorization: {}", e),
+            ).into_response();
+        }
+    };
+
+    if !user_address.eq_ignore_ascii_case(&payer_address) {
+        tracing::info!(
+            payer = %payer_address,
+            credited = %user_address,
+            "Sponsored deposit authorized"
+        );
+    }
+
+    // Don't take payments for suspended accounts
+    if let Err(response) = refuse_if_suspended(&state, &user_address).await {
+        return response;
+    }
+
+    // Nor payments that would open an account past the cap, which couldn't be credited
+    match state.database.accepts_credit(&user_address).await {
+        Ok(true) => {}
+        Ok(false) => {
+            tracing::warn!(address = %user_address, "Refusing deposit opening an account past the account cap");
+            return (StatusCode::SERVICE_UNAVAILABLE, "Not accepting new accounts").into_response();
+        }
+        Err(e) => {
+            tracing::error!(address = %user_address, error = %e, "Failed to check the account cap");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    }
+
+    // The deposit pays for a request too, so it can't get past the gateway-wide budget
+    let price = asset_price(&state, state.config.price_for(resource, &user_address));
+    let budget_window = match spend_global_budget(&state, &user_address, price) {
+        Ok(window) => window,
+        Err(response) => return response,
+    };
+
+    // The payment must answer a challenge this gateway issued, once; redeemed last so
+    // a deposit refused above can retry with the same challenge
+    if let Err(e) = redeem_challenge(&state, &headers) {
+        tracing::warn!(payer = %payer_address, error = %e, "Payment challenge rejected");
+        refund_global_budget(&state, price, budget_window);
+        return verification_failed(&state, resource, &e);
+    }
+
+    tracing::info!(
+        address = %user_address,
+        amount = amount_usdc,
+        async_settlement = state.config.async_settlement,
+        "Payment verified, settling and adding to balance"
+    );
+
+    // Settle on-chain before crediting, unless settlement is deferred to the background
+    // (bounded concurrency towards the facilitator either way)
+    let settle = {
+        let permits = state.settlement_permits.clone();
+        async move { with_settlement_permit(&permits, paygate.settle(&verify_request)).await }
+    };
+    let background_settlement = if state.config.async_settlement {
+        Some(settle)
+    } else {
+        let settled = match state.config.settlement_timeout_secs {
+            Some(secs) => tokio::time::timeout(Duration::from_secs(secs), settle)
+                .await
+                .unwrap_or_else(|_| Err(format!("timed out after {}s", secs))),
+            None => settle.await,
+        };
+        if let Err(reason) = settled {
+            let error = SettlementError {
+                payer: payer_address,
+                credited: user_address,
+                amount_usdc,
+                reason,
+            };
+            tracing::error!(
+                payer = %error.payer,
+                credited = %error.credited,
+                amount = error.amount_usdc,
+                reason = %error.reason,
+                "{}",
+                error
+            );
+            refund_global_budget(&state, price, budget_window);
+            return error.into_response();
+        }
+        tracing::info!(
+            address = %user_address,
+            "Payment settled successfully"
+        );
+        None
+    };
+
+    // Credit the deposit and pay for this request
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let reserved = reservation(&state, price);
+    let deducted = match credit_deposit(&state, &user_address, amount_usdc, reserved, timestamp).await {
+        Ok(deducted) => deducted,
+        Err(e @ CreditError::Deduction(_)) => {
+            state.metrics.deduction_failures.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let proceed = state.config.on_deduction_failure == DeductionFailurePolicy::Proceed;
+            tracing::error!(
+                address = %user_address,
+                amount = amount_usdc,
+                price = reserved,
+                error = %e,
+                relayed = proceed,
+                "Deposit credited but the request could not be charged"
+            );
+            if !proceed {
+                refund_global_budget(&state, price, budget_window);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Deposit credited but the request could not be charged, please retry",
+                ).into_response();
+            }
+            None
+        }
+        Err(e) => {
+            tracing::error!(
+                address = %user_address,
+                error = %e,
+                "Failed to add balance"
+            );
+            refund_global_budget(&state, price, budget_window);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to process payment: {}", e),
+            ).into_response();
+        }
+    };
+    if deducted.is_none() {
+        refund_global_budget(&state, price, budget_window);
+    }
+
+    // The optimistic credit is taken back if the deferred settlement fails
+    if let Some(settle) = background_settlement {
+        let deposit = PendingDeposit {
+            payer: payer_address,
+            credited: user_address.to_lowercase(),
+            amount_usdc,
+        };
+        let handle = settlement::spawn_settlement(state.database.clone(), deposit, settle);
+        state.background_tasks.track(handle);
+    }
+
+    // Process the original request
+    let (response, node_latency) = relay_to_node(&state, resource, body.clone()).await;
+
+    if let Some(payment) = deducted {
+        let (charged, balance) =
+            settle_payment(&state, price, payment, reserved, response.body.len()).await;
+        if let Some(alerts) = &state.balance_alerts {
+            alerts.observe_deduction(&user_address, charged, balance);
+        }
+        record_spend(&state, &user_address, charged).await;
+        record_request(&state, &user_address, &body, charged, &response).await;
+    }
+
+    with_upstream_time(&state, response.into_response(), node_latency)
+}
+
+/// Credit a deposit and take payment for the request it came with. Under fixed pricing both
+/// happen in one atomic write, so no read sees the credit without the deduction; bandwidth
+/// billing holds a reservation, so there the deposit is credited first
+/// Returns the payment to settle (None if the balance didn't cover the request)
+async fn credit_deposit(
+    state: &AppState,
+    address: &str,
+    credit: f64,
+    reserved: f64,
+    timestamp: u64,
+) -> Result<Option<(f64, Option<Reservation>)>, CreditError> {
+    if state.config.bandwidth_billing.is_none() {
+        match state.database.deposit_and_deduct(address, credit, reserved, timestamp).await {
+            Ok(remaining) => {
+                record_deposit(state, address, credit, timestamp).await;
+                return Ok(Some((remaining, None)));
+            }
+            // Too small to pay for the request, but the deposit is still credited below
+            Err(DatabaseError::InsufficientBalance { .. }) => {}
+            Err(e) => return Err(CreditError::Deposit(e)),
+        }
+    }
+
+    let new_balance = state.database.add_balance(address, credit).await.map_err(CreditError::Deposit)?;
+    tracing::info!(
+        address = %address,
+        new_balance = new_balance,
+        "Balance updated successfully"
+    );
+    record_deposit(state, address, credit, timestamp).await;
+
+    match take_payment(state, address, reserved, timestamp).await {
+        Ok(payment) => Ok(Some(payment)),
+        Err(DatabaseError::InsufficientBalance { .. }) => {
+            tracing::warn!(address = %address, "Deposit too small to pay for its request");
+            Ok(None)
+        }
+        Err(e) => Err(CreditError::Deduction(e)),
+    }
+}
+
+/// Record a credited deposit in the audit ledger, so reconciliation can account for it
+async fn record_deposit(state: &AppState, address: &str, amount: f64, timestamp: u64) {
+    if state.config.reconciliation.is_none() {
+        return;
+    }
+    let entry = reconciliation::deposit_entry(address, amount, timestamp);
+    if let Err(e) = state.database.append_audit(&entry).await {
+        tracing::error!(error = %e, entry = ?entry, "Failed to record deposit in the ledger");
+    }
+}
+
+/// Query parameters for the price quote endpoint
+#[derive(Debug, Deserialize)]
+pub struct QuoteParams {
+    /// Resource name (defaults to the first configured resource)
+    resource: Option<String>,
+    /// Address to quote for, picking up any negotiated price
+    address: Option<String>,
+}
+
+/// Price quote endpoint (not paywalled)
+pub async fn quote(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<QuoteParams>,
+) -> Response {
+    let resource = match &params.resource {
+        Some(name) => state.config.resources.iter().find(|r| &r.name == name),
+        None => state.config.resources.first(),
+    };
+
+    let Some(resource) = resource else {
+        return (StatusCode::NOT_FOUND, "Unknown resource").into_response();
+    };
+
+    let price = match &params.address {
+        Some(address) => state.config.price_for(resource, address),
+        None => resource.price_per_request,
+    };
+
+    // Quote what will actually be charged and credited, in USDC
+    let price = asset_price(&state, price);
+    let topup_amount = asset_price(&state, TOPUP_AMOUNT_USDC);
+
+    axum::Json(json!({
+        "resource": resource.name,
+        "path": resource.path,
+        "network": resource.network,
+        "price_per_request": price,
+        "topup_amount": topup_amount,
+    })).into_response()
+}
+
+/// Discovery document describing how to pay this gateway (not paywalled)
+/// Only public values are included: negotiated per-address prices and credentials stay private
+pub async fn discovery(State(state): State<Arc<AppState>>) -> Response {
+    let mut networks: Vec<String> = Vec::new();
+    for resource in &state.config.resources {
+        let network = resource.network.to_string();
+        if !networks.contains(&network) {
+            networks.push(network);
+        }
+    }
+
+    let resources: Vec<serde_json::Value> = state.config.resources.iter().map(|resource| json!({
+        "name": resource.name,
+        "path": resource.path,
+        "network": resource.network,
+        "price_per_request": resource.price_per_request,
+    })).collect();
+
+    let payment_addresses: serde_json::Map<String, serde_json::Value> = state
+        .config
+        .payment_addresses
+        .iter()
+        .map(|(network, address)| (network.to_string(), json!(auth::display_address(address))))
+        .collect();
+
+    axum::Json(json!({
+        "x402_version": 1,
+        "payment_address": auth::display_address(&state.config.payment_address),
+        "payment_addresses": payment_addresses,
+        "networks": networks,
+        "assets": [{"symbol": USDC.symbol, "address": auth::display_address(USDC.address), "decimals": USDC.decimals}],
+        "deposits_enabled": state.config.deposits_enabled,
+        "accept_permit": state.config.accept_permit,
+        "price_model": {
+            "type": "prepaid",
+            "topup_amount": TOPUP_AMOUNT_USDC,
+            "resources": resources,
+        },
+    })).into_response()
+}
+
+/// Balance endpoint - the authenticated caller's balance
+pub async fn balance(
+    State(state): State<Arc<AppState>>,
+    peer: Peer,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    let auth = match auth::authenticate(&state.auth_schemes, &AuthRequest {
+        method: &method,
+        path: uri.path(),
+        headers: &headers,
+        body: &[],
+        client_subject: client_subject(&peer),
+    }) {
+        Ok(auth) => auth,
+        Err(e) => return (e.status(), e.to_string()).into_response(),
+    };
+    if let Err(response) = advance_timestamp(&state, &auth).await {
+        return response;
+    }
+    consume_signature(&state, auth.signature.as_deref());
+    let address = auth::account_key(&auth.address);
+
+    // Display-only read, so a slightly stale replica value is fine
+    match state.database.get_user_consistent(&address, ConsistencyLevel::Eventual).await {
+        Ok(user) => axum::Json(json!({
+            "address": auth::display_address(&address),
+            "balance": user.map_or(0.0, |user| user.balance),
+        })).into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, address = %address, "Balance lookup failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// Query parameters for the request history endpoint
+#[derive(Debug, Deserialize)]
+pub struct HistoryParams {
+    /// Most entries to return (capped at `request_history.max_page_size`, which is the default)
+    limit: Option<usize>,
+    /// Cursor from the previous page's `next`, continuing with older entries
+    before: Option<String>,
+}
+
+/// Request history endpoint - the caller's relayed requests, newest first, a page at a time
+pub async fn history(
+    State(state): State<Arc<AppState>>,
+    peer: Peer,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    Query(params): Query<HistoryParams>,
+) -> Response {
+    let Some(history) = &state.config.request_history else {
+        return (StatusCode::NOT_FOUND, "Request history is not recorded").into_response();
+    };
+    let before = match params.before.as_deref().map(str::parse::<u128>).transpose() {
+        Ok(before) => before,
+        Err(_) => return (StatusCode::BAD_REQUEST, "Invalid history cursor").into_response(),
+    };
+    let limit = params.limit.unwrap_or(history.max_page_size).clamp(1, history.max_page_size);
+
+    let auth = match auth::authenticate(&state.auth_schemes, &AuthRequest {
+        method: &method,
+        path: uri.path(),
+        headers: &headers,
+        body: &[],
+        client_subject: client_subject(&peer),
+    }) {
+        Ok(auth) => auth,
+        Err(e) => return (e.status(), e.to_string()).into_response(),
+    };
+    if let Err(response) = advance_timestamp(&state, &auth).await {
+        return response;
+    }
+    consume_signature(&state, auth.signature.as_deref());
+    let address = auth::account_key(&auth.address);
+
+    // Entries past retention may not have been removed yet
+    let since = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        .saturating_sub(history.retention_secs);
+    match state.database.list_requests(&address, since, before, limit).await {
+        Ok(page) => axum::Json(json!({
+            "address": auth::display_address(&address),
+            "entries": page.records,
+            "next": page.next.map(|next| next.to_string()),
+        })).into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, address = %address, "Request history lookup failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// Auth state endpoint - what the gateway last accepted from the caller, so a client that
+/// lost track (e.g. after a crash) can resync. Not held to monotonic timestamps itself,
+/// since the caller may not know a timestamp that would pass
+pub async fn auth_state(
+    State(state): State<Arc<AppState>>,
+    peer: Peer,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    let auth = match auth::authenticate(&state.auth_schemes, &AuthRequest {
+        method: &method,
+        path: uri.path(),
+        headers: &headers,
+        body: &[],
+        client_subject: client_subject(&peer),
+    }) {
+        Ok(auth) => auth,
+        Err(e) => return (e.status(), e.to_string()).into_response(),
+    };
+    consume_signature(&state, auth.signature.as_deref());
+    let address = auth::account_key(&auth.address);
+
+    let lookups = tokio::try_join!(
+        state.database.get_signed_timestamp(&address),
+        state.database.get_user(&address),
+    );
+    match lookups {
+        Ok((last_accepted, user)) => axum::Json(json!({
+            "address": auth::display_address(&address),
+            "monotonic_timestamps": state.config.monotonic_timestamps,
+            "last_accepted_timestamp": last_accepted,
+            "last_request_timestamp": user.map(|user| user.latest_timestamp).filter(|timestamp| *timestamp > 0),
+        })).into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, address = %address, "Auth state lookup failed");
+            (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response()
+        }
+    }
+}
+
+/// Signed request details submitted to the debug verifier
+#[derive(Debug, Deserialize)]
+pub struct DebugVerifyRequest {
+    /// X-Auth-Address exactly as sent
+    address: String,
+    signature: String,
+    timestamp: u64,
+    /// Request body exactly as sent
+    #[serde(default)]
+    body: String,
+    /// Defaults to POST
+    method: Option<String>,
+    /// Defaults to the first resource's path
+    path: Option<String>,
+}
+
+/// Debug endpoint - show the message the gateway reconstructs for a signature and who signed it
+/// Nothing is authenticated, billed or recorded
+pub async fn debug_verify(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::Json(request): axum::Json<DebugVerifyRequest>,
+) -> Response {
+    let method = request.method.unwrap_or_else(|| Method::POST.to_string()).to_uppercase();
+    let path = request
+        .path
+        .unwrap_or_else(|| state.config.resources[0].path.clone());
+
+    // Hash the body as the client's X-Auth-Body-Hash-Algorithm and X-Auth-Body-Canonicalization
+    // say, accepting what signature authentication accepts
+    let message = auth::body_hash_algorithm(&headers, &state.config.body_hash_algorithms)
+        .and_then(|algorithm| {
+            let canonicalization = auth::body_canonicalization(&headers, &state.config.body_canonicalizations)?;
+            auth::signed_message_with(
+                &request.address,
+                request.timestamp,
+                &method,
+                &path,
+                request.body.as_bytes(),
+                algorithm,
+                canonicalization,
+            )
+        });
+    let message = match message {
+        Ok(message) => message,
+        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+    };
+
+    let recovered = auth::recover_signer(&message, &request.signature);
+    let claimed = Address::from_str(&request.address);
+    let matches = matches!((&recovered, &claimed), (Ok(recovered), Ok(claimed)) if recovered == claimed);
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    axum::Json(json!({
+        "message": message,
+        "message_hash": alloy::primitives::keccak256(message.as_bytes()).to_string(),
+        "recovered_address": recovered.as_ref().ok().map(|address| address.to_string()),
+        "matches": matches,
+        "error": recovered.err().or_else(|| claimed.err().map(|e| format!("Invalid address format: {}", e))),
+        "timestamp_drift_secs": now as i64 - request.timestamp as i64,
+    })).into_response()
+}
+
+/// Manual balance adjustment requested by an admin
+#[derive(Debug, Deserialize)]
+pub struct AdjustRequest {
+    address: String,
+    /// Signed amount in USDC (positive credits, negative debits)
+    delta: f64,
+    reason: String,
+}
+
+/// Whether the request carries the configured admin bearer token
+fn is_admin(state: &AppState, headers: &HeaderMap) -> bool {
+    let Some(expected) = &state.config.admin_token else {
+        return false;
+    };
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    // Compare digests so the comparison time doesn't depend on the token contents
+    provided.is_some_and(|token| {
+        alloy::primitives::keccak256(token.as_bytes()) == alloy::primitives::keccak256(expected.as_bytes())
+    })
+}
+
+/// Admin endpoint - credit or debit a balance and record the adjustment in the audit ledger
+pub async fn admin_adjust(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::Json(request): axum::Json<AdjustRequest>,
+) -> Response {
+    if !is_admin(&state, &headers) {
+        return (StatusCode::UNAUTHORIZED, "Invalid admin token").into_response();
+    }
+
+    // Adjust the key signed requests are billed under, however the address was written
+    let Ok(address) = Address::from_str(&request.address) else {
+        return (StatusCode::BAD_REQUEST, "Invalid address").into_response();
+    };
+    let address = auth::account_key(&address);
+    if !request.delta.is_finite() || request.delta == 0.0 {
+        return (StatusCode::BAD_REQUEST, "delta must be a non-zero number").into_response();
+    }
+    if request.reason.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, "reason is required").into_response();
+    }
+
+    let actor = headers
+        .get("x-admin-actor")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("admin")
+        .to_string();
+
+    let result = if request.delta > 0.0 {
+        state.database.add_balance(&address, request.delta).await
+    } else {
+        // Keep the account's last request time so a debit doesn't count as activity
+        match state.database.get_user(&address).await {
+            Ok(user) => {
+                let latest_timestamp = user.map_or(0, |user| user.latest_timestamp);
+                state
+                    .database
+                    .deduct_balance(&address, -request.delta, latest_timestamp)
+                    .await
+            }
+            Err(e) => Err(e),
+        }
+    };
+
+    let balance = match result {
+        Ok(balance) => balance,
+        Err(DatabaseError::InsufficientBalance { .. }) => {
+            return (StatusCode::CONFLICT, "Adjustment would overdraw the balance").into_response();
+        }
+        Err(e) => {
+            tracing::error!(error = %e, address = %address, "Admin adjustment failed");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    // Debits can take an account below the alert threshold too
+    if request.delta < 0.0 {
+        if let Some(alerts) = &state.balance_alerts {
+            alerts.observe_deduction(&address, -request.delta, balance);
+        }
+    }
+
+    let entry = AuditEntry {
+        address,
+        actor,
+        delta: request.delta,
+        reason: request.reason,
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    };
+
+    // The balance has already moved, so a lost audit record must at least reach the logs
+    if let Err(e) = state.database.append_audit(&entry).await {
+        tracing::error!(error = %e, entry = ?entry, "Failed to record admin adjustment");
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Adjustment applied but not audited").into_response();
+    }
+
+    tracing::info!(
+        address = %entry.address,
+        actor = %entry.actor,
+        delta = entry.delta,
+        reason = %entry.reason,
+        balance,
+        "Admin balance adjustment"
+    );
+
+    axum::Json(json!({
+        "address": auth::display_address(&entry.address),
+        "balance": balance,
+    })).into_response()
+}
+
+/// Account suspension requested by an admin
+#[derive(Debug, Deserialize)]
+pub struct SuspensionRequest {
+    address: String,
+    reason: String,
+}
+
+/// Admin endpoint - suspend an account, keeping its balance
+pub async fn admin_suspend(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::Json(request): axum::Json<SuspensionRequest>,
+) -> Response {
+    set_suspension(&state, &headers, request, true).await
+}
+
+/// Admin endpoint - lift an account's suspension
+pub async fn admin_unsuspend(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::Json(request): axum::Json<SuspensionRequest>,
+) -> Response {
+    set_suspension(&state, &headers, request, false).await
+}
+
+/// Suspend or reinstate an account and record it in the audit ledger
+async fn set_suspension(state: &AppState, headers: &HeaderMap, request: SuspensionRequest, suspended: bool) -> Response {
+    if !is_admin(state, headers) {
+        return (StatusCode::UNAUTHORIZED, "Invalid admin token").into_response();
+    }
+
+    let Ok(address) = Address::from_str(&request.address) else {
+        return (StatusCode::BAD_REQUEST, "Invalid address").into_response();
+    };
+    let address = auth::account_key(&address);
+    if request.reason.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, "reason is required").into_response();
+    }
+
+    let actor = headers
+        .get("x-admin-actor")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("admin")
+        .to_string();
+
+    match state.database.set_suspended(&address, suspended).await {
+        Ok(true) => {}
+        Ok(false) => return (StatusCode::NOT_FOUND, "Unknown account").into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, address = %address, "Admin suspension update failed");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    }
+
+    let entry = AuditEntry {
+        address,
+        actor,
+        delta: 0.0,
+        reason: format!("{}: {}", if suspended { "suspended" } else { "unsuspended" }, request.reason),
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    };
+
+    if let Err(e) = state.database.append_audit(&entry).await {
+        tracing::error!(error = %e, entry = ?entry, "Failed to record admin suspension");
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Suspension applied but not audited").into_response();
+    }
+
+    tracing::info!(
+        address = %entry.address,
+        actor = %entry.actor,
+        suspended,
+        reason = %entry.reason,
+        "Admin suspension update"
+    );
+
+    axum::Json(json!({
+        "address": auth::display_address(&entry.address),
+        "suspended": suspended,
+    })).into_response()
+}
+
+/// Accounts an admin wants to look up
+#[derive(Debug, Deserialize)]
+pub struct BalancesRequest {
+    addresses: Vec<String>,
+}
+
+/// Admin endpoint - balances of many accounts in one call, in the order asked for
+pub async fn admin_balances(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::Json(request): axum::Json<BalancesRequest>,
+) -> Response {
+    if !is_admin(&state, &headers) {
+        return (StatusCode::UNAUTHORIZED, "Invalid admin token").into_response();
+    }
+
+    if request.addresses.len() > MAX_ADMIN_LOOKUP_ADDRESSES {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("At most {} addresses per request", MAX_ADMIN_LOOKUP_ADDRESSES),
+        ).into_response();
+    }
+    if let Some(invalid) = request.addresses.iter().find(|address| Address::from_str(address).is_err()) {
+        return (StatusCode::BAD_REQUEST, format!("Invalid address: {}", invalid)).into_response();
+    }
+
+    let users = match state.database.get_user_batch(&request.addresses).await {
+        Ok(users) => users,
+        Err(e) => {
+            tracing::error!(error = %e, count = request.addresses.len(), "Admin balance lookup failed");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    let accounts: Vec<serde_json::Value> = users.into_iter().map(|(address, user)| json!({
+        "address": auth::display_address(&address),
+        "balance": user.as_ref().map(|user| user.balance),
+        "latest_timestamp": user.as_ref().map(|user| user.latest_timestamp),
+        "suspended": user.map(|user| user.suspended),
+    })).collect();
+
+    axum::Json(json!({ "accounts": accounts })).into_response()
+}
+
+/// Account and period an admin wants spend reported for
+#[derive(Debug, Deserialize)]
+pub struct SpendRequest {
+    address: String,
+    /// Start of the period (unix seconds, inclusive)
+    from: u64,
+    /// End of the period (unix seconds, exclusive)
+    to: u64,
+}
+
+/// Admin endpoint - what an account was charged over a period, for invoicing
+pub async fn admin_spend(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::Json(request): axum::Json<SpendRequest>,
+) -> Response {
+    if !is_admin(&state, &headers) {
+        return (StatusCode::UNAUTHORIZED, "Invalid admin token").into_response();
+    }
+
+    let Ok(address) = Address::from_str(&request.address) else {
+        return (StatusCode::BAD_REQUEST, format!("Invalid address: {}", request.address)).into_response();
+    };
+    let address = auth::account_key(&address);
+    if request.from >= request.to {
+        return (StatusCode::BAD_REQUEST, "Period must end after it starts").into_response();
+    }
+
+    let total = match state.database.spend_total(&address, request.from, request.to).await {
+        Ok(total) => total,
+        Err(e) => {
+            tracing::error!(error = %e, address = %address, "Spend report failed");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Database error").into_response();
+        }
+    };
+
+    axum::Json(json!({
+        "address": auth::display_address(&address),
+        "from": request.from,
+        "to": request.to,
+        "spent": USDC.to_tokens(u128::from(total)),
+        "spent_units": total,
+    })).into_response()
+}
+
+/// Prometheus metrics endpoint (not paywalled)
+pub async fn metrics(State(state): State<Arc<AppState>>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    ).into_response()
+}
+
+/// Server time endpoint so clients can correct for clock drift (not paywalled)
+pub async fn time(State(state): State<Arc<AppState>>) -> Response {
+    // The same clock request timestamps are judged against
+    let timestamp = match &state.block_clock {
+        Some(clock) => clock.now_secs(),
+        None => std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs(),
+    };
+
+    axum::Json(json!({ "timestamp": timestamp })).into_response()
+}
+
+/// Health check endpoint (not paywalled) - stays OK in maintenance, since the process is alive
 pub async fn health() -> &'static str {
     "OK"
 }
+
+/// Readiness endpoint - fails in maintenance and when the database is unreachable
+pub async fn ready(State(state): State<Arc<AppState>>) -> Response {
+    if state.maintenance.load(std::sync::atomic::Ordering::Relaxed) {
+        return (StatusCode::SERVICE_UNAVAILABLE, "In maintenance").into_response();
+    }
+
+    match state.database.health_check().await {
+        Ok(()) => (StatusCode::OK, "READY").into_response(),
+        Err(e) => {
+            tracing::warn!(error = %e, "Readiness check failed");
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                format!("Database unavailable: {}", e),
+            ).into_response()
+        }
+    }
+}
+
+/// Gateway status endpoint (not paywalled)
+pub async fn status(State(state): State<Arc<AppState>>) -> Response {
+    axum::Json(json!({
+        "maintenance": state.maintenance.load(std::sync::atomic::Ordering::Relaxed),
+    })).into_response()
+}
+
+/// Maintenance mode to switch to
+#[derive(Debug, Deserialize)]
+pub struct MaintenanceRequest {
+    enabled: bool,
+}
+
+/// Admin endpoint - enter or leave maintenance mode
+pub async fn admin_maintenance(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    axum::Json(request): axum::Json<MaintenanceRequest>,
+) -> Response {
+    if !is_admin(&state, &headers) {
+        return (StatusCode::UNAUTHORIZED, "Invalid admin token").into_response();
+    }
+
+    let was_enabled = state.maintenance.swap(request.enabled, std::sync::atomic::Ordering::Relaxed);
+    if was_enabled != request.enabled {
+        tracing::info!(maintenance = request.enabled, "Maintenance mode changed");
+    }
+
+    axum::Json(json!({ "maintenance": request.enabled })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_router;
+    use crate::paygate::Paygate;
+    use crate::pricing::{PricingOracle, RoundingMode};
+    use crate::test_utils::*;
+    use alloy::signers::local::PrivateKeySigner;
+    use async_trait::async_trait;
+    use axum::{body::Body, http::Request};
+    use serde_json::Value;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+    use x402_rs::network::Network;
+    use x402_rs::types::EvmAddress;
+    use x402_rs::types::VerifyRequest;
+
+    #[tokio::test]
+    async fn test_repeated_idempotency_key_returns_cached_response_without_billing() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.5\nidempotency_window_secs = 60\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 10.0).await.unwrap();
+
+        let body = rpc_call("eth_blockNumber", 7);
+        let mut first = signed_request("/relay", &signer, &body);
+        first.headers_mut().insert("X-Idempotency-Key", "order-42".parse().unwrap());
+        let (status, first_body) = send(&app, first).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Same logical request, fresh signature
+        let mut second = signed_request_at("/relay", &signer, &body, now_secs() - 1);
+        second.headers_mut().insert("X-Idempotency-Key", "order-42".parse().unwrap());
+        let (status, second_body) = send(&app, second).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(first_body, second_body);
+
+        let user = state.database.get_user(&address).await.unwrap().unwrap();
+        assert_eq!(user.balance, 9.5);
+
+        // A different key is billed normally
+        let mut third = signed_request("/relay", &signer, &rpc_call("eth_blockNumber", 8));
+        third.headers_mut().insert("X-Idempotency-Key", "order-43".parse().unwrap());
+        let (status, third_body) = send(&app, third).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&third_body).unwrap();
+        assert_eq!(reply["id"], 8);
+
+        let user = state.database.get_user(&address).await.unwrap().unwrap();
+        assert_eq!(user.balance, 9.0);
+    }
+
+    #[tokio::test]
+    async fn test_replayed_read_returns_cached_response() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.5\nreplayable_methods = [\"eth_call\"]\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 10.0).await.unwrap();
+        let timestamp = now_secs();
+
+        // Signatures are deterministic, so the same request at the same timestamp is a byte-identical retry
+        let read = rpc_call("eth_call", 1);
+        let (status, first_body) = send(&app, signed_request_at("/relay", &signer, &read, timestamp)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, retried_body) = send(&app, signed_request_at("/relay", &signer, &read, timestamp)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(first_body, retried_body);
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().balance, 9.5);
+
+        // Writes are still refused as replays
+        let write = rpc_call("eth_sendRawTransaction", 2);
+        let (status, _) = send(&app, signed_request_at("/relay", &signer, &write, timestamp)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(&app, signed_request_at("/relay", &signer, &write, timestamp)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body, "Replay detected: signature already used");
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().balance, 9.0);
+    }
+
+    #[test]
+    fn test_sponsored_deposit_credit_address() {
+        use alloy::signers::SignerSync;
+
+        let payer = PrivateKeySigner::random();
+        let beneficiary = PrivateKeySigner::random().address().to_string();
+        let nonce = "0x0000000000000000000000000000000000000000000000000000000000000001";
+        let authorize = |signer: &PrivateKeySigner, nonce: &str| {
+            let message = credit_authorization_message(&beneficiary, nonce);
+            let hash = alloy::primitives::keccak256(message.as_bytes());
+            signer.sign_hash_sync(&hash).unwrap().to_string()
+        };
+        let headers_with = |signature: String| {
+            let mut headers = HeaderMap::new();
+            headers.insert("X-Credit-Address", beneficiary.parse().unwrap());
+            headers.insert("X-Credit-Signature", signature.parse().unwrap());
+            headers
+        };
+        let payer_address = payer.address().to_string();
+
+        // No credit header credits the payer
+        assert_eq!(resolve_credit_address(&HeaderMap::new(), &payer_address, nonce).unwrap(), payer_address.to_lowercase());
+
+        // Payer-signed authorization credits the beneficiary
+        let headers = headers_with(authorize(&payer, nonce));
+        assert_eq!(resolve_credit_address(&headers, &payer_address, nonce).unwrap(), beneficiary.to_lowercase());
+
+        // Signed by someone other than the payer
+        let headers = headers_with(authorize(&PrivateKeySigner::random(), nonce));
+        assert!(resolve_credit_address(&headers, &payer_address, nonce).is_err());
+
+        // Authorization for a different payment can't be reused
+        let headers = headers_with(authorize(&payer, "0x02"));
+        assert!(resolve_credit_address(&headers, &payer_address, nonce).is_err());
+
+        // Missing signature
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Credit-Address", beneficiary.parse().unwrap());
+        assert!(resolve_credit_address(&headers, &payer_address, nonce).is_err());
+
+        // Written without 0x, the beneficiary is still credited under the key it spends from
+        let bare = &beneficiary[2..];
+        let message = credit_authorization_message(bare, nonce);
+        let signature = payer.sign_hash_sync(&alloy::primitives::keccak256(message.as_bytes())).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Credit-Address", bare.parse().unwrap());
+        headers.insert("X-Credit-Signature", signature.to_string().parse().unwrap());
+        assert_eq!(resolve_credit_address(&headers, &payer_address, nonce).unwrap(), beneficiary.to_lowercase());
+    }
+
+    #[tokio::test]
+    async fn test_deposit_credit_and_request_price_applied_together() {
+        let (state, _dir) = test_state(test_config(DEFAULT_RESOURCE));
+        let address = "0x00000000000000000000000000000000000000aa";
+        let price = state.config.price_for(&state.config.resources[0], address);
+
+        let payment = credit_deposit(&state, address, TOPUP_AMOUNT_USDC, price, 100).await.unwrap();
+        let (remaining, reservation) = payment.unwrap();
+        assert_eq!(remaining, TOPUP_AMOUNT_USDC - price);
+        assert!(reservation.is_none());
+
+        let user = state.database.get_user(address).await.unwrap().unwrap();
+        assert_eq!(user.balance, TOPUP_AMOUNT_USDC - price);
+        assert_eq!(user.latest_timestamp, 100);
+
+        // A deposit too small for the request is still credited, leaving the request unpaid
+        let other = "0x00000000000000000000000000000000000000bb";
+        assert!(credit_deposit(&state, other, price / 2.0, price, 100).await.unwrap().is_none());
+        assert_eq!(state.database.get_user(other).await.unwrap().unwrap().balance, price / 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_settlements_bounded_by_permits() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        const LIMIT: usize = 3;
+        let permits = Arc::new(Semaphore::new(LIMIT));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        // Instrumented mock settlement tracking how many run at once
+        let tasks: Vec<_> = (0..20)
+            .map(|_| {
+                let (permits, in_flight, peak) = (permits.clone(), in_flight.clone(), peak.clone());
+                tokio::spawn(async move {
+                    with_settlement_permit(&permits, async {
+                        let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now, Ordering::SeqCst);
+                        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                    })
+                    .await
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        // Every settlement completed (queued, not failed) without exceeding the limit
+        assert_eq!(peak.load(Ordering::SeqCst), LIMIT);
+        assert_eq!(in_flight.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_mapped_jsonrpc_error_code_sets_http_status() {
+        let not_found = spawn_fixed_node(json!({
+            "jsonrpc": "2.0", "id": 1, "error": {"code": -32601, "message": "method not found"}
+        })).await;
+        let execution_error = spawn_fixed_node(json!({
+            "jsonrpc": "2.0", "id": 1, "error": {"code": -32000, "message": "execution reverted"}
+        })).await;
+        let config = test_config(&format!(
+            r#"
+            [jsonrpc_error_status]
+            "-32601" = 404
+
+            [[resources]]
+            name = "mapped"
+            path = "/mapped"
+            node_url = "{}"
+            price_per_request = 0.0
+
+            [[resources]]
+            name = "unmapped"
+            path = "/unmapped"
+            node_url = "{}"
+            price_per_request = 0.0
+            "#,
+            not_found, execution_error
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state);
+        let signer = PrivateKeySigner::random();
+
+        let (status, _) = send(&app, signed_request("/mapped", &signer, &rpc_call("eth_foo", 1))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) = send(&app, signed_request("/unmapped", &signer, &rpc_call("eth_call", 1))).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["error"]["code"], -32000);
+    }
+
+    #[tokio::test]
+    async fn test_transient_jsonrpc_errors_retried_on_next_node() {
+        let syncing = spawn_fixed_node(json!({
+            "jsonrpc": "2.0", "id": 1, "error": {"code": -32000, "message": "Node is syncing"}
+        })).await;
+        let not_found = spawn_fixed_node(json!({
+            "jsonrpc": "2.0", "id": 1, "error": {"code": -32601, "message": "method not found"}
+        })).await;
+        let healthy = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!(
+            r#"
+            [transient_jsonrpc_errors]
+            messages = ["syncing"]
+
+            [[resources]]
+            name = "syncing"
+            path = "/syncing"
+            node_url = "{syncing}"
+            fallback_node_urls = ["{healthy}"]
+            price_per_request = 0.0
+
+            [[resources]]
+            name = "not-found"
+            path = "/not-found"
+            node_url = "{not_found}"
+            fallback_node_urls = ["{healthy}"]
+            price_per_request = 0.0
+
+            [[resources]]
+            name = "down"
+            path = "/down"
+            node_url = "http://127.0.0.1:1"
+            fallback_node_urls = ["{syncing}", "{healthy}"]
+            price_per_request = 0.0
+            "#
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state);
+        let signer = PrivateKeySigner::random();
+
+        // A syncing node is skipped for the next one
+        let (status, body) = send(&app, signed_request("/syncing", &signer, &rpc_call("eth_blockNumber", 1))).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["result"], "0x1");
+
+        // Permanent errors are the answer
+        let (status, body) = send(&app, signed_request("/not-found", &signer, &rpc_call("eth_foo", 2))).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["error"]["code"], -32601);
+
+        // Unreachable nodes fail over too, through as many nodes as it takes
+        let (status, body) = send(&app, signed_request("/down", &signer, &rpc_call("eth_blockNumber", 3))).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["result"], "0x1");
+    }
+
+    #[tokio::test]
+    async fn test_node_status_normalized_for_jsonrpc_replies() {
+        let rejecting = spawn_server(axum::Router::new().route(
+            "/",
+            axum::routing::post(|| async {
+                let reply = json!({"jsonrpc": "2.0", "id": 1, "error": {"code": -32602, "message": "invalid params"}});
+                (StatusCode::BAD_REQUEST, axum::Json(reply))
+            }),
+        ))
+        .await;
+        let failing = spawn_server(axum::Router::new().route(
+            "/",
+            axum::routing::post(|| async { (StatusCode::INTERNAL_SERVER_ERROR, "upstream exploded") }),
+        ))
+        .await;
+        let resources = format!(
+            r#"
+            [[resources]]
+            name = "rejecting"
+            path = "/rejecting"
+            node_url = "{}"
+            price_per_request = 0.0
+
+            [[resources]]
+            name = "failing"
+            path = "/failing"
+            node_url = "{}"
+            price_per_request = 0.0
+            "#,
+            rejecting, failing
+        );
+        let signer = PrivateKeySigner::random();
+
+        // Without the option the node's status passes through
+        let (state, _dir) = test_state(test_config(&resources));
+        let app = build_router(state);
+        let (status, _) = send(&app, signed_request("/rejecting", &signer, &rpc_call("eth_call", 1))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (state, _dir) = test_state(test_config(&format!("normalize_jsonrpc_status = true\n{}", resources)));
+        let app = build_router(state);
+        let (status, body) = send(&app, signed_request("/rejecting", &signer, &rpc_call("eth_call", 2))).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["error"]["code"], -32602);
+
+        let (status, body) = send(&app, signed_request("/failing", &signer, &rpc_call("eth_call", 3))).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(&body[..], b"upstream exploded");
+    }
+
+    #[tokio::test]
+    async fn test_rewritten_ids_do_not_collide_across_clients() {
+        // Node reports the id it saw upstream and which method it answered
+        let node = spawn_server(axum::Router::new().route(
+            "/",
+            axum::routing::post(|body: Bytes| async move {
+                let call: Value = serde_json::from_slice(&body).unwrap();
+                axum::Json(json!({
+                    "jsonrpc": "2.0",
+                    "id": call["id"],
+                    "result": {"method": call["method"], "upstream_id": call["id"]}
+                }))
+            }),
+        ))
+        .await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.0\nrewrite_jsonrpc_ids = true\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state);
+
+        let alice = PrivateKeySigner::random();
+        let bob = PrivateKeySigner::random();
+        let ((_, alice_body), (_, bob_body)) = tokio::join!(
+            send(&app, signed_request("/relay", &alice, &rpc_call("eth_chainId", 1))),
+            send(&app, signed_request("/relay", &bob, &rpc_call("eth_blockNumber", 1))),
+        );
+
+        let alice_reply: Value = serde_json::from_slice(&alice_body).unwrap();
+        let bob_reply: Value = serde_json::from_slice(&bob_body).unwrap();
+        assert_eq!(alice_reply["id"], 1);
+        assert_eq!(alice_reply["result"]["method"], "eth_chainId");
+        assert_eq!(bob_reply["id"], 1);
+        assert_eq!(bob_reply["result"]["method"], "eth_blockNumber");
+        assert_ne!(alice_reply["result"]["upstream_id"], bob_reply["result"]["upstream_id"]);
+    }
+
+    #[tokio::test]
+    async fn test_illegal_id_rejected_without_billing() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.001\nnormalize_jsonrpc_ids = true\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 1.0).await.unwrap();
+
+        let call = json!({"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": {"nested": 1}});
+        let (status, _) = send(&app, signed_request("/relay", &signer, &call.to_string())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let user = state.database.get_user(&address).await.unwrap().unwrap();
+        assert_eq!(user.balance, 1.0);
+
+        // String, numeric and null ids are all relayed
+        for id in [json!("abc"), json!(7), json!(null)] {
+            let call = json!({"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": id});
+            let (status, _) = send(&app, signed_request("/relay", &signer, &call.to_string())).await;
+            assert_eq!(status, StatusCode::OK, "id {}", id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_only_reads_at_finalized_blocks_are_cached() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        // Finalized block is 0x64; every other call returns how many reads the node has served
+        let reads = Arc::new(AtomicU64::new(0));
+        let node_reads = reads.clone();
+        let node = spawn_server(axum::Router::new().route(
+            "/",
+            axum::routing::post(move |body: Bytes| {
+                let reads = node_reads.clone();
+                async move {
+                    let call: Value = serde_json::from_slice(&body).unwrap();
+                    let result = if call["params"][0] == "finalized" {
+                        json!({"number": "0x64"})
+                    } else {
+                        json!(reads.fetch_add(1, Ordering::SeqCst))
+                    };
+                    axum::Json(json!({"jsonrpc": "2.0", "id": call["id"], "result": result}))
+                }
+            }),
+        ))
+        .await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.0\n[finalized_cache]\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state);
+        let signer = PrivateKeySigner::random();
+        let get_balance = |block: &str, id: u64| {
+            json!({"jsonrpc": "2.0", "method": "eth_getBalance", "params": [PAYMENT_ADDRESS, block], "id": id}).to_string()
+        };
+
+        // Finalized block: second read is served from cache with the caller's id
+        let (_, first) = send(&app, signed_request("/relay", &signer, &get_balance("0x10", 1))).await;
+        let (_, second) = send(&app, signed_request("/relay", &signer, &get_balance("0x10", 2))).await;
+        let first: Value = serde_json::from_slice(&first).unwrap();
+        let second: Value = serde_json::from_slice(&second).unwrap();
+        assert_eq!(first["result"], second["result"]);
+        assert_eq!(second["id"], 2);
+        assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+        // Recent block: every read reaches the node
+        send(&app, signed_request("/relay", &signer, &get_balance("0xc8", 3))).await;
+        send(&app, signed_request("/relay", &signer, &get_balance("0xc8", 4))).await;
+        assert_eq!(reads.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_finalized_reads_within_reorg_safety_depth_not_cached() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        // Latest block is 0x78 and finalized 0x64; every other call returns how many reads the node has served
+        let reads = Arc::new(AtomicU64::new(0));
+        let node_reads = reads.clone();
+        let node = spawn_server(axum::Router::new().route(
+            "/",
+            axum::routing::post(move |body: Bytes| {
+                let reads = node_reads.clone();
+                async move {
+                    let call: Value = serde_json::from_slice(&body).unwrap();
+                    let result = match call["params"][0].as_str() {
+                        Some("latest") => json!({"number": "0x78"}),
+                        Some("finalized") => json!({"number": "0x64"}),
+                        _ => json!(reads.fetch_add(1, Ordering::SeqCst)),
+                    };
+                    axum::Json(json!({"jsonrpc": "2.0", "id": call["id"], "result": result}))
+                }
+            }),
+        ))
+        .await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.0\n[finalized_cache]\nreorg_safety_depth = 64\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state);
+        let signer = PrivateKeySigner::random();
+        let get_balance = |block: &str, id: u64| {
+            json!({"jsonrpc": "2.0", "method": "eth_getBalance", "params": [PAYMENT_ADDRESS, block], "id": id}).to_string()
+        };
+
+        // Finalized but only 0x38 blocks below the latest: every read reaches the node
+        send(&app, signed_request("/relay", &signer, &get_balance("0x40", 1))).await;
+        send(&app, signed_request("/relay", &signer, &get_balance("0x40", 2))).await;
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+
+        // Buried deeper than the margin: second read is served from cache
+        send(&app, signed_request("/relay", &signer, &get_balance("0x38", 3))).await;
+        send(&app, signed_request("/relay", &signer, &get_balance("0x38", 4))).await;
+        assert_eq!(reads.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_gateway_pays_upstream_node_that_requires_payment() {
+        use crate::upstream_payment::tests::{payment_signer, requirement};
+
+        let upstream = PrivateKeySigner::random();
+        let payments = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let node = spawn_server(axum::Router::new().route(
+            "/",
+            axum::routing::post({
+                let payments = payments.clone();
+                move |headers: HeaderMap| async move {
+                    let Some(payment) = headers.get("x-payment") else {
+                        let required = json!({"x402Version": 1, "error": "X-PAYMENT header is required", "accepts": [requirement(1_000)]});
+                        return (StatusCode::PAYMENT_REQUIRED, axum::Json(required));
+                    };
+                    payments.lock().unwrap().push(payment_signer(payment.to_str().unwrap(), &requirement(1_000)));
+                    (StatusCode::OK, axum::Json(json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"})))
+                }
+            }),
+        ))
+        .await;
+        let mut config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.01\n\n[upstream_payment]\nmax_amount = 0.005\n",
+            node
+        ));
+        config.upstream_private_key = Some(hex::encode(upstream.to_bytes()));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        state.database.add_balance(&signer.address().to_string(), 1.0).await.unwrap();
+
+        let (status, body) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_chainId", 1))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["result"], "0x1");
+
+        let payments = payments.lock().unwrap();
+        assert_eq!(payments.len(), 1);
+        let (payment, payer) = &payments[0];
+        assert_eq!(*payer, upstream.address());
+        assert_eq!(payment["payload"]["authorization"]["value"], "1000");
+    }
+
+    #[tokio::test]
+    async fn test_strict_content_type_refuses_non_json_unbilled() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.25\nrequire_json_content_type = true\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 1.0).await.unwrap();
+
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_chainId", 1))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let mut request = signed_request("/relay", &signer, &rpc_call("eth_chainId", 2));
+        request.headers_mut().insert(header::CONTENT_TYPE, "application/json; charset=utf-8".parse().unwrap());
+        let (status, _) = send(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let mut request = signed_request("/relay", &signer, &rpc_call("eth_chainId", 3));
+        request.headers_mut().insert(header::CONTENT_TYPE, "text/html".parse().unwrap());
+        let (status, _) = send(&app, request).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let mut request = signed_request("/relay", &signer, &rpc_call("eth_chainId", 4));
+        request.headers_mut().remove(header::CONTENT_TYPE);
+        let (status, _) = send(&app, request).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        // Only the two JSON requests were billed
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().balance, 0.5);
+    }
+
+    #[tokio::test]
+    async fn test_requests_over_relay_limit_are_shed() {
+        let node = spawn_server(axum::Router::new().route(
+            "/",
+            axum::routing::post(|| async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                axum::Json(json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"}))
+            }),
+        ))
+        .await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 1.0\nmax_concurrent_relays = 2\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 10.0).await.unwrap();
+
+        // Fill both slots with slow requests
+        let in_flight: Vec<_> = (0..2)
+            .map(|id| {
+                let (app, request) = (app.clone(), signed_request("/relay", &signer, &rpc_call("eth_call", id)));
+                tokio::spawn(async move { send(&app, request).await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let response = app
+            .clone()
+            .oneshot(signed_request("/relay", &signer, &rpc_call("eth_call", 2)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+
+        for request in in_flight {
+            assert_eq!(request.await.unwrap().0, StatusCode::OK);
+        }
+        // The shed request wasn't billed, and capacity is available again
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().balance, 8.0);
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_call", 3))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_new_accounts_get_free_requests_before_paying() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.001\nfree_requests = 2\nreject_unfunded_before_auth = true\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+
+        for id in 0..2 {
+            let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_call", id))).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let user = state.database.get_user(&address).await.unwrap().unwrap();
+        assert_eq!((user.free_requests_used, user.balance), (2, 0.0));
+
+        // Once the quota is used up, requests are paid for as usual
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_call", 2))).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        state.database.add_balance(&address, 1.0).await.unwrap();
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_call", 3))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(state.database.get_user(&address).await.unwrap().unwrap().balance < 1.0);
+
+        // Accounts that existed before get no free requests
+        let funded = PrivateKeySigner::random();
+        state.database.add_balance(&funded.address().to_string(), 0.0001).await.unwrap();
+        let (status, _) = send(&app, signed_request("/relay", &funded, &rpc_call("eth_call", 4))).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    }
+
+    #[tokio::test]
+    async fn test_free_requests_with_monotonic_timestamps() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.001\nfree_requests = 2\nmonotonic_timestamps = true\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        let now = now_secs();
+
+        // Recording the signed timestamp first doesn't open the account and cost it its free requests
+        for offset in 0..2 {
+            let request = signed_request_at("/relay", &signer, &rpc_call("eth_call", offset), now + offset);
+            let (status, _) = send(&app, request).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let user = state.database.get_user(&address).await.unwrap().unwrap();
+        assert_eq!((user.free_requests_used, user.balance), (2, 0.0));
+
+        let (status, _) = send(&app, signed_request_at("/relay", &signer, &rpc_call("eth_call", 2), now + 2)).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    }
+
+    #[tokio::test]
+    async fn test_refused_requests_keep_free_requests() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.001\nfree_requests = 2\nprecheck_raw_transactions = true\n[global_budget]\nmax_requests = 1\nwindow_secs = 3600\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+
+        // A transaction refused by the pre-check doesn't use up a free request
+        let doomed = json!({"jsonrpc": "2.0", "method": "eth_sendRawTransaction", "params": ["0xdeadbeef"], "id": 1});
+        let (status, _) = send(&app, signed_request("/relay", &signer, &doomed.to_string())).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(state.database.get_user(&address).await.unwrap().is_none());
+
+        // Nor does a request shed by the global budget
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_call", 1))).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_call", 2))).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().free_requests_used, 1);
+    }
+
+    #[tokio::test]
+    async fn test_paid_requests_shed_once_global_budget_spent() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.5\n[global_budget]\nmax_spend = 1.0\nwindow_secs = 3600\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+
+        // The budget is shared: two accounts spend it, and a third with funds is still turned away
+        let signers: Vec<_> = (0..3).map(|_| PrivateKeySigner::random()).collect();
+        for signer in &signers {
+            state.database.add_balance(&signer.address().to_string(), 10.0).await.unwrap();
+        }
+        // Requests the account couldn't pay for don't use up the budget
+        let unfunded = PrivateKeySigner::random();
+        state.database.add_balance(&unfunded.address().to_string(), 0.1).await.unwrap();
+        let (status, _) = send(&app, signed_request("/relay", &unfunded, &rpc_call("eth_call", 9))).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+
+        for (id, signer) in signers[..2].iter().enumerate() {
+            let (status, _) = send(&app, signed_request("/relay", signer, &rpc_call("eth_call", id as u64))).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let response = app
+            .clone()
+            .oneshot(signed_request("/relay", &signers[2], &rpc_call("eth_call", 2)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+        assert_eq!(state.database.get_user(&signers[2].address().to_string()).await.unwrap().unwrap().balance, 10.0);
+    }
+
+    #[tokio::test]
+    async fn test_body_logging_redacts_sensitive_params() {
+        let (logs, _guard) = capture_logs();
+        let node = spawn_echo_node(json!("0xtxhash")).await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.0\n[body_logging]\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+
+        let body = json!({"jsonrpc": "2.0", "method": "eth_sendRawTransaction", "params": ["0x02f8secret"], "id": 41}).to_string();
+        let request = signed_request("/relay", &signer, &body);
+        let signature = request.headers()["x-auth-signature"].to_str().unwrap().to_string();
+        let (status, _) = send(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_chainId", 42))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let logs = logs.contents();
+        assert!(logs.contains("Relay request body") && logs.contains("Relay response body"), "{}", logs);
+        assert!(!logs.contains("0x02f8secret") && logs.contains(r#""result":"[redacted]""#), "{}", logs);
+        assert!(!logs.contains(&signature), "{}", logs);
+        assert!(logs.contains("eth_sendRawTransaction") && logs.contains(r#""id":41"#), "{}", logs);
+        assert!(logs.contains("eth_chainId") && logs.contains(r#""id":42"#), "{}", logs);
+    }
+
+    #[tokio::test]
+    async fn test_slow_request_warning_separates_node_time() {
+        let (logs, _guard) = capture_logs();
+        let node = spawn_server(axum::Router::new().route(
+            "/",
+            axum::routing::post(|| async {
+                tokio::time::sleep(Duration::from_millis(150)).await;
+                axum::Json(json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"}))
+            }),
+        ))
+        .await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.0\nslow_request_threshold_ms = 100\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_call", 1))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let logs = logs.contents();
+        assert!(logs.contains("Slow relay request"), "{}", logs);
+        let node_ms: u64 = logs
+            .split("node_ms=")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .and_then(|ms| ms.parse().ok())
+            .unwrap();
+        assert!(node_ms >= 150, "{}", logs);
+
+        let (_, metrics) = send(&app, Request::get("/metrics").body(Body::empty()).unwrap()).await;
+        let metrics = String::from_utf8_lossy(&metrics);
+        assert!(metrics.contains("gateway_relay_latency_seconds_count 1"));
+        assert!(metrics.contains("gateway_node_latency_seconds_bucket{le=\"0.1\"} 0"));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_time_header_reports_node_latency() {
+        let node = spawn_server(axum::Router::new().route(
+            "/",
+            axum::routing::post(|| async {
+                tokio::time::sleep(Duration::from_millis(150)).await;
+                axum::Json(json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"}))
+            }),
+        ))
+        .await;
+        let signer = PrivateKeySigner::random();
+
+        for enabled in [true, false] {
+            let config = test_config(&format!(
+                "node_url = \"{}\"\nprice_per_request = 0.0\nupstream_time_header = {}\n",
+                node, enabled
+            ));
+            let (state, _dir) = test_state(config);
+            let app = build_router(state);
+
+            let started = Instant::now();
+            let request = signed_request("/relay", &signer, &rpc_call("eth_call", u64::from(enabled)));
+            let response = app.oneshot(request).await.unwrap();
+            let elapsed = started.elapsed().as_millis() as u64;
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let header = response.headers().get("x-upstream-time-ms");
+            if !enabled {
+                assert!(header.is_none());
+                continue;
+            }
+            let upstream_ms: u64 = header.unwrap().to_str().unwrap().parse().unwrap();
+            assert!((150..=elapsed).contains(&upstream_ms), "upstream {}ms of {}ms", upstream_ms, elapsed);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_insufficient_balance_402_differs_from_unauthenticated_402() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let (state, _dir) = test_state(test_config(&format!("node_url = \"{}\"\nprice_per_request = 1.0\n", node)));
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        state.database.add_balance(&signer.address().to_string(), 0.25).await.unwrap();
+
+        let unauthenticated = Request::post("/relay").body(Body::from(rpc_call("eth_chainId", 1))).unwrap();
+        let (status, body) = send(&app, unauthenticated).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["error"], "X-PAYMENT header is required");
+        assert!(reply.get("balance").is_none());
+        assert!(reply.get("shortfall").is_none());
+
+        let (status, body) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_chainId", 1))).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["error"], "Insufficient balance");
+        assert_eq!(reply["balance"], 0.25);
+        assert_eq!(reply["required"], 1.0);
+        assert_eq!(reply["shortfall"], 0.75);
+        // Still a valid x402 response the client can pay
+        assert!(!reply["accepts"].as_array().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_address_spellings_share_one_account() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let (state, _dir) = test_state(test_config(&format!("node_url = \"{}\"\nprice_per_request = 1.0\n", node)));
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let key = format!("{:#x}", signer.address());
+        state.database.add_balance(&key, 5.0).await.unwrap();
+
+        let hex = signer.address().to_string()[2..].to_string();
+        let spellings = [format!("0x{}", hex.to_uppercase()), hex.to_lowercase(), signer.address().to_string()];
+        for (id, address) in spellings.iter().enumerate() {
+            let request = signed_request_as("/relay", &signer, address, &rpc_call("eth_chainId", id as u64), now_secs());
+            let (status, _) = send(&app, request).await;
+            assert_eq!(status, StatusCode::OK, "address spelled {}", address);
+        }
+
+        let users = state.database.list_users().await.unwrap();
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].0, key);
+        assert_eq!(users[0].1.balance, 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_node_connections_reused_under_concurrent_load() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let (state, _dir) = test_state(test_config(&format!("node_url = \"{}\"\nprice_per_request = 0.01\n", node)));
+        let app = build_router(state.clone());
+        let signers: Vec<PrivateKeySigner> = (0..20).map(|_| PrivateKeySigner::random()).collect();
+        for signer in &signers {
+            state.database.add_balance(&signer.address().to_string(), 1.0).await.unwrap();
+        }
+
+        // Waves of concurrent relays: later waves pick up the connections earlier ones pooled
+        for wave in signers.chunks(5) {
+            let mut relays = tokio::task::JoinSet::new();
+            for signer in wave {
+                let app = app.clone();
+                let request = signed_request("/relay", signer, &rpc_call("eth_chainId", 1));
+                relays.spawn(async move { send(&app, request).await.0 });
+            }
+            while let Some(status) = relays.join_next().await {
+                assert_eq!(status.unwrap(), StatusCode::OK);
+            }
+        }
+
+        let metrics = &state.metrics;
+        assert_eq!(metrics.node_requests.load(Ordering::SeqCst), 20);
+        let connections = metrics.node_connections.load(Ordering::SeqCst);
+        assert!((1..20).contains(&connections), "{} connections", connections);
+        assert_eq!(metrics.node_connections_reused(), 20 - connections);
+        assert!(metrics.render().contains(&format!("gateway_node_connections_reused_total {}\n", 20 - connections)));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_batch_replies_replaced_with_errors() {
+        // Echo each call's params back as its result
+        let node = spawn_server(axum::Router::new().route("/", axum::routing::post(|body: Bytes| async move {
+            let reply = |call: &Value| json!({"jsonrpc": "2.0", "id": call["id"], "result": call["params"][0]});
+            match serde_json::from_slice::<Value>(&body).unwrap() {
+                Value::Array(calls) => axum::Json(Value::Array(calls.iter().map(reply).collect())),
+                call => axum::Json(reply(&call)),
+            }
+        })))
+        .await;
+        let config = test_config(&format!("node_url = \"{}\"\nprice_per_request = 0.01\nmax_node_response_bytes = 300\n", node));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        state.database.add_balance(&signer.address().to_string(), 1.0).await.unwrap();
+
+        let call = |id: i64, param: &str| json!({"jsonrpc": "2.0", "method": "eth_call", "params": [param], "id": id});
+        let batch = json!([call(1, "0x1"), call(2, &"a".repeat(1000)), call(3, "0x3")]).to_string();
+        let (status, body) = send(&app, signed_request("/relay", &signer, &batch)).await;
+        assert_eq!(status, StatusCode::OK);
+        let replies: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(replies[0]["result"], "0x1");
+        assert_eq!(replies[1], json!({"jsonrpc": "2.0", "id": 2, "error": {"code": -32000, "message": "response too large"}}));
+        assert_eq!(replies[2]["result"], "0x3");
+
+        // A single oversized reply has nothing to keep
+        let (status, body) = send(&app, signed_request("/relay", &signer, &call(4, &"a".repeat(1000)).to_string())).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["error"]["message"], "response too large");
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_and_slow_nodes_answered_distinctly() {
+        let slow = spawn_server(axum::Router::new().route(
+            "/",
+            axum::routing::post(|| async {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                axum::Json(json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"}))
+            }),
+        ))
+        .await;
+        let resources = format!(
+            r#"
+            node_timeout_ms = 200
+            [[resources]]
+            name = "unreachable"
+            path = "/unreachable"
+            node_url = "http://127.0.0.1:1"
+            price_per_request = 0.0
+            [[resources]]
+            name = "slow"
+            path = "/slow"
+            node_url = "{}"
+            price_per_request = 0.0
+            "#,
+            slow
+        );
+        let (state, _dir) = test_state(test_config(&resources));
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+
+        // Nothing listening: the node is down
+        let (status, body) = send(&app, signed_request("/unreachable", &signer, &rpc_call("eth_chainId", 1))).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["error"]["message"], "Node unreachable");
+        assert_eq!(state.metrics.node_connect_failures.load(Ordering::Relaxed), 1);
+
+        // Connected but no answer in time: the node is slow
+        let (status, body) = send(&app, signed_request("/slow", &signer, &rpc_call("eth_chainId", 2))).await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["error"]["message"], "Node did not respond in time");
+        assert_eq!(state.metrics.node_timeouts.load(Ordering::Relaxed), 1);
+        assert_eq!(state.metrics.node_connect_failures.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_batches_over_max_batch_size_rejected_before_billing() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!("node_url = \"{}\"\nprice_per_request = 1.0\nmax_batch_size = 3\n", node));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 10.0).await.unwrap();
+
+        let batch = |calls: u64| {
+            let calls: Vec<Value> = (0..calls)
+                .map(|id| serde_json::from_str(&rpc_call("eth_chainId", id)).unwrap())
+                .collect();
+            Value::from(calls).to_string()
+        };
+
+        let (status, _) = send(&app, signed_request("/relay", &signer, &batch(3))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().balance, 9.0);
+
+        let (status, body) = send(&app, signed_request("/relay", &signer, &batch(4))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(String::from_utf8_lossy(&body), "Batch of 4 calls exceeds the limit of 3");
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().balance, 9.0);
+    }
+
+    #[tokio::test]
+    async fn test_partial_batch_relays_calls_the_balance_covers() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!("node_url = \"{}\"\nprice_per_request = 1.0\npartial_batch = true\n", node));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 2.5).await.unwrap();
+
+        let batch = json!([
+            {"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 1},
+            {"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 2},
+            {"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 3},
+        ]);
+        let (status, body) = send(&app, signed_request("/relay", &signer, &batch.to_string())).await;
+        assert_eq!(status, StatusCode::OK);
+        let replies: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(replies[0], json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"}));
+        assert_eq!(replies[1], json!({"jsonrpc": "2.0", "id": 2, "result": "0x1"}));
+        assert_eq!(replies[2]["id"], 3);
+        assert_eq!(replies[2]["error"]["code"], -402);
+        assert!(replies[2].get("result").is_none());
+
+        // Only the two relayed calls were billed
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().balance, 0.5);
+    }
+
+    #[tokio::test]
+    async fn test_batch_calls_left_unanswered_are_refunded() {
+        // Node that truncates batches after two replies
+        let node = spawn_server(axum::Router::new().route(
+            "/",
+            axum::routing::post(|body: Bytes| async move {
+                let calls: Vec<Value> = serde_json::from_slice(&body).unwrap();
+                let replies: Vec<_> = calls.iter().take(2).map(|call| json!({"jsonrpc": "2.0", "id": call["id"], "result": "0x1"})).collect();
+                axum::Json(replies)
+            }),
+        ))
+        .await;
+        let config = test_config(&format!("node_url = \"{}\"\nprice_per_request = 1.0\npartial_batch = true\n", node));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 5.0).await.unwrap();
+
+        let batch = json!([
+            {"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 1},
+            {"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 2},
+            {"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 3},
+        ]);
+        let (status, body) = send(&app, signed_request("/relay", &signer, &batch.to_string())).await;
+        assert_eq!(status, StatusCode::OK);
+        let replies: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(replies.as_array().unwrap().len(), 2);
+
+        // Three calls billed, the third refunded
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().balance, 3.0);
+    }
+
+    /// Node that, per spec, only replies to calls carrying an id
+    async fn spawn_spec_node() -> String {
+        spawn_server(axum::Router::new().route(
+            "/",
+            axum::routing::post(|body: Bytes| async move {
+                let reply = |call: &Value| call.get("id").map(|id| json!({"jsonrpc": "2.0", "id": id, "result": "0x1"}));
+                match serde_json::from_slice::<Value>(&body).unwrap() {
+                    Value::Array(calls) => {
+                        let replies: Vec<_> = calls.iter().filter_map(reply).collect();
+                        if replies.is_empty() { String::new() } else { Value::Array(replies).to_string() }
+                    }
+                    call => reply(&call).map_or_else(String::new, |reply| reply.to_string()),
+                }
+            }),
+        ))
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_notifications_billed_without_reply() {
+        let node = spawn_spec_node().await;
+        let config = test_config(&format!("node_url = \"{}\"\nprice_per_request = 0.5\n", node));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 2.0).await.unwrap();
+
+        let notification = json!({"jsonrpc": "2.0", "method": "eth_subscribe", "params": []});
+        let (status, body) = send(&app, signed_request("/relay", &signer, &notification.to_string())).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(body.is_empty());
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().balance, 1.5);
+
+        // Relay failures keep their status but the synthetic error is suppressed
+        let config = test_config("node_url = \"http://127.0.0.1:1\"\nprice_per_request = 0.5\n");
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        state.database.add_balance(&address, 2.0).await.unwrap();
+        let (status, body) = send(&app, signed_request("/relay", &signer, &notification.to_string())).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mixed_batch_replies_only_to_calls() {
+        let node = spawn_spec_node().await;
+        let config = test_config(&format!("node_url = \"{}\"\nprice_per_request = 1.0\npartial_batch = true\n", node));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 3.5).await.unwrap();
+
+        let batch = json!([
+            {"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 1},
+            {"jsonrpc": "2.0", "method": "eth_subscribe", "params": []},
+            {"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 2},
+        ]);
+        let (status, body) = send(&app, signed_request("/relay", &signer, &batch.to_string())).await;
+        assert_eq!(status, StatusCode::OK);
+        let replies: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(replies, json!([
+            {"jsonrpc": "2.0", "id": 1, "result": "0x1"},
+            {"jsonrpc": "2.0", "id": 2, "result": "0x1"},
+        ]));
+        // The notification is billed like any other call
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().balance, 0.5);
+
+        // An unpaid call still gets its error when only a notification was relayed,
+        // while an unpaid notification gets nothing
+        state.database.add_balance(&address, 0.5).await.unwrap();
+        let batch = json!([
+            {"jsonrpc": "2.0", "method": "eth_subscribe", "params": []},
+            {"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 3},
+            {"jsonrpc": "2.0", "method": "eth_subscribe", "params": []},
+        ]);
+        let (status, body) = send(&app, signed_request("/relay", &signer, &batch.to_string())).await;
+        assert_eq!(status, StatusCode::OK);
+        let replies: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(replies.as_array().unwrap().len(), 1);
+        assert_eq!(replies[0]["id"], 3);
+        assert_eq!(replies[0]["error"]["code"], -402);
+    }
+
+    #[tokio::test]
+    async fn test_payment_rejected_when_deposits_disabled() {
+        let (state, _dir) = test_state(test_config(&format!("{}\ndeposits_enabled = false\n", DEFAULT_RESOURCE)));
+        assert!(state.paygate.is_none());
+        let app = build_router(state);
+
+        let request = Request::post("/relay")
+            .header("X-Payment", "eyJ4NDAyVmVyc2lvbiI6MX0=")
+            .body(Body::from(rpc_call("eth_chainId", 1)))
+            .unwrap();
+        let (status, _) = send(&app, request).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Unfunded callers aren't offered payment requirements
+        let signer = PrivateKeySigner::random();
+        let (status, body) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_chainId", 1))).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["error"], "Insufficient balance (deposits are disabled on this gateway)");
+        assert_eq!(reply["balance"], 0.0);
+        assert!(reply.get("accepts").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_verification_and_settlement_failures_are_distinct() {
+        let (state, _dir) = test_state(test_config(DEFAULT_RESOURCE));
+        let resource = &state.config.resources[0];
+
+        // Rejected payment: retry with a new one against the same requirements
+        let response = verification_failed(&state, resource, "insufficient funds");
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert!(reply["error"].as_str().unwrap().contains("insufficient funds"));
+        assert_eq!(reply["accepts"].as_array().unwrap().len(), 1);
+
+        // Verified but unsettled: the client is told to contact support
+        let error = SettlementError {
+            payer: "0xaaaa".to_string(),
+            credited: "0xaaaa".to_string(),
+            amount_usdc: 1.0,
+            reason: "facilitator timeout".to_string(),
+        };
+        assert!(error.to_string().contains("facilitator timeout"));
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["error"], "Payment verified but settlement failed, contact support");
+    }
+
+    #[tokio::test]
+    async fn test_time_endpoint_reports_server_time() {
+        let (state, _dir) = test_state(test_config(DEFAULT_RESOURCE));
+        let app = build_router(state);
+
+        let (status, body) = send(&app, Request::get("/time").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert!(reply["timestamp"].as_u64().unwrap().abs_diff(now_secs()) <= 1);
+    }
+
+    #[tokio::test]
+    async fn test_timestamps_judged_against_block_time() {
+        // The chain is ten minutes behind the wall clock
+        let chain_now = now_secs() - 600;
+        let node = spawn_fixed_node(json!({
+            "jsonrpc": "2.0", "id": 1, "result": {"number": "0x64", "timestamp": format!("{:#x}", chain_now)}
+        })).await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.0\n[block_time]\nmax_skew_secs = 3600\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        state.block_clock.as_ref().unwrap().refresh(&state.client, &node).await;
+
+        let (status, body) = send(&app, Request::get("/time").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert!(reply["timestamp"].as_u64().unwrap().abs_diff(chain_now) <= 1);
+
+        // A client on chain time is accepted, one on the wall clock is now out of the window
+        let (status, _) = send(&app, signed_request_at("/relay", &signer, &rpc_call("eth_chainId", 1), chain_now)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(&app, signed_request_at("/relay", &signer, &rpc_call("eth_chainId", 2), now_secs())).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(String::from_utf8_lossy(&body).contains("Timestamp outside window"));
+    }
+
+    #[tokio::test]
+    async fn test_discovery_document_matches_config() {
+        let mut config = test_config(
+            r#"
+            [[resources]]
+            name = "eth"
+            path = "/eth"
+            node_url = "http://127.0.0.1:1"
+            price_per_request = 0.001
+
+            [[resources]]
+            name = "base"
+            path = "/base"
+            node_url = "http://127.0.0.1:1"
+            price_per_request = 0.002
+            network = "base"
+
+            [address_pricing]
+            "0x00000000000000000000000000000000000000aa" = 0.0001
+            "#,
+        );
+        config.admin_token = Some("a".repeat(32));
+        let (state, _dir) = test_state(config.clone());
+        let app = build_router(state);
+
+        let (status, body) = send(&app, Request::get("/.well-known/x402").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        let document: Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(document["x402_version"], 1);
+        assert_eq!(document["payment_address"], "0x1234567890AbcdEF1234567890aBcdef12345678");
+        assert_eq!(document["networks"], json!(["base-sepolia", "base"]));
+        assert_eq!(document["assets"][0]["address"], "0x036CbD53842c5426634e7929541eC2318f3dCF7e");
+        assert_eq!(document["deposits_enabled"], config.deposits_enabled);
+        assert_eq!(document["price_model"]["topup_amount"], TOPUP_AMOUNT_USDC);
+        assert_eq!(
+            document["price_model"]["resources"],
+            json!([
+                {"name": "eth", "path": "/eth", "network": "base-sepolia", "price_per_request": 0.001},
+                {"name": "base", "path": "/base", "network": "base", "price_per_request": 0.002},
+            ])
+        );
+
+        // Nothing private leaks into the document
+        let raw = String::from_utf8(body.to_vec()).unwrap();
+        assert!(!raw.contains(&"a".repeat(32)));
+        assert!(!raw.contains("0x00000000000000000000000000000000000000aa"));
+        assert!(!raw.contains("127.0.0.1"));
+    }
+
+    #[tokio::test]
+    async fn test_monotonic_timestamps_reject_equal_and_older() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let (state, _dir) = test_state(test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.001\nmonotonic_timestamps = true\n",
+            node
+        )));
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        state.database.add_balance(&signer.address().to_string(), 1.0).await.unwrap();
+        let now = now_secs();
+
+        let (status, _) = send(&app, signed_request_at("/relay", &signer, &rpc_call("eth_chainId", 1), now)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Fresh signatures (different bodies) at the same or an earlier time are rejected
+        for (timestamp, id) in [(now, 2), (now - 1, 3)] {
+            let request = signed_request_at("/relay", &signer, &rpc_call("eth_chainId", id), timestamp);
+            let (status, body) = send(&app, request).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(body, "Replay detected: timestamp not after the last accepted request");
+        }
+
+        let (status, _) = send(&app, signed_request_at("/relay", &signer, &rpc_call("eth_chainId", 4), now + 1)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Other addresses aren't held back by this one
+        let other = PrivateKeySigner::random();
+        state.database.add_balance(&other.address().to_string(), 1.0).await.unwrap();
+        let (status, _) = send(&app, signed_request_at("/relay", &other, &rpc_call("eth_chainId", 1), now)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_auth_state_reports_last_accepted_timestamp() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let (state, _dir) = test_state(test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.001\nmonotonic_timestamps = true\n",
+            node
+        )));
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        state.database.add_balance(&signer.address().to_string(), 1.0).await.unwrap();
+        let now = now_secs();
+
+        let auth_state = |timestamp: u64| {
+            Request::get("/auth-state")
+                .header("X-Auth-Address", signer.address().to_string())
+                .header("X-Auth-Signature", sign(&signer, timestamp, "GET", "/auth-state", b""))
+                .header("X-Auth-Timestamp", timestamp.to_string())
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let (status, body) = send(&app, auth_state(now - 5)).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["monotonic_timestamps"], true);
+        assert_eq!(reply["last_accepted_timestamp"], Value::Null);
+        assert_eq!(reply["last_request_timestamp"], Value::Null);
+
+        let (status, _) = send(&app, signed_request_at("/relay", &signer, &rpc_call("eth_chainId", 1), now)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Readable with an older timestamp, and reading doesn't move the last accepted one
+        let (status, body) = send(&app, auth_state(now - 1)).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["address"], signer.address().to_checksum(None));
+        assert_eq!(reply["last_accepted_timestamp"], now);
+        assert_eq!(reply["last_request_timestamp"], now);
+
+        let (status, _) = send(&app, signed_request_at("/relay", &signer, &rpc_call("eth_chainId", 2), now + 1)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_auth_headers_rejected() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let (state, _dir) = test_state(test_config(&format!("node_url = \"{}\"\nprice_per_request = 1.0\n", node)));
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 5.0).await.unwrap();
+
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_chainId", 1))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // A second signature is smuggled in after a valid one
+        let mut request = signed_request("/relay", &signer, &rpc_call("eth_chainId", 2));
+        let injected = sign(&PrivateKeySigner::random(), now_secs(), "POST", "/relay", b"{}");
+        request.headers_mut().append("X-Auth-Signature", injected.parse().unwrap());
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, "Duplicate x-auth-signature header");
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().balance, 4.0);
+
+        // Without any auth headers the caller is still asked to pay
+        let unauthenticated = Request::post("/relay").body(Body::from(rpc_call("eth_chainId", 3))).unwrap();
+        let (status, _) = send(&app, unauthenticated).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    }
+
+    #[tokio::test]
+    async fn test_debug_verify_explains_signatures() {
+        use crate::config::BodyCanonicalization;
+        use alloy::signers::SignerSync;
+
+        let signer = PrivateKeySigner::random();
+        let timestamp = now_secs();
+        let body = rpc_call("eth_chainId", 1);
+        let verify = |address: String, signature: &str| {
+            Request::post("/debug/verify")
+                .header("content-type", "application/json")
+                .body(Body::from(json!({
+                    "address": address,
+                    "signature": signature,
+                    "timestamp": timestamp,
+                    "body": body,
+                }).to_string()))
+                .unwrap()
+        };
+        let signature = sign(&signer, timestamp, "POST", "/relay", body.as_bytes());
+
+        // Off by default
+        let (state, _dir) = test_state(test_config(DEFAULT_RESOURCE));
+        let (status, _) = send(&build_router(state), verify(signer.address().to_string(), &signature)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (state, _dir) = test_state(test_config(&format!("{}\ndebug_endpoints = true\n", DEFAULT_RESOURCE)));
+        let app = build_router(state);
+
+        let (status, reply) = send(&app, verify(signer.address().to_string(), &signature)).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&reply).unwrap();
+        assert_eq!(reply["matches"], true);
+        assert_eq!(reply["recovered_address"], signer.address().to_string());
+        assert_eq!(
+            reply["message"],
+            format!("{}{}POST/relay{}", signer.address(), timestamp, hex::encode(alloy::primitives::keccak256(&body)))
+        );
+
+        // Signed for another address: the signer is still recovered, just not matched
+        let other = PrivateKeySigner::random();
+        let (status, reply) = send(&app, verify(other.address().to_string(), &signature)).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&reply).unwrap();
+        assert_eq!(reply["matches"], false);
+        assert_ne!(reply["recovered_address"], other.address().to_string());
+        assert!(reply["message"].as_str().unwrap().starts_with(&other.address().to_string()));
+
+        // Clients hashing sha256 over canonical JSON are verified as the gateway would verify them
+        let (state, _dir) = test_state(test_config(&format!(
+            "{}\ndebug_endpoints = true\nbody_hash_algorithms = [\"keccak256\", \"sha256\"]\nbody_canonicalizations = [\"exact\", \"canonical_json\"]\n",
+            DEFAULT_RESOURCE
+        )));
+        let app = build_router(state);
+        let message = auth::signed_message_with(
+            &signer.address().to_string(),
+            timestamp,
+            "POST",
+            "/relay",
+            body.as_bytes(),
+            BodyHashAlgorithm::Sha256,
+            BodyCanonicalization::CanonicalJson,
+        ).unwrap();
+        let signature = signer.sign_hash_sync(&alloy::primitives::keccak256(message.as_bytes())).unwrap().to_string();
+        let with_headers = |algorithm: &'static str, canonicalization: &'static str| {
+            let mut request = verify(signer.address().to_string(), &signature);
+            request.headers_mut().insert("x-auth-body-hash-algorithm", algorithm.parse().unwrap());
+            request.headers_mut().insert("x-auth-body-canonicalization", canonicalization.parse().unwrap());
+            request
+        };
+
+        let (status, reply) = send(&app, with_headers("sha256", "canonical_json")).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&reply).unwrap();
+        assert_eq!(reply["matches"], true);
+        assert_eq!(reply["message"], message);
+
+        let (status, reply) = send(&app, with_headers("keccak256", "canonical_json")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_slice::<Value>(&reply).unwrap()["matches"], false);
+
+        // Forms the gateway doesn't accept are refused, as authentication would refuse them
+        let (state, _dir) = test_state(test_config(&format!("{}\ndebug_endpoints = true\n", DEFAULT_RESOURCE)));
+        let (status, reply) = send(&build_router(state), with_headers("sha256", "exact")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(reply, "Body hash algorithm Sha256 is not accepted");
+    }
+
+    #[tokio::test]
+    async fn test_signature_for_other_path_rejected() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!(
+            r#"
+            [[resources]]
+            name = "eth"
+            path = "/eth"
+            node_url = "{0}"
+            price_per_request = 0.0
+
+            [[resources]]
+            name = "base"
+            path = "/base"
+            node_url = "{0}"
+            price_per_request = 0.0
+            "#,
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state);
+        let signer = PrivateKeySigner::random();
+        let body = rpc_call("eth_chainId", 1);
+
+        // Signed for /eth but sent to /base
+        let mut request = signed_request("/eth", &signer, &body);
+        *request.uri_mut() = "/base".parse().unwrap();
+        let (status, _) = send(&app, request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = send(&app, signed_request("/base", &signer, &body)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_address_pricing_applies_to_relay_and_quote() {
+        let discounted = PrivateKeySigner::random();
+        let regular = PrivateKeySigner::random();
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.5\n[address_pricing]\n\"{}\" = 0.25\n",
+            node,
+            discounted.address()
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+
+        for signer in [&discounted, &regular] {
+            state.database.add_balance(&signer.address().to_string(), 10.0).await.unwrap();
+            let (status, _) = send(&app, signed_request("/relay", signer, &rpc_call("eth_chainId", 1))).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let balance = |signer: &PrivateKeySigner| {
+            let address = signer.address().to_string();
+            let database = state.database.clone();
+            async move { database.get_user(&address).await.unwrap().unwrap().balance }
+        };
+        assert_eq!(balance(&discounted).await, 9.75);
+        assert_eq!(balance(&regular).await, 9.5);
+
+        // Quotes reflect the negotiated price once the address is known
+        let uri = format!("/quote?address={}", discounted.address().to_string().to_lowercase());
+        let (status, body) = send(&app, Request::get(uri).body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+        let quote: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(quote["price_per_request"], 0.25);
+
+        let (_, body) = send(&app, Request::get("/quote").body(Body::empty()).unwrap()).await;
+        let quote: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(quote["price_per_request"], 0.5);
+    }
+
+    /// Oracle pricing the asset at a fixed number of dollars per token
+    struct FixedRateOracle {
+        usd_per_token: f64,
+    }
+
+    impl PricingOracle for FixedRateOracle {
+        fn usd_to_asset_units(&self, usd_amount: f64, rounding: RoundingMode) -> u64 {
+            USDC.to_units(usd_amount / self.usd_per_token, rounding) as u64
+        }
+    }
+
+    #[test]
+    fn test_inflated_payload_value_credits_verified_amount() {
+        let (state, _dir) = test_state(test_config(DEFAULT_RESOURCE));
+        let requirements = &create_payment_requirements(&state, &state.config.resources[0]).unwrap()[0];
+        let topup = USDC.to_units(TOPUP_AMOUNT_USDC, RoundingMode::Round);
+
+        // A payload claiming 5 USDC for a 1 USDC top-up is credited 1 USDC
+        assert_eq!(verified_amount(requirements, USDC.to_units(5.0, RoundingMode::Round)), Ok(topup));
+        assert_eq!(verified_amount(requirements, topup), Ok(topup));
+        assert_eq!(verified_amount(requirements, topup - 1), Ok(topup - 1));
+    }
+
+    #[test]
+    fn test_payment_requirements_follow_asset_and_network() {
+        let config = test_config(
+            r#"
+            [[resources]]
+            name = "sepolia"
+            path = "/sepolia"
+            node_url = "http://localhost:8545"
+            price_per_request = 0.001
+
+            [[resources]]
+            name = "base"
+            path = "/base"
+            node_url = "http://localhost:8546"
+            price_per_request = 0.001
+            network = "base"
+
+            [[resources]]
+            name = "fuji"
+            path = "/fuji"
+            node_url = "http://localhost:8547"
+            price_per_request = 0.001
+            network = "avalanche-fuji"
+
+            [[payment_assets]]
+            network = "base-sepolia"
+            address = "0x036CbD53842c5426634e7929541eC2318f3dCF7e"
+            name = "USDC"
+            decimals = 6
+            max_amount = 100.0
+
+            [[payment_assets]]
+            network = "base"
+            address = "0x50c5725949A6F0c72E6C4a641F24049A917DB0Cb"
+            name = "Dai Stablecoin"
+            version = "1"
+            decimals = 18
+            min_amount = 1.0
+            max_timeout_seconds = 600
+            "#,
+        );
+        let (state, _dir) = test_state(config);
+
+        let sepolia = create_payment_requirements(&state, &state.config.resources[0]).unwrap();
+        assert_eq!(sepolia.len(), 1);
+        assert_eq!(sepolia[0].network, Network::BaseSepolia);
+        assert_eq!(sepolia[0].max_amount_required, TokenAmount::from(1_000_000u64));
+        assert_eq!(sepolia[0].max_timeout_seconds, 300);
+        assert_eq!(sepolia[0].extra.as_ref().unwrap()["name"], "USDC");
+
+        // The same $1 top-up is scaled to the token's 18 decimals
+        let base = create_payment_requirements(&state, &state.config.resources[1]).unwrap();
+        assert_eq!(base.len(), 1);
+        assert_eq!(base[0].network, Network::Base);
+        assert_eq!(base[0].max_amount_required, TokenAmount::from(1_000_000_000_000_000_000u128));
+        assert_eq!(base[0].max_timeout_seconds, 600);
+        assert_eq!(base[0].extra.as_ref().unwrap()["version"], "1");
+        assert_eq!(verified_tokens(&state, &base[0], 1_000_000_000_000_000_000), 1.0);
+
+        // No token is accepted on a network without one configured
+        let error = create_payment_requirements(&state, &state.config.resources[2]).unwrap_err();
+        assert!(error.contains("avalanche-fuji"), "{}", error);
+    }
+
+    #[test]
+    fn test_payment_requirements_pay_to_network_address() {
+        const SEPOLIA_TREASURY: &str = "0x00000000000000000000000000000000000000a1";
+        const BASE_TREASURY: &str = "0x00000000000000000000000000000000000000b2";
+        let config = test_config(&format!(
+            r#"
+            [[resources]]
+            name = "sepolia"
+            path = "/sepolia"
+            node_url = "http://localhost:8545"
+            price_per_request = 0.001
+
+            [[resources]]
+            name = "base"
+            path = "/base"
+            node_url = "http://localhost:8546"
+            price_per_request = 0.001
+            network = "base"
+
+            [[resources]]
+            name = "fuji"
+            path = "/fuji"
+            node_url = "http://localhost:8547"
+            price_per_request = 0.001
+            network = "avalanche-fuji"
+
+            [payment_addresses]
+            base-sepolia = "{SEPOLIA_TREASURY}"
+            base = "{BASE_TREASURY}"
+            "#,
+        ));
+        let (state, _dir) = test_state(config);
+        let pay_to = |resource: usize| create_payment_requirements(&state, &state.config.resources[resource]).unwrap()[0].pay_to.clone();
+        let evm = |address: &str| MixedAddress::Evm(EvmAddress::from_str(address).unwrap());
+
+        assert_eq!(pay_to(0), evm(SEPOLIA_TREASURY));
+        assert_eq!(pay_to(1), evm(BASE_TREASURY));
+        // Networks without their own address are paid to PAYMENT_ADDRESS
+        assert_eq!(pay_to(2), evm(PAYMENT_ADDRESS));
+    }
+
+    #[test]
+    fn test_topup_outside_asset_bounds_refused() {
+        let config = test_config(&format!(
+            r#"{}
+            [[payment_assets]]
+            network = "base-sepolia"
+            address = "0x036CbD53842c5426634e7929541eC2318f3dCF7e"
+            name = "USDC"
+            decimals = 6
+            min_amount = 5.0
+            "#,
+            DEFAULT_RESOURCE
+        ));
+        let (state, _dir) = test_state(config);
+        let error = create_payment_requirements(&state, &state.config.resources[0]).unwrap_err();
+        assert!(error.contains("outside the bounds"), "{}", error);
+    }
+
+    #[test]
+    fn test_deposits_below_minimum_refused() {
+        let config = test_config(&format!("{}\nmin_deposit = 0.5\n", DEFAULT_RESOURCE));
+        let below = USDC.to_tokens(USDC.to_units(0.49, RoundingMode::Round));
+        let at = USDC.to_tokens(USDC.to_units(0.5, RoundingMode::Round));
+
+        let error = check_min_deposit(config.min_deposit, below).unwrap_err();
+        assert!(error.contains("below the minimum of 0.5 USDC"), "{}", error);
+        assert_eq!(check_min_deposit(config.min_deposit, at), Ok(()));
+        assert_eq!(check_min_deposit(None, 0.000001), Ok(()));
+    }
+
+    /// Paygate verifying every payment as a transfer from `payer`, counting settlements
+    struct MockPaygate {
+        payer: String,
+        value: u128,
+        settlements: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Paygate for MockPaygate {
+        async fn verify(
+            &self,
+            _headers: &HeaderMap,
+            requirements: Vec<PaymentRequirements>,
+        ) -> Result<VerifyRequest, PaygateError> {
+            let payment_requirements = requirements.into_iter().next().unwrap();
+            let payment_payload = serde_json::from_value(json!({
+                "x402Version": 1,
+                "scheme": "exact",
+                "network": payment_requirements.network,
+                "payload": {
+                    "signature": format!("0x{}", "11".repeat(65)),
+                    "authorization": {
+                        "from": self.payer,
+                        "to": PAYMENT_ADDRESS,
+                        "value": self.value.to_string(),
+                        "validAfter": "0",
+                        "validBefore": (now_secs() + 600).to_string(),
+                        "nonce": format!("0x{}", "22".repeat(32)),
+                    }
+                }
+            }))
+            .unwrap();
+            Ok(VerifyRequest { x402_version: X402Version::V1, payment_payload, payment_requirements })
+        }
+
+        async fn settle(&self, _request: &VerifyRequest) -> Result<(), String> {
+            self.settlements.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn deposit_request() -> Request<Body> {
+        Request::post("/relay")
+            .header("X-Payment", "eyJ4NDAyVmVyc2lvbiI6MX0=")
+            .body(Body::from(rpc_call("eth_chainId", 1)))
+            .unwrap()
+    }
+
+    /// Paygate taking the payment from the X-Payment header and, like a facilitator, rejecting
+    /// it unless it pays the address it's verified against
+    struct HeaderPaygate;
+
+    #[async_trait]
+    impl Paygate for HeaderPaygate {
+        async fn verify(
+            &self,
+            headers: &HeaderMap,
+            requirements: Vec<PaymentRequirements>,
+        ) -> Result<VerifyRequest, PaygateError> {
+            use base64::Engine;
+
+            let header = headers.get("X-Payment").unwrap().to_str().unwrap();
+            let payment: Value = serde_json::from_slice(&base64::engine::general_purpose::STANDARD.decode(header).unwrap()).unwrap();
+            let payment_requirements = requirements.into_iter().next().unwrap();
+            let recipient = payment["payload"]["authorization"]["to"].as_str().unwrap();
+            if payment_requirements.pay_to != MixedAddress::Evm(EvmAddress::from_str(recipient).unwrap()) {
+                return Err(PaygateError::Verification(format!("payment to {} instead of the payment address", recipient)));
+            }
+            let payment_payload = serde_json::from_value(payment).unwrap();
+            Ok(VerifyRequest { x402_version: X402Version::V1, payment_payload, payment_requirements })
+        }
+
+        async fn settle(&self, _request: &VerifyRequest) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deposit_to_rotated_out_address_accepted_during_grace() {
+        use base64::Engine;
+
+        let node = spawn_echo_node(json!("0x1")).await;
+        let previous = "0x00000000000000000000000000000000000000c1";
+        let retired = "0x00000000000000000000000000000000000000c2";
+        let (state, _dir) = test_state(test_config(&format!(
+            r#"
+            node_url = "{}"
+            price_per_request = 0.1
+            [[previous_payment_addresses]]
+            address = "{}"
+            accepted_until = {}
+            [[previous_payment_addresses]]
+            address = "{}"
+            accepted_until = {}
+            "#,
+            node,
+            previous,
+            now_secs() + 3600,
+            retired,
+            now_secs() - 1
+        )));
+        let mut with_paygate = (*state).clone();
+        with_paygate.paygate = Some(Arc::new(HeaderPaygate));
+        let state = Arc::new(with_paygate);
+        let app = build_router(state.clone());
+
+        let deposit = |payer: &str, to: &str, nonce: u8| {
+            let payment = json!({
+                "x402Version": 1,
+                "scheme": "exact",
+                "network": "base-sepolia",
+                "payload": {
+                    "signature": format!("0x{}", "11".repeat(65)),
+                    "authorization": {
+                        "from": payer,
+                        "to": to,
+                        "value": "1000000",
+                        "validAfter": "0",
+                        "validBefore": (now_secs() + 600).to_string(),
+                        "nonce": format!("0x{}", format!("{:02x}", nonce).repeat(32)),
+                    }
+                }
+            });
+            Request::post("/relay")
+                .header("X-Payment", base64::engine::general_purpose::STANDARD.encode(payment.to_string()))
+                .body(Body::from(rpc_call("eth_chainId", 1)))
+                .unwrap()
+        };
+
+        // Paid against a challenge issued before the rotation, within the grace period
+        let payer = PrivateKeySigner::random().address().to_string();
+        let (status, body) = send(&app, deposit(&payer, previous, 1)).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        let user = state.database.get_user(&payer).await.unwrap().unwrap();
+        assert!((user.balance - 0.9).abs() < 1e-9, "{}", user.balance);
+
+        // The current address is accepted as before
+        let payer = PrivateKeySigner::random().address().to_string();
+        let (status, _) = send(&app, deposit(&payer, PAYMENT_ADDRESS, 2)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Past its grace period the old address is refused, and the new challenge names the current one
+        let payer = PrivateKeySigner::random().address().to_string();
+        let (status, body) = send(&app, deposit(&payer, retired, 3)).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        let challenge: PaymentRequirements = serde_json::from_value(reply["accepts"][0].clone()).unwrap();
+        assert_eq!(challenge.pay_to, MixedAddress::Evm(EvmAddress::from_str(PAYMENT_ADDRESS).unwrap()));
+        assert!(state.database.get_user(&payer).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_verified_deposit_settled_and_credited() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let (state, _dir) = test_state(test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.1\nmin_deposit = 0.5\n",
+            node
+        )));
+        let payer = PrivateKeySigner::random().address().to_string();
+        let paygate = Arc::new(MockPaygate { payer: payer.clone(), value: 1_000_000, settlements: AtomicUsize::new(0) });
+        let mut with_paygate = (*state).clone();
+        with_paygate.paygate = Some(paygate.clone());
+        let state = Arc::new(with_paygate);
+        let app = build_router(state.clone());
+
+        let (status, body) = send(&app, deposit_request()).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["result"], "0x1");
+        assert_eq!(paygate.settlements.load(Ordering::SeqCst), 1);
+
+        // The 1 USDC deposit is credited, less the request it paid for
+        let user = state.database.get_user(&payer).await.unwrap().unwrap();
+        assert!((user.balance - 0.9).abs() < 1e-9, "{}", user.balance);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_on_other_network_refused_before_settlement() {
+        /// Pays on Base whatever network was asked for
+        struct OtherNetworkPaygate(MockPaygate);
+
+        #[async_trait]
+        impl Paygate for OtherNetworkPaygate {
+            async fn verify(
+                &self,
+                headers: &HeaderMap,
+                requirements: Vec<PaymentRequirements>,
+            ) -> Result<VerifyRequest, PaygateError> {
+                let mut request = self.0.verify(headers, requirements).await?;
+                let mut payload = serde_json::to_value(&request.payment_payload).unwrap();
+                payload["network"] = json!(Network::Base);
+                request.payment_payload = serde_json::from_value(payload).unwrap();
+                Ok(request)
+            }
+
+            async fn settle(&self, request: &VerifyRequest) -> Result<(), String> {
+                self.0.settle(request).await
+            }
+        }
+
+        let node = spawn_echo_node(json!("0x1")).await;
+        let payer = PrivateKeySigner::random().address().to_string();
+        for require_match in [true, false] {
+            let (state, _dir) = test_state(test_config(&format!(
+                "node_url = \"{}\"\nprice_per_request = 0.1\nrequire_deposit_network_match = {}\n",
+                node, require_match
+            )));
+            let paygate = Arc::new(OtherNetworkPaygate(MockPaygate {
+                payer: payer.clone(),
+                value: 1_000_000,
+                settlements: AtomicUsize::new(0),
+            }));
+            let mut with_paygate = (*state).clone();
+            with_paygate.paygate = Some(paygate.clone());
+            let state = Arc::new(with_paygate);
+
+            let (status, body) = send(&build_router(state.clone()), deposit_request()).await;
+            if require_match {
+                assert_eq!(status, StatusCode::BAD_REQUEST);
+                assert!(String::from_utf8_lossy(&body).contains("Payment is on network 'base' but this resource relays to 'base-sepolia'"));
+                assert_eq!(paygate.0.settlements.load(Ordering::SeqCst), 0);
+                assert!(state.database.get_user(&payer).await.unwrap().is_none());
+            } else {
+                assert_eq!(status, StatusCode::OK);
+                assert_eq!(paygate.0.settlements.load(Ordering::SeqCst), 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deposit_opening_account_past_cap_refused_before_settlement() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!("node_url = \"{}\"\nprice_per_request = 0.1\n", node));
+        let temp_dir = tempfile::tempdir().unwrap();
+        let rocksdb = crate::config::RocksDbConfig { max_accounts: Some(1), ..Default::default() };
+        let database: Arc<dyn crate::database::DatabaseTrait> = Arc::new(
+            crate::database::rocksdb::RocksDbDatabase::open_with_config(
+                temp_dir.path().join("test.db").to_str().unwrap(),
+                &rocksdb,
+            )
+            .unwrap(),
+        );
+        let existing = PrivateKeySigner::random().address().to_string();
+        database.add_balance(&existing, 0.5).await.unwrap();
+
+        let deposit_state = |payer: &str| {
+            let mut state = AppState::new(config.clone(), database.clone()).unwrap();
+            let paygate = Arc::new(MockPaygate { payer: payer.to_string(), value: 1_000_000, settlements: AtomicUsize::new(0) });
+            state.paygate = Some(paygate.clone());
+            (Arc::new(state), paygate)
+        };
+
+        // A newcomer's payment isn't taken, since it couldn't be credited
+        let newcomer = PrivateKeySigner::random().address().to_string();
+        let (state, paygate) = deposit_state(&newcomer);
+        let (status, _) = send(&build_router(state.clone()), deposit_request()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(paygate.settlements.load(Ordering::SeqCst), 0);
+        assert!(state.database.get_user(&newcomer).await.unwrap().is_none());
+
+        // The existing account still tops up
+        let (state, paygate) = deposit_state(&existing);
+        let (status, _) = send(&build_router(state.clone()), deposit_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(paygate.settlements.load(Ordering::SeqCst), 1);
+        assert!((state.database.get_user(&existing).await.unwrap().unwrap().balance - 1.4).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_request_not_relayed_when_charge_fails() {
+        // Bandwidth billing credits the deposit before reserving the request's charge
+        let relayed = Arc::new(AtomicUsize::new(0));
+        let node = spawn_server(axum::Router::new().route("/", axum::routing::post({
+            let relayed = relayed.clone();
+            move || async move {
+                relayed.fetch_add(1, Ordering::SeqCst);
+                axum::Json(json!({"jsonrpc": "2.0", "id": 1, "result": "0x1"}))
+            }
+        })))
+        .await;
+        let deposit_state = |on_deduction_failure: &str| {
+            let config = test_config(&format!(
+                "node_url = \"{}\"\nprice_per_request = 0.1\non_deduction_failure = \"{}\"\n\n[bandwidth_billing]\nper_byte_price = 0.00001\nmax_billed_bytes = 1000\n",
+                node, on_deduction_failure
+            ));
+            let temp_dir = tempfile::tempdir().unwrap();
+            let database = Arc::new(RecordingDatabase::open(&temp_dir.path().join("test.db"), true));
+            let mut state = AppState::new(config, database).unwrap();
+            let payer = PrivateKeySigner::random().address().to_string();
+            state.paygate = Some(Arc::new(MockPaygate { payer: payer.clone(), value: 1_000_000, settlements: AtomicUsize::new(0) }));
+            (Arc::new(state), payer, temp_dir)
+        };
+
+        // Safe default: the request isn't served for free, and the credited deposit stays for a retry
+        let (state, payer, _dir) = deposit_state("fail");
+        let (status, _) = send(&build_router(state.clone()), deposit_request()).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(relayed.load(Ordering::SeqCst), 0);
+        assert_eq!(state.database.get_user(&payer).await.unwrap().unwrap().balance, 1.0);
+        assert_eq!(state.metrics.deduction_failures.load(Ordering::SeqCst), 1);
+
+        let (state, _, _dir) = deposit_state("proceed");
+        let (status, _) = send(&build_router(state.clone()), deposit_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(relayed.load(Ordering::SeqCst), 1);
+        assert_eq!(state.metrics.deduction_failures.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_doomed_raw_transactions_rejected_before_billing() {
+        use alloy::consensus::{SignableTransaction, TxEip1559, TxEnvelope};
+        use alloy::eips::eip2718::Encodable2718;
+        use alloy::primitives::{TxKind, U256};
+        use alloy::signers::SignerSync;
+
+        // Next nonce 5, base fee 100 wei; estimating a transfer of any value fails
+        let relayed = Arc::new(AtomicUsize::new(0));
+        let prechecks = Arc::new(AtomicUsize::new(0));
+        let node = spawn_server(axum::Router::new().route("/", axum::routing::post({
+            let relayed = relayed.clone();
+            let prechecks = prechecks.clone();
+            move |body: Bytes| async move {
+                let call: Value = serde_json::from_slice(&body).unwrap();
+                if call["method"] != "eth_sendRawTransaction" {
+                    prechecks.fetch_add(1, Ordering::SeqCst);
+                }
+                let reply = match call["method"].as_str().unwrap() {
+                    "eth_getTransactionCount" => json!({"result": "0x5"}),
+                    "eth_getBlockByNumber" => json!({"result": {"number": "0x10", "baseFeePerGas": "0x64"}}),
+                    "eth_estimateGas" if call["params"][0]["value"] != "0x0" => {
+                        json!({"error": {"code": -32000, "message": "insufficient funds for transfer"}})
+                    }
+                    "eth_estimateGas" => json!({"result": "0x5208"}),
+                    _ => {
+                        relayed.fetch_add(1, Ordering::SeqCst);
+                        json!({"result": "0xabc"})
+                    }
+                };
+                let mut reply = reply;
+                reply["jsonrpc"] = json!("2.0");
+                reply["id"] = call["id"].clone();
+                axum::Json(reply)
+            }
+        })))
+        .await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.01\nprecheck_raw_transactions = true\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 1.0).await.unwrap();
+
+        let sender = PrivateKeySigner::random();
+        let raw_transaction = |nonce: u64, max_fee_per_gas: u128, value: u64| {
+            let tx = TxEip1559 {
+                chain_id: 84532,
+                nonce,
+                gas_limit: 21_000,
+                max_fee_per_gas,
+                max_priority_fee_per_gas: 1,
+                to: TxKind::Call(Address::ZERO),
+                value: U256::from(value),
+                ..Default::default()
+            };
+            let signature = sender.sign_hash_sync(&tx.signature_hash()).unwrap();
+            format!("0x{}", hex::encode(TxEnvelope::from(tx.into_signed(signature)).encoded_2718()))
+        };
+        let send_raw = |raw: String| {
+            json!({"jsonrpc": "2.0", "method": "eth_sendRawTransaction", "params": [raw], "id": 1}).to_string()
+        };
+
+        // Doomed transactions get a 400 naming the problem, and nothing is billed or relayed
+        for (raw, reason) in [
+            ("0xdeadbeef".to_string(), "could not be decoded"),
+            (raw_transaction(4, 1_000, 0), "nonce 4 is too low, the sender's next nonce is 5"),
+            (raw_transaction(5, 10, 0), "below the current base fee 100"),
+            (raw_transaction(5, 1_000, 1), "insufficient funds for transfer"),
+        ] {
+            let (status, body) = send(&app, signed_request("/relay", &signer, &send_raw(raw))).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(String::from_utf8_lossy(&body).contains(reason), "{}", String::from_utf8_lossy(&body));
+        }
+        assert_eq!(relayed.load(Ordering::SeqCst), 0);
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().balance, 1.0);
+
+        // A sound transaction is billed and relayed as usual
+        let (status, _) = send(&app, signed_request("/relay", &signer, &send_raw(raw_transaction(5, 1_000, 0)))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(relayed.load(Ordering::SeqCst), 1);
+        assert!((state.database.get_user(&address).await.unwrap().unwrap().balance - 0.99).abs() < 1e-9);
+
+        // A transaction past the pending nonce may depend on the ones before it, such as the
+        // previous one in a batch, so it isn't estimated against the latest state
+        let (status, _) = send(&app, signed_request("/relay", &signer, &send_raw(raw_transaction(6, 1_000, 1)))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(relayed.load(Ordering::SeqCst), 2);
+
+        // An account that can't pay gets no node calls made on its behalf
+        let prechecked = prechecks.load(Ordering::SeqCst);
+        let unfunded = PrivateKeySigner::random();
+        let (status, _) = send(&app, signed_request("/relay", &unfunded, &send_raw(raw_transaction(4, 1_000, 0)))).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(prechecks.load(Ordering::SeqCst), prechecked);
+        assert_eq!(relayed.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_deposit_must_redeem_issued_challenge() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let (state, _dir) = test_state(test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.1\npayment_challenge_ttl_secs = 60\n",
+            node
+        )));
+        let payer = PrivateKeySigner::random().address().to_string();
+        let paygate = Arc::new(MockPaygate { payer: payer.clone(), value: 1_000_000, settlements: AtomicUsize::new(0) });
+        let mut with_paygate = (*state).clone();
+        with_paygate.paygate = Some(paygate.clone());
+        let state = Arc::new(with_paygate);
+        let app = build_router(state.clone());
+        let with_challenge = |challenge: &str| {
+            let mut request = deposit_request();
+            request.headers_mut().insert("X-Payment-Challenge", challenge.parse().unwrap());
+            request
+        };
+
+        // The 402 hands out a challenge
+        let (status, body) = send(&app, Request::post("/relay").body(Body::from(rpc_call("eth_chainId", 1))).unwrap()).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        let challenge = serde_json::from_slice::<Value>(&body).unwrap()["challenge"].as_str().unwrap().to_string();
+
+        // Without it, or with one never issued, nothing is settled
+        let (status, _) = send(&app, deposit_request()).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        let (status, _) = send(&app, with_challenge("0xforged")).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(paygate.settlements.load(Ordering::SeqCst), 0);
+
+        let (status, _) = send(&app, with_challenge(&challenge)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(paygate.settlements.load(Ordering::SeqCst), 1);
+
+        // A redeemed challenge can't be reused, and the refusal carries a fresh one
+        let (status, body) = send(&app, with_challenge(&challenge)).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(paygate.settlements.load(Ordering::SeqCst), 1);
+        let fresh = serde_json::from_slice::<Value>(&body).unwrap()["challenge"].as_str().unwrap().to_string();
+        assert_ne!(fresh, challenge);
+    }
+
+    #[tokio::test]
+    async fn test_dust_deposit_rejected_before_settlement() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let (state, _dir) = test_state(test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.1\nmin_deposit = 0.5\n",
+            node
+        )));
+        let payer = PrivateKeySigner::random().address().to_string();
+        let paygate = Arc::new(MockPaygate { payer: payer.clone(), value: 400_000, settlements: AtomicUsize::new(0) });
+        let mut with_paygate = (*state).clone();
+        with_paygate.paygate = Some(paygate.clone());
+        let state = Arc::new(with_paygate);
+        let app = build_router(state.clone());
+
+        let (status, body) = send(&app, deposit_request()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("below the minimum of 0.5 USDC"), "{}", body);
+        assert_eq!(paygate.settlements.load(Ordering::SeqCst), 0);
+        assert!(state.database.get_user(&payer).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_deposits_shed_once_global_budget_spent() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let (state, _dir) = test_state(test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.5\n[global_budget]\nmax_spend = 1.0\nwindow_secs = 3600\n",
+            node
+        )));
+        let payer = PrivateKeySigner::random().address().to_string();
+        let paygate = Arc::new(MockPaygate { payer: payer.clone(), value: 1_000_000, settlements: AtomicUsize::new(0) });
+        let mut with_paygate = (*state).clone();
+        with_paygate.paygate = Some(paygate.clone());
+        let state = Arc::new(with_paygate);
+        let app = build_router(state.clone());
+
+        // A deposit's request counts against the budget like any other
+        let (status, _) = send(&app, deposit_request()).await;
+        assert_eq!(status, StatusCode::OK);
+        let signer = PrivateKeySigner::random();
+        state.database.add_balance(&signer.address().to_string(), 10.0).await.unwrap();
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_call", 1))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Once it's spent, attaching a deposit doesn't get past the shedding, and nothing is settled
+        let response = app.clone().oneshot(deposit_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+        assert_eq!(paygate.settlements.load(Ordering::SeqCst), 1);
+        let user = state.database.get_user(&payer).await.unwrap().unwrap();
+        assert!((user.balance - 0.5).abs() < 1e-9, "{}", user.balance);
+    }
+
+    #[tokio::test]
+    async fn test_charged_price_rounded_with_configured_mode() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+
+        // $0.0000015 is 1.5 USDC units
+        for (mode, charged_units) in [("ceil", 2), ("floor", 1), ("round", 2)] {
+            let (state, _dir) = test_state(test_config(&format!(
+                "node_url = \"{}\"\nprice_per_request = 0.0000015\nprice_rounding = \"{}\"\n",
+                node, mode
+            )));
+            let app = build_router(state.clone());
+            state.database.add_balance(&address, 1.0).await.unwrap();
+
+            let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_chainId", 1))).await;
+            assert_eq!(status, StatusCode::OK);
+            let balance = state.database.get_user(&address).await.unwrap().unwrap().balance;
+            assert_eq!(USDC.to_units(1.0 - balance, RoundingMode::Round), charged_units, "{}", mode);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pricing_oracle_converts_charged_amounts() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let (state, _dir) = test_state(test_config(&format!("node_url = \"{}\"\nprice_per_request = 0.5\n", node)));
+        let mut state = (*state).clone();
+        state.pricing_oracle = Arc::new(FixedRateOracle { usd_per_token: 2.0 });
+        let state = Arc::new(state);
+        let app = build_router(state.clone());
+
+        // A $0.50 request costs 0.25 tokens at $2 per token
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 1.0).await.unwrap();
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_chainId", 1))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().balance, 0.75);
+
+        let (_, body) = send(&app, Request::get("/quote").body(Body::empty()).unwrap()).await;
+        let quote: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(quote["price_per_request"], 0.25);
+        assert_eq!(quote["topup_amount"], 0.5);
+
+        // The $1 top-up asks for half a token
+        let requirements = create_payment_requirements(&state, &state.config.resources[0]).unwrap();
+        assert_eq!(requirements[0].max_amount_required, TokenAmount::from(500_000u64));
+    }
+
+    #[tokio::test]
+    async fn test_bandwidth_billing_charges_by_response_size() {
+        let small_node = spawn_echo_node(json!("0x1")).await;
+        let large_node = spawn_echo_node(json!(format!("0x{}", "ab".repeat(5_000)))).await;
+        let config = test_config(&format!(
+            r#"
+            [[resources]]
+            name = "small"
+            path = "/small"
+            node_url = "{}"
+            price_per_request = 0.01
+
+            [[resources]]
+            name = "large"
+            path = "/large"
+            node_url = "{}"
+            price_per_request = 0.01
+
+            [bandwidth_billing]
+            per_byte_price = 0.00001
+            max_billed_bytes = 100000
+            "#,
+            small_node, large_node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+
+        let mut costs = Vec::new();
+        for path in ["/small", "/large"] {
+            state.database.add_balance(&address, 10.0).await.unwrap();
+            let (status, body) = send(&app, signed_request(path, &signer, &rpc_call("eth_call", 1))).await;
+            assert_eq!(status, StatusCode::OK);
+
+            let balance = state.database.get_user(&address).await.unwrap().unwrap().balance;
+            state.database.deduct_balance(&address, balance, 0).await.unwrap();
+
+            let expected = 0.01 + 0.00001 * body.len() as f64;
+            assert!((10.0 - balance - expected).abs() < 1e-9, "{} cost {}", path, 10.0 - balance);
+            costs.push(10.0 - balance);
+        }
+        assert!(costs[1] > costs[0] + 0.09);
+
+        // The whole reservation must be covered up front, not just the request price
+        state.database.add_balance(&address, 0.5).await.unwrap();
+        let (status, _) = send(&app, signed_request("/small", &signer, &rpc_call("eth_call", 2))).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_authenticates_relay_alongside_signatures() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let key = "internal-service-key-0123456789abcdef";
+        let service = PrivateKeySigner::random().address().to_string();
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.5\nauth_schemes = [\"signature\", \"api_key\"]\n[api_keys]\n\"{}\" = \"{}\"\n",
+            node, key, service
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        state.database.add_balance(&service, 10.0).await.unwrap();
+
+        let request = |key: &str| {
+            Request::post("/relay")
+                .header("content-type", "application/json")
+                .header("X-Api-Key", key)
+                .body(Body::from(rpc_call("eth_chainId", 1)))
+                .unwrap()
+        };
+
+        let (status, _) = send(&app, request(key)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.database.get_user(&service).await.unwrap().unwrap().balance, 9.5);
+
+        let (status, _) = send(&app, request("not-a-configured-key")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Signatures keep working
+        let signer = PrivateKeySigner::random();
+        state.database.add_balance(&signer.address().to_string(), 1.0).await.unwrap();
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_chainId", 1))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    fn adjust_request(token: &str, address: &str, delta: f64, reason: &str) -> Request<Body> {
+        Request::post("/admin/adjust")
+            .header("content-type", "application/json")
+            .header("authorization", format!("Bearer {}", token))
+            .header("x-admin-actor", "support@example.com")
+            .body(Body::from(json!({"address": address, "delta": delta, "reason": reason}).to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_admin_adjustments_are_applied_and_audited() {
+        let token = "a".repeat(32);
+        let mut config = test_config(DEFAULT_RESOURCE);
+        config.admin_token = Some(token.clone());
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let address = "0x00000000000000000000000000000000000000aa";
+
+        // Credit
+        let (status, body) = send(&app, adjust_request(&token, address, 2.5, "failed settlement refund")).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["balance"], 2.5);
+        assert_eq!(reply["address"], Address::from_str(address).unwrap().to_checksum(None));
+
+        // Debit
+        let (status, _) = send(&app, adjust_request(&token, address, -1.0, "duplicate credit")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.database.get_user(address).await.unwrap().unwrap().balance, 1.5);
+
+        // Over-deduction is rejected and not audited
+        let (status, _) = send(&app, adjust_request(&token, address, -5.0, "too much")).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(state.database.get_user(address).await.unwrap().unwrap().balance, 1.5);
+
+        let ledger = state.database.list_audit(address).await.unwrap();
+        assert_eq!(ledger.len(), 2);
+        assert_eq!(ledger[0].delta, 2.5);
+        assert_eq!(ledger[0].reason, "failed settlement refund");
+        assert_eq!(ledger[0].actor, "support@example.com");
+        assert_eq!(ledger[1].delta, -1.0);
+
+        // Ledger entries don't show up as user accounts
+        assert_eq!(state.database.list_users().await.unwrap().len(), 1);
+
+        // Wrong token
+        let (status, _) = send(&app, adjust_request(&"b".repeat(32), address, 1.0, "nope")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_admin_adjustment_credits_canonical_account() {
+        let token = "a".repeat(32);
+        let mut config = test_config(DEFAULT_RESOURCE);
+        config.admin_token = Some(token.clone());
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let key = auth::account_key(&signer.address());
+
+        // Written without 0x, the credit still lands where the account's signed requests are billed
+        let (status, _) = send(&app, adjust_request(&token, &key[2..], 1.0, "goodwill credit")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.database.get_user(&key).await.unwrap().unwrap().balance, 1.0);
+        assert!(state.database.get_user(&key[2..]).await.unwrap().is_none());
+        assert_eq!(state.database.list_audit(&key).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_admin_balance_lookup_returns_accounts_in_order() {
+        let token = "a".repeat(32);
+        let mut config = test_config(DEFAULT_RESOURCE);
+        config.admin_token = Some(token.clone());
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+
+        let funded = "0x00000000000000000000000000000000000000AA";
+        let missing = "0x00000000000000000000000000000000000000bb";
+        state.database.add_balance(funded, 2.5).await.unwrap();
+
+        let lookup = |token: &str, addresses: Value| {
+            Request::post("/admin/balances")
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({ "addresses": addresses }).to_string()))
+                .unwrap()
+        };
+
+        let (status, body) = send(&app, lookup(&token, json!([missing, funded]))).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            reply["accounts"],
+            json!([
+                {"address": Address::from_str(missing).unwrap().to_checksum(None), "balance": null, "latest_timestamp": null, "suspended": null},
+                {"address": Address::from_str(funded).unwrap().to_checksum(None), "balance": 2.5, "latest_timestamp": 0, "suspended": false},
+            ])
+        );
+
+        let (status, _) = send(&app, lookup(&token, json!([funded, "not-an-address"]))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = send(&app, lookup(&"b".repeat(32), json!([funded]))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_admin_spend_reports_recorded_charges() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let token = "a".repeat(32);
+        let mut config = test_config(&format!("node_url = \"{}\"\nprice_per_request = 0.25\nrecord_spend = true\n", node));
+        config.admin_token = Some(token.clone());
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 1.0).await.unwrap();
+
+        for id in 0..3 {
+            let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_chainId", id))).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let report = |address: &str, from: u64, to: u64| {
+            Request::post("/admin/spend")
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({"address": address, "from": from, "to": to}).to_string()))
+                .unwrap()
+        };
+
+        let now = now_secs();
+        let (status, body) = send(&app, report(&address, now - 60, now + 60)).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["spent_units"], 750_000);
+        assert_eq!(reply["spent"], 0.75);
+
+        // Nothing was spent before the requests
+        let (_, body) = send(&app, report(&address, now - 3600, now - 60)).await;
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["spent_units"], 0);
+
+        // Written without 0x, the address still names the account
+        let (_, body) = send(&app, report(&address[2..], now - 60, now + 60)).await;
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["spent_units"], 750_000);
+
+        let (status, _) = send(&app, report(&address, now, now)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_relayed_requests_listed_in_history() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.25\n[request_history]\nmax_page_size = 2\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+        let signer = PrivateKeySigner::random();
+        state.database.add_balance(&signer.address().to_string(), 1.0).await.unwrap();
+
+        for (id, method) in ["eth_chainId", "eth_blockNumber", "eth_gasPrice"].into_iter().enumerate() {
+            let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call(method, id as u64))).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+
+        let timestamp = now_secs();
+        let history = |uri: &str, timestamp: u64| {
+            Request::get(uri)
+                .header("X-Auth-Address", signer.address().to_string())
+                .header("X-Auth-Signature", sign(&signer, timestamp, "GET", "/history", b""))
+                .header("X-Auth-Timestamp", timestamp.to_string())
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Newest first, paged at the configured maximum
+        let (status, body) = send(&app, history("/history?limit=10", timestamp)).await;
+        assert_eq!(status, StatusCode::OK);
+        let page: Value = serde_json::from_slice(&body).unwrap();
+        let entries = page["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["method"], "eth_gasPrice");
+        assert_eq!(entries[0]["price"], 0.25);
+        assert_eq!(entries[0]["success"], true);
+        assert!(entries[0]["timestamp"].as_u64().unwrap().abs_diff(timestamp) <= 5);
+        assert_eq!(entries[1]["method"], "eth_blockNumber");
+
+        let next = page["next"].as_str().unwrap();
+        let (status, body) = send(&app, history(&format!("/history?before={}", next), timestamp + 1)).await;
+        assert_eq!(status, StatusCode::OK);
+        let page: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(page["entries"].as_array().unwrap().len(), 1);
+        assert_eq!(page["entries"][0]["method"], "eth_chainId");
+        assert_eq!(page["entries"][0]["price"], 0.25);
+        assert!(page["next"].is_null());
+
+        let (status, _) = send(&app, history("/history?before=latest", timestamp + 2)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&app, Request::get("/history").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_unfunded_accounts_rejected_before_verification() {
+        use std::sync::atomic::Ordering;
+
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!(
+            "node_url = \"{}\"\nprice_per_request = 0.5\nreject_unfunded_before_auth = true\n",
+            node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+
+        // An emptied account and one that never existed get the same answer, unverified
+        let emptied = PrivateKeySigner::random();
+        state.database.add_balance(&emptied.address().to_string(), 0.25).await.unwrap();
+        let unknown = PrivateKeySigner::random();
+        let (status, emptied_body) = send(&app, signed_request("/relay", &emptied, &rpc_call("eth_chainId", 1))).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        let (status, unknown_body) = send(&app, signed_request("/relay", &unknown, &rpc_call("eth_chainId", 1))).await;
+        assert_eq!(status, StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(emptied_body, unknown_body);
+        assert_eq!(state.metrics.authentications.load(Ordering::Relaxed), 0);
+
+        // Funded accounts are verified and billed as usual
+        let funded = PrivateKeySigner::random();
+        state.database.add_balance(&funded.address().to_string(), 1.0).await.unwrap();
+        let (status, _) = send(&app, signed_request("/relay", &funded, &rpc_call("eth_chainId", 1))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.metrics.authentications.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_suspended_account_refused_until_unsuspended() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let token = "a".repeat(32);
+        let mut config = test_config(&format!("node_url = \"{}\"\nprice_per_request = 0.5\n", node));
+        config.admin_token = Some(token.clone());
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 2.0).await.unwrap();
+
+        let suspension = |path: &str, address: &str| {
+            Request::post(path)
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(json!({"address": address, "reason": "chargeback dispute"}).to_string()))
+                .unwrap()
+        };
+
+        let (status, _) = send(&app, suspension("/admin/suspend", &address)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_chainId", 1))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // The balance is kept while suspended
+        let user = state.database.get_user(&address).await.unwrap().unwrap();
+        assert!(user.suspended);
+        assert_eq!(user.balance, 2.0);
+
+        let (status, _) = send(&app, suspension("/admin/unsuspend", &address)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_chainId", 2))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.database.get_user(&address).await.unwrap().unwrap().balance, 1.5);
+
+        let audit = state.database.list_audit(&address).await.unwrap();
+        let reasons: Vec<_> = audit.iter().map(|entry| entry.reason.as_str()).collect();
+        assert_eq!(reasons, ["suspended: chargeback dispute", "unsuspended: chargeback dispute"]);
+
+        // Unknown accounts can't be suspended
+        let (status, _) = send(&app, suspension("/admin/suspend", "0x00000000000000000000000000000000000000bb")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Written without 0x, the address still names the account
+        let (status, _) = send(&app, suspension("/admin/suspend", &address[2..])).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(state.database.get_user(&address).await.unwrap().unwrap().suspended);
+    }
+
+    /// Records the consistency level of every user lookup and counts attempts to use a free
+    /// request, optionally failing every charge
+    struct RecordingDatabase {
+        inner: crate::database::rocksdb::RocksDbDatabase,
+        reads: std::sync::Mutex<Vec<ConsistencyLevel>>,
+        free_request_attempts: AtomicUsize,
+        fail_charges: bool,
+    }
+
+    impl RecordingDatabase {
+        fn open(path: &std::path::Path, fail_charges: bool) -> Self {
+            Self {
+                inner: crate::database::rocksdb::RocksDbDatabase::open(path.to_str().unwrap()).unwrap(),
+                reads: Default::default(),
+                free_request_attempts: AtomicUsize::new(0),
+                fail_charges,
+            }
+        }
+
+        fn charge_failure(&self) -> Result<(), DatabaseError> {
+            match self.fail_charges {
+                true => Err(DatabaseError::RocksDB("write failed".to_string())),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl crate::database::DatabaseTrait for RecordingDatabase {
+        async fn get_user_consistent(
+            &self,
+            address: &str,
+            consistency: ConsistencyLevel,
+        ) -> Result<Option<crate::database::UserData>, DatabaseError> {
+            self.reads.lock().unwrap().push(consistency);
+            self.inner.get_user_consistent(address, consistency).await
+        }
+
+        async fn get_user_batch(
+            &self,
+            addresses: &[String],
+        ) -> Result<Vec<(String, Option<crate::database::UserData>)>, DatabaseError> {
+            self.inner.get_user_batch(addresses).await
+        }
+
+        async fn update_user(&self, address: &str, data: crate::database::UserData) -> Result<(), DatabaseError> {
+            self.inner.update_user(address, data).await
+        }
+
+        async fn add_balance(&self, address: &str, amount: f64) -> Result<f64, DatabaseError> {
+            self.inner.add_balance(address, amount).await
+        }
+
+        async fn deduct_balance(&self, address: &str, amount: f64, timestamp: u64) -> Result<f64, DatabaseError> {
+            self.charge_failure()?;
+            self.inner.deduct_balance(address, amount, timestamp).await
+        }
+
+        async fn deposit_and_deduct(
+            &self,
+            address: &str,
+            credit: f64,
+            amount: f64,
+            timestamp: u64,
+        ) -> Result<f64, DatabaseError> {
+            self.inner.deposit_and_deduct(address, credit, amount, timestamp).await
+        }
+
+        async fn reserve_balance(
+            &self,
+            address: &str,
+            amount: f64,
+            timestamp: u64,
+        ) -> Result<Reservation, DatabaseError> {
+            self.charge_failure()?;
+            self.inner.reserve_balance(address, amount, timestamp).await
+        }
+
+        async fn commit_reservation(&self, reservation: &Reservation, cost: f64) -> Result<f64, DatabaseError> {
+            self.inner.commit_reservation(reservation, cost).await
+        }
+
+        async fn get_signed_timestamp(&self, address: &str) -> Result<Option<u64>, DatabaseError> {
+            self.inner.get_signed_timestamp(address).await
+        }
+
+        async fn advance_signed_timestamp(&self, address: &str, timestamp: u64) -> Result<bool, DatabaseError> {
+            self.inner.advance_signed_timestamp(address, timestamp).await
+        }
+
+        async fn list_users(&self) -> Result<Vec<(String, crate::database::UserData)>, DatabaseError> {
+            self.inner.list_users().await
+        }
+
+        async fn set_suspended(&self, address: &str, suspended: bool) -> Result<bool, DatabaseError> {
+            self.inner.set_suspended(address, suspended).await
+        }
+
+        async fn use_free_request(&self, address: &str, quota: u32, timestamp: u64) -> Result<bool, DatabaseError> {
+            self.free_request_attempts.fetch_add(1, Ordering::SeqCst);
+            self.inner.use_free_request(address, quota, timestamp).await
+        }
+
+        async fn delete_user(&self, address: &str, stale_before: u64) -> Result<bool, DatabaseError> {
+            self.inner.delete_user(address, stale_before).await
+        }
+
+        async fn append_audit(&self, entry: &AuditEntry) -> Result<(), DatabaseError> {
+            self.inner.append_audit(entry).await
+        }
+
+        async fn list_audit(&self, address: &str) -> Result<Vec<AuditEntry>, DatabaseError> {
+            self.inner.list_audit(address).await
+        }
+
+        async fn record_spend(&self, address: &str, amount: u64, timestamp: u64) -> Result<(), DatabaseError> {
+            self.inner.record_spend(address, amount, timestamp).await
+        }
+
+        async fn spend_total(&self, address: &str, from_ts: u64, to_ts: u64) -> Result<u64, DatabaseError> {
+            self.inner.spend_total(address, from_ts, to_ts).await
+        }
+
+        async fn record_request(&self, address: &str, record: &RequestRecord, retention_secs: u64) -> Result<(), DatabaseError> {
+            self.inner.record_request(address, record, retention_secs).await
+        }
+
+        async fn list_requests(
+            &self,
+            address: &str,
+            since: u64,
+            before: Option<u128>,
+            limit: usize,
+        ) -> Result<crate::database::RequestPage, DatabaseError> {
+            self.inner.list_requests(address, since, before, limit).await
+        }
+
+        async fn health_check(&self) -> Result<(), DatabaseError> {
+            self.inner.health_check().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_free_requests_not_counted_for_accounts_that_cannot_qualify() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!("node_url = \"{}\"\nprice_per_request = 0.001\nfree_requests = 2\n", node));
+        let temp_dir = tempfile::tempdir().unwrap();
+        let database = Arc::new(RecordingDatabase::open(&temp_dir.path().join("test.db"), false));
+        let state = Arc::new(AppState::new(config, database.clone()).unwrap());
+        let app = build_router(state.clone());
+
+        // A funded account pays without a write attempting a free request first
+        let funded = PrivateKeySigner::random();
+        state.database.add_balance(&funded.address().to_string(), 1.0).await.unwrap();
+        for id in 0..3 {
+            let (status, _) = send(&app, signed_request("/relay", &funded, &rpc_call("eth_call", id))).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        assert_eq!(database.free_request_attempts.load(Ordering::SeqCst), 0);
+
+        // A new account's free requests are counted until used up
+        let new = PrivateKeySigner::random();
+        for id in 0..3 {
+            send(&app, signed_request("/relay", &new, &rpc_call("eth_call", id))).await;
+        }
+        assert_eq!(database.free_request_attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_balance_endpoint_reads_eventually_consistent() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let database = Arc::new(RecordingDatabase::open(&temp_dir.path().join("test.db"), false));
+        let state = Arc::new(AppState::new(test_config(DEFAULT_RESOURCE), database.clone()).unwrap());
+        let app = build_router(state.clone());
+
+        let signer = PrivateKeySigner::random();
+        state.database.add_balance(&signer.address().to_string(), 3.0).await.unwrap();
+
+        let timestamp = now_secs();
+        let request = Request::get("/balance")
+            .header("X-Auth-Address", signer.address().to_string())
+            .header("X-Auth-Signature", sign(&signer, timestamp, "GET", "/balance", b""))
+            .header("X-Auth-Timestamp", timestamp.to_string())
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["balance"], 3.0);
+        assert_eq!(reply["address"], signer.address().to_checksum(None));
+
+        assert_eq!(*database.reads.lock().unwrap(), vec![ConsistencyLevel::Eventual]);
+    }
+}
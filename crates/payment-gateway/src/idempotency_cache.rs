@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::handlers::NodeResponse;
+
+/// Cache of relayed responses keyed by client-supplied idempotency key
+pub struct IdempotencyCache {
+    /// Maps "address:key" -> (when it was stored, response)
+    entries: HashMap<String, (Instant, NodeResponse)>,
+    /// How long a response can be replayed for
+    ttl: Duration,
+}
+
+impl IdempotencyCache {
+    /// Create a new idempotency cache keeping responses for `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            ttl,
+        }
+    }
+
+    /// Look up a previously stored response for this address and key
+    /// Also automatically cleans up expired entries
+    pub fn get(&mut self, address: &str, key: &str) -> Option<NodeResponse> {
+        let now = Instant::now();
+
+        self.cleanup(now);
+
+        self.entries
+            .get(&Self::entry_key(address, key))
+            .map(|(_, response)| response.clone())
+    }
+
+    /// Store the response for this address and key
+    pub fn insert(&mut self, address: &str, key: &str, response: NodeResponse) {
+        self.entries
+            .insert(Self::entry_key(address, key), (Instant::now(), response));
+
+        tracing::debug!(
+            address = %address,
+            cache_size = self.entries.len(),
+            "Idempotent response cached"
+        );
+    }
+
+    /// Keys are scoped per address so clients can't read each other's responses
+    fn entry_key(address: &str, key: &str) -> String {
+        format!("{}:{}", address.to_lowercase(), key)
+    }
+
+    /// Remove responses older than TTL
+    fn cleanup(&mut self, now: Instant) {
+        self.entries
+            .retain(|_, (stored_at, _)| now.duration_since(*stored_at) < self.ttl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use std::thread;
+
+    #[test]
+    fn test_keys_are_scoped_per_address_and_expire() {
+        let mut cache = IdempotencyCache::new(Duration::from_millis(100));
+        let response = NodeResponse {
+            status: StatusCode::OK,
+            body: "{}".into(),
+        };
+
+        cache.insert("0xAAAA", "key-1", response);
+        assert!(cache.get("0xaaaa", "key-1").is_some());
+        assert!(cache.get("0xbbbb", "key-1").is_none());
+
+        thread::sleep(Duration::from_millis(150));
+        assert!(cache.get("0xaaaa", "key-1").is_none());
+    }
+}
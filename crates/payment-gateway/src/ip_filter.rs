@@ -0,0 +1,302 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, HeaderName, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::IpFilterConfig;
+use crate::tls::TlsPeer;
+
+/// Addresses tracked for rate limiting before expired windows are pruned
+const MAX_TRACKED_ADDRESSES: usize = 100_000;
+
+/// A single address or CIDR range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let network: IpAddr = address.trim().parse().map_err(|_| format!("invalid address '{}'", s))?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("invalid prefix length in '{}'", s))?,
+            None => max_prefix,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix)).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Requests counted for one address in the current window
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+/// Blocklist and per-address rate limit checked before authentication, so abusive
+/// clients are turned away without signature checks or 402 generation
+pub struct IpFilter {
+    blocklist: Vec<IpRange>,
+    requests_per_window: Option<u32>,
+    window: Duration,
+    client_ip_header: Option<HeaderName>,
+    trusted_proxies: Vec<IpRange>,
+    windows: Mutex<HashMap<IpAddr, Window>>,
+}
+
+impl IpFilter {
+    pub fn new(config: &IpFilterConfig) -> Result<Self, String> {
+        let ranges = |entries: &[String]| entries.iter().map(|entry| entry.parse()).collect::<Result<Vec<_>, _>>();
+        let client_ip_header = config
+            .client_ip_header
+            .as_deref()
+            .map(HeaderName::from_str)
+            .transpose()
+            .map_err(|e| format!("invalid client_ip_header: {}", e))?;
+
+        Ok(Self {
+            blocklist: ranges(&config.blocklist)?,
+            requests_per_window: config.requests_per_window,
+            window: Duration::from_secs(config.window_secs),
+            client_ip_header,
+            trusted_proxies: ranges(&config.trusted_proxies)?,
+            windows: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|range| range.contains(ip))
+    }
+
+    /// Address the request is attributed to: the peer, or when the peer is a trusted proxy,
+    /// the rightmost address in the client IP header that isn't another trusted proxy
+    fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let Some(name) = &self.client_ip_header else {
+            return peer;
+        };
+        if !self.is_trusted_proxy(peer) {
+            return peer;
+        }
+        let Some(value) = headers.get(name).and_then(|value| value.to_str().ok()) else {
+            return peer;
+        };
+
+        let mut client = peer;
+        for entry in value.rsplit(',') {
+            let Ok(ip) = entry.trim().parse::<IpAddr>() else {
+                break;
+            };
+            client = ip;
+            if !self.is_trusted_proxy(ip) {
+                break;
+            }
+        }
+        client
+    }
+
+    fn is_blocked(&self, ip: IpAddr) -> bool {
+        self.blocklist.iter().any(|range| range.contains(ip))
+    }
+
+    /// Count a request from `ip`, returning how long until it may retry if over the limit
+    fn check_rate(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let Some(limit) = self.requests_per_window else {
+            return Ok(());
+        };
+
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() >= MAX_TRACKED_ADDRESSES && !windows.contains_key(&ip) {
+            windows.retain(|_, window| now.duration_since(window.start) < self.window);
+        }
+
+        let window = windows.entry(ip).or_insert(Window { start: now, count: 0 });
+        if now.duration_since(window.start) >= self.window {
+            *window = Window { start: now, count: 0 };
+        }
+        if window.count >= limit {
+            return Err(self.window.saturating_sub(now.duration_since(window.start)));
+        }
+        window.count += 1;
+        Ok(())
+    }
+}
+
+/// Connection peer address, whether served over plain HTTP or TLS (None outside a server)
+fn peer_ip(request: &Request) -> Option<IpAddr> {
+    let extensions = request.extensions();
+    extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip())
+        .or_else(|| extensions.get::<ConnectInfo<TlsPeer>>().map(|info| info.0.addr.ip()))
+}
+
+/// Middleware refusing blocklisted addresses with 403 and rate-limited ones with 429
+pub async fn filter(State(filter): State<Arc<IpFilter>>, request: Request, next: Next) -> Response {
+    let Some(peer) = peer_ip(&request) else {
+        return next.run(request).await;
+    };
+    let client = filter.client_ip(peer, request.headers());
+
+    if filter.is_blocked(client) {
+        tracing::debug!(client = %client, "Refusing blocklisted address");
+        return (StatusCode::FORBIDDEN, "Forbidden").into_response();
+    }
+
+    if let Err(retry_after) = filter.check_rate(client, Instant::now()) {
+        tracing::debug!(client = %client, "Rate limiting address");
+        let retry_after = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+            "Too many requests",
+        )
+            .into_response();
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::send;
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn config(extra: &str) -> IpFilterConfig {
+        toml::from_str(extra).unwrap()
+    }
+
+    fn router(filter: IpFilter) -> Router {
+        Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(Arc::new(filter), super::filter))
+    }
+
+    fn request_from(peer: &str, forwarded_for: Option<&str>) -> Request {
+        let mut request = Request::get("/").body(Body::empty()).unwrap();
+        let peer: SocketAddr = format!("{}:40000", peer).parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(peer));
+        if let Some(forwarded_for) = forwarded_for {
+            request.headers_mut().insert("x-forwarded-for", forwarded_for.parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn test_ranges_match_addresses_and_cidrs() {
+        let range: IpRange = "198.51.100.0/24".parse().unwrap();
+        assert!(range.contains("198.51.100.7".parse().unwrap()));
+        assert!(range.contains("::ffff:198.51.100.7".parse().unwrap()));
+        assert!(!range.contains("198.51.101.7".parse().unwrap()));
+
+        let single: IpRange = "2001:db8::1".parse().unwrap();
+        assert!(single.contains("2001:db8::1".parse().unwrap()));
+        assert!(!single.contains("2001:db8::2".parse().unwrap()));
+
+        assert!("0.0.0.0/0".parse::<IpRange>().unwrap().contains("203.0.113.9".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("not-an-ip".parse::<IpRange>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_blocklisted_addresses_refused() {
+        let app = router(IpFilter::new(&config(r#"blocklist = ["203.0.113.0/24"]"#)).unwrap());
+
+        let (status, _) = send(&app, request_from("203.0.113.9", None)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = send(&app, request_from("198.51.100.7", None)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_addresses_rate_limited_per_window() {
+        let app = router(IpFilter::new(&config("requests_per_window = 2\nwindow_secs = 60")).unwrap());
+
+        for _ in 0..2 {
+            let (status, _) = send(&app, request_from("198.51.100.7", None)).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let response = app.clone().oneshot(request_from("198.51.100.7", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(header::RETRY_AFTER));
+
+        // Other addresses have their own budget
+        let (status, _) = send(&app, request_from("198.51.100.8", None)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // A new window starts the count again
+        let filter = IpFilter::new(&config("requests_per_window = 1\nwindow_secs = 1")).unwrap();
+        let ip = "198.51.100.7".parse().unwrap();
+        let now = Instant::now();
+        assert_eq!(filter.check_rate(ip, now), Ok(()));
+        assert!(filter.check_rate(ip, now).is_err());
+        assert_eq!(filter.check_rate(ip, now + Duration::from_secs(1)), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_address_trusted_only_from_proxies() {
+        let app = router(
+            IpFilter::new(&config(
+                r#"
+                blocklist = ["203.0.113.9"]
+                client_ip_header = "X-Forwarded-For"
+                trusted_proxies = ["10.0.0.0/8"]
+                "#,
+            ))
+            .unwrap(),
+        );
+
+        // Through the proxy the forwarded client is blocked, even behind a second proxy
+        let (status, _) = send(&app, request_from("10.0.0.1", Some("203.0.113.9"))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&app, request_from("10.0.0.1", Some("203.0.113.9, 10.0.0.2"))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // A client can't dodge the blocklist by prepending a spoofed address
+        let (status, _) = send(&app, request_from("10.0.0.1", Some("198.51.100.7, 203.0.113.9"))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        // Nor have the header believed when connecting directly
+        let (status, _) = send(&app, request_from("203.0.113.9", Some("198.51.100.7"))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send(&app, request_from("198.51.100.7", Some("203.0.113.9"))).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
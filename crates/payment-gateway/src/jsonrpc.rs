@@ -0,0 +1,591 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::config::TransientJsonRpcErrors;
+
+/// Apply `f` to every error object in a JSON-RPC response (single or batch)
+pub fn for_each_error_mut(response: &mut Value, mut f: impl FnMut(&mut Value)) {
+    match response {
+        Value::Array(replies) => {
+            for reply in replies {
+                if let Some(error) = reply.get_mut("error") {
+                    f(error);
+                }
+            }
+        }
+        reply => {
+            if let Some(error) = reply.get_mut("error") {
+                f(error);
+            }
+        }
+    }
+}
+
+/// Whether a body is a JSON-RPC response: a reply object carrying a result or error, or a batch of them
+pub fn is_response(body: &[u8]) -> bool {
+    let is_reply = |reply: &Value| {
+        reply.get("jsonrpc").and_then(Value::as_str) == Some("2.0")
+            && (reply.get("result").is_some() || reply.get("error").is_some())
+    };
+    match serde_json::from_slice::<Value>(body) {
+        Ok(Value::Array(replies)) => !replies.is_empty() && replies.iter().all(is_reply),
+        Ok(reply) => is_reply(&reply),
+        Err(_) => false,
+    }
+}
+
+/// Number of calls in a JSON-RPC batch request (None for single calls and unparseable bodies)
+pub fn batch_size(request: &[u8]) -> Option<usize> {
+    serde_json::from_slice::<Vec<serde::de::IgnoredAny>>(request)
+        .ok()
+        .map(|calls| calls.len())
+}
+
+/// Methods a request calls, as recorded in request history: the method of a single call, or
+/// the distinct methods of a batch in order, comma separated ("unknown" if none can be read)
+pub fn method_summary(request: &[u8]) -> String {
+    let calls = match serde_json::from_slice::<Value>(request) {
+        Ok(Value::Array(calls)) => calls,
+        Ok(call) => vec![call],
+        Err(_) => Vec::new(),
+    };
+    let mut methods: Vec<&str> = Vec::new();
+    for method in calls.iter().filter_map(|call| call.get("method")?.as_str()) {
+        if !methods.contains(&method) {
+            methods.push(method);
+        }
+    }
+    if methods.is_empty() {
+        return "unknown".to_string();
+    }
+    methods.join(",")
+}
+
+/// Whether a request (single call or non-empty batch) consists only of notifications,
+/// which the spec says get no reply at all, not even an error
+pub fn is_notification(request: &[u8]) -> bool {
+    let is_notification = |call: &Value| call.as_object().is_some_and(|call| !call.contains_key("id"));
+    match serde_json::from_slice::<Value>(request) {
+        Ok(Value::Array(calls)) => !calls.is_empty() && calls.iter().all(is_notification),
+        Ok(call) => is_notification(&call),
+        Err(_) => false,
+    }
+}
+
+/// Error reply for a call that was not relayed (None for notifications, which get no reply)
+pub fn error_reply(call: &Value, code: i64, message: &str) -> Option<Value> {
+    let id = call.get("id")?;
+    Some(json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}}))
+}
+
+/// Code and message of the error replacing a reply too large to relay
+pub const RESPONSE_TOO_LARGE: (i64, &str) = (-32000, "response too large");
+
+/// Longest string kept in a reply's outline; longer ones are emptied
+const OUTLINE_STRING_MAX: usize = 256;
+
+/// The response being read isn't a well-formed JSON-RPC batch
+#[derive(Debug, PartialEq)]
+pub struct NotBatch;
+
+/// Splits a batch response into its replies as chunks arrive, holding at most
+/// `max_reply_bytes` of any one reply. A reply growing past that is dropped, keeping only
+/// an outline of it (nested values emptied, long strings cut) to answer its id with a
+/// `RESPONSE_TOO_LARGE` error
+pub struct BatchReader {
+    max_reply_bytes: usize,
+    /// Nesting depth: 1 inside the batch array, 2 inside a reply object
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    in_reply: bool,
+    finished: bool,
+    /// The reply being read, None once it grew past `max_reply_bytes`
+    reply: Option<Vec<u8>>,
+    outline: Vec<u8>,
+    /// Where the reply-level string being read starts in the outline, until it's cut
+    string_start: Option<usize>,
+    replies: Vec<Value>,
+    replaced: usize,
+}
+
+impl BatchReader {
+    pub fn new(max_reply_bytes: usize) -> Self {
+        Self {
+            max_reply_bytes,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+            in_reply: false,
+            finished: false,
+            reply: None,
+            outline: Vec::new(),
+            string_start: None,
+            replies: Vec::new(),
+            replaced: 0,
+        }
+    }
+
+    /// Read the next chunk of the response
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<(), NotBatch> {
+        for &byte in chunk {
+            self.feed_byte(byte)?;
+        }
+        Ok(())
+    }
+
+    /// The replies read, and how many of them were replaced for being too large
+    pub fn finish(self) -> Result<(Vec<Value>, usize), NotBatch> {
+        if !self.finished {
+            return Err(NotBatch);
+        }
+        Ok((self.replies, self.replaced))
+    }
+
+    fn feed_byte(&mut self, byte: u8) -> Result<(), NotBatch> {
+        if self.finished {
+            return if byte.is_ascii_whitespace() { Ok(()) } else { Err(NotBatch) };
+        }
+        if self.depth == 0 {
+            return match byte {
+                b'[' => {
+                    self.depth = 1;
+                    Ok(())
+                }
+                byte if byte.is_ascii_whitespace() => Ok(()),
+                _ => Err(NotBatch),
+            };
+        }
+        if !self.in_reply {
+            return match byte {
+                b']' => {
+                    self.depth = 0;
+                    self.finished = true;
+                    Ok(())
+                }
+                b',' => Ok(()),
+                byte if byte.is_ascii_whitespace() => Ok(()),
+                b'{' => {
+                    self.in_reply = true;
+                    self.reply = Some(Vec::new());
+                    self.outline.clear();
+                    self.feed_reply_byte(byte)
+                }
+                _ => Err(NotBatch),
+            };
+        }
+        self.feed_reply_byte(byte)
+    }
+
+    fn feed_reply_byte(&mut self, byte: u8) -> Result<(), NotBatch> {
+        if let Some(reply) = &mut self.reply {
+            reply.push(byte);
+            if reply.len() > self.max_reply_bytes {
+                self.reply = None;
+            }
+        }
+
+        if self.in_string {
+            let closing = !self.escaped && byte == b'"';
+            self.escaped = !self.escaped && byte == b'\\';
+            if closing {
+                self.in_string = false;
+            }
+            if self.depth == 2 {
+                if let Some(start) = self.string_start.filter(|start| self.outline.len() - start > OUTLINE_STRING_MAX) {
+                    self.outline.truncate(start + 1);
+                    self.string_start = None;
+                }
+                if self.string_start.is_some() || closing {
+                    self.outline.push(byte);
+                }
+            }
+            return Ok(());
+        }
+
+        match byte {
+            b'"' => {
+                self.in_string = true;
+                if self.depth == 2 {
+                    self.string_start = Some(self.outline.len());
+                    self.outline.push(byte);
+                }
+            }
+            b'{' | b'[' => {
+                self.depth += 1;
+                if self.depth <= 3 {
+                    self.outline.push(byte);
+                }
+            }
+            b'}' | b']' => {
+                if self.depth <= 3 {
+                    self.outline.push(byte);
+                }
+                self.depth -= 1;
+                if self.depth == 1 {
+                    self.finish_reply()?;
+                }
+            }
+            _ if self.depth == 2 => self.outline.push(byte),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish_reply(&mut self) -> Result<(), NotBatch> {
+        self.in_reply = false;
+        let reply = match self.reply.take() {
+            Some(reply) => serde_json::from_slice(&reply).map_err(|_| NotBatch)?,
+            None => {
+                let outline = serde_json::from_slice(&self.outline).unwrap_or(Value::Null);
+                self.replaced += 1;
+                too_large_reply(&outline)
+            }
+        };
+        self.replies.push(reply);
+        Ok(())
+    }
+}
+
+/// `RESPONSE_TOO_LARGE` error answering the id of `reply`
+fn too_large_reply(reply: &Value) -> Value {
+    let (code, message) = RESPONSE_TOO_LARGE;
+    let id = reply.get("id").cloned().unwrap_or(Value::Null);
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+/// Replace the largest replies of a batch response with `RESPONSE_TOO_LARGE` errors until the
+/// serialized batch fits in `max_bytes`, keeping every other reply. Returns how many were replaced
+pub fn fit_batch(response: &mut Value, max_bytes: usize) -> usize {
+    let Some(replies) = response.as_array_mut() else {
+        return 0;
+    };
+    let mut sizes: Vec<(usize, usize)> = replies.iter().map(|reply| reply.to_string().len()).zip(0..).collect();
+    // Brackets plus a comma between replies
+    let mut total = sizes.iter().map(|(size, _)| size).sum::<usize>() + replies.len().saturating_sub(1) + 2;
+    sizes.sort_unstable();
+
+    let mut replaced = 0;
+    while total > max_bytes {
+        let Some((size, index)) = sizes.pop() else {
+            break;
+        };
+        let error = too_large_reply(&replies[index]);
+        total = total - size + error.to_string().len();
+        replies[index] = error;
+        replaced += 1;
+    }
+    replaced
+}
+
+/// Error code of a single (non-batch) JSON-RPC error response
+pub fn error_code(response: &Value) -> Option<i64> {
+    response.get("error")?.get("code")?.as_i64()
+}
+
+/// Whether a single JSON-RPC response is an error listed as transient, by code or by
+/// a case-insensitive substring of its message
+pub fn is_transient_error(response: &Value, transient: &TransientJsonRpcErrors) -> bool {
+    let Some(error) = response.get("error") else {
+        return false;
+    };
+    if error_code(response).is_some_and(|code| transient.codes.contains(&code)) {
+        return true;
+    }
+    let message = error.get("message").and_then(Value::as_str).unwrap_or_default().to_lowercase();
+    transient.messages.iter().any(|pattern| message.contains(&pattern.to_lowercase()))
+}
+
+/// Calls of a JSON-RPC request (single or batch); non-object calls are skipped
+fn calls(request: &Value) -> impl Iterator<Item = &Value> {
+    match request {
+        Value::Array(calls) => calls.iter().collect::<Vec<_>>(),
+        call => vec![call],
+    }
+    .into_iter()
+    .filter(|call| call.is_object())
+}
+
+/// First call id that isn't a string, number or null (notifications have no id at all)
+pub fn invalid_id(request: &Value) -> Option<&Value> {
+    calls(request)
+        .filter_map(|call| call.get("id"))
+        .find(|id| !matches!(id, Value::String(_) | Value::Number(_) | Value::Null))
+}
+
+/// Whether a JSON-RPC request (single or batch) has calls and all of them are to one of `methods`
+pub fn only_methods(request: &Value, methods: &[String]) -> bool {
+    let mut calls = calls(request).peekable();
+    calls.peek().is_some()
+        && calls.all(|call| {
+            call.get("method")
+                .and_then(Value::as_str)
+                .is_some_and(|method| methods.iter().any(|allowed| allowed == method))
+        })
+}
+
+/// Calls of a batch request that expected a reply but got none in the batch response
+/// (notifications expect none); 0 unless both are batches
+pub fn unanswered_calls(request: &Value, response: &Value) -> usize {
+    let (Value::Array(_), Value::Array(replies)) = (request, response) else {
+        return 0;
+    };
+    let mut reply_ids: Vec<&Value> = replies.iter().filter_map(|reply| reply.get("id")).collect();
+    calls(request)
+        .filter_map(|call| call.get("id"))
+        .filter(|id| match reply_ids.iter().position(|reply_id| reply_id == id) {
+            Some(position) => {
+                reply_ids.swap_remove(position);
+                false
+            }
+            None => true,
+        })
+        .count()
+}
+
+/// Write numeric ids without a fractional part (1.0, 1e2) as integers
+pub fn normalize_ids(request: &mut Value) {
+    let normalize = |call: &mut Value| {
+        let Some(Value::Number(id)) = call.get_mut("id") else {
+            return;
+        };
+        if id.is_f64() {
+            let float = id.as_f64().unwrap_or(f64::NAN);
+            if float.fract() == 0.0 && float.abs() <= u64::MAX as f64 {
+                *id = if float < 0.0 { (float as i64).into() } else { (float as u64).into() };
+            }
+        }
+    };
+    match request {
+        Value::Array(calls) => calls.iter_mut().for_each(normalize),
+        call => normalize(call),
+    }
+}
+
+/// Replace every call id in a JSON-RPC request (single or batch) with a gateway-unique id
+/// Returns the original ids keyed by the id that replaced them; notifications are left as-is
+pub fn rewrite_ids(request: &mut Value, next_id: &AtomicU64) -> HashMap<u64, Value> {
+    let mut original_ids = HashMap::new();
+    let mut rewrite = |call: &mut Value| {
+        if let Some(id) = call.get_mut("id") {
+            let gateway_id = next_id.fetch_add(1, Ordering::Relaxed);
+            original_ids.insert(gateway_id, std::mem::replace(id, Value::from(gateway_id)));
+        }
+    };
+    match request {
+        Value::Array(calls) => calls.iter_mut().for_each(rewrite),
+        call => rewrite(call),
+    }
+    original_ids
+}
+
+/// Put the client's original ids back on a JSON-RPC response (single or batch)
+pub fn restore_ids(response: &mut Value, original_ids: &HashMap<u64, Value>) {
+    let restore = |reply: &mut Value| {
+        if let Some(id) = reply.get_mut("id") {
+            if let Some(original) = id.as_u64().and_then(|gateway_id| original_ids.get(&gateway_id)) {
+                *id = original.clone();
+            }
+        }
+    };
+    match response {
+        Value::Array(replies) => replies.iter_mut().for_each(restore),
+        reply => restore(reply),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_responses_recognized() {
+        let reply = json!({"jsonrpc": "2.0", "id": 1, "error": {"code": -32602, "message": "invalid params"}});
+        assert!(is_response(reply.to_string().as_bytes()));
+        assert!(is_response(json!([reply, {"jsonrpc": "2.0", "id": 2, "result": null}]).to_string().as_bytes()));
+        assert!(!is_response(br#"{"error": "not found"}"#));
+        assert!(!is_response(b"[]"));
+        assert!(!is_response(b"<html>Bad Gateway</html>"));
+    }
+
+    #[test]
+    fn test_transient_errors_classified_by_code_or_message() {
+        let transient: TransientJsonRpcErrors =
+            toml::from_str("codes = [-32005]\nmessages = [\"syncing\"]").unwrap();
+        let error = |code: i64, message: &str| json!({"jsonrpc": "2.0", "id": 1, "error": {"code": code, "message": message}});
+
+        assert!(is_transient_error(&error(-32005, "limit exceeded"), &transient));
+        assert!(is_transient_error(&error(-32000, "Node is SYNCING"), &transient));
+        assert!(!is_transient_error(&error(-32601, "method not found"), &transient));
+        assert!(!is_transient_error(&json!({"jsonrpc": "2.0", "id": 1, "result": "syncing"}), &transient));
+        assert!(!is_transient_error(&json!([error(-32005, "limit exceeded")]), &transient));
+    }
+
+    #[test]
+    fn test_requests_limited_to_methods() {
+        let methods = vec!["eth_call".to_string(), "eth_getBalance".to_string()];
+        assert!(only_methods(&json!({"method": "eth_call", "id": 1}), &methods));
+        assert!(only_methods(&json!([{"method": "eth_call", "id": 1}, {"method": "eth_getBalance", "id": 2}]), &methods));
+        assert!(!only_methods(&json!([{"method": "eth_call", "id": 1}, {"method": "eth_sendRawTransaction", "id": 2}]), &methods));
+        assert!(!only_methods(&json!({"id": 1}), &methods));
+        assert!(!only_methods(&json!([]), &methods));
+    }
+
+    #[test]
+    fn test_method_summary() {
+        assert_eq!(method_summary(br#"{"method": "eth_call", "id": 1}"#), "eth_call");
+        let batch = br#"[{"method": "eth_call", "id": 1}, {"method": "eth_chainId", "id": 2}, {"method": "eth_call", "id": 3}]"#;
+        assert_eq!(method_summary(batch), "eth_call,eth_chainId");
+        assert_eq!(method_summary(b"not json"), "unknown");
+    }
+
+    #[test]
+    fn test_unanswered_batch_calls_counted() {
+        let batch = json!([
+            {"jsonrpc": "2.0", "method": "eth_chainId", "id": 1},
+            {"jsonrpc": "2.0", "method": "eth_chainId", "id": "a"},
+            {"jsonrpc": "2.0", "method": "eth_subscribe"},
+            {"jsonrpc": "2.0", "method": "eth_chainId", "id": 3},
+        ]);
+        let replies = json!([
+            {"jsonrpc": "2.0", "id": "a", "result": "0x1"},
+            {"jsonrpc": "2.0", "id": 1, "result": "0x1"},
+        ]);
+        assert_eq!(unanswered_calls(&batch, &replies), 1);
+        assert_eq!(unanswered_calls(&batch, &json!([])), 3);
+
+        // Not comparable unless both are batches
+        assert_eq!(unanswered_calls(&batch, &json!({"jsonrpc": "2.0", "id": null, "error": {}})), 0);
+        assert_eq!(unanswered_calls(&json!({"id": 1}), &json!([])), 0);
+    }
+
+    #[test]
+    fn test_largest_batch_replies_replaced_until_it_fits() {
+        let reply = |id: i64, result: &str| json!({"jsonrpc": "2.0", "id": id, "result": result});
+        let mut response = json!([reply(1, "0x1"), reply(2, &"a".repeat(500)), reply(3, &"b".repeat(100)), reply(4, "0x4")]);
+
+        assert_eq!(fit_batch(&mut response, 400), 1);
+        assert_eq!(response[0], reply(1, "0x1"));
+        assert_eq!(response[1], json!({"jsonrpc": "2.0", "id": 2, "error": {"code": -32000, "message": "response too large"}}));
+        assert_eq!(response[2], reply(3, &"b".repeat(100)));
+        assert!(response.to_string().len() <= 400);
+
+        assert_eq!(fit_batch(&mut response, 250), 1);
+        assert_eq!(response[2]["error"]["message"], "response too large");
+        assert_eq!(response[3], reply(4, "0x4"));
+
+        assert_eq!(fit_batch(&mut json!({"jsonrpc": "2.0", "id": 1, "result": "a"}), 1), 0);
+    }
+
+    #[test]
+    fn test_batch_read_in_chunks_drops_oversized_replies() {
+        let reply = |id: Value, result: Value| json!({"jsonrpc": "2.0", "id": id, "result": result});
+        let huge = reply(json!("b"), json!({"logs": ["a".repeat(500)], "data": "c".repeat(500)}));
+        let response = format!(
+            " [{}, {}, {}]\n",
+            reply(json!(1), json!("0x1")),
+            huge,
+            reply(json!(3), json!("0x\\\"3\"")),
+        );
+
+        // However the response is chunked
+        for chunk_size in [1, 7, response.len()] {
+            let mut reader = BatchReader::new(200);
+            for chunk in response.as_bytes().chunks(chunk_size) {
+                reader.feed(chunk).unwrap();
+            }
+            let (replies, replaced) = reader.finish().unwrap();
+            assert_eq!(replaced, 1);
+            assert_eq!(replies[0], reply(json!(1), json!("0x1")));
+            assert_eq!(replies[1], json!({"jsonrpc": "2.0", "id": "b", "error": {"code": -32000, "message": "response too large"}}));
+            assert_eq!(replies[2], reply(json!(3), json!("0x\\\"3\"")));
+        }
+
+        let mut reader = BatchReader::new(200);
+        assert_eq!(reader.feed(br#"{"jsonrpc": "2.0", "id": null, "error": {}}"#), Err(NotBatch));
+        let mut reader = BatchReader::new(200);
+        reader.feed(br#"[{"id": 1}"#).unwrap();
+        assert!(reader.finish().is_err());
+    }
+
+    #[test]
+    fn test_notifications_detected() {
+        let notification = json!({"jsonrpc": "2.0", "method": "eth_subscribe"});
+        let call = json!({"jsonrpc": "2.0", "method": "eth_chainId", "id": null});
+        assert!(is_notification(notification.to_string().as_bytes()));
+        assert!(!is_notification(call.to_string().as_bytes()));
+
+        assert!(is_notification(json!([notification, notification]).to_string().as_bytes()));
+        assert!(!is_notification(json!([notification, call]).to_string().as_bytes()));
+        assert!(!is_notification(b"[]"));
+        assert!(!is_notification(b"not json"));
+    }
+
+    #[test]
+    fn test_legal_id_types_accepted() {
+        for request in [
+            json!({"jsonrpc": "2.0", "method": "eth_chainId", "id": "abc"}),
+            json!({"jsonrpc": "2.0", "method": "eth_chainId", "id": 7}),
+            json!({"jsonrpc": "2.0", "method": "eth_chainId", "id": null}),
+            json!({"jsonrpc": "2.0", "method": "eth_subscribe"}),
+            json!([{"jsonrpc": "2.0", "method": "eth_chainId", "id": 1.5}, {"jsonrpc": "2.0", "method": "eth_chainId", "id": "x"}]),
+        ] {
+            assert_eq!(invalid_id(&request), None, "{}", request);
+        }
+    }
+
+    #[test]
+    fn test_illegal_id_types_rejected() {
+        let object_id = json!({"jsonrpc": "2.0", "method": "eth_chainId", "id": {"nested": 1}});
+        assert_eq!(invalid_id(&object_id), Some(&json!({"nested": 1})));
+
+        let batch = json!([
+            {"jsonrpc": "2.0", "method": "eth_chainId", "id": 1},
+            {"jsonrpc": "2.0", "method": "eth_chainId", "id": [1]},
+        ]);
+        assert_eq!(invalid_id(&batch), Some(&json!([1])));
+
+        let boolean_id = json!({"jsonrpc": "2.0", "method": "eth_chainId", "id": true});
+        assert!(invalid_id(&boolean_id).is_some());
+    }
+
+    #[test]
+    fn test_integral_numeric_ids_normalized() {
+        let mut request = json!([
+            {"jsonrpc": "2.0", "method": "eth_chainId", "id": 1.0},
+            {"jsonrpc": "2.0", "method": "eth_chainId", "id": -3.0},
+            {"jsonrpc": "2.0", "method": "eth_chainId", "id": 1.5},
+            {"jsonrpc": "2.0", "method": "eth_chainId", "id": "1.0"},
+        ]);
+        normalize_ids(&mut request);
+        assert_eq!(request[0]["id"].to_string(), "1");
+        assert_eq!(request[1]["id"].to_string(), "-3");
+        assert_eq!(request[2]["id"], 1.5);
+        assert_eq!(request[3]["id"], "1.0");
+    }
+
+    #[test]
+    fn test_batch_ids_rewritten_and_restored() {
+        let next_id = AtomicU64::new(100);
+        let mut request = json!([
+            {"jsonrpc": "2.0", "method": "eth_chainId", "id": 1},
+            {"jsonrpc": "2.0", "method": "eth_subscribe"},
+            {"jsonrpc": "2.0", "method": "eth_blockNumber", "id": "abc"},
+        ]);
+
+        let original_ids = rewrite_ids(&mut request, &next_id);
+        assert_eq!(request[0]["id"], 100);
+        assert!(request[1].get("id").is_none());
+        assert_eq!(request[2]["id"], 101);
+
+        // Nodes may answer batch calls in any order
+        let mut response = json!([
+            {"jsonrpc": "2.0", "id": 101, "result": "0x10"},
+            {"jsonrpc": "2.0", "id": 100, "result": "0x1"},
+        ]);
+        restore_ids(&mut response, &original_ids);
+        assert_eq!(response[0]["id"], "abc");
+        assert_eq!(response[1]["id"], 1);
+    }
+}
@@ -0,0 +1,122 @@
+use std::process::Command;
+use thiserror::Error;
+
+use crate::config::KeySource;
+
+#[derive(Error, Debug)]
+pub enum KeySourceError {
+    #[error("{0}")]
+    MissingEnvVar(String),
+
+    #[error("failed to read key file {path}: {error}")]
+    FileRead { path: String, error: std::io::Error },
+
+    #[error("key file {0} is accessible to other users; restrict it with chmod 600")]
+    Permissions(String),
+
+    #[error("key command failed: {0}")]
+    Command(String),
+
+    #[error("key source returned an empty key")]
+    Empty,
+}
+
+impl KeySource {
+    /// Read the key text (surrounding whitespace trimmed)
+    pub fn read(&self) -> Result<String, KeySourceError> {
+        let key = match self {
+            Self::Env { var } => std::env::var(var).map_err(|_| KeySourceError::MissingEnvVar(var.clone()))?,
+            Self::File { path } => read_key_file(path)?,
+            Self::Command { command } => run_key_command(command)?,
+        };
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(KeySourceError::Empty);
+        }
+        Ok(key.to_string())
+    }
+}
+
+/// Read a key file, refusing one that other users can access
+fn read_key_file(path: &str) -> Result<String, KeySourceError> {
+    let file_error = |error| KeySourceError::FileRead { path: path.to_string(), error };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(path).map_err(file_error)?.permissions().mode();
+        if mode & 0o007 != 0 {
+            return Err(KeySourceError::Permissions(path.to_string()));
+        }
+    }
+
+    std::fs::read_to_string(path).map_err(file_error)
+}
+
+/// Run a command (e.g. a secret manager CLI) and take the key from its output
+/// Only the exit status and stderr are reported on failure, never the output
+fn run_key_command(command: &str) -> Result<String, KeySourceError> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .map_err(|e| KeySourceError::Command(e.to_string()))?;
+    if !output.status.success() {
+        return Err(KeySourceError::Command(format!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    String::from_utf8(output.stdout).map_err(|_| KeySourceError::Command("output is not UTF-8".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    #[test]
+    fn test_key_read_from_env() {
+        std::env::set_var("KEY_SOURCE_TEST_KEY", format!("{}\n", KEY));
+        let source = KeySource::Env { var: "KEY_SOURCE_TEST_KEY".to_string() };
+        assert_eq!(source.read().unwrap(), KEY);
+
+        let missing = KeySource::Env { var: "KEY_SOURCE_TEST_MISSING".to_string() };
+        assert!(matches!(missing.read(), Err(KeySourceError::MissingEnvVar(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_key_read_from_private_file_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("upstream.key");
+        std::fs::write(&path, format!("{}\n", KEY)).unwrap();
+        let source = KeySource::File { path: path.to_str().unwrap().to_string() };
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(source.read().unwrap(), KEY);
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(matches!(source.read(), Err(KeySourceError::Permissions(_))));
+
+        let missing = KeySource::File { path: dir.path().join("missing.key").to_str().unwrap().to_string() };
+        assert!(matches!(missing.read(), Err(KeySourceError::FileRead { .. })));
+    }
+
+    #[test]
+    fn test_key_read_from_command() {
+        let source = KeySource::Command { command: format!("echo {}", KEY) };
+        assert_eq!(source.read().unwrap(), KEY);
+
+        let failing = KeySource::Command { command: "echo denied >&2; exit 3".to_string() };
+        let error = failing.read().unwrap_err().to_string();
+        assert!(error.contains("denied"), "{}", error);
+
+        let empty = KeySource::Command { command: "true".to_string() };
+        assert!(matches!(empty.read(), Err(KeySourceError::Empty)));
+    }
+}
@@ -1,18 +1,82 @@
+mod asset;
+mod auth;
+mod block_time;
+mod body_log;
+mod budget;
+mod challenge;
+mod clock;
 mod config;
 mod database;
+mod deposit;
+mod finalized_cache;
 mod handlers;
+mod idempotency_cache;
+mod ip_filter;
+mod jsonrpc;
+mod key_source;
+mod metrics;
+mod paygate;
+mod pricing;
+mod reconciliation;
+mod requirements;
+mod sanitizer;
+mod selftest;
+mod settlement;
+mod shutdown;
 mod signature_cache;
 mod state;
+mod sweeper;
+#[cfg(test)]
+mod test_utils;
+mod tls;
+mod tx_precheck;
+mod upstream_payment;
+mod webhook;
 
-use axum::{routing::{get, post}, Router};
+use axum::{middleware, routing::{get, post}, serve::Listener, Extension, Router};
+use std::net::SocketAddr;
+use std::process::ExitCode;
 use std::sync::Arc;
+use thiserror::Error;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use config::Config;
+use config::{Config, ConfigError};
+use database::{DatabaseError, DatabaseTrait};
 use state::AppState;
 
+/// Longest startup waits for DynamoDB tables it creates when no wait is configured
+const DEFAULT_TABLE_WAIT_SECS: u64 = 60;
+
+/// Why the gateway failed to start or stopped serving
+#[derive(Debug, Error)]
+enum StartupError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+
+    #[error("Failed to open the database: {0}")]
+    Database(#[from] DatabaseError),
+
+    #[error("Failed to build the HTTP client: {0}")]
+    HttpClient(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    State(#[from] state::StateError),
+
+    #[error("Facilitator {url} can't settle the payments this gateway advertises: {reason}")]
+    Facilitator { url: String, reason: String },
+
+    #[error("Failed to bind to {addr}: {source}")]
+    Bind { addr: String, source: std::io::Error },
+
+    #[error("Failed to set up TLS: {0}")]
+    Tls(#[from] tls::TlsError),
+
+    #[error("Server failed: {0}")]
+    Serve(std::io::Error),
+}
+
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     // Initialize tracing
     tracing_subscriber::registry()
         .with(
@@ -24,67 +88,407 @@ async fn main() {
     
     tracing::info!("Starting x402 Prepayment RPC Gateway");
 
+    // Report startup failures as one line and a non-zero exit instead of a panic
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            tracing::error!(error = %e, "Gateway failed");
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run() -> Result<(), StartupError> {
     // Load configuration
-    let config = Config::load().expect("Failed to load configuration");
+    let config = Config::load()?;
     tracing::info!(
-        node_url = %config.node_url,
         port = config.port,
-        price_per_request = config.price_per_request,
         database_path = %config.database_path,
         payment_address = %config.payment_address,
         "Configuration loaded"
     );
 
-    // Initialize database based on configuration
-    let database: Arc<dyn database::DatabaseTrait> = match config.database_type.as_str() {
-        "rocksdb" => {
-            let db = database::rocksdb::RocksDbDatabase::open(&config.database_path)
-                .expect("Failed to open RocksDB database");
-            Arc::new(db)
-        }
-        "dynamodb" => {
-            let table_name = config.dynamodb_table_name.clone()
-                .expect("DynamoDB table name is required");
-            let db = database::dynamodb::DynamoDbDatabase::new(table_name)
-                .await
-                .expect("Failed to initialize DynamoDB database");
-            Arc::new(db)
-        }
-        _ => panic!("Invalid database type: {}", config.database_type),
-    };
+    for resource in &config.resources {
+        tracing::info!(
+            name = %resource.name,
+            path = %resource.path,
+            node_url = %resource.node_url,
+            price_per_request = resource.price_per_request,
+            network = %resource.network,
+            "Resource configured"
+        );
+    }
+
+    let database = open_database(&config).await?;
 
     tracing::info!(
         database_type = %config.database_type,
         "Database initialized"
     );
 
+    // With --selftest, check every dependency and exit instead of serving
+    if std::env::args().any(|arg| arg == "--selftest") {
+        let checks = selftest::run(&config, database.as_ref(), &http_client()?).await;
+        for check in &checks {
+            match &check.result {
+                Ok(()) => tracing::info!(check = %check.name, "Self-test passed"),
+                Err(e) => tracing::error!(check = %check.name, error = %e, "Self-test failed"),
+            }
+        }
+        std::process::exit(selftest::exit_code(&checks));
+    }
+
+    // Refuse to start if the facilitator can't settle the payments we advertise
+    if config.deposits_enabled {
+        selftest::check_facilitator(&http_client()?, &config)
+            .await
+            .map_err(|reason| StartupError::Facilitator { url: config.facilitator_url.clone(), reason })?;
+    }
+
+    // Warn, without refusing to start, if the payment address can't pay for settlement gas
+    if config.check_payment_address_funding {
+        let client = http_client()?;
+        for resource in &config.resources {
+            let address = config.payment_address_for(resource.network);
+            if let Err(e) = selftest::check_payment_address_funding(&client, &resource.node_url, address).await {
+                tracing::warn!(resource = %resource.name, network = %resource.network, error = %e, "Payment address funding check failed");
+            }
+        }
+    }
+
     // Create application state
-    let state = Arc::new(AppState::new(config.clone(), database));
+    let state = Arc::new(AppState::new(config.clone(), database.clone())?);
+
+    // Start pruning stale accounts if configured
+    if let Some(sweeper_config) = config.sweeper.clone() {
+        tracing::info!(
+            retention_secs = sweeper_config.retention_secs,
+            interval_secs = sweeper_config.interval_secs,
+            "Stale account sweeper enabled"
+        );
+        let handle = sweeper::spawn(database.clone(), sweeper_config, state.background_tasks.token());
+        state.background_tasks.track(handle);
+    }
+
+    // Start reconciling balances against their ledger if configured
+    if let Some(reconciliation_config) = config.reconciliation.clone() {
+        tracing::info!(
+            interval_secs = reconciliation_config.interval_secs,
+            tolerance = reconciliation_config.tolerance,
+            "Balance reconciliation enabled"
+        );
+        let handle = reconciliation::spawn(database.clone(), state.metrics.clone(), reconciliation_config, state.background_tasks.token());
+        state.background_tasks.track(handle);
+    }
+
+    // Follow chain time for request timestamps if configured
+    if let (Some(block_time), Some(clock), Some(node_url)) =
+        (config.block_time.clone(), state.block_clock.clone(), config.block_time_node_url())
+    {
+        tracing::info!(node = %node_url, refresh_secs = block_time.refresh_secs, "Following chain time");
+        let handle = block_time::spawn(clock, state.client.clone(), node_url.to_string(), block_time, state.background_tasks.token());
+        state.background_tasks.track(handle);
+    }
 
     tracing::info!(
         facilitator = %config.facilitator_url,
         "Prepayment system initialized"
     );
 
-    // Build router - single endpoint, no x402 layer
-    let app = Router::new()
-        // Health check endpoint
-        .route("/health", get(handlers::health))
-        // Main relay endpoint - handles authentication and payments
-        .route("/relay", post(handlers::relay))
-        .with_state(state);
+    let background_tasks = state.background_tasks.clone();
+    let app = build_router(state);
 
     // Start server
     let addr = format!("0.0.0.0:{}", config.port);
     let listener = tokio::net::TcpListener::bind(&addr)
         .await
-        .expect("Failed to bind to address");
+        .map_err(|source| StartupError::Bind { addr: addr.clone(), source })?;
 
-    tracing::info!(
-        address = %listener.local_addr().unwrap(),
-        "Server listening"
-    );
+    // Serve over TLS with client certificate verification if configured
+    if let Some(mtls) = &config.mtls {
+        let listener = tls::TlsListener::new(listener, mtls)?;
+        tracing::info!(
+            address = %Listener::local_addr(&listener).map_err(StartupError::Serve)?.addr,
+            "Server listening with TLS"
+        );
+        let app = app.into_make_service_with_connect_info::<tls::TlsPeer>();
+        axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown::signal())
+            .await
+            .map_err(StartupError::Serve)?;
+    } else {
+        tracing::info!(
+            address = %listener.local_addr().map_err(StartupError::Serve)?,
+            "Server listening"
+        );
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown::signal())
+            .await
+            .map_err(StartupError::Serve)?;
+    }
+
+    // Let in-flight settlements and webhook deliveries finish before exiting
+    let drain_timeout = std::time::Duration::from_secs(config.shutdown_drain_timeout_secs);
+    let drained = background_tasks.drain(drain_timeout).await;
+
+    // Write out buffered deductions so a redeploy doesn't lose them
+    if let Err(e) = database.flush().await {
+        tracing::error!(error = %e, "Failed to flush database on shutdown");
+    }
+    if drained {
+        tracing::info!("Shutdown complete");
+    }
 
-    axum::serve(listener, app).await.unwrap();
+    Ok(())
 }
 
+/// Open the configured database
+async fn open_database(config: &Config) -> Result<Arc<dyn DatabaseTrait>, StartupError> {
+    let missing = |setting: &str| {
+        ConfigError::Invalid(format!("{} is required when database_type is '{}'", setting, config.database_type))
+    };
+
+    match config.database_type.as_str() {
+        "rocksdb" => {
+            let db = database::rocksdb::RocksDbDatabase::open_with_config(&config.database_path, &config.rocksdb)?;
+            Ok(Arc::new(db))
+        }
+        "dynamodb" => {
+            let table_name = config.dynamodb_table_name.clone()
+                .ok_or_else(|| missing("dynamodb_table_name"))?;
+            let audit_table_name = config.dynamodb_audit_table_name.clone()
+                .ok_or_else(|| missing("dynamodb_audit_table_name"))?;
+            let spend_table_name = config.dynamodb_spend_table_name.clone()
+                .ok_or_else(|| missing("dynamodb_spend_table_name"))?;
+            let mut db = database::dynamodb::DynamoDbDatabase::new(table_name, audit_table_name, spend_table_name).await?;
+            if config.request_history.is_some() {
+                let history_table_name = config.dynamodb_history_table_name.clone()
+                    .ok_or_else(|| missing("dynamodb_history_table_name"))?;
+                db = db.with_history_table(history_table_name);
+            }
+
+            // Tables just created (by us or a deployment) reject requests until they're active
+            if config.dynamodb_create_tables || config.dynamodb_table_wait_secs.is_some() {
+                let timeout = std::time::Duration::from_secs(
+                    config.dynamodb_table_wait_secs.unwrap_or(DEFAULT_TABLE_WAIT_SECS),
+                );
+                db.wait_for_tables(config.dynamodb_create_tables, timeout).await?;
+            }
+            Ok(Arc::new(db))
+        }
+        other => Err(ConfigError::Invalid(format!("Invalid database type: {}", other)).into()),
+    }
+}
+
+/// Client for the startup checks against the facilitator and nodes
+fn http_client() -> Result<reqwest::Client, StartupError> {
+    Ok(reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()?)
+}
+
+
+/// Build the router - one relay endpoint per configured resource, no x402 layer
+fn build_router(state: Arc<AppState>) -> Router {
+    let mut app = Router::new()
+        // Health check endpoint (liveness)
+        .route("/health", get(handlers::health))
+        // Readiness endpoint - includes database status
+        .route("/health/ready", get(handlers::ready))
+        // Gateway status, including maintenance mode
+        .route("/status", get(handlers::status))
+        // Price quote endpoint - reflects per-address pricing
+        .route("/quote", get(handlers::quote))
+        // Server time for clients correcting clock drift
+        .route("/time", get(handlers::time))
+        // Discovery document for wallets and aggregators
+        .route("/.well-known/x402", get(handlers::discovery))
+        // Prometheus metrics
+        .route("/metrics", get(handlers::metrics))
+        // Balance endpoint - authenticated, eventually consistent
+        .route("/balance", get(handlers::balance))
+        // Last accepted timestamps, for clients resyncing under monotonic timestamps
+        .route("/auth-state", get(handlers::auth_state));
+
+    // Request history endpoint - authenticated, only mounted when history is recorded
+    if state.config.request_history.is_some() {
+        app = app.route("/history", get(handlers::history));
+    }
+
+    // Relay endpoints - handle authentication and payments for their resource
+    for resource in &state.config.resources {
+        app = app.route(
+            &resource.path,
+            post(handlers::relay).layer(Extension(Arc::new(resource.clone()))),
+        );
+    }
+
+    // Debugging aids for client developers - off by default so they can't serve as a signing oracle
+    if state.config.debug_endpoints {
+        app = app.route("/debug/verify", post(handlers::debug_verify));
+    }
+
+    // Admin endpoints - only mounted when an admin token is configured
+    if state.config.admin_token.is_some() {
+        app = app
+            .route("/admin/adjust", post(handlers::admin_adjust))
+            .route("/admin/balances", post(handlers::admin_balances))
+            .route("/admin/maintenance", post(handlers::admin_maintenance))
+            .route("/admin/suspend", post(handlers::admin_suspend))
+            .route("/admin/unsuspend", post(handlers::admin_unsuspend));
+
+        // Spend reports need spend events, which are only recorded if configured
+        if state.config.record_spend {
+            app = app.route("/admin/spend", post(handlers::admin_spend));
+        }
+    }
+
+    // Turn away blocklisted and rate-limited addresses before any route does work
+    if let Some(filter) = state.ip_filter.clone() {
+        app = app.layer(middleware::from_fn_with_state(filter, ip_filter::filter));
+    }
+
+    app.with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use alloy::signers::local::PrivateKeySigner;
+    use axum::{body::Body, http::{Request, StatusCode}};
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn test_resources_relay_to_their_own_nodes_and_prices() {
+        let eth_node = spawn_echo_node(json!("eth")).await;
+        let base_node = spawn_echo_node(json!("base")).await;
+        let config = test_config(&format!(
+            r#"
+            [[resources]]
+            name = "eth"
+            path = "/eth"
+            node_url = "{}"
+            price_per_request = 0.5
+
+            [[resources]]
+            name = "base"
+            path = "/base"
+            node_url = "{}"
+            price_per_request = 0.25
+            network = "base"
+            "#,
+            eth_node, base_node
+        ));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+
+        let signer = PrivateKeySigner::random();
+        let address = signer.address().to_string();
+        state.database.add_balance(&address, 10.0).await.unwrap();
+
+        let (status, body) = send(&app, signed_request("/eth", &signer, &rpc_call("eth_chainId", 1))).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["result"], "eth");
+
+        let (status, body) = send(&app, signed_request("/base", &signer, &rpc_call("eth_chainId", 2))).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["result"], "base");
+
+        // Both resources draw from the same balance at their own price
+        let user = state.database.get_user(&address).await.unwrap().unwrap();
+        assert_eq!(user.balance, 10.0 - 0.5 - 0.25);
+    }
+
+    #[tokio::test]
+    async fn test_relay_served_at_configured_path() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let config = test_config(&format!("node_url = \"{}\"\nprice_per_request = 0.001\nrelay_path = \"/\"\n", node));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state.clone());
+
+        let signer = PrivateKeySigner::random();
+        state.database.add_balance(&signer.address().to_string(), 1.0).await.unwrap();
+
+        let (status, body) = send(&app, signed_request("/", &signer, &rpc_call("eth_chainId", 1))).await;
+        assert_eq!(status, StatusCode::OK);
+        let reply: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["result"], "0x1");
+
+        let (status, _) = send(&app, signed_request("/relay", &signer, &rpc_call("eth_chainId", 2))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Other endpoints are unaffected by relaying at the root
+        let (status, _) = send(&app, Request::get("/health").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_missing_database_settings_are_startup_errors() {
+        let mut config = test_config(DEFAULT_RESOURCE);
+        config.database_type = "dynamodb".to_string();
+        config.dynamodb_table_name = None;
+        let error = open_database(&config).await.err().unwrap();
+        assert!(matches!(error, StartupError::Config(_)));
+        assert_eq!(
+            error.to_string(),
+            "Invalid configuration: dynamodb_table_name is required when database_type is 'dynamodb'"
+        );
+
+        config.database_type = "postgres".to_string();
+        let error = open_database(&config).await.err().unwrap();
+        assert_eq!(error.to_string(), "Invalid configuration: Invalid database type: postgres");
+    }
+
+    #[tokio::test]
+    async fn test_readiness_reports_database_status() {
+        let (state, _dir) = test_state(test_config(DEFAULT_RESOURCE));
+        let app = build_router(state);
+        let (status, _) = send(&app, Request::get("/health/ready").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // A DynamoDB client pointed at nothing must make readiness fail
+        let state = Arc::new(AppState::new(test_config(DEFAULT_RESOURCE), Arc::new(unreachable_dynamodb())).unwrap());
+        let app = build_router(state);
+        let (status, _) = send(&app, Request::get("/health/ready").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_fails_readiness_but_not_liveness() {
+        let mut config = test_config(DEFAULT_RESOURCE);
+        config.admin_token = Some("a".repeat(32));
+        let (state, _dir) = test_state(config);
+        let app = build_router(state);
+
+        let set_maintenance = |enabled: bool| {
+            Request::post("/admin/maintenance")
+                .header("authorization", format!("Bearer {}", "a".repeat(32)))
+                .header("content-type", "application/json")
+                .body(Body::from(json!({ "enabled": enabled }).to_string()))
+                .unwrap()
+        };
+        let probe = |path: &str| Request::get(path).body(Body::empty()).unwrap();
+
+        let (status, _) = send(&app, set_maintenance(true)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = send(&app, probe("/health/ready")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, "In maintenance");
+        let (status, _) = send(&app, probe("/health")).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = send(&app, probe("/status")).await;
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["maintenance"], true);
+
+        let (status, _) = send(&app, set_maintenance(false)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, probe("/health/ready")).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = send(&app, probe("/status")).await;
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["maintenance"], false);
+    }
+}
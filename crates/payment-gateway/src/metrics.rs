@@ -0,0 +1,225 @@
+use std::fmt::Write;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the latency buckets in milliseconds (slower observations land in +Inf)
+const BUCKETS_MS: &[u64] = &[5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// Latency histogram that concurrent requests record into without locking
+pub struct LatencyHistogram {
+    /// Observations per bucket, with a final overflow bucket
+    counts: Vec<AtomicU64>,
+    /// Sum of all observations in microseconds
+    sum_micros: AtomicU64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            counts: (0..=BUCKETS_MS.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    /// Record one observation
+    pub fn record(&self, latency: Duration) {
+        let millis = latency.as_millis() as u64;
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|bound| millis <= *bound)
+            .unwrap_or(BUCKETS_MS.len());
+
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Append this histogram in Prometheus text format
+    fn render(&self, name: &str, help: &str, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+
+        let mut cumulative = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let le = BUCKETS_MS
+                .get(i)
+                .map_or("+Inf".to_string(), |bound| format!("{}", *bound as f64 / 1000.0));
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+        }
+
+        let sum_secs = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "{}_sum {}", name, sum_secs);
+        let _ = writeln!(out, "{}_count {}", name, cumulative);
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Gateway metrics exposed on /metrics
+#[derive(Default)]
+pub struct Metrics {
+    /// Time from receiving an authenticated relay request to having the response ready
+    pub relay_latency: LatencyHistogram,
+    /// Portion of relay latency spent waiting on the node
+    pub node_latency: LatencyHistogram,
+    /// Relay requests whose credentials were verified (signature checks are the costly part)
+    pub authentications: AtomicU64,
+    /// Deposits credited whose request then couldn't be charged, leaving the ledger short a deduction
+    pub deduction_failures: AtomicU64,
+    /// Accounts whose balance differed from their ledger at the last reconciliation
+    pub drifted_accounts: AtomicU64,
+    /// Total difference between those balances and their ledgers, in smallest USDC units
+    pub balance_drift_units: AtomicU64,
+    /// Requests sent to nodes on behalf of clients (including payment retries)
+    pub node_requests: AtomicU64,
+    /// Connections the node client opened; requests beyond these reused pooled connections
+    pub node_connections: AtomicU64,
+    /// Time spent opening node connections, which requests wait out when none is idle in the pool
+    pub node_connect_latency: LatencyHistogram,
+    /// Node requests that failed because no connection to the node could be opened
+    pub node_connect_failures: AtomicU64,
+    /// Node requests that timed out, connecting or waiting for the response
+    pub node_timeouts: AtomicU64,
+}
+
+impl Metrics {
+    /// Render all metrics in Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.relay_latency.render(
+            "gateway_relay_latency_seconds",
+            "End-to-end latency of authenticated relay requests",
+            &mut out,
+        );
+        self.node_latency.render(
+            "gateway_node_latency_seconds",
+            "Latency of the upstream node call",
+            &mut out,
+        );
+
+        let _ = writeln!(out, "# HELP gateway_authentications_total Relay requests whose credentials were verified");
+        let _ = writeln!(out, "# TYPE gateway_authentications_total counter");
+        let _ = writeln!(out, "gateway_authentications_total {}", self.authentications.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# HELP gateway_deduction_failures_total Deposits credited whose request couldn't be charged");
+        let _ = writeln!(out, "# TYPE gateway_deduction_failures_total counter");
+        let _ = writeln!(out, "gateway_deduction_failures_total {}", self.deduction_failures.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# HELP gateway_drifted_accounts Accounts whose balance differed from their ledger at the last reconciliation");
+        let _ = writeln!(out, "# TYPE gateway_drifted_accounts gauge");
+        let _ = writeln!(out, "gateway_drifted_accounts {}", self.drifted_accounts.load(Ordering::Relaxed));
+
+        let drift = self.balance_drift_units.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(out, "# HELP gateway_balance_drift_usdc Total difference between drifted balances and their ledgers");
+        let _ = writeln!(out, "# TYPE gateway_balance_drift_usdc gauge");
+        let _ = writeln!(out, "gateway_balance_drift_usdc {}", drift);
+
+        let requests = self.node_requests.load(Ordering::Relaxed);
+        let connections = self.node_connections.load(Ordering::Relaxed);
+        let _ = writeln!(out, "# HELP gateway_node_requests_total Requests sent to nodes on behalf of clients");
+        let _ = writeln!(out, "# TYPE gateway_node_requests_total counter");
+        let _ = writeln!(out, "gateway_node_requests_total {}", requests);
+
+        let _ = writeln!(out, "# HELP gateway_node_connections_total Connections opened to nodes");
+        let _ = writeln!(out, "# TYPE gateway_node_connections_total counter");
+        let _ = writeln!(out, "gateway_node_connections_total {}", connections);
+
+        let _ = writeln!(out, "# HELP gateway_node_connections_reused_total Node requests served over a pooled connection (requests less connections opened)");
+        let _ = writeln!(out, "# TYPE gateway_node_connections_reused_total counter");
+        let _ = writeln!(out, "gateway_node_connections_reused_total {}", self.node_connections_reused());
+
+        self.node_connect_latency.render(
+            "gateway_node_connect_seconds",
+            "Time spent opening node connections while no pooled connection was idle",
+            &mut out,
+        );
+
+        let _ = writeln!(out, "# HELP gateway_node_connect_failures_total Node requests failed because no connection could be opened");
+        let _ = writeln!(out, "# TYPE gateway_node_connect_failures_total counter");
+        let _ = writeln!(out, "gateway_node_connect_failures_total {}", self.node_connect_failures.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# HELP gateway_node_timeouts_total Node requests timed out connecting or waiting for the response");
+        let _ = writeln!(out, "# TYPE gateway_node_timeouts_total counter");
+        let _ = writeln!(out, "gateway_node_timeouts_total {}", self.node_timeouts.load(Ordering::Relaxed));
+        out
+    }
+
+    /// Node requests that didn't need a connection of their own
+    pub fn node_connections_reused(&self) -> u64 {
+        self.node_requests
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.node_connections.load(Ordering::Relaxed))
+    }
+}
+
+/// Connector middleware for the node client, counting the connections it opens and how long
+/// opening them takes, since the connection pool itself reports nothing
+#[derive(Clone)]
+pub struct CountConnections<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S> CountConnections<S> {
+    /// Layer for `reqwest::ClientBuilder::connector_layer`
+    pub fn layer(metrics: Arc<Metrics>) -> impl tower::Layer<S, Service = Self> + Clone {
+        tower::layer::layer_fn(move |inner| Self { inner, metrics: metrics.clone() })
+    }
+}
+
+impl<S, R> tower::Service<R> for CountConnections<S>
+where
+    S: tower::Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let metrics = self.metrics.clone();
+        let started = Instant::now();
+        let connecting = self.inner.call(request);
+        Box::pin(async move {
+            let connection = connecting.await?;
+            metrics.node_connections.fetch_add(1, Ordering::Relaxed);
+            metrics.node_connect_latency.record(started.elapsed());
+            Ok(connection)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let histogram = LatencyHistogram::new();
+        histogram.record(Duration::from_millis(3));
+        histogram.record(Duration::from_millis(40));
+        histogram.record(Duration::from_secs(60));
+
+        let mut out = String::new();
+        histogram.render("latency", "test", &mut out);
+
+        assert!(out.contains("latency_bucket{le=\"0.005\"} 1\n"));
+        assert!(out.contains("latency_bucket{le=\"0.05\"} 2\n"));
+        assert!(out.contains("latency_bucket{le=\"10\"} 2\n"));
+        assert!(out.contains("latency_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("latency_count 3\n"));
+    }
+}
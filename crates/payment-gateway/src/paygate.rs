@@ -0,0 +1,75 @@
+use async_trait::async_trait;
+use axum::{http::HeaderMap, response::{IntoResponse, Response}};
+use std::sync::Arc;
+use x402_axum::facilitator_client::FacilitatorClient;
+use x402_axum::layer::X402Paygate;
+use x402_rs::types::{PaymentRequirements, VerifyRequest};
+
+/// Why a payment was not verified
+pub enum PaygateError {
+    /// The X-Payment header is missing or malformed; the response explains what was expected
+    Extraction(Response),
+    /// The facilitator rejected the payment
+    Verification(String),
+}
+
+/// Verifies and settles deposit payments
+/// Backed by the x402 facilitator; tests substitute canned results
+#[async_trait]
+pub trait Paygate: Send + Sync {
+    /// Extract the payment from the request headers and verify it against `requirements`
+    async fn verify(
+        &self,
+        headers: &HeaderMap,
+        requirements: Vec<PaymentRequirements>,
+    ) -> Result<VerifyRequest, PaygateError>;
+
+    /// Settle a verified payment on-chain
+    async fn settle(&self, request: &VerifyRequest) -> Result<(), String>;
+}
+
+/// Paygate verifying and settling through an x402 facilitator
+pub struct FacilitatorPaygate {
+    facilitator: Arc<FacilitatorClient>,
+}
+
+impl FacilitatorPaygate {
+    pub fn new(facilitator: FacilitatorClient) -> Self {
+        Self { facilitator: Arc::new(facilitator) }
+    }
+
+    fn paygate(&self, requirements: Vec<PaymentRequirements>) -> X402Paygate {
+        X402Paygate {
+            facilitator: self.facilitator.clone(),
+            payment_requirements: Arc::new(requirements),
+            settle_before_execution: false, // Settled by the caller once the deposit checks pass
+        }
+    }
+}
+
+#[async_trait]
+impl Paygate for FacilitatorPaygate {
+    async fn verify(
+        &self,
+        headers: &HeaderMap,
+        requirements: Vec<PaymentRequirements>,
+    ) -> Result<VerifyRequest, PaygateError> {
+        let paygate = self.paygate(requirements);
+        let payload = paygate
+            .extract_payment_payload(headers)
+            .await
+            .map_err(|err| PaygateError::Extraction(err.into_response()))?;
+        paygate
+            .verify_payment(payload)
+            .await
+            .map_err(|err| PaygateError::Verification(format!("{:?}", err)))
+    }
+
+    async fn settle(&self, request: &VerifyRequest) -> Result<(), String> {
+        self.paygate(vec![request.payment_requirements.clone()])
+            .settle_payment(request)
+            .await
+            .map(|_settlement| ())
+            .map_err(|err| format!("{:?}", err))
+    }
+}
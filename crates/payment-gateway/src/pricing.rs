@@ -0,0 +1,87 @@
+use serde::Deserialize;
+
+use crate::asset::Asset;
+
+/// How a price that lands between smallest units is turned into whole units
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    /// Up, in the operator's favor
+    Ceil,
+    /// Down, in the user's favor
+    Floor,
+    /// To the nearest unit, halves away from zero
+    #[default]
+    Round,
+}
+
+impl RoundingMode {
+    /// Whole units for a fractional amount of units
+    /// Float error within a millionth of a unit is treated as exact, so 1500.0000000002 never rounds up to 1501
+    pub fn apply(self, units: f64) -> u128 {
+        let nearest = units.round();
+        if (units - nearest).abs() < 1e-6 {
+            return nearest as u128;
+        }
+        match self {
+            Self::Ceil => units.ceil() as u128,
+            Self::Floor => units.floor() as u128,
+            Self::Round => nearest as u128,
+        }
+    }
+}
+
+/// Converts USD prices into amounts of the payment asset
+/// Implementations backed by a live rate source should cache the rate: this is called on every request
+pub trait PricingOracle: Send + Sync {
+    /// Smallest asset units worth `usd_amount` at the current rate, rounded with `rounding`
+    fn usd_to_asset_units(&self, usd_amount: f64, rounding: RoundingMode) -> u64;
+}
+
+/// Fixed 1:1 rate for stablecoins pegged to the dollar
+#[derive(Debug, Clone, Copy)]
+pub struct StablecoinOracle {
+    asset: Asset,
+}
+
+impl StablecoinOracle {
+    pub fn new(asset: Asset) -> Self {
+        Self { asset }
+    }
+}
+
+impl PricingOracle for StablecoinOracle {
+    fn usd_to_asset_units(&self, usd_amount: f64, rounding: RoundingMode) -> u64 {
+        u64::try_from(self.asset.to_units(usd_amount, rounding)).unwrap_or(u64::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::USDC;
+
+    #[test]
+    fn test_stablecoin_oracle_is_one_to_one() {
+        let oracle = StablecoinOracle::new(USDC);
+        assert_eq!(oracle.usd_to_asset_units(1.0, RoundingMode::Round), 1_000_000);
+        assert_eq!(oracle.usd_to_asset_units(0.001, RoundingMode::Round), 1_000);
+        assert_eq!(oracle.usd_to_asset_units(0.0, RoundingMode::Round), 0);
+    }
+
+    #[test]
+    fn test_prices_between_units_follow_rounding_mode() {
+        let oracle = StablecoinOracle::new(USDC);
+
+        // $0.0000014 and $0.0000015 land between 1 and 2 USDC units
+        assert_eq!(oracle.usd_to_asset_units(0.0000014, RoundingMode::Ceil), 2);
+        assert_eq!(oracle.usd_to_asset_units(0.0000014, RoundingMode::Floor), 1);
+        assert_eq!(oracle.usd_to_asset_units(0.0000014, RoundingMode::Round), 1);
+        assert_eq!(oracle.usd_to_asset_units(0.0000015, RoundingMode::Round), 2);
+
+        // Prices on a unit stay there whatever the mode, despite float error (0.0015 * 1e6 > 1500)
+        for mode in [RoundingMode::Ceil, RoundingMode::Floor, RoundingMode::Round] {
+            assert_eq!(oracle.usd_to_asset_units(0.0015, mode), 1_500);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use crate::asset::USDC;
+use crate::config::ReconciliationConfig;
+use crate::database::{AuditEntry, DatabaseError, DatabaseTrait};
+use crate::metrics::Metrics;
+use crate::pricing::RoundingMode;
+
+/// Actor recorded in the audit ledger for credited deposits, so the ledger accounts for every credit
+pub const DEPOSIT_ACTOR: &str = "deposit";
+
+/// An account whose balance differs from what its ledger adds up to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drift {
+    pub address: String,
+    pub balance: f64,
+    /// Audited credits and debits (deposits, admin adjustments, reversed settlements) less recorded spend
+    pub expected: f64,
+}
+
+impl Drift {
+    pub fn amount(&self) -> f64 {
+        self.balance - self.expected
+    }
+}
+
+/// Ledger entry for a deposit credited to `address`
+pub fn deposit_entry(address: &str, amount: f64, timestamp: u64) -> AuditEntry {
+    AuditEntry {
+        address: address.to_lowercase(),
+        actor: DEPOSIT_ACTOR.to_string(),
+        delta: amount,
+        reason: "deposit".to_string(),
+        timestamp,
+    }
+}
+
+/// Accounts whose balance is more than `tolerance` USDC away from their ledger
+pub async fn find_drift(database: &dyn DatabaseTrait, tolerance: f64) -> Result<Vec<Drift>, DatabaseError> {
+    let mut drifted = Vec::new();
+
+    for (address, user_data) in database.list_users().await? {
+        let credited: f64 = database.list_audit(&address).await?.iter().map(|entry| entry.delta).sum();
+        let spent = USDC.to_tokens(u128::from(database.spend_total(&address, 0, u64::MAX).await?));
+        let drift = Drift {
+            address,
+            balance: user_data.balance,
+            expected: credited - spent,
+        };
+        if drift.amount().abs() > tolerance {
+            drifted.push(drift);
+        }
+    }
+
+    Ok(drifted)
+}
+
+/// Check every account against its ledger, warning about each one that drifted and
+/// publishing the totals as metrics
+pub async fn reconcile(database: &dyn DatabaseTrait, metrics: &Metrics, tolerance: f64) -> Result<Vec<Drift>, DatabaseError> {
+    let drifted = find_drift(database, tolerance).await?;
+
+    let total: f64 = drifted.iter().map(|drift| drift.amount().abs()).sum();
+    for drift in &drifted {
+        tracing::warn!(
+            address = %drift.address,
+            balance = drift.balance,
+            expected = drift.expected,
+            drift = drift.amount(),
+            "Balance drifted from its ledger"
+        );
+    }
+    metrics.drifted_accounts.store(drifted.len() as u64, Ordering::Relaxed);
+    metrics.balance_drift_units.store(
+        u64::try_from(USDC.to_units(total, RoundingMode::Round)).unwrap_or(u64::MAX),
+        Ordering::Relaxed,
+    );
+
+    Ok(drifted)
+}
+
+/// Spawn the background task periodically reconciling balances until `shutdown` is cancelled
+pub fn spawn(
+    database: Arc<dyn DatabaseTrait>,
+    metrics: Arc<Metrics>,
+    config: ReconciliationConfig,
+    shutdown: CancellationToken,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = interval.tick() => {}
+            }
+
+            match reconcile(database.as_ref(), &metrics, config.tolerance).await {
+                Ok(drifted) => {
+                    tracing::info!(drifted = drifted.len(), "Balance reconciliation completed");
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, "Balance reconciliation failed");
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::rocksdb::RocksDbDatabase;
+    use crate::test_utils::capture_logs;
+
+    #[tokio::test]
+    async fn test_discrepancy_reported_as_drift() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = RocksDbDatabase::open(temp_dir.path().join("test.db").to_str().unwrap()).unwrap();
+        let metrics = Metrics::default();
+        let address = "0x000000000000000000000000000000000000000a";
+
+        // Deposit 2 USDC and spend 0.5 of it: balance and ledger agree
+        db.add_balance(address, 2.0).await.unwrap();
+        db.append_audit(&deposit_entry(address, 2.0, 100)).await.unwrap();
+        db.deduct_balance(address, 0.5, 100).await.unwrap();
+        db.record_spend(address, 500_000, 100).await.unwrap();
+        assert!(reconcile(&db, &metrics, 1e-6).await.unwrap().is_empty());
+        assert_eq!(metrics.drifted_accounts.load(Ordering::Relaxed), 0);
+
+        // A credit that bypassed the ledger (e.g. a lost update) shows up as drift
+        db.add_balance(address, 0.25).await.unwrap();
+        let (logs, _guard) = capture_logs();
+        let drifted = reconcile(&db, &metrics, 1e-6).await.unwrap();
+        assert_eq!(drifted.len(), 1);
+        assert!((drifted[0].amount() - 0.25).abs() < 1e-9);
+        assert!((drifted[0].expected - 1.5).abs() < 1e-9);
+        assert!(logs.contents().contains("Balance drifted from its ledger"), "{}", logs.contents());
+        assert_eq!(metrics.drifted_accounts.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.balance_drift_units.load(Ordering::Relaxed), 250_000);
+
+        // Within tolerance it's left alone
+        assert!(reconcile(&db, &metrics, 0.5).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+use reqwest::Url;
+use std::collections::HashMap;
+use std::str::FromStr;
+use x402_rs::network::Network;
+use x402_rs::types::{EvmAddress, MixedAddress};
+
+use crate::asset::USDC;
+use crate::config::Config;
+
+/// Payment requirement fields parsed from the config once at startup, so a 402 clones
+/// prepared values rather than parsing them (and possibly panicking) on every request
+#[derive(Debug, Clone)]
+pub struct PreparedRequirements {
+    /// PAYMENT_ADDRESS, where payments go on networks without their own address
+    default_pay_to: MixedAddress,
+    pay_to: HashMap<Network, MixedAddress>,
+    /// Rotated-out payment addresses, by lowercase address
+    previous_pay_to: HashMap<String, MixedAddress>,
+    /// Resource URL advertised for each resource path
+    resources: HashMap<String, Url>,
+    usdc: MixedAddress,
+    /// Contract of each configured payment asset, in config order
+    assets: Vec<MixedAddress>,
+}
+
+impl PreparedRequirements {
+    /// Parse every address and URL payment requirements are built from
+    pub fn new(config: &Config) -> Result<Self, String> {
+        let pay_to = config
+            .payment_addresses
+            .iter()
+            .map(|(network, address)| Ok((*network, parse_address(address, "payment_addresses entry")?)))
+            .collect::<Result<_, String>>()?;
+        let previous_pay_to = config
+            .previous_payment_addresses
+            .iter()
+            .map(|previous| {
+                Ok((previous.address.to_lowercase(), parse_address(&previous.address, "previous_payment_addresses entry")?))
+            })
+            .collect::<Result<_, String>>()?;
+        let resources = config
+            .resources
+            .iter()
+            .map(|resource| {
+                let url = format!("http://localhost:{}{}", config.port, resource.path);
+                let url = url
+                    .parse()
+                    .map_err(|e| format!("resource '{}' has no valid URL '{}': {}", resource.name, url, e))?;
+                Ok((resource.path.clone(), url))
+            })
+            .collect::<Result<_, String>>()?;
+        let assets = config
+            .payment_assets
+            .iter()
+            .map(|asset| parse_address(&asset.address, "payment_assets address"))
+            .collect::<Result<_, String>>()?;
+
+        Ok(Self {
+            default_pay_to: parse_address(&config.payment_address, "PAYMENT_ADDRESS")?,
+            pay_to,
+            previous_pay_to,
+            resources,
+            usdc: parse_address(USDC.address, "USDC address")?,
+            assets,
+        })
+    }
+
+    /// Address deposits on `network` are paid to (see `Config::payment_address_for`)
+    pub fn pay_to(&self, network: Network) -> &MixedAddress {
+        self.pay_to.get(&network).unwrap_or(&self.default_pay_to)
+    }
+
+    /// A rotated-out payment address, if `address` is one
+    pub fn previous_pay_to(&self, address: &str) -> Option<&MixedAddress> {
+        self.previous_pay_to.get(&address.to_lowercase())
+    }
+
+    /// Resource URL for the resource served at `path`
+    pub fn resource(&self, path: &str) -> &Url {
+        self.resources.get(path).expect("Every configured resource is prepared")
+    }
+
+    pub fn usdc(&self) -> &MixedAddress {
+        &self.usdc
+    }
+
+    /// Contracts of the configured payment assets, in the order of `Config::payment_assets`
+    pub fn assets(&self) -> &[MixedAddress] {
+        &self.assets
+    }
+}
+
+fn parse_address(address: &str, what: &str) -> Result<MixedAddress, String> {
+    EvmAddress::from_str(address)
+        .map(MixedAddress::Evm)
+        .map_err(|_| format!("{} '{}' is not a valid EVM address", what, address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_malformed_payment_address_fails_at_load() {
+        // Right length and prefix, but not hex: this used to panic on the first 402
+        let malformed = format!("0x{}", "z".repeat(40));
+        let toml = format!(
+            "port = 3000\nfacilitator_url = \"https://x402.org/facilitator\"\ndatabase_path = \"unused\"\ndatabase_type = \"rocksdb\"\n{}",
+            DEFAULT_RESOURCE
+        );
+        let error = Config::from_toml_str(&toml, &malformed).map(|_| ()).unwrap_err();
+        assert!(error.to_string().contains("PAYMENT_ADDRESS"), "{}", error);
+
+        let config = test_config(DEFAULT_RESOURCE);
+        let prepared = PreparedRequirements::new(&config).unwrap();
+        let expected = MixedAddress::Evm(EvmAddress::from_str(PAYMENT_ADDRESS).unwrap());
+        assert_eq!(prepared.pay_to(Network::BaseSepolia), &expected);
+        assert_eq!(prepared.resource("/relay").as_str(), "http://localhost:3000/relay");
+    }
+}
@@ -0,0 +1,94 @@
+use axum::body::Bytes;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::config::ErrorSanitizerConfig;
+use crate::jsonrpc;
+
+/// Rewrites JSON-RPC error messages from the node so internal details don't reach clients
+#[derive(Debug, Clone)]
+pub struct ErrorSanitizer {
+    patterns: Vec<Regex>,
+    replacement: String,
+}
+
+impl ErrorSanitizer {
+    /// Compile the configured patterns
+    pub fn new(config: &ErrorSanitizerConfig) -> Result<Self, regex::Error> {
+        let patterns = config
+            .patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            patterns,
+            replacement: config.replacement.clone(),
+        })
+    }
+
+    /// Sanitize error messages in a response body
+    /// Returns None when the body isn't JSON or nothing was rewritten
+    pub fn sanitize(&self, body: &[u8]) -> Option<Bytes> {
+        let mut response: Value = serde_json::from_slice(body).ok()?;
+        let mut changed = false;
+
+        // Only the message (and free-form data) is rewritten; code and id are preserved
+        jsonrpc::for_each_error_mut(&mut response, |error| {
+            if let Some(Value::String(message)) = error.get_mut("message") {
+                changed |= self.rewrite(message);
+            }
+            if let Some(Value::String(data)) = error.get_mut("data") {
+                changed |= self.rewrite(data);
+            }
+        });
+
+        if !changed {
+            return None;
+        }
+
+        serde_json::to_vec(&response).ok().map(Bytes::from)
+    }
+
+    fn rewrite(&self, text: &mut String) -> bool {
+        let mut changed = false;
+        for pattern in &self.patterns {
+            if pattern.is_match(text) {
+                *text = pattern.replace_all(text, self.replacement.as_str()).into_owned();
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_sensitive_error_sanitized_preserving_code_and_id() {
+        let sanitizer = ErrorSanitizer::new(&ErrorSanitizerConfig {
+            patterns: vec![r"(?i)acme-rpc".to_string(), r"\b\d{1,3}(\.\d{1,3}){3}(:\d+)?\b".to_string()],
+            replacement: "[redacted]".to_string(),
+        })
+        .unwrap();
+
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 42,
+            "error": {"code": -32000, "message": "ACME-RPC backend 10.0.3.7:8545 unavailable"}
+        });
+        let sanitized = sanitizer.sanitize(body.to_string().as_bytes()).unwrap();
+        let sanitized: Value = serde_json::from_slice(&sanitized).unwrap();
+
+        assert_eq!(sanitized["error"]["message"], "[redacted] backend [redacted] unavailable");
+        assert_eq!(sanitized["error"]["code"], -32000);
+        assert_eq!(sanitized["id"], 42);
+
+        // Successful results are left untouched
+        let ok = json!({"jsonrpc": "2.0", "id": 1, "result": "10.0.3.7"});
+        assert!(sanitizer.sanitize(ok.to_string().as_bytes()).is_none());
+    }
+}
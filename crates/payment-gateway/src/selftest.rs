@@ -0,0 +1,325 @@
+use alloy::primitives::Address;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+use crate::asset::USDC;
+use crate::config::Config;
+use crate::database::{DatabaseTrait, UserData};
+use x402_rs::types::Scheme;
+
+/// Account used to probe database writes; nobody holds the zero address's key
+const PROBE_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+
+/// Outcome of one startup check
+#[derive(Debug)]
+pub struct Check {
+    pub name: String,
+    pub result: Result<(), String>,
+}
+
+/// Verify the payment address, every node, the facilitator (if deposits are enabled) and the database
+pub async fn run(config: &Config, database: &dyn DatabaseTrait, client: &Client) -> Vec<Check> {
+    let mut checks = vec![Check {
+        name: "payment_address".to_string(),
+        result: Address::from_str(&config.payment_address)
+            .map(|_| ())
+            .map_err(|e| e.to_string()),
+    }];
+    for (network, address) in &config.payment_addresses {
+        checks.push(Check {
+            name: format!("payment_address ({})", network),
+            result: Address::from_str(address).map(|_| ()).map_err(|e| e.to_string()),
+        });
+    }
+
+    for resource in &config.resources {
+        checks.push(Check {
+            name: format!("node ({})", resource.name),
+            result: check_node(client, &resource.node_url).await,
+        });
+    }
+
+    if config.deposits_enabled {
+        checks.push(Check {
+            name: "facilitator".to_string(),
+            result: check_facilitator(client, config).await,
+        });
+    }
+
+    checks.push(Check {
+        name: "database".to_string(),
+        result: check_database(database).await,
+    });
+
+    checks
+}
+
+/// Process exit code for a set of checks: 0 only if every check passed
+pub fn exit_code(checks: &[Check]) -> i32 {
+    if checks.iter().all(|check| check.result.is_ok()) {
+        0
+    } else {
+        1
+    }
+}
+
+/// The node must answer eth_chainId with a result
+async fn check_node(client: &Client, node_url: &str) -> Result<(), String> {
+    let request = json!({"jsonrpc": "2.0", "method": "eth_chainId", "params": [], "id": 1});
+    let response: Value = client
+        .post(node_url)
+        .json(&request)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid JSON-RPC response: {}", e))?;
+
+    match response.get("result") {
+        Some(Value::String(_)) => Ok(()),
+        _ => Err(format!("Unexpected eth_chainId response: {}", response)),
+    }
+}
+
+/// The facilitator's supported kinds must cover the exact scheme on every resource network,
+/// and include the USDC asset wherever the facilitator lists the assets it settles
+pub async fn check_facilitator(client: &Client, config: &Config) -> Result<(), String> {
+    let url = format!("{}/supported", config.facilitator_url.trim_end_matches('/'));
+    let headers = config.facilitator_headers().map_err(|e| e.to_string())?;
+    let response = client.get(&url).headers(headers).send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("{} returned {}", url, response.status()));
+    }
+
+    let supported: Value = response
+        .json()
+        .await
+        .map_err(|e| format!("Invalid supported kinds response: {}", e))?;
+    let kinds = supported
+        .get("kinds")
+        .and_then(Value::as_array)
+        .ok_or_else(|| format!("Unexpected supported kinds response: {}", supported))?;
+
+    let scheme = Scheme::Exact.to_string();
+    for resource in &config.resources {
+        let network = resource.network.to_string();
+        let kind = kinds
+            .iter()
+            .find(|kind| kind["scheme"] == scheme.as_str() && kind["network"] == network.as_str())
+            .ok_or_else(|| format!("Facilitator does not support scheme '{}' on network '{}'", scheme, network))?;
+
+        if let Some(assets) = kind["extra"]["assets"].as_array() {
+            let mut advertised: Vec<&str> = config
+                .payment_assets
+                .iter()
+                .filter(|asset| asset.network == resource.network)
+                .map(|asset| asset.address.as_str())
+                .collect();
+            if config.payment_assets.is_empty() {
+                advertised.push(USDC.address);
+            }
+            for address in advertised {
+                let listed = assets
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .any(|asset| asset.eq_ignore_ascii_case(address));
+                if !listed {
+                    return Err(format!("Facilitator does not support asset {} on network '{}'", address, network));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The payment address should hold ETH on the node's network so settlement can pay for gas
+pub async fn check_payment_address_funding(client: &Client, node_url: &str, address: &str) -> Result<(), String> {
+    let balance = rpc_quantity(client, node_url, "eth_getBalance", address).await?;
+    if balance > 0 {
+        return Ok(());
+    }
+
+    let nonce = rpc_quantity(client, node_url, "eth_getTransactionCount", address).await?;
+    if nonce == 0 {
+        Err(format!("Payment address {} has never been funded", address))
+    } else {
+        Err(format!("Payment address {} has no ETH for gas", address))
+    }
+}
+
+/// Call a node method taking `[address, "latest"]` and parse its hex quantity result
+async fn rpc_quantity(client: &Client, node_url: &str, method: &str, address: &str) -> Result<u128, String> {
+    let request = json!({"jsonrpc": "2.0", "method": method, "params": [address, "latest"], "id": 1});
+    let response: Value = client
+        .post(node_url)
+        .json(&request)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid JSON-RPC response: {}", e))?;
+
+    response
+        .get("result")
+        .and_then(Value::as_str)
+        .and_then(|quantity| u128::from_str_radix(quantity.trim_start_matches("0x"), 16).ok())
+        .ok_or_else(|| format!("Unexpected {} response: {}", method, response))
+}
+
+/// Write an empty probe account and delete it again
+async fn check_database(database: &dyn DatabaseTrait) -> Result<(), String> {
+    database
+        .update_user(PROBE_ADDRESS, UserData::new(0.0, 0))
+        .await
+        .map_err(|e| e.to_string())?;
+    database
+        .delete_user(PROBE_ADDRESS, u64::MAX)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use axum::{routing::get, Router};
+
+    /// Facilitator reporting the given supported kinds
+    async fn spawn_facilitator_with(kinds: Value) -> String {
+        let supported = json!({"kinds": kinds});
+        spawn_server(Router::new().route("/supported", get(|| async move { axum::Json(supported) }))).await
+    }
+
+    async fn spawn_facilitator() -> String {
+        spawn_facilitator_with(json!([{"x402Version": 1, "scheme": "exact", "network": "base-sepolia"}])).await
+    }
+
+    fn failed(checks: &[Check]) -> Vec<&str> {
+        checks
+            .iter()
+            .filter(|check| check.result.is_err())
+            .map(|check| check.name.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_each_failing_component_fails_the_selftest() {
+        let node = spawn_echo_node(json!("0x14a34")).await;
+        let facilitator = spawn_facilitator().await;
+        let config = |node_url: &str, facilitator_url: &str| {
+            let mut config = test_config(&format!("node_url = \"{}\"\nprice_per_request = 0.001\n", node_url));
+            config.facilitator_url = facilitator_url.to_string();
+            config
+        };
+        let (state, _dir) = test_state(config(&node, &facilitator));
+        let database = state.database.as_ref();
+        let client = Client::new();
+
+        let checks = run(&config(&node, &facilitator), database, &client).await;
+        assert!(failed(&checks).is_empty());
+        assert_eq!(exit_code(&checks), 0);
+
+        let mut bad_address = config(&node, &facilitator);
+        bad_address.payment_address = "0xZZZZ567890abcdef1234567890abcdef12345678".to_string();
+        let checks = run(&bad_address, database, &client).await;
+        assert_eq!(failed(&checks), vec!["payment_address"]);
+        assert_eq!(exit_code(&checks), 1);
+
+        let checks = run(&config("http://127.0.0.1:1", &facilitator), database, &client).await;
+        assert_eq!(failed(&checks), vec!["node (default)"]);
+        assert_eq!(exit_code(&checks), 1);
+
+        let checks = run(&config(&node, "http://127.0.0.1:1"), database, &client).await;
+        assert_eq!(failed(&checks), vec!["facilitator"]);
+        assert_eq!(exit_code(&checks), 1);
+
+        let checks = run(&config(&node, &facilitator), &unreachable_dynamodb(), &client).await;
+        assert_eq!(failed(&checks), vec!["database"]);
+        assert_eq!(exit_code(&checks), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unfunded_payment_address_reported() {
+        let address = "0x1234567890abcdef1234567890abcdef12345678";
+        let client = Client::new();
+
+        let funded = spawn_echo_node(json!("0xde0b6b3a7640000")).await;
+        assert_eq!(check_payment_address_funding(&client, &funded, address).await, Ok(()));
+
+        let empty = spawn_echo_node(json!("0x0")).await;
+        let error = check_payment_address_funding(&client, &empty, address).await.unwrap_err();
+        assert!(error.contains("never been funded"), "{}", error);
+
+        let error = check_payment_address_funding(&client, "http://127.0.0.1:1", address).await.unwrap_err();
+        assert!(!error.contains("funded"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_facilitator_must_support_configured_payments() {
+        let config = |facilitator_url: &str| {
+            let mut config = test_config(DEFAULT_RESOURCE);
+            config.facilitator_url = facilitator_url.to_string();
+            config
+        };
+        let client = Client::new();
+
+        assert_eq!(check_facilitator(&client, &config(&spawn_facilitator().await)).await, Ok(()));
+
+        let mainnet_only = spawn_facilitator_with(json!([{"x402Version": 1, "scheme": "exact", "network": "base"}])).await;
+        let error = check_facilitator(&client, &config(&mainnet_only)).await.unwrap_err();
+        assert!(error.contains("network 'base-sepolia'"), "{}", error);
+
+        let other_asset = spawn_facilitator_with(json!([{
+            "x402Version": 1,
+            "scheme": "exact",
+            "network": "base-sepolia",
+            "extra": {"assets": ["0x0000000000000000000000000000000000000001"]}
+        }]))
+        .await;
+        let error = check_facilitator(&client, &config(&other_asset)).await.unwrap_err();
+        assert!(error.contains(USDC.address), "{}", error);
+
+        let listed_asset = spawn_facilitator_with(json!([{
+            "x402Version": 1,
+            "scheme": "exact",
+            "network": "base-sepolia",
+            "extra": {"assets": [USDC.address.to_lowercase()]}
+        }]))
+        .await;
+        assert_eq!(check_facilitator(&client, &config(&listed_asset)).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_facilitator_credentials_are_sent() {
+        use crate::config::{FacilitatorAuthConfig, Secret};
+        use axum::http::{HeaderMap, StatusCode};
+
+        let supported = json!({"kinds": [{"x402Version": 1, "scheme": "exact", "network": "base-sepolia"}]});
+        let facilitator = spawn_server(Router::new().route(
+            "/supported",
+            get(|headers: HeaderMap| async move {
+                match headers.get("authorization") {
+                    Some(value) if value == "Bearer facilitator-secret" => Ok(axum::Json(supported)),
+                    _ => Err(StatusCode::UNAUTHORIZED),
+                }
+            }),
+        ))
+        .await;
+        let mut config = test_config(DEFAULT_RESOURCE);
+        config.facilitator_url = facilitator;
+        config.facilitator_auth = Some(FacilitatorAuthConfig::Bearer);
+        let client = Client::new();
+
+        // Without the token nothing is sent and the facilitator refuses
+        let error = check_facilitator(&client, &config).await.unwrap_err();
+        assert!(error.contains("401"), "{}", error);
+
+        config.facilitator_token = Some(Secret::new("facilitator-secret".to_string()));
+        assert_eq!(check_facilitator(&client, &config).await, Ok(()));
+    }
+}
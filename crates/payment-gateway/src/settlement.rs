@@ -0,0 +1,186 @@
+use std::future::Future;
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+use crate::database::{AuditEntry, DatabaseError, DatabaseTrait};
+
+/// Actor recorded in the audit ledger for debits reversing failed settlements
+pub const RECONCILIATION_ACTOR: &str = "async-settlement";
+
+/// A deposit credited before its on-chain settlement completed
+#[derive(Debug, Clone)]
+pub struct PendingDeposit {
+    /// Address that signed the payment
+    pub payer: String,
+    /// Account the deposit was credited to (lowercase)
+    pub credited: String,
+    /// Amount credited in USDC
+    pub amount_usdc: f64,
+}
+
+/// Run `settle` in the background, debiting the optimistic credit back if it fails
+pub fn spawn_settlement<F>(
+    database: Arc<dyn DatabaseTrait>,
+    deposit: PendingDeposit,
+    settle: F,
+) -> JoinHandle<()>
+where
+    F: Future<Output = Result<(), String>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        match settle.await {
+            Ok(()) => tracing::info!(
+                payer = %deposit.payer,
+                credited = %deposit.credited,
+                amount = deposit.amount_usdc,
+                "Background settlement completed"
+            ),
+            Err(reason) => reconcile(database.as_ref(), &deposit, &reason).await,
+        }
+    })
+}
+
+/// Take back a deposit whose settlement failed. Whatever was already spent can't be
+/// recovered, so the debit stops at the current balance and the shortfall is logged
+async fn reconcile(database: &dyn DatabaseTrait, deposit: &PendingDeposit, reason: &str) {
+    tracing::error!(
+        payer = %deposit.payer,
+        credited = %deposit.credited,
+        amount = deposit.amount_usdc,
+        reason = %reason,
+        "Background settlement failed, debiting the deposit back"
+    );
+
+    let recovered = match debit_back(database, deposit).await {
+        Ok(recovered) => recovered,
+        Err(e) => {
+            tracing::error!(deposit = ?deposit, error = %e, "Failed to debit back unsettled deposit");
+            return;
+        }
+    };
+
+    if recovered < deposit.amount_usdc {
+        tracing::error!(
+            credited = %deposit.credited,
+            amount = deposit.amount_usdc,
+            recovered,
+            "Unsettled deposit was partly spent before it could be debited back"
+        );
+    }
+    if recovered <= 0.0 {
+        return;
+    }
+
+    let entry = AuditEntry {
+        address: deposit.credited.clone(),
+        actor: RECONCILIATION_ACTOR.to_string(),
+        delta: -recovered,
+        reason: format!("settlement failed: {}", reason),
+        timestamp: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    };
+    if let Err(e) = database.append_audit(&entry).await {
+        tracing::error!(error = %e, entry = ?entry, "Failed to record settlement reconciliation");
+    }
+}
+
+/// Debit up to the deposit amount, returning how much was taken back
+async fn debit_back(database: &dyn DatabaseTrait, deposit: &PendingDeposit) -> Result<f64, DatabaseError> {
+    // Keep the account's last request time so the debit doesn't count as activity
+    let Some(user) = database.get_user(&deposit.credited).await? else {
+        return Ok(0.0);
+    };
+
+    let amount = deposit.amount_usdc.min(user.balance);
+    if amount <= 0.0 {
+        return Ok(0.0);
+    }
+
+    match database.deduct_balance(&deposit.credited, amount, user.latest_timestamp).await {
+        Ok(_) => Ok(amount),
+        // Spent between the read and the debit; take whatever is left now
+        Err(DatabaseError::InsufficientBalance { .. }) => {
+            let Some(user) = database.get_user(&deposit.credited).await? else {
+                return Ok(0.0);
+            };
+            let amount = amount.min(user.balance);
+            if amount <= 0.0 {
+                return Ok(0.0);
+            }
+            database.deduct_balance(&deposit.credited, amount, user.latest_timestamp).await?;
+            Ok(amount)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::rocksdb::RocksDbDatabase;
+
+    const ADDRESS: &str = "0x1234567890abcdef1234567890abcdef12345678";
+
+    fn deposit(amount_usdc: f64) -> PendingDeposit {
+        PendingDeposit {
+            payer: ADDRESS.to_string(),
+            credited: ADDRESS.to_string(),
+            amount_usdc,
+        }
+    }
+
+    fn open_database() -> (Arc<dyn DatabaseTrait>, tempfile::TempDir) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = RocksDbDatabase::open(db_path.to_str().unwrap()).unwrap();
+        (Arc::new(db), temp_dir)
+    }
+
+    #[tokio::test]
+    async fn test_successful_background_settlement_keeps_credit() {
+        let (database, _dir) = open_database();
+        database.add_balance(ADDRESS, 1.0).await.unwrap();
+
+        spawn_settlement(database.clone(), deposit(1.0), async { Ok(()) }).await.unwrap();
+
+        assert_eq!(database.get_user(ADDRESS).await.unwrap().unwrap().balance, 1.0);
+        assert!(database.list_audit(ADDRESS).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_background_settlement_debits_back() {
+        let (database, _dir) = open_database();
+        database.add_balance(ADDRESS, 0.5).await.unwrap();
+        database.add_balance(ADDRESS, 1.0).await.unwrap();
+        database.deduct_balance(ADDRESS, 0.25, 100).await.unwrap();
+
+        let failed = async { Err("transaction reverted".to_string()) };
+        spawn_settlement(database.clone(), deposit(1.0), failed).await.unwrap();
+
+        // The deposit is gone, the rest of the balance and the last request time are kept
+        let user = database.get_user(ADDRESS).await.unwrap().unwrap();
+        assert_eq!(user.balance, 0.25);
+        assert_eq!(user.latest_timestamp, 100);
+
+        let audit = database.list_audit(ADDRESS).await.unwrap();
+        assert_eq!(audit.len(), 1);
+        assert_eq!(audit[0].actor, RECONCILIATION_ACTOR);
+        assert_eq!(audit[0].delta, -1.0);
+        assert!(audit[0].reason.contains("transaction reverted"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_settlement_of_spent_deposit_debits_what_is_left() {
+        let (database, _dir) = open_database();
+        database.add_balance(ADDRESS, 1.0).await.unwrap();
+        database.deduct_balance(ADDRESS, 0.75, 100).await.unwrap();
+
+        let failed = async { Err("facilitator timeout".to_string()) };
+        spawn_settlement(database.clone(), deposit(1.0), failed).await.unwrap();
+
+        assert_eq!(database.get_user(ADDRESS).await.unwrap().unwrap().balance, 0.0);
+        assert_eq!(database.list_audit(ADDRESS).await.unwrap()[0].delta, -0.25);
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// Background work that must finish before the process exits: settlements, webhook
+/// deliveries and maintenance loops spawned while serving
+#[derive(Default)]
+pub struct BackgroundTasks {
+    /// Cancelled when shutdown starts; long-running loops stop at their next iteration
+    token: CancellationToken,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl BackgroundTasks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Token cancelled when draining starts
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// Wait for `handle` when draining; finished tasks are forgotten as new ones arrive
+    pub fn track(&self, handle: JoinHandle<()>) {
+        let mut handles = self.handles.lock().unwrap();
+        handles.retain(|handle| !handle.is_finished());
+        handles.push(handle);
+    }
+
+    /// Signal shutdown and wait up to `timeout` for every tracked task to finish
+    /// Returns false if some were still running (and have been aborted) at the deadline
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.token.cancel();
+        let handles = std::mem::take(&mut *self.handles.lock().unwrap());
+        let pending = handles.iter().filter(|handle| !handle.is_finished()).count();
+        if pending > 0 {
+            tracing::info!(pending, timeout_secs = timeout.as_secs(), "Draining background tasks");
+        }
+
+        let aborts: Vec<_> = handles.iter().map(JoinHandle::abort_handle).collect();
+        let joined = tokio::time::timeout(timeout, async {
+            for handle in handles {
+                if let Err(e) = handle.await {
+                    tracing::error!(error = %e, "Background task failed while draining");
+                }
+            }
+        })
+        .await;
+
+        if joined.is_err() {
+            let unfinished = aborts.iter().filter(|abort| !abort.is_finished()).count();
+            tracing::error!(unfinished, "Background tasks still running after drain timeout, abandoning them");
+            for abort in aborts {
+                abort.abort();
+            }
+            return false;
+        }
+        true
+    }
+}
+
+/// Resolves on Ctrl-C or SIGTERM
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("Failed to listen for Ctrl-C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutdown signal received");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_queued_task_completes_during_drain() {
+        let tasks = BackgroundTasks::new();
+        let done = Arc::new(AtomicBool::new(false));
+
+        let finished = done.clone();
+        tasks.track(tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            finished.store(true, Ordering::SeqCst);
+        }));
+
+        assert!(tasks.drain(Duration::from_secs(5)).await);
+        assert!(done.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_drain_stops_loops_and_gives_up_on_stuck_tasks() {
+        let tasks = BackgroundTasks::new();
+
+        // A maintenance loop stops once draining starts
+        let token = tasks.token();
+        tasks.track(tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => return,
+                    _ = tokio::time::sleep(Duration::from_millis(10)) => {}
+                }
+            }
+        }));
+        assert!(tasks.drain(Duration::from_secs(5)).await);
+
+        let tasks = BackgroundTasks::new();
+        tasks.track(tokio::spawn(std::future::pending()));
+        assert!(!tasks.drain(Duration::from_millis(50)).await);
+    }
+}
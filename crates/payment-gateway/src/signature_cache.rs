@@ -1,77 +1,213 @@
-use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
 use std::time::{Duration, Instant};
 
-/// Cache for tracking used signatures to prevent replay attacks
-pub struct SignatureCache {
+/// Default cap on cached signatures
+pub const DEFAULT_MAX_ENTRIES: usize = 100_000;
+
+/// Most shards a cache is split into
+const MAX_SHARDS: usize = 32;
+
+/// Fewest signatures per shard; smaller caches see too little traffic to need many shards
+const MIN_SHARD_ENTRIES: usize = 1024;
+
+/// Signatures hashing to one shard
+#[derive(Default)]
+struct Shard {
     /// Maps signature -> when it was first seen
     signatures: HashMap<String, Instant>,
+    /// Signatures in insertion order (oldest first) for expiry and eviction
+    order: VecDeque<(String, Instant)>,
+}
+
+/// Cache for tracking used signatures to prevent replay attacks
+/// Split into independently locked shards so concurrent requests rarely wait on each other;
+/// locks are only held for map operations, never across an await
+pub struct SignatureCache {
+    shards: Vec<Mutex<Shard>>,
+    /// Randomly keyed so clients can't aim signatures at one shard
+    hasher: RandomState,
     /// How long to keep signatures in cache (2x timestamp window for safety)
     ttl: Duration,
+    /// Maximum number of signatures kept across all shards; the oldest overall is evicted beyond this
+    max_entries: usize,
+    /// Signatures currently kept across all shards
+    entries: AtomicUsize,
 }
 
 impl SignatureCache {
     /// Create a new signature cache with 2-minute TTL (2x the 60s timestamp window)
     pub fn new() -> Self {
+        Self::with_max_entries(DEFAULT_MAX_ENTRIES)
+    }
+
+    /// Create a new signature cache holding at most about `max_entries` signatures
+    pub fn with_max_entries(max_entries: usize) -> Self {
+        let shard_count = (max_entries / MIN_SHARD_ENTRIES).clamp(1, MAX_SHARDS);
         Self {
-            signatures: HashMap::new(),
+            shards: (0..shard_count).map(|_| Mutex::default()).collect(),
+            hasher: RandomState::new(),
             ttl: Duration::from_secs(120), // 2 minutes
+            max_entries,
+            entries: AtomicUsize::new(0),
         }
     }
 
+    /// Keep signatures for `extra` longer, for when timestamps may be accepted from further out
+    pub fn extend_ttl(&mut self, extra: Duration) {
+        self.ttl += extra;
+    }
+
+    /// How long a signature is remembered
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Index of the shard holding `signature`
+    fn shard_index(&self, signature: &str) -> usize {
+        self.hasher.hash_one(signature) as usize % self.shards.len()
+    }
+
+    /// Lock the shard holding `signature`
+    fn shard(&self, signature: &str) -> MutexGuard<'_, Shard> {
+        lock(&self.shards[self.shard_index(signature)])
+    }
+
     /// Check if a signature has been used before (replay attack detection)
     /// Also automatically cleans up old entries
     /// Returns true if this is a replay (signature already seen)
-    pub fn is_replay(&mut self, signature: &str) -> bool {
+    pub fn is_replay(&self, signature: &str) -> bool {
         let now = Instant::now();
-        
+        let mut shard = self.shard(signature);
+
         // Clean up old signatures first
-        self.cleanup(now);
-        
+        self.cleanup_shard(&mut shard, now);
+
         // Check if signature is in cache
-        if self.signatures.contains_key(signature) {
+        if shard.signatures.contains_key(signature) {
             tracing::warn!(signature = %signature, "Replay attack detected");
             return true;
         }
-        
+
         false
     }
 
     /// Add a signature to the cache
-    pub fn add(&mut self, signature: &str) {
+    /// Evicts the oldest signature across all shards if the cache is full
+    pub fn add(&self, signature: &str) {
+        if self.shard(signature).signatures.contains_key(signature) {
+            return;
+        }
+
+        // Only one shard is locked at a time, so eviction can't deadlock with other adds
+        while self.entries.load(Ordering::Relaxed) >= self.max_entries {
+            if !self.evict_oldest() {
+                break;
+            }
+            tracing::debug!(
+                max_entries = self.max_entries,
+                "Signature cache full, evicted oldest signature"
+            );
+        }
+
         let now = Instant::now();
-        self.signatures.insert(signature.to_string(), now);
-        
+        let mut shard = self.shard(signature);
+        if shard.signatures.contains_key(signature) {
+            return;
+        }
+        shard.signatures.insert(signature.to_string(), now);
+        shard.order.push_back((signature.to_string(), now));
+        self.entries.fetch_add(1, Ordering::Relaxed);
+
         tracing::debug!(
             signature = %signature,
-            cache_size = self.signatures.len(),
+            shard_size = shard.signatures.len(),
             "Signature added to cache"
         );
     }
 
-    /// Remove signatures older than TTL
-    fn cleanup(&mut self, now: Instant) {
-        let before_count = self.signatures.len();
-        
-        self.signatures.retain(|_, &mut first_seen| {
-            now.duration_since(first_seen) < self.ttl
-        });
-        
-        let removed = before_count - self.signatures.len();
+    /// Remove the oldest signature across all shards, expiring old ones along the way
+    /// Returns false if there was nothing to remove
+    fn evict_oldest(&self) -> bool {
+        let now = Instant::now();
+        loop {
+            // Each shard's signatures are oldest first, so the oldest overall is at the front of one
+            let mut oldest: Option<(usize, Instant)> = None;
+            for (index, shard) in self.shards.iter().enumerate() {
+                let mut shard = lock(shard);
+                if self.cleanup_shard(&mut shard, now) > 0 {
+                    return true;
+                }
+                if let Some((_, first_seen)) = shard.order.front() {
+                    if oldest.is_none_or(|(_, seen)| *first_seen < seen) {
+                        oldest = Some((index, *first_seen));
+                    }
+                }
+            }
+
+            let Some((index, first_seen)) = oldest else {
+                return false;
+            };
+            let mut shard = lock(&self.shards[index]);
+            // Another add may have evicted it meanwhile; look again if so
+            if shard.order.front().is_some_and(|(_, seen)| *seen == first_seen) {
+                if let Some((signature, _)) = shard.order.pop_front() {
+                    shard.signatures.remove(&signature);
+                    self.entries.fetch_sub(1, Ordering::Relaxed);
+                }
+                return true;
+            }
+        }
+    }
+
+    /// Remove signatures older than TTL from every shard
+    #[cfg(test)]
+    fn cleanup(&self, now: Instant) {
+        for shard in &self.shards {
+            self.cleanup_shard(&mut lock(shard), now);
+        }
+    }
+
+    /// Remove signatures older than TTL from one shard, returning how many were removed
+    fn cleanup_shard(&self, shard: &mut Shard, now: Instant) -> usize {
+        let before_count = shard.signatures.len();
+
+        // Entries are in insertion order, so expired ones are all at the front
+        while let Some((signature, first_seen)) = shard.order.front() {
+            if now.duration_since(*first_seen) < self.ttl {
+                break;
+            }
+            shard.signatures.remove(signature);
+            shard.order.pop_front();
+        }
+
+        let removed = before_count - shard.signatures.len();
         if removed > 0 {
+            self.entries.fetch_sub(removed, Ordering::Relaxed);
             tracing::debug!(
                 removed = removed,
-                remaining = self.signatures.len(),
+                remaining = shard.signatures.len(),
                 "Cleaned up old signatures from cache"
             );
         }
+        removed
     }
 
     /// Get current cache size (for monitoring)
     pub fn size(&self) -> usize {
-        self.signatures.len()
+        self.entries.load(Ordering::Relaxed)
     }
 }
 
+/// Lock a shard, recovering from poisoning: every update leaves the shard consistent,
+/// so a panic elsewhere while holding the lock can't corrupt it
+fn lock(shard: &Mutex<Shard>) -> MutexGuard<'_, Shard> {
+    shard.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
 impl Default for SignatureCache {
     fn default() -> Self {
         Self::new()
@@ -85,7 +221,7 @@ mod tests {
 
     #[test]
     fn test_replay_detection() {
-        let mut cache = SignatureCache::new();
+        let cache = SignatureCache::new();
         let sig = "0x1234567890abcdef";
 
         // First time - not a replay
@@ -100,9 +236,9 @@ mod tests {
 
     #[test]
     fn test_cleanup() {
-        let mut cache = SignatureCache {
-            signatures: HashMap::new(),
+        let cache = SignatureCache {
             ttl: Duration::from_millis(100),
+            ..SignatureCache::new()
         };
 
         let sig1 = "0xaaaa";
@@ -126,5 +262,86 @@ mod tests {
         // sig2 should be a replay
         assert!(cache.is_replay(sig2));
     }
-}
 
+    #[test]
+    fn test_max_entries_evicts_oldest() {
+        let cache = SignatureCache::with_max_entries(3);
+
+        for sig in ["0x01", "0x02", "0x03", "0x04", "0x05"] {
+            cache.add(sig);
+        }
+
+        assert_eq!(cache.size(), 3);
+
+        // The two oldest were evicted
+        assert!(!cache.is_replay("0x01"));
+        assert!(!cache.is_replay("0x02"));
+
+        // Recent signatures still detect replays
+        assert!(cache.is_replay("0x03"));
+        assert!(cache.is_replay("0x04"));
+        assert!(cache.is_replay("0x05"));
+    }
+
+    #[test]
+    fn test_full_shard_evicts_oldest_overall() {
+        let max_entries = 2 * MIN_SHARD_ENTRIES;
+        let cache = SignatureCache::with_max_entries(max_entries);
+        assert_eq!(cache.shards.len(), 2);
+
+        // The oldest signature lands in the other shard from everything after it
+        let oldest = "0xoldest";
+        cache.add(oldest);
+        thread::sleep(Duration::from_millis(1));
+        let crowded: Vec<String> = (0..)
+            .map(|i| format!("0x{:08x}", i))
+            .filter(|signature| cache.shard_index(signature) != cache.shard_index(oldest))
+            .take(max_entries)
+            .collect();
+
+        // Filling one shard past its share evicts nothing while the cache has room
+        for signature in &crowded[..max_entries - 1] {
+            cache.add(signature);
+        }
+        assert_eq!(cache.size(), max_entries);
+        assert!(crowded[..max_entries - 1].iter().all(|signature| cache.is_replay(signature)));
+
+        // Once full, the oldest overall goes, not the oldest in the crowded shard
+        cache.add(&crowded[max_entries - 1]);
+        assert_eq!(cache.size(), max_entries);
+        assert!(!cache.is_replay(oldest));
+        assert!(crowded.iter().all(|signature| cache.is_replay(signature)));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_concurrent_replay_detection() {
+        let cache = std::sync::Arc::new(SignatureCache::new());
+        assert!(cache.shards.len() > 1);
+
+        let tasks: Vec<_> = (0..64)
+            .map(|task| {
+                let cache = cache.clone();
+                tokio::spawn(async move {
+                    for i in 0..1_000 {
+                        let signature = format!("0x{:04x}{:04x}", task, i);
+                        assert!(!cache.is_replay(&signature));
+                        cache.add(&signature);
+                        assert!(cache.is_replay(&signature));
+
+                        // Every task also races on signatures shared between them
+                        cache.add(&format!("0xshared{:04x}", i));
+                    }
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(cache.size(), 64 * 1_000 + 1_000);
+        assert!(cache.is_replay("0x00000000"));
+        assert!(cache.is_replay("0xshared03e7"));
+        assert!(!cache.is_replay("0xffffffff"));
+    }
+}
@@ -1,11 +1,45 @@
-use crate::config::Config;
+use crate::asset::USDC;
+use crate::auth::{ApiKeyAuth, AuthScheme, ClientCertAuth, SignatureAuth};
+use crate::block_time::BlockClock;
+use crate::body_log::BodyLogger;
+use crate::budget::GlobalBudget;
+use crate::challenge::ChallengeStore;
+use crate::clock::{self, ClockMonitor};
+use crate::config::{AuthSchemeKind, Config};
 use crate::database::DatabaseTrait;
+use crate::finalized_cache::FinalizedCache;
+use crate::idempotency_cache::IdempotencyCache;
+use crate::ip_filter::IpFilter;
+use crate::metrics::{CountConnections, Metrics};
+use crate::paygate::{FacilitatorPaygate, Paygate};
+use crate::pricing::{PricingOracle, StablecoinOracle};
+use crate::requirements::PreparedRequirements;
+use crate::sanitizer::ErrorSanitizer;
+use crate::shutdown::BackgroundTasks;
 use crate::signature_cache::SignatureCache;
+use crate::upstream_payment::UpstreamPayer;
+use crate::webhook::BalanceAlerts;
 use reqwest::Client;
+use std::sync::atomic::{AtomicBool, AtomicU64};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::Semaphore;
 use x402_axum::facilitator_client::FacilitatorClient;
 
+/// Why application state couldn't be built from the configuration
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("Failed to build the node HTTP client: {0}")]
+    HttpClient(#[from] reqwest::Error),
+
+    #[error("Failed to create the facilitator client for {url}: {reason}")]
+    Facilitator { url: String, reason: String },
+
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+}
+
 /// Application state shared across handlers
 #[derive(Clone)]
 pub struct AppState {
@@ -19,40 +53,316 @@ pub struct AppState {
     pub database: Arc<dyn DatabaseTrait>,
 
     /// In-memory signature cache for replay attack prevention
-    pub signature_cache: Arc<Mutex<SignatureCache>>,
+    pub signature_cache: Arc<SignatureCache>,
+
+    /// Enabled authentication schemes, tried in configured order
+    pub auth_schemes: Arc<Vec<Box<dyn AuthScheme>>>,
+
+    /// Responses replayable by X-Idempotency-Key (None when idempotency is disabled)
+    pub idempotency_cache: Option<Arc<Mutex<IdempotencyCache>>>,
+
+    /// Responses to signed reads of replayable methods, keyed by their signed request (None when disabled)
+    pub replay_cache: Option<Arc<Mutex<IdempotencyCache>>>,
+
+    /// Challenges issued with 402s that deposits must redeem (None when not required)
+    pub payment_challenges: Option<Arc<Mutex<ChallengeStore>>>,
+
+    /// Responses for reads at finalized blocks (None when caching is disabled)
+    pub finalized_cache: Option<Arc<Mutex<FinalizedCache>>>,
+
+    /// Payment requirement fields parsed from the config at startup
+    pub prepared_requirements: PreparedRequirements,
+
+    /// Rewrites node error messages before they reach clients (None when disabled)
+    pub error_sanitizer: Option<ErrorSanitizer>,
+
+    /// Logs relayed bodies with sensitive parts redacted (None when disabled)
+    pub body_logger: Option<BodyLogger>,
+
+    /// Payment verification and settlement through the x402 facilitator (None when deposits are disabled)
+    pub paygate: Option<Arc<dyn Paygate>>,
+
+    /// Bounds concurrent settlement calls to the facilitator
+    pub settlement_permits: Arc<Semaphore>,
+
+    /// Pays nodes that answer with x402 payment requirements (None passes node 402s through)
+    pub upstream_payer: Option<Arc<UpstreamPayer>>,
+
+    /// Bounds relay requests in flight across all clients (None when unlimited)
+    pub relay_permits: Option<Arc<Semaphore>>,
+
+    /// Paid requests and spend counted against the gateway-wide budget (None when unlimited)
+    pub global_budget: Option<Arc<GlobalBudget>>,
+
+    /// Relay and node latency histograms
+    pub metrics: Arc<Metrics>,
+
+    /// Next gateway-unique JSON-RPC id (used when id rewriting is enabled)
+    pub next_jsonrpc_id: Arc<AtomicU64>,
+
+    /// Low balance webhook dispatcher (None when alerts are disabled)
+    pub balance_alerts: Option<BalanceAlerts>,
+
+    /// Address blocklist and rate limit applied before authentication (None when disabled)
+    pub ip_filter: Option<Arc<IpFilter>>,
+
+    /// Settlements, webhook deliveries and maintenance loops drained on shutdown
+    pub background_tasks: Arc<BackgroundTasks>,
+
+    /// Server time from the latest block seen (None uses the wall clock)
+    pub block_clock: Option<Arc<BlockClock>>,
+
+    /// Converts configured USD prices into USDC amounts (1:1 unless replaced)
+    pub pricing_oracle: Arc<dyn PricingOracle>,
+
+    /// Set while in maintenance: still serving, but reporting not ready so load balancers drain us
+    pub maintenance: Arc<AtomicBool>,
+}
+
+/// HTTP client with reasonable defaults for RPC relay
+/// With `node_http2`, https nodes are offered HTTP/2 via ALPN and fall back to HTTP/1.1 if they
+/// don't accept it. Plain http nodes always get HTTP/1.1: cleartext HTTP/2 would need prior
+/// knowledge, which HTTP/1.1-only nodes can't answer.
+/// Connections opened are counted in `metrics`, for sizing `node_pool_max_idle_per_host`
+fn node_client(config: &Config, metrics: Arc<Metrics>) -> Result<Client, reqwest::Error> {
+    let builder = Client::builder()
+        // Connection timeout for establishing connection to node
+        .connect_timeout(Duration::from_millis(config.node_connect_timeout_ms))
+        // Request timeout - some RPC calls can take longer
+        .timeout(Duration::from_millis(config.node_timeout_ms))
+        // Enable connection pooling for better performance
+        .pool_max_idle_per_host(config.node_pool_max_idle_per_host)
+        .connector_layer(CountConnections::layer(metrics));
+
+    let builder = if config.node_http2 {
+        builder
+            // Keep multiplexed connections alive through idle periods
+            .http2_keep_alive_interval(Duration::from_secs(30))
+            .http2_adaptive_window(true)
+    } else {
+        builder.http1_only()
+    };
 
-    /// X402 facilitator client for payment verification and settlement
-    pub facilitator: Arc<FacilitatorClient>,
+    builder.build()
 }
 
 impl AppState {
     /// Create new application state with configured HTTP client and database
-    pub fn new(config: Config, database: Arc<dyn DatabaseTrait>) -> Self {
-        // Configure HTTP client with reasonable defaults for RPC relay
-        let client = Client::builder()
-            // Connection timeout for establishing connection to node
-            .connect_timeout(Duration::from_secs(10))
-            // Request timeout - some RPC calls can take longer
-            .timeout(Duration::from_secs(30))
-            // Enable connection pooling for better performance
-            .pool_max_idle_per_host(10)
-            .build()
-            .expect("Failed to build HTTP client");
+    /// Errors if a client can't be built or the configuration is inconsistent
+    pub fn new(config: Config, database: Arc<dyn DatabaseTrait>) -> Result<Self, StateError> {
+        let metrics = Arc::new(Metrics::default());
+        let client = node_client(&config, metrics.clone())?;
 
         // Initialize signature cache
-        let signature_cache = SignatureCache::new();
+        let mut signature_cache = SignatureCache::with_max_entries(config.signature_cache_max_entries);
 
-        // Initialize X402 facilitator client
-        let facilitator = FacilitatorClient::try_from(config.facilitator_url.as_str())
-            .expect("Failed to create facilitator client");
+        // A widened timestamp window needs signatures remembered for longer
+        let clock_grace = config.clock_jump_grace_secs.map(Duration::from_secs);
+        if clock_grace.is_some() {
+            signature_cache.extend_ttl(Duration::from_secs(clock::MAX_WIDENING_SECS));
+        }
+        // ...as does chain time, which may run up to `max_skew_secs` from the wall clock
+        if let Some(block_time) = &config.block_time {
+            signature_cache.extend_ttl(Duration::from_secs(block_time.max_skew_secs));
+        }
+
+        let signature_cache = Arc::new(signature_cache);
+        // Detects wall clock jumps and widens the timestamp window after backward ones
+        let clock = Arc::new(Mutex::new(ClockMonitor::new(clock_grace)));
+        // Follows chain time if configured; refreshed in the background once serving
+        let block_clock = config.block_time.as_ref().map(|block_time| Arc::new(BlockClock::new(block_time.max_skew_secs)));
+
+        // Build the enabled authentication schemes
+        let auth_schemes = config
+            .auth_schemes
+            .iter()
+            .map(|scheme| -> Result<Box<dyn AuthScheme>, StateError> {
+                Ok(match scheme {
+                    AuthSchemeKind::Signature => Box::new(SignatureAuth::new(
+                        signature_cache.clone(),
+                        clock.clone(),
+                        block_clock.clone(),
+                        config.body_hash_algorithms.clone(),
+                        config.body_canonicalizations.clone(),
+                        config.max_delegation_secs,
+                    )),
+                    AuthSchemeKind::ApiKey => Box::new(ApiKeyAuth::new(&config.api_keys)),
+                    AuthSchemeKind::ClientCert => {
+                        let mtls = config
+                            .mtls
+                            .as_ref()
+                            .ok_or_else(|| StateError::Config("client_cert requires mtls".to_string()))?;
+                        Box::new(ClientCertAuth::new(&mtls.accounts))
+                    }
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Initialize idempotency cache if enabled
+        let idempotency_cache = config.idempotency_window_secs.map(|secs| {
+            Arc::new(Mutex::new(IdempotencyCache::new(Duration::from_secs(secs))))
+        });
+
+        // Remember responses to replayable reads for as long as their signatures are remembered
+        let replay_cache = (!config.replayable_methods.is_empty())
+            .then(|| Arc::new(Mutex::new(IdempotencyCache::new(signature_cache.ttl()))));
+
+        // Initialize payment challenges if deposits must echo one back
+        let payment_challenges = config.payment_challenge_ttl_secs.map(|secs| {
+            Arc::new(Mutex::new(ChallengeStore::new(Duration::from_secs(secs))))
+        });
+
+        // Initialize finalized-block response cache if enabled
+        let finalized_cache = config.finalized_cache.as_ref().map(|cache| {
+            Arc::new(Mutex::new(FinalizedCache::new(
+                cache.max_entries,
+                Duration::from_secs(cache.refresh_secs),
+                cache.ttl_secs.map(Duration::from_secs),
+            )))
+        });
+
+        // Compile error sanitizer (patterns were validated at config load)
+        let error_sanitizer = config
+            .error_sanitizer
+            .as_ref()
+            .map(|sanitizer| {
+                ErrorSanitizer::new(sanitizer)
+                    .map_err(|e| StateError::Config(format!("Invalid error sanitizer pattern: {}", e)))
+            })
+            .transpose()?;
+
+        let body_logger = config.body_logging.as_ref().map(BodyLogger::new);
+
+        // Parse payment requirement fields once (they were validated at config load)
+        let prepared_requirements = PreparedRequirements::new(&config)
+            .map_err(|e| StateError::Config(format!("Invalid payment requirements: {}", e)))?;
+
+        // Initialize X402 facilitator client if deposits are accepted
+        let paygate = config
+            .deposits_enabled
+            .then(|| -> Result<Arc<dyn Paygate>, StateError> {
+                let facilitator = FacilitatorClient::try_from(config.facilitator_url.as_str())
+                    .map_err(|e| StateError::Facilitator { url: config.facilitator_url.clone(), reason: e.to_string() })?;
+                let headers = config
+                    .facilitator_headers()
+                    .map_err(|_| StateError::Config("Invalid FACILITATOR_AUTH_TOKEN".to_string()))?;
+                Ok(Arc::new(FacilitatorPaygate::new(facilitator.with_headers(headers))))
+            })
+            .transpose()?;
+
+        // Build the IP filter (ranges were validated at config load)
+        let ip_filter = config
+            .ip_filter
+            .as_ref()
+            .map(|filter| {
+                IpFilter::new(filter)
+                    .map(Arc::new)
+                    .map_err(|e| StateError::Config(format!("Invalid ip_filter configuration: {}", e)))
+            })
+            .transpose()?;
+
+        let background_tasks = Arc::new(BackgroundTasks::new());
 
-        Self {
+        // Initialize low balance webhook dispatcher if configured
+        let balance_alerts = config
+            .balance_alert
+            .clone()
+            .map(|alert| BalanceAlerts::new(client.clone(), alert, background_tasks.clone()));
+
+        // Initialize upstream payer if nodes are paid via x402
+        let upstream_payer = config
+            .upstream_payment
+            .as_ref()
+            .map(|upstream| -> Result<Arc<UpstreamPayer>, StateError> {
+                let key = config
+                    .upstream_private_key
+                    .as_deref()
+                    .ok_or_else(|| StateError::Config("upstream_payment requires a signing key".to_string()))?;
+                let signer = key
+                    .parse()
+                    .map_err(|_| StateError::Config("Invalid upstream payment key".to_string()))?;
+                Ok(Arc::new(UpstreamPayer::new(signer, upstream.max_amount)))
+            })
+            .transpose()?;
+
+        let settlement_permits = Arc::new(Semaphore::new(config.max_concurrent_settlements));
+        let relay_permits = config.max_concurrent_relays.map(|max| Arc::new(Semaphore::new(max)));
+        let global_budget = config.global_budget.as_ref().map(|budget| Arc::new(GlobalBudget::new(budget)));
+        let maintenance = Arc::new(AtomicBool::new(config.maintenance));
+
+        Ok(Self {
             client,
             config,
             database,
-            signature_cache: Arc::new(Mutex::new(signature_cache)),
-            facilitator: Arc::new(facilitator),
-        }
+            signature_cache,
+            auth_schemes: Arc::new(auth_schemes),
+            idempotency_cache,
+            replay_cache,
+            payment_challenges,
+            finalized_cache,
+            prepared_requirements,
+            error_sanitizer,
+            body_logger,
+            paygate,
+            settlement_permits,
+            relay_permits,
+            global_budget,
+            upstream_payer,
+            metrics,
+            next_jsonrpc_id: Arc::new(AtomicU64::new(1)),
+            balance_alerts,
+            ip_filter,
+            background_tasks,
+            block_clock,
+            pricing_oracle: Arc::new(StablecoinOracle::new(USDC)),
+            maintenance,
+        })
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_client_relays_to_http1_node_in_both_modes() {
+        let node = spawn_echo_node(json!("0x1")).await;
+
+        for http2 in [false, true] {
+            let mut config = test_config(DEFAULT_RESOURCE);
+            config.node_http2 = http2;
+            let client = node_client(&config, Arc::new(Metrics::default())).unwrap();
+            let response = client
+                .post(&node)
+                .header("content-type", "application/json")
+                .body(rpc_call("eth_chainId", 1))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.version(), reqwest::Version::HTTP_11, "http2 = {}", http2);
+            let reply: serde_json::Value = response.json().await.unwrap();
+            assert_eq!(reply["result"], "0x1");
+        }
+    }
+
+    #[test]
+    fn test_inconsistent_config_is_an_error() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let database: Arc<dyn DatabaseTrait> = Arc::new(
+            crate::database::rocksdb::RocksDbDatabase::open(temp_dir.path().join("test.db").to_str().unwrap()).unwrap(),
+        );
+
+        let mut config = test_config(DEFAULT_RESOURCE);
+        config.auth_schemes = vec![AuthSchemeKind::ClientCert];
+        config.mtls = None;
+        let error = AppState::new(config, database.clone()).err().unwrap();
+        assert!(error.to_string().contains("client_cert requires mtls"), "{}", error);
+
+        let mut config = test_config(DEFAULT_RESOURCE);
+        config.deposits_enabled = true;
+        config.facilitator_url = "not a url".to_string();
+        assert!(matches!(AppState::new(config, database).err().unwrap(), StateError::Facilitator { .. }));
+    }
+}
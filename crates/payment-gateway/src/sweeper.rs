@@ -0,0 +1,89 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::SweeperConfig;
+use crate::database::{DatabaseError, DatabaseTrait};
+
+/// Delete accounts with an empty balance whose last request is older than `retention_secs`
+/// Returns the number of accounts pruned
+pub async fn sweep_stale_accounts(
+    database: &dyn DatabaseTrait,
+    retention_secs: u64,
+    now: u64,
+) -> Result<usize, DatabaseError> {
+    let stale_before = now.saturating_sub(retention_secs);
+    let mut pruned = 0;
+
+    for (address, user_data) in database.list_users().await? {
+        if !user_data.is_stale(stale_before) {
+            continue;
+        }
+
+        // delete_user re-checks staleness atomically, so an account that was topped up
+        // or used since the scan is kept
+        if database.delete_user(&address, stale_before).await? {
+            pruned += 1;
+        }
+    }
+
+    Ok(pruned)
+}
+
+/// Spawn the background task periodically pruning stale accounts until `shutdown` is cancelled
+pub fn spawn(
+    database: Arc<dyn DatabaseTrait>,
+    config: SweeperConfig,
+    shutdown: CancellationToken,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs));
+
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => return,
+                _ = interval.tick() => {}
+            }
+
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+
+            match sweep_stale_accounts(database.as_ref(), config.retention_secs, now).await {
+                Ok(pruned) => {
+                    tracing::info!(pruned = pruned, "Stale account sweep completed");
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, "Stale account sweep failed");
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::rocksdb::RocksDbDatabase;
+    use crate::database::UserData;
+
+    #[tokio::test]
+    async fn test_stale_zero_balance_account_pruned() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db_path = temp_dir.path().join("test.db");
+        let db = RocksDbDatabase::open(db_path.to_str().unwrap()).unwrap();
+
+        let now = 1_000_000;
+        let stale = "0x000000000000000000000000000000000000000a";
+        let active = "0x000000000000000000000000000000000000000b";
+        db.update_user(stale, UserData::new(0.0, now - 10_000)).await.unwrap();
+        db.update_user(active, UserData::new(0.0, now - 10)).await.unwrap();
+
+        let pruned = sweep_stale_accounts(&db, 3_600, now).await.unwrap();
+        assert_eq!(pruned, 1);
+
+        assert!(db.get_user(stale).await.unwrap().is_none());
+        assert!(db.get_user(active).await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,201 @@
+//! Shared helpers for handler and routing tests
+
+use alloy::signers::{local::PrivateKeySigner, SignerSync};
+use axum::{
+    body::{Body, Bytes},
+    http::{Request, StatusCode},
+    routing::post,
+    Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use crate::config::Config;
+use crate::database::dynamodb::DynamoDbDatabase;
+use crate::database::rocksdb::RocksDbDatabase;
+use crate::state::AppState;
+
+pub const PAYMENT_ADDRESS: &str = "0x1234567890abcdef1234567890abcdef12345678";
+
+/// Settings every test config needs, with tables appended by callers
+const BASE_CONFIG: &str = r#"
+port = 3000
+facilitator_url = "https://x402.org/facilitator"
+database_path = "unused"
+database_type = "rocksdb"
+"#;
+
+/// Single relay resource pointing at an unused node
+pub const DEFAULT_RESOURCE: &str = r#"
+node_url = "http://127.0.0.1:1"
+price_per_request = 0.001
+"#;
+
+/// Build a config from the base settings plus extra TOML
+pub fn test_config(extra: &str) -> Config {
+    Config::from_toml_str(&format!("{}\n{}", BASE_CONFIG, extra), PAYMENT_ADDRESS).unwrap()
+}
+
+/// Create application state backed by a temporary RocksDB database
+pub fn test_state(config: Config) -> (Arc<AppState>, tempfile::TempDir) {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("test.db");
+    let db = RocksDbDatabase::open(db_path.to_str().unwrap()).unwrap();
+    (Arc::new(AppState::new(config, Arc::new(db)).unwrap()), temp_dir)
+}
+
+/// A DynamoDB database whose endpoint refuses connections
+pub fn unreachable_dynamodb() -> DynamoDbDatabase {
+    use aws_sdk_dynamodb::config::{BehaviorVersion, Credentials, Region};
+
+    let config = aws_sdk_dynamodb::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+        .endpoint_url("http://127.0.0.1:1")
+        .build();
+    DynamoDbDatabase::from_client(
+        aws_sdk_dynamodb::Client::from_conf(config),
+        "balances".to_string(),
+        "balances-audit".to_string(),
+        "balances-spend".to_string(),
+    )
+}
+
+/// Serve `app` on an ephemeral local port and return its base URL
+pub async fn spawn_server(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    format!("http://{}", addr)
+}
+
+/// Answer every JSON-RPC call (single or batch) with `result`, echoing the call's id
+pub fn echo_node(result: Value) -> Router {
+    Router::new().route(
+        "/",
+        post(move |body: Bytes| {
+            let result = result.clone();
+            async move {
+                let reply = |call: &Value| {
+                    json!({"jsonrpc": "2.0", "id": call.get("id").cloned().unwrap_or(Value::Null), "result": result})
+                };
+                let request: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+                let response = match &request {
+                    Value::Array(calls) => Value::Array(calls.iter().map(reply).collect()),
+                    call => reply(call),
+                };
+                axum::Json(response)
+            }
+        }),
+    )
+}
+
+/// Spawn a mock node answering every call with `result`
+pub async fn spawn_echo_node(result: Value) -> String {
+    spawn_server(echo_node(result)).await
+}
+
+/// Spawn a mock node answering every request with the same JSON body
+pub async fn spawn_fixed_node(response: Value) -> String {
+    spawn_server(Router::new().route(
+        "/",
+        post(move || {
+            let response = response.clone();
+            async move { axum::Json(response) }
+        }),
+    ))
+    .await
+}
+
+/// Current unix time in seconds
+pub fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Sign a request the way payment-transport does, returning the signature string
+pub fn sign(signer: &PrivateKeySigner, timestamp: u64, method: &str, path: &str, body: &[u8]) -> String {
+    sign_as(signer, &signer.address().to_string(), timestamp, method, path, body)
+}
+
+/// Like `sign`, but over the address exactly as it will appear in X-Auth-Address
+pub fn sign_as(signer: &PrivateKeySigner, address: &str, timestamp: u64, method: &str, path: &str, body: &[u8]) -> String {
+    let body_hash = alloy::primitives::keccak256(body);
+    let message = format!("{}{}{}{}{}", address, timestamp, method, path, hex::encode(body_hash));
+    let message_hash = alloy::primitives::keccak256(message.as_bytes());
+    signer.sign_hash_sync(&message_hash).unwrap().to_string()
+}
+
+/// Build an authenticated POST request to `uri`
+pub fn signed_request(uri: &str, signer: &PrivateKeySigner, body: &str) -> Request<Body> {
+    signed_request_at(uri, signer, body, now_secs())
+}
+
+/// Build an authenticated POST request to `uri` signed at `timestamp`
+pub fn signed_request_at(uri: &str, signer: &PrivateKeySigner, body: &str, timestamp: u64) -> Request<Body> {
+    signed_request_as(uri, signer, &signer.address().to_string(), body, timestamp)
+}
+
+/// Build an authenticated POST request sending `address` verbatim in X-Auth-Address
+pub fn signed_request_as(uri: &str, signer: &PrivateKeySigner, address: &str, body: &str, timestamp: u64) -> Request<Body> {
+    let path = uri.split('?').next().unwrap_or(uri);
+    let signature = sign_as(signer, address, timestamp, "POST", path, body.as_bytes());
+    Request::post(uri)
+        .header("content-type", "application/json")
+        .header("X-Auth-Address", address)
+        .header("X-Auth-Signature", signature)
+        .header("X-Auth-Timestamp", timestamp.to_string())
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// Send a request through the router and collect the response
+pub async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Bytes) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, body)
+}
+
+/// A JSON-RPC call body with the given id
+pub fn rpc_call(method: &str, id: u64) -> String {
+    json!({"jsonrpc": "2.0", "method": method, "params": [], "id": id}).to_string()
+}
+
+/// Log output collected by `capture_logs`
+#[derive(Clone, Default)]
+pub struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Capture log output on this thread until the guard is dropped
+pub fn capture_logs() -> (CapturedLogs, tracing::subscriber::DefaultGuard) {
+    let logs = CapturedLogs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .finish();
+    (logs, tracing::subscriber::set_default(subscriber))
+}
@@ -0,0 +1,240 @@
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::MtlsConfig;
+
+/// Handshakes still running after this are dropped so slow clients can't hold connections open
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Completed handshakes waiting to be served
+const ACCEPT_QUEUE: usize = 128;
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("Failed to read {path}: {reason}")]
+    Pem { path: String, reason: String },
+
+    #[error("Invalid TLS configuration: {0}")]
+    Config(String),
+}
+
+/// Remote end of a TLS connection
+#[derive(Debug, Clone)]
+pub struct TlsPeer {
+    pub addr: SocketAddr,
+
+    /// Common name of the verified client certificate (None if the client sent none)
+    pub client_subject: Option<String>,
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for TlsPeer {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        stream.remote_addr().clone()
+    }
+}
+
+/// TLS listener that optionally verifies client certificates
+/// Handshakes run on their own tasks, so a slow client doesn't hold up accepting others
+pub struct TlsListener {
+    local_addr: SocketAddr,
+    handshakes: mpsc::Receiver<(TlsStream<TcpStream>, TlsPeer)>,
+}
+
+impl TlsListener {
+    pub fn new(tcp: TcpListener, config: &MtlsConfig) -> Result<Self, TlsError> {
+        let local_addr = tcp.local_addr().map_err(|e| TlsError::Config(e.to_string()))?;
+        let acceptor = TlsAcceptor::from(Arc::new(server_config(config)?));
+        let (sender, handshakes) = mpsc::channel(ACCEPT_QUEUE);
+
+        let mut tcp = tcp;
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = Listener::accept(&mut tcp).await;
+                let acceptor = acceptor.clone();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let client_subject = stream
+                                .get_ref()
+                                .1
+                                .peer_certificates()
+                                .and_then(|certs| certs.first())
+                                .and_then(|cert| common_name(cert));
+                            let _ = sender.send((stream, TlsPeer { addr, client_subject })).await;
+                        }
+                        Ok(Err(e)) => tracing::debug!(peer = %addr, error = %e, "TLS handshake failed"),
+                        Err(_) => tracing::debug!(peer = %addr, "TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+
+        Ok(Self { local_addr, handshakes })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = TlsPeer;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        self.handshakes
+            .recv()
+            .await
+            .expect("TLS accept task never stops")
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(TlsPeer {
+            addr: self.local_addr,
+            client_subject: None,
+        })
+    }
+}
+
+/// Server config presenting the gateway certificate and verifying client certificates
+/// against the configured CA. Clients without a certificate are still accepted so
+/// other authentication schemes keep working over the same listener.
+fn server_config(config: &MtlsConfig) -> Result<ServerConfig, TlsError> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(&config.client_ca_path)? {
+        roots.add(cert).map_err(|e| TlsError::Config(e.to_string()))?;
+    }
+    let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .allow_unauthenticated()
+        .build()
+        .map_err(|e| TlsError::Config(e.to_string()))?;
+
+    let key = PrivateKeyDer::from_pem_file(&config.key_path).map_err(|e| TlsError::Pem {
+        path: config.key_path.clone(),
+        reason: e.to_string(),
+    })?;
+
+    ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| TlsError::Config(e.to_string()))?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(read_certs(&config.cert_path)?, key)
+        .map_err(|e| TlsError::Config(e.to_string()))
+}
+
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let pem_error = |reason: String| TlsError::Pem { path: path.to_string(), reason };
+    let certs = CertificateDer::pem_file_iter(path)
+        .map_err(|e| pem_error(e.to_string()))?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| pem_error(e.to_string()))?;
+
+    if certs.is_empty() {
+        return Err(pem_error("no certificates found".to_string()));
+    }
+    Ok(certs)
+}
+
+/// Subject common name of a DER certificate
+fn common_name(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert).ok()?;
+    let common_name = cert.subject().iter_common_name().next()?;
+    common_name.as_str().ok().map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use alloy::signers::local::PrivateKeySigner;
+    use serde_json::json;
+
+    // Certificates in testdata/mtls were generated with openssl: a test CA issued the
+    // server certificate (localhost, 127.0.0.1) and the "billing-service" client
+    // certificate; rogue-client.pem has the same name but comes from an untrusted CA
+    fn testdata(file: &str) -> String {
+        format!("{}/testdata/mtls/{}", env!("CARGO_MANIFEST_DIR"), file)
+    }
+
+    fn read(file: &str) -> Vec<u8> {
+        std::fs::read(testdata(file)).unwrap()
+    }
+
+    /// Serve the router over mutual TLS, returning its https base URL
+    async fn spawn_tls_gateway(state: Arc<crate::state::AppState>) -> String {
+        let config = state.config.mtls.clone().unwrap();
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener = TlsListener::new(tcp, &config).unwrap();
+        let port = listener.local_addr().unwrap().addr.port();
+        let app = crate::build_router(state).into_make_service_with_connect_info::<TlsPeer>();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("https://127.0.0.1:{}", port)
+    }
+
+    fn client(identity: Option<(&str, &str)>) -> reqwest::Client {
+        let mut builder = reqwest::Client::builder()
+            .use_rustls_tls()
+            .add_root_certificate(reqwest::Certificate::from_pem(&read("ca.pem")).unwrap());
+        if let Some((cert, key)) = identity {
+            let pem = [read(cert), read(key)].concat();
+            builder = builder.identity(reqwest::Identity::from_pem(&pem).unwrap());
+        }
+        builder.build().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_authenticates_mapped_account() {
+        let node = spawn_echo_node(json!("0x1")).await;
+        let service = PrivateKeySigner::random().address().to_string();
+        let config = test_config(&format!(
+            r#"
+            node_url = "{}"
+            price_per_request = 0.5
+            auth_schemes = ["client_cert", "signature"]
+
+            [mtls]
+            cert_path = "{}"
+            key_path = "{}"
+            client_ca_path = "{}"
+
+            [mtls.accounts]
+            "billing-service" = "{}"
+            "#,
+            node,
+            testdata("server.pem"),
+            testdata("server-key.pem"),
+            testdata("ca.pem"),
+            service
+        ));
+        let (state, _dir) = test_state(config);
+        state.database.add_balance(&service, 10.0).await.unwrap();
+        let url = format!("{}/relay", spawn_tls_gateway(state.clone()).await);
+        let body = rpc_call("eth_chainId", 1);
+
+        let trusted = client(Some(("client.pem", "client-key.pem")));
+        let response = trusted.post(&url).body(body.clone()).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(state.database.get_user(&service).await.unwrap().unwrap().balance, 9.5);
+
+        // Without a certificate the client is asked to pay like any unauthenticated caller
+        let anonymous = client(None);
+        let response = anonymous.post(&url).body(body.clone()).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::PAYMENT_REQUIRED);
+
+        // A certificate from another CA fails the handshake
+        let rogue = client(Some(("rogue-client.pem", "rogue-client-key.pem")));
+        assert!(rogue.post(&url).body(body).send().await.is_err());
+        assert_eq!(state.database.get_user(&service).await.unwrap().unwrap().balance, 9.5);
+    }
+}
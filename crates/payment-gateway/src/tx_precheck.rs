@@ -0,0 +1,100 @@
+use alloy::consensus::{Transaction, TxEnvelope};
+use alloy::eips::eip2718::Decodable2718;
+use alloy::rpc::types::TransactionRequest;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+/// Most raw transactions pre-checked per request; any beyond are left to the node
+pub const MAX_PRECHECKED_TRANSACTIONS: usize = 10;
+
+/// What the node answered to a pre-check call
+enum Reply {
+    Result(Value),
+    Error(String),
+}
+
+/// Call `method` on the node; None if it couldn't be asked or answered nonsense
+async fn call(client: &Client, node_url: &str, method: &str, params: Value) -> Option<Reply> {
+    let request = json!({"jsonrpc": "2.0", "method": method, "params": params, "id": 1});
+    let response: Value = match client.post(node_url).json(&request).send().await {
+        Ok(response) => response.json().await.ok()?,
+        Err(e) => {
+            tracing::warn!(method, error = %e, "Transaction pre-check call failed");
+            return None;
+        }
+    };
+
+    if let Some(error) = response.get("error") {
+        let message = error.get("message").and_then(Value::as_str).unwrap_or("unknown error");
+        return Some(Reply::Error(message.to_string()));
+    }
+    response.get("result").cloned().map(Reply::Result)
+}
+
+fn parse_quantity(value: &Value) -> Option<u128> {
+    u128::from_str_radix(value.as_str()?.strip_prefix("0x")?, 16).ok()
+}
+
+/// Check that a raw transaction sent with eth_sendRawTransaction isn't obviously doomed:
+/// it must decode and recover a sender, and the node must not see a stale nonce, a fee
+/// below the base fee or an execution failure. Returns why it would fail
+/// Execution is only estimated for the sender's next pending nonce: a later one may depend on
+/// transactions before it (e.g. earlier in the same batch) that the latest state doesn't have yet.
+/// Checks the node can't answer are skipped, leaving the decision to the node itself
+pub async fn precheck(client: &Client, node_url: &str, raw: &str) -> Result<(), String> {
+    let bytes = raw
+        .strip_prefix("0x")
+        .and_then(|hex| hex::decode(hex).ok())
+        .ok_or_else(|| "raw transaction is not 0x-prefixed hex".to_string())?;
+    let envelope = TxEnvelope::decode_2718_exact(&bytes)
+        .map_err(|e| format!("raw transaction could not be decoded: {}", e))?;
+    let sender = envelope
+        .signature()
+        .recover_address_from_prehash(&envelope.signature_hash())
+        .map_err(|_| "raw transaction signature is invalid".to_string())?;
+
+    let next_nonce = match call(client, node_url, "eth_getTransactionCount", json!([sender, "pending"])).await {
+        Some(Reply::Result(count)) => parse_quantity(&count),
+        _ => None,
+    };
+    if let Some(next_nonce) = next_nonce.filter(|next_nonce| u128::from(envelope.nonce()) < *next_nonce) {
+        return Err(format!("nonce {} is too low, the sender's next nonce is {}", envelope.nonce(), next_nonce));
+    }
+
+    let latest = call(client, node_url, "eth_getBlockByNumber", json!(["latest", false])).await;
+    if let Some(Reply::Result(block)) = latest {
+        let base_fee = block.get("baseFeePerGas").and_then(parse_quantity);
+        if let Some(base_fee) = base_fee.filter(|base_fee| envelope.max_fee_per_gas() < *base_fee) {
+            return Err(format!(
+                "max fee per gas {} is below the current base fee {}",
+                envelope.max_fee_per_gas(),
+                base_fee
+            ));
+        }
+    }
+
+    if next_nonce != Some(u128::from(envelope.nonce())) {
+        return Ok(());
+    }
+
+    // Estimating with the transaction's own gas limit also fails when that limit is too low
+    let request = TransactionRequest::from_transaction_with_sender(envelope, sender);
+    if let Some(Reply::Error(message)) = call(client, node_url, "eth_estimateGas", json!([request])).await {
+        return Err(format!("transaction would fail: {}", message));
+    }
+
+    Ok(())
+}
+
+/// Raw transactions sent by a JSON-RPC request or batch
+pub fn raw_transactions(request: &Value) -> Vec<&str> {
+    let calls = match request.as_array() {
+        Some(calls) => calls.iter().collect(),
+        None => vec![request],
+    };
+    calls
+        .into_iter()
+        .filter(|call| call.get("method").and_then(Value::as_str) == Some("eth_sendRawTransaction"))
+        .filter_map(|call| call.get("params")?.get(0)?.as_str())
+        .collect()
+}
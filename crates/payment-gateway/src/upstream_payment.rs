@@ -0,0 +1,255 @@
+use alloy::primitives::{keccak256, Address, B256, U256};
+use alloy::signers::local::PrivateKeySigner;
+use alloy::signers::SignerSync;
+use alloy::sol;
+use alloy::sol_types::{eip712_domain, Eip712Domain, SolStruct};
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::json;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::asset::USDC;
+
+/// How far back a payment's validity starts, tolerating clock skew with the facilitator
+const VALID_AFTER_SKEW_SECS: u64 = 600;
+
+sol! {
+    /// ERC-3009 transfer authorization signed by the payer
+    struct TransferWithAuthorization {
+        address from;
+        address to;
+        uint256 value;
+        uint256 validAfter;
+        uint256 validBefore;
+        bytes32 nonce;
+    }
+}
+
+/// Body of a node's 402 response
+#[derive(Debug, Deserialize)]
+struct PaymentRequired {
+    /// Parsed one by one so an unfamiliar kind of requirement doesn't hide the others
+    accepts: Vec<serde_json::Value>,
+}
+
+/// One way a node accepts payment
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Requirement {
+    scheme: String,
+    network: String,
+    max_amount_required: String,
+    pay_to: String,
+    asset: String,
+    max_timeout_seconds: u64,
+    #[serde(default)]
+    extra: Option<serde_json::Value>,
+}
+
+/// Pays upstream nodes that answer with x402 payment requirements
+pub struct UpstreamPayer {
+    signer: PrivateKeySigner,
+    /// Most USDC paid for a single 402
+    max_amount: f64,
+}
+
+impl UpstreamPayer {
+    pub fn new(signer: PrivateKeySigner, max_amount: f64) -> Self {
+        Self { signer, max_amount }
+    }
+
+    /// X-PAYMENT header value paying the first requirement in a 402 body that this payer can meet:
+    /// the exact scheme, in USDC, on a known chain, for no more than `max_amount`
+    pub fn payment_header(&self, payment_required: &[u8], now: u64) -> Result<String, String> {
+        let payment_required: PaymentRequired = serde_json::from_slice(payment_required)
+            .map_err(|e| format!("invalid payment requirements: {}", e))?;
+
+        let mut rejections = Vec::new();
+        for requirement in payment_required.accepts {
+            let paid = serde_json::from_value::<Requirement>(requirement)
+                .map_err(|e| format!("invalid requirement: {}", e))
+                .and_then(|requirement| self.pay(&requirement, now));
+            match paid {
+                Ok(payment) => return Ok(payment),
+                Err(e) => rejections.push(e),
+            }
+        }
+
+        Err(if rejections.is_empty() {
+            "node offered no payment requirements".to_string()
+        } else {
+            rejections.join("; ")
+        })
+    }
+
+    fn pay(&self, requirement: &Requirement, now: u64) -> Result<String, String> {
+        if requirement.scheme != "exact" {
+            return Err(format!("unsupported scheme '{}'", requirement.scheme));
+        }
+        let chain_id = chain_id(&requirement.network)
+            .ok_or_else(|| format!("unsupported network '{}'", requirement.network))?;
+        if !requirement.asset.eq_ignore_ascii_case(USDC.address) {
+            return Err(format!("unsupported asset {}", requirement.asset));
+        }
+
+        let value: u128 = requirement
+            .max_amount_required
+            .parse()
+            .map_err(|_| format!("invalid amount '{}'", requirement.max_amount_required))?;
+        if USDC.to_tokens(value) > self.max_amount {
+            return Err(format!(
+                "{} USDC exceeds the upstream payment limit of {}",
+                USDC.to_tokens(value),
+                self.max_amount
+            ));
+        }
+
+        let asset = Address::from_str(&requirement.asset).map_err(|e| e.to_string())?;
+        let pay_to = Address::from_str(&requirement.pay_to)
+            .map_err(|e| format!("invalid payTo '{}': {}", requirement.pay_to, e))?;
+
+        let authorization = TransferWithAuthorization {
+            from: self.signer.address(),
+            to: pay_to,
+            value: U256::from(value),
+            validAfter: U256::from(now.saturating_sub(VALID_AFTER_SKEW_SECS)),
+            validBefore: U256::from(now + requirement.max_timeout_seconds),
+            nonce: unique_nonce(),
+        };
+        let extra = requirement.extra.as_ref();
+        let name = extra.and_then(|extra| extra["name"].as_str()).unwrap_or(USDC.symbol);
+        let version = extra.and_then(|extra| extra["version"].as_str()).unwrap_or("2");
+        let domain = token_domain(name, version, chain_id, asset);
+
+        let signature = self
+            .signer
+            .sign_hash_sync(&authorization.eip712_signing_hash(&domain))
+            .map_err(|e| e.to_string())?;
+
+        let payment = json!({
+            "x402Version": 1,
+            "scheme": requirement.scheme,
+            "network": requirement.network,
+            "payload": {
+                "signature": signature.to_string(),
+                "authorization": {
+                    "from": authorization.from.to_string(),
+                    "to": authorization.to.to_string(),
+                    "value": authorization.value.to_string(),
+                    "validAfter": authorization.validAfter.to_string(),
+                    "validBefore": authorization.validBefore.to_string(),
+                    "nonce": authorization.nonce.to_string(),
+                },
+            },
+        });
+
+        Ok(base64::engine::general_purpose::STANDARD.encode(payment.to_string()))
+    }
+}
+
+/// Authorization nonce; ERC-3009 only needs it unused by this payer, not secret
+fn unique_nonce() -> B256 {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let seed = format!("{}:{}:{}", nanos, std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+    keccak256(seed.as_bytes())
+}
+
+/// EIP-712 domain of the token contract the authorization is redeemed against
+fn token_domain(name: &str, version: &str, chain_id: u64, token: Address) -> Eip712Domain {
+    eip712_domain! {
+        name: name.to_string(),
+        version: version.to_string(),
+        chain_id: chain_id,
+        verifying_contract: token,
+    }
+}
+
+/// Chain id of an x402 network name
+fn chain_id(network: &str) -> Option<u64> {
+    match network {
+        "base-sepolia" => Some(84532),
+        "base" => Some(8453),
+        "avalanche-fuji" => Some(43113),
+        "avalanche" => Some(43114),
+        "polygon" => Some(137),
+        "polygon-amoy" => Some(80002),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Decode an X-PAYMENT header and return the address that signed its authorization
+    pub fn payment_signer(header: &str, requirement: &serde_json::Value) -> (serde_json::Value, Address) {
+        let payment: serde_json::Value =
+            serde_json::from_slice(&base64::engine::general_purpose::STANDARD.decode(header).unwrap()).unwrap();
+        let fields = &payment["payload"]["authorization"];
+        let field = |name: &str| U256::from_str(fields[name].as_str().unwrap()).unwrap();
+        let authorization = TransferWithAuthorization {
+            from: Address::from_str(fields["from"].as_str().unwrap()).unwrap(),
+            to: Address::from_str(fields["to"].as_str().unwrap()).unwrap(),
+            value: field("value"),
+            validAfter: field("validAfter"),
+            validBefore: field("validBefore"),
+            nonce: B256::from_str(fields["nonce"].as_str().unwrap()).unwrap(),
+        };
+        let domain = token_domain(
+            requirement["extra"]["name"].as_str().unwrap(),
+            requirement["extra"]["version"].as_str().unwrap(),
+            chain_id(requirement["network"].as_str().unwrap()).unwrap(),
+            Address::from_str(requirement["asset"].as_str().unwrap()).unwrap(),
+        );
+        let signature = alloy::primitives::Signature::from_str(payment["payload"]["signature"].as_str().unwrap()).unwrap();
+        let signer = signature
+            .recover_address_from_prehash(&authorization.eip712_signing_hash(&domain))
+            .unwrap();
+        (payment, signer)
+    }
+
+    /// Requirements a paid node might answer with
+    pub fn requirement(amount: u64) -> serde_json::Value {
+        json!({
+            "scheme": "exact",
+            "network": "base-sepolia",
+            "maxAmountRequired": amount.to_string(),
+            "resource": "https://node.example/rpc",
+            "description": "RPC access",
+            "mimeType": "application/json",
+            "payTo": "0x00000000000000000000000000000000000000AA",
+            "maxTimeoutSeconds": 60,
+            "asset": USDC.address,
+            "extra": {"name": "USDC", "version": "2"},
+        })
+    }
+
+    #[test]
+    fn test_payment_signed_within_limit() {
+        let signer = PrivateKeySigner::random();
+        let payer = UpstreamPayer::new(signer.clone(), 0.01);
+        let now = 1_700_000_000;
+
+        let unsupported = json!({"scheme": "upto", "network": "base-sepolia"});
+        let body = json!({"x402Version": 1, "error": "payment required", "accepts": [unsupported, requirement(5_000)]});
+        let header = payer.payment_header(body.to_string().as_bytes(), now).unwrap();
+
+        let (payment, recovered) = payment_signer(&header, &requirement(5_000));
+        assert_eq!(recovered, signer.address());
+        let authorization = &payment["payload"]["authorization"];
+        assert_eq!(authorization["value"], "5000");
+        assert_eq!(authorization["to"].as_str().unwrap().to_lowercase(), "0x00000000000000000000000000000000000000aa");
+        assert_eq!(authorization["validBefore"], (now + 60).to_string());
+
+        // Over the limit: 0.02 USDC
+        let body = json!({"accepts": [requirement(20_000)]});
+        let error = payer.payment_header(body.to_string().as_bytes(), now).unwrap_err();
+        assert!(error.contains("exceeds the upstream payment limit"), "{}", error);
+    }
+}
@@ -0,0 +1,229 @@
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::BalanceAlertConfig;
+use crate::shutdown::BackgroundTasks;
+
+/// Delay before the first redelivery, doubled on each further attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Posts low_balance events to the configured webhook without blocking requests
+#[derive(Clone)]
+pub struct BalanceAlerts {
+    client: Client,
+    config: BalanceAlertConfig,
+    /// Deliveries in flight are finished before shutdown
+    tasks: Arc<BackgroundTasks>,
+    /// When each account was last alerted, while its cooldown lasts
+    last_alerted: Arc<Mutex<HashMap<String, Instant>>>,
+    /// Alerts waiting for the next batch delivery
+    batch: Arc<Mutex<Vec<Value>>>,
+}
+
+impl BalanceAlerts {
+    pub fn new(client: Client, config: BalanceAlertConfig, tasks: Arc<BackgroundTasks>) -> Self {
+        Self {
+            client,
+            config,
+            tasks,
+            last_alerted: Arc::default(),
+            batch: Arc::default(),
+        }
+    }
+
+    /// Report a deduction of `amount` that left `balance`; only the deduction that
+    /// crosses below the threshold fires an event, so an account isn't alerted per request,
+    /// and then only once per cooldown
+    pub fn observe_deduction(&self, address: &str, amount: f64, balance: f64) {
+        let threshold = self.config.threshold;
+        if balance >= threshold || balance + amount < threshold {
+            return;
+        }
+
+        let address = address.to_lowercase();
+        if !self.start_cooldown(&address) {
+            tracing::debug!(address = %address, balance, "Low balance alert suppressed during cooldown");
+            return;
+        }
+
+        let Some(interval_ms) = self.config.batch_interval_ms else {
+            let alerts = self.clone();
+            let event = json!({
+                "event": "low_balance",
+                "address": address,
+                "balance": balance,
+            });
+            self.tasks.track(tokio::spawn(async move { alerts.deliver(&event).await }));
+            return;
+        };
+
+        // The first alert of a batch schedules its delivery; later ones join it
+        let mut batch = self.batch.lock().unwrap();
+        batch.push(json!({"address": address, "balance": balance}));
+        if batch.len() == 1 {
+            let alerts = self.clone();
+            self.tasks.track(tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(interval_ms)).await;
+                let batch = std::mem::take(&mut *alerts.batch.lock().unwrap());
+                alerts.deliver(&json!({"event": "low_balance_batch", "alerts": batch})).await
+            }));
+        }
+    }
+
+    /// Whether `address` may be alerted now, starting its cooldown if so
+    fn start_cooldown(&self, address: &str) -> bool {
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        if cooldown.is_zero() {
+            return true;
+        }
+
+        let now = Instant::now();
+        let mut last_alerted = self.last_alerted.lock().unwrap();
+        last_alerted.retain(|_, alerted_at| now.duration_since(*alerted_at) < cooldown);
+        if last_alerted.contains_key(address) {
+            return false;
+        }
+        last_alerted.insert(address.to_string(), now);
+        true
+    }
+
+    /// POST the event, retrying with backoff until it's accepted or attempts run out
+    async fn deliver(&self, event: &Value) {
+        let mut delay = RETRY_BASE_DELAY;
+        for attempt in 1..=self.config.max_attempts {
+            let result = self.client.post(&self.config.webhook_url).json(event).send().await;
+            let error = match result {
+                Ok(response) if response.status().is_success() => return,
+                Ok(response) => format!("webhook returned {}", response.status()),
+                Err(e) => e.to_string(),
+            };
+
+            tracing::warn!(event = %event["event"], attempt, error = %error, "Low balance webhook delivery failed");
+            if attempt < self.config.max_attempts {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+
+        tracing::error!(event = %event, "Dropping low balance event after retries");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use axum::{extract::State, http::StatusCode, routing::post, Json, Router};
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+
+    /// Events received so far and delivery attempts seen
+    type ReceiverState = (Arc<Mutex<Vec<Value>>>, Arc<Mutex<usize>>);
+
+    /// Webhook receiver recording events, failing the first `failures` deliveries
+    async fn spawn_receiver(failures: usize) -> (String, Arc<Mutex<Vec<Value>>>) {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let attempts = Arc::new(Mutex::new(0));
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(
+                    move |State((received, attempts)): State<ReceiverState>,
+                     Json(event): Json<Value>| async move {
+                        let mut attempts = attempts.lock().unwrap();
+                        *attempts += 1;
+                        if *attempts <= failures {
+                            return StatusCode::SERVICE_UNAVAILABLE;
+                        }
+                        received.lock().unwrap().push(event);
+                        StatusCode::OK
+                    },
+                ),
+            )
+            .with_state((received.clone(), attempts));
+        (format!("{}/hook", spawn_server(app).await), received)
+    }
+
+    fn alerts(webhook_url: String) -> BalanceAlerts {
+        alerts_with(webhook_url, 0, None)
+    }
+
+    fn alerts_with(webhook_url: String, cooldown_secs: u64, batch_interval_ms: Option<u64>) -> BalanceAlerts {
+        BalanceAlerts::new(
+            Client::new(),
+            BalanceAlertConfig { webhook_url, threshold: 0.5, max_attempts: 3, cooldown_secs, batch_interval_ms },
+            Arc::new(BackgroundTasks::new()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_event_fires_once_when_crossing_threshold() {
+        let (url, received) = spawn_receiver(0).await;
+        let alerts = alerts(url);
+
+        alerts.observe_deduction("0xABC", 0.25, 0.75);
+        alerts.observe_deduction("0xABC", 0.25, 0.5);
+        alerts.observe_deduction("0xABC", 0.25, 0.25);
+        alerts.observe_deduction("0xABC", 0.25, 0.0);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(*received, vec![json!({"event": "low_balance", "address": "0xabc", "balance": 0.25})]);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_crossings_alerted_once_per_cooldown() {
+        let (url, received) = spawn_receiver(0).await;
+        let alerts = alerts_with(url, 60, None);
+
+        // Topped up and drained again and again within the cooldown
+        for _ in 0..3 {
+            alerts.observe_deduction("0xabc", 0.25, 0.4);
+        }
+        alerts.observe_deduction("0xdef", 0.25, 0.3);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // Each account is alerted once, in whichever order the deliveries land
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert!(received.contains(&json!({"event": "low_balance", "address": "0xabc", "balance": 0.4})));
+        assert!(received.contains(&json!({"event": "low_balance", "address": "0xdef", "balance": 0.3})));
+    }
+
+    #[tokio::test]
+    async fn test_alerts_batched_per_interval() {
+        let (url, received) = spawn_receiver(0).await;
+        let alerts = alerts_with(url, 0, Some(100));
+
+        alerts.observe_deduction("0xabc", 0.25, 0.4);
+        alerts.observe_deduction("0xdef", 0.25, 0.3);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        alerts.observe_deduction("0xabc", 0.25, 0.35);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let received = received.lock().unwrap();
+        assert_eq!(
+            *received,
+            vec![
+                json!({"event": "low_balance_batch", "alerts": [
+                    {"address": "0xabc", "balance": 0.4},
+                    {"address": "0xdef", "balance": 0.3},
+                ]}),
+                json!({"event": "low_balance_batch", "alerts": [{"address": "0xabc", "balance": 0.35}]}),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_delivery_is_retried() {
+        let (url, received) = spawn_receiver(1).await;
+
+        alerts(url).observe_deduction("0xabc", 1.0, 0.1);
+        tokio::time::sleep(RETRY_BASE_DELAY + Duration::from_millis(300)).await;
+
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+}
@@ -1,5 +1,6 @@
 use std::task::{self};
 
+use alloy::primitives::{Address, Signature};
 use alloy::transports::TransportErrorKind;
 use alloy::signers::{Signer, local::PrivateKeySigner};
 use tower::Service;
@@ -8,17 +9,194 @@ use tracing::{debug_span, Instrument};
 use alloy_transport::{BoxTransport, Transport, TransportConnect, TransportError, TransportFut, TransportResult};
 use alloy_json_rpc::{RequestPacket, ResponsePacket};
 use reqwest_middleware::ClientWithMiddleware;
+use sha2::{Digest, Sha256};
+
+/// Hash of the request body included in the signed message
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BodyHashAlgorithm {
+    #[default]
+    Keccak256,
+    /// Must be enabled in the gateway's `body_hash_algorithms`
+    Sha256,
+}
+
+impl BodyHashAlgorithm {
+    /// Name sent in X-Auth-Body-Hash-Algorithm
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Keccak256 => "keccak256",
+            Self::Sha256 => "sha256",
+        }
+    }
+
+    /// Hex digest of a request body
+    pub fn hash(self, body: &[u8]) -> String {
+        match self {
+            Self::Keccak256 => hex::encode(alloy::primitives::keccak256(body)),
+            Self::Sha256 => hex::encode(Sha256::digest(body)),
+        }
+    }
+}
+
+/// Form of the request body that is hashed into the signed message
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BodyCanonicalization {
+    /// The body bytes as sent, so any change to them in transit (even whitespace) fails verification
+    #[default]
+    Exact,
+    /// The body's JSON with object keys sorted and no whitespace, so a proxy re-serializing
+    /// the JSON doesn't break the signature. Must be enabled in the gateway's `body_canonicalizations`
+    CanonicalJson,
+}
+
+impl BodyCanonicalization {
+    /// Name sent in X-Auth-Body-Canonicalization
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Exact => "exact",
+            Self::CanonicalJson => "canonical_json",
+        }
+    }
+
+    /// The bytes of `body` that are hashed; a body that isn't JSON is hashed as is
+    pub fn apply(self, body: &str) -> String {
+        match (self, serde_json::from_str(body)) {
+            (Self::CanonicalJson, Ok(value)) => canonical_json(value).to_string(),
+            _ => body.to_string(),
+        }
+    }
+}
+
+/// `value` with every object's keys in sorted order
+/// Must match the gateway's canonical JSON
+fn canonical_json(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(entries.into_iter().map(|(key, value)| (key, canonical_json(value))).collect())
+        }
+        serde_json::Value::Array(values) => serde_json::Value::Array(values.into_iter().map(canonical_json).collect()),
+        other => other,
+    }
+}
+
+/// Message an account signs to let `session` sign its requests for `scope` until `expires`
+/// Must match the gateway's `delegation_message`
+pub fn delegation_message(account: Address, session: Address, expires: u64, scope: &str) -> String {
+    format!(
+        "x402-delegation:{}:{}:{}:{}",
+        account.to_string().to_lowercase(),
+        session.to_string().to_lowercase(),
+        expires,
+        scope
+    )
+}
+
+/// An account's authorization for a session key to sign its requests, so the account's
+/// own key can stay offline while the session key is used (and billed) on its behalf
+#[derive(Clone, Debug)]
+pub struct SessionDelegation {
+    account: Address,
+    session: Address,
+    /// Unix time the delegation is accepted until
+    expires: u64,
+    /// The one path the session key may sign for, or `*` for every path
+    scope: String,
+    signature: Signature,
+}
+
+impl SessionDelegation {
+    /// Sign a delegation from `account` to `session`
+    pub async fn sign(
+        account: &PrivateKeySigner,
+        session: Address,
+        expires: u64,
+        scope: impl Into<String>,
+    ) -> alloy::signers::Result<Self> {
+        let scope = scope.into();
+        let message = delegation_message(account.address(), session, expires, &scope);
+        let signature = account.sign_hash(&alloy::primitives::keccak256(message.as_bytes())).await?;
+        Ok(Self { account: account.address(), session, expires, scope, signature })
+    }
+
+    /// Value sent in X-Auth-Delegation
+    pub fn header_value(&self) -> String {
+        format!("{}:{}:{}:{}", self.session, self.expires, self.scope, self.signature)
+    }
+}
+
+/// Times a request is sent through the payment middleware before a 402 is given up on
+pub const DEFAULT_MAX_PAYMENT_ATTEMPTS: u32 = 2;
+
+/// The gateway still asked for payment after `attempts` tries through the payment middleware,
+/// e.g. because the wallet can't cover the top-up. Returned as a custom transport error;
+/// see `PaymentFailed::from_error`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentFailed {
+    pub attempts: u32,
+    /// Body of the last 402 response
+    pub body: String,
+}
+
+impl PaymentFailed {
+    /// The payment failure behind a transport error, if that's what it was
+    pub fn from_error(error: &TransportError) -> Option<&Self> {
+        match error {
+            alloy_json_rpc::RpcError::Transport(TransportErrorKind::Custom(source)) => source.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for PaymentFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "payment required after {} attempts: {}", self.attempts, self.body)
+    }
+}
+
+impl std::error::Error for PaymentFailed {}
 
 #[derive(Clone)]
 pub struct PaymentTransport {
     client: ClientWithMiddleware,
     url: reqwest::Url,
     signer: PrivateKeySigner,
+    body_hash: BodyHashAlgorithm,
+    canonicalization: BodyCanonicalization,
+    delegation: Option<SessionDelegation>,
+    max_payment_attempts: u32,
 }
 
 impl PaymentTransport {
     pub fn new(client: ClientWithMiddleware, url: reqwest::Url, signer: PrivateKeySigner) -> Self {
-        Self { client, url, signer }
+        Self { client, url, signer, body_hash: BodyHashAlgorithm::default(), canonicalization: BodyCanonicalization::default(), delegation: None, max_payment_attempts: DEFAULT_MAX_PAYMENT_ATTEMPTS }
+    }
+
+    /// Hash request bodies with `algorithm` instead of keccak256
+    pub fn with_body_hash(mut self, algorithm: BodyHashAlgorithm) -> Self {
+        self.body_hash = algorithm;
+        self
+    }
+
+    /// Sign the `canonicalization` form of request bodies instead of their exact bytes
+    pub fn with_body_canonicalization(mut self, canonicalization: BodyCanonicalization) -> Self {
+        self.canonicalization = canonicalization;
+        self
+    }
+
+    /// Send a request through the payment middleware at most `attempts` times (at least once)
+    /// before failing with `PaymentFailed`
+    pub fn with_max_payment_attempts(mut self, attempts: u32) -> Self {
+        self.max_payment_attempts = attempts.max(1);
+        self
+    }
+
+    /// Sign requests with a session key on behalf of the account that delegated to it;
+    /// `signer` must be the delegated session key
+    pub fn with_delegation(mut self, delegation: SessionDelegation) -> Self {
+        self.delegation = Some(delegation);
+        self
     }
 }
 
@@ -45,19 +223,65 @@ impl PaymentTransport {
     async fn do_reqwest(self, req: RequestPacket) -> TransportResult<ResponsePacket> {
         // Serialize request body
         let body = serde_json::to_string(&req).unwrap();
-        let body_bytes = body.as_bytes();
-        
+
+        // x402 middleware lives *inside* self.client, so a 402 reaching us means its
+        // pay -> retry dance didn't satisfy the gateway. Each attempt is signed afresh,
+        // as the gateway refuses a replayed signature
+        let mut attempts = 0;
+        let (status, body) = loop {
+            attempts += 1;
+            let resp = self.send_signed(&body).await?;
+            let status = resp.status();
+            let body = resp.bytes().await.map_err(TransportErrorKind::custom)?;
+
+            if status != reqwest::StatusCode::PAYMENT_REQUIRED {
+                break (status, body);
+            }
+            if attempts >= self.max_payment_attempts {
+                tracing::warn!(attempts, "Payment not accepted, giving up");
+                return Err(TransportErrorKind::custom(PaymentFailed {
+                    attempts,
+                    body: String::from_utf8_lossy(&body).into_owned(),
+                }));
+            }
+            tracing::debug!(attempts, "Payment not accepted, retrying");
+        };
+
+        if !status.is_success() {
+            // At this point, non-2xx is *not* x402 — it's a genuine error.
+            return Err(TransportErrorKind::http_error(
+                status.as_u16(),
+                String::from_utf8_lossy(&body).into_owned(),
+            ));
+        }
+
+        serde_json::from_slice(&body)
+            .map_err(|err| TransportError::deser_err(err, String::from_utf8_lossy(&body)))
+    }
+
+    /// Sign `body` and send it to the gateway
+    async fn send_signed(&self, body: &str) -> TransportResult<reqwest::Response> {
         // Generate authentication headers
         let timestamp = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .unwrap()
             .as_secs();
         
-        let address = self.signer.address();
+        // Under a delegation the session key signs as the account
+        let address = match &self.delegation {
+            Some(delegation) => delegation.account,
+            None => self.signer.address(),
+        };
         
-        // Sign: address + timestamp + keccak256(body)
-        let body_hash = alloy::primitives::keccak256(body_bytes);
-        let message = format!("{}{}{}", address, timestamp, hex::encode(body_hash));
+        // Sign: address + timestamp + method + path + hash(body)
+        let body_hash = self.body_hash.hash(self.canonicalization.apply(body).as_bytes());
+        let message = format!(
+            "{}{}POST{}{}",
+            address,
+            timestamp,
+            self.url.path(),
+            body_hash
+        );
         let message_hash = alloy::primitives::keccak256(message.as_bytes());
         
         let signature = self.signer
@@ -71,32 +295,23 @@ impl PaymentTransport {
             "Authenticated request"
         );
 
-        // x402 middleware lives *inside* self.client. By the time this returns,
-        // any 402 -> pay -> retry dance should already be handled.
-        let resp = self
+        let mut request = self
             .client
             .post(self.url.clone())
             .header("X-Auth-Address", address.to_string())
             .header("X-Auth-Signature", signature.to_string())
             .header("X-Auth-Timestamp", timestamp.to_string())
-            .body(body)
+            .header("X-Auth-Body-Hash", body_hash)
+            .header("X-Auth-Body-Hash-Algorithm", self.body_hash.name())
+            .header("X-Auth-Body-Canonicalization", self.canonicalization.name());
+        if let Some(delegation) = &self.delegation {
+            request = request.header("X-Auth-Delegation", delegation.header_value());
+        }
+        request
+            .body(body.to_string())
             .send()
             .await
-            .map_err(TransportErrorKind::custom)?;
-
-        let status = resp.status();
-        let body = resp.bytes().await.map_err(TransportErrorKind::custom)?;
-
-        if !status.is_success() {
-            // At this point, non-2xx is *not* x402 — it's a genuine error.
-            return Err(TransportErrorKind::http_error(
-                status.as_u16(),
-                String::from_utf8_lossy(&body).into_owned(),
-            ));
-        }
-
-        serde_json::from_slice(&body)
-            .map_err(|err| TransportError::deser_err(err, String::from_utf8_lossy(&body)))
+            .map_err(TransportErrorKind::custom)
     }
 }
 
@@ -110,3 +325,123 @@ impl TransportConnect for PaymentTransport {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_json_rpc::{Id, Request};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Server answering every request with a 402, counting the requests it saw
+    async fn spawn_payment_required_server() -> (reqwest::Url, Arc<AtomicU32>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/relay", listener.local_addr().unwrap()).parse().unwrap();
+        let requests = Arc::new(AtomicU32::new(0));
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 64 * 1024];
+                let _ = stream.read(&mut buf).await;
+                seen.fetch_add(1, Ordering::SeqCst);
+                let body = r#"{"error":"X-PAYMENT header is required"}"#;
+                let response = format!(
+                    "HTTP/1.1 402 Payment Required\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (url, requests)
+    }
+
+    /// Server answering one JSON-RPC request, handing back the raw request it saw
+    async fn spawn_capturing_server() -> (reqwest::Url, tokio::sync::oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/relay", listener.local_addr().unwrap()).parse().unwrap();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 64 * 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            let _ = tx.send(String::from_utf8_lossy(&buf[..n]).into_owned());
+            let body = r#"{"jsonrpc":"2.0","id":1,"result":"0x1"}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+        (url, rx)
+    }
+
+    #[test]
+    fn test_canonical_json_ignores_whitespace_and_key_order() {
+        let body = r#"{"jsonrpc":"2.0","method":"eth_chainId","params":[],"id":1}"#;
+        let reserialized = "{\n  \"id\": 1,\n  \"jsonrpc\": \"2.0\",\n  \"method\": \"eth_chainId\",\n  \"params\": [ ]\n}";
+
+        // Same vector as the gateway's canonical JSON test, so both sides hash the same bytes
+        let canonical = BodyCanonicalization::CanonicalJson.apply(body);
+        assert_eq!(canonical, r#"{"id":1,"jsonrpc":"2.0","method":"eth_chainId","params":[]}"#);
+        assert_eq!(BodyCanonicalization::CanonicalJson.apply(reserialized), canonical);
+
+        assert_eq!(BodyCanonicalization::Exact.apply(body), body);
+        assert_ne!(BodyCanonicalization::Exact.apply(reserialized), body);
+    }
+
+    #[tokio::test]
+    async fn test_signature_covers_canonicalized_body() {
+        for canonicalization in [BodyCanonicalization::Exact, BodyCanonicalization::CanonicalJson] {
+            let (url, captured) = spawn_capturing_server().await;
+            let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+            let signer = PrivateKeySigner::random();
+            let mut transport = PaymentTransport::new(client, url, signer.clone()).with_body_canonicalization(canonicalization);
+
+            let request = Request::new("eth_chainId", Id::Number(1), ()).serialize().unwrap();
+            transport.call(RequestPacket::Single(request)).await.unwrap();
+
+            let raw = captured.await.unwrap();
+            let (head, body) = raw.split_once("\r\n\r\n").unwrap();
+            let header = |name: &str| {
+                head.lines()
+                    .find_map(|line| line.split_once(": ").filter(|(key, _)| key.eq_ignore_ascii_case(name)))
+                    .map(|(_, value)| value.to_string())
+                    .unwrap()
+            };
+            assert_eq!(header("x-auth-body-canonicalization"), canonicalization.name());
+
+            // Verify as the gateway would, against the body as sent and as re-serialized by a proxy
+            let signature: Signature = header("x-auth-signature").parse().unwrap();
+            let recovered = |body: &str| {
+                let body_hash = BodyHashAlgorithm::Keccak256.hash(canonicalization.apply(body).as_bytes());
+                let message = format!("{}{}POST/relay{}", header("x-auth-address"), header("x-auth-timestamp"), body_hash);
+                signature.recover_address_from_prehash(&alloy::primitives::keccak256(message.as_bytes())).unwrap()
+            };
+            let reserialized = serde_json::to_string_pretty(&serde_json::from_str::<serde_json::Value>(body).unwrap()).unwrap();
+            assert_eq!(recovered(body), signer.address());
+            assert_eq!(
+                recovered(&reserialized) == signer.address(),
+                canonicalization == BodyCanonicalization::CanonicalJson
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_repeated_payment_required_fails_after_cap() {
+        let (url, requests) = spawn_payment_required_server().await;
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+        let mut transport = PaymentTransport::new(client, url, PrivateKeySigner::random()).with_max_payment_attempts(3);
+
+        let request = Request::new("eth_chainId", Id::Number(1), ()).serialize().unwrap();
+        let error = transport.call(RequestPacket::Single(request)).await.unwrap_err();
+
+        let failed = PaymentFailed::from_error(&error).expect("payment failure");
+        assert_eq!(failed.attempts, 3);
+        assert!(failed.body.contains("X-PAYMENT header is required"));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+}